@@ -16,7 +16,26 @@ async fn main() {
         MediaEvent::StateChanged(state) => println!("Changed state to {:?}", state),
         // Gets called on a set interval, wont get called if player is paused or stopped,
         // Value is a percentage of the position between 0 and 1
-        MediaEvent::ProgressChanged(elapsed) => println!("Changed progress to {:?}", elapsed)
+        MediaEvent::ProgressChanged(elapsed) => println!("Changed progress to {:?}", elapsed),
+        // Gets called when the upcoming tracks change, if the media client provides them
+        MediaEvent::QueueChanged(queue) => println!("Changed queue to {:#?}", queue),
+        // Gets called when a track finished playing
+        MediaEvent::TrackEnded(info) => println!("Finished playing {:#?}", info),
+        // Gets called for each piece of a large cover, the background source puts them back together
+        MediaEvent::CoverChunk(chunk) => println!("Received cover chunk {}", chunk.index),
+        // Only sent by the listeners, never by media clients
+        MediaEvent::ActiveSourceChanged(_)
+        | MediaEvent::ClientConnected(_)
+        | MediaEvent::ClientDisconnected(_)
+        | MediaEvent::SourceStale(_)
+        | MediaEvent::SourceGaveUp(_)
+        | MediaEvent::SourceError { .. }
+        | MediaEvent::Throttled(_)
+        | MediaEvent::ConflictDetected { .. }
+        | MediaEvent::SourceAdded(_)
+        | MediaEvent::SourceRemoved(_)
+        | MediaEvent::ListenThresholdReached { .. }
+        | MediaEvent::ControlRequested(_) => {}
       }
     }
   }
@@ -24,6 +24,21 @@ const checkConnectionInterval = 1000;
 // default: 1000
 let progressUpdateInterval = 1000;
 
+// Which events the other end wants, bits match `EventKind` on the other end
+//
+// can be changed by the other end
+//
+// default: all
+let subscribedEvents = 0xFFFFFFFF;
+
+const EVENT_MEDIA_CHANGED = 1 << 0;
+const EVENT_STATE_CHANGED = 1 << 1;
+const EVENT_PROGRESS_CHANGED = 1 << 2;
+
+// Close codes of the other end, match `CloseReason` on the other end
+const CLOSE_PROTOCOL_ERROR = 1002;
+const CLOSE_REPLACED = 4000;
+
 // --------------------
 
 function SpotifyInfo() {
@@ -34,6 +49,11 @@ function SpotifyInfo() {
 
   let ws;
   let ws_connected;
+
+  function isSubscribed(event) {
+    return (subscribedEvents & event) === event;
+  }
+
   let storage = {
     uid: undefined,
     uri: undefined,
@@ -84,7 +104,7 @@ function SpotifyInfo() {
     if (local.uid !== storage.uid) {
       storage = local;
 
-      if (ws_connected) {
+      if (ws_connected && isSubscribed(EVENT_MEDIA_CHANGED)) {
         ws.send(JSON.stringify({
           "MediaChanged": storage
         }));
@@ -92,11 +112,13 @@ function SpotifyInfo() {
     } else if (local.state !== storage.state) {
       storage.state = local.state;
 
-      if (ws_connected) {
+      if (ws_connected && isSubscribed(EVENT_STATE_CHANGED)) {
         ws.send(JSON.stringify({
           "StateChanged": local.state ?? "Stopped"
         }));
+      }
 
+      if (ws_connected && isSubscribed(EVENT_PROGRESS_CHANGED)) {
         if (storage.state !== "Playing") {
           ws.send(JSON.stringify({
             "ProgressChanged": Spicetify.Player.getProgress()
@@ -113,9 +135,10 @@ function SpotifyInfo() {
 
   function init() {
     ws_connected = false;
+    subscribedEvents = 0xFFFFFFFF;
     
     try {
-      ws = new WebSocket(`ws://127.0.0.1:${port}`);
+      ws = new WebSocket(`ws://127.0.0.1:${port}/ws`);
     } catch (e) {
       console.log(e);
       setTimeout(init, 500);
@@ -124,6 +147,10 @@ function SpotifyInfo() {
     ws.onopen = () => {
       ws_connected = true;
 
+      ws.send(JSON.stringify({
+        "Capabilities": { "control": true }
+      }));
+
       if (storage) {
         ws.send(JSON.stringify({
           "MediaChanged": storage
@@ -131,9 +158,15 @@ function SpotifyInfo() {
       }
     };
 
-    ws.onclose = () => {
+    ws.onclose = (event) => {
       ws_connected = false;
-      setTimeout(init, checkConnectionInterval);
+
+      // another client took over, connecting again would only take it back
+      if (event.code === CLOSE_REPLACED) {
+        return;
+      }
+
+      setTimeout(init, event.code === CLOSE_PROTOCOL_ERROR ? 5000 : checkConnectionInterval);
     };
 
     ws.onmessage = (message) => {
@@ -147,13 +180,38 @@ function SpotifyInfo() {
           progressUpdateInterval = n;
         }
       }
+
+      let subscribe = data["Subscribe"];
+
+      if (subscribe !== undefined) {
+        let n = Number.parseInt(subscribe);
+
+        if (!isNaN(n)) {
+          subscribedEvents = n;
+        }
+      }
+
+      switch (data) {
+        case "Play": Spicetify.Player.play(); break;
+        case "Pause": Spicetify.Player.pause(); break;
+        case "NextTrack": Spicetify.Player.next(); break;
+        case "PreviousTrack": Spicetify.Player.back(); break;
+      }
+
+      if (data["SeekTo"] !== undefined) {
+        Spicetify.Player.seek(data["SeekTo"]);
+      }
+
+      if (data["SetVolume"] !== undefined) {
+        Spicetify.Player.setVolume(data["SetVolume"]);
+      }
     };
   }
 
   init();
 
   const progressInterval = () => {
-    if (ws_connected && storage.state === "Playing") {
+    if (ws_connected && storage.state === "Playing" && isSubscribed(EVENT_PROGRESS_CHANGED)) {
       ws.send(JSON.stringify({
         "ProgressChanged": Spicetify.Player.getProgress()
       }));
@@ -0,0 +1,141 @@
+#![cfg_attr(not(any(feature = "system", feature = "ws")), allow(dead_code))]
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How long a source waits before setting its connection up again after it failed
+///
+/// ```rs
+/// // retry quickly at first, then every 30 seconds, give up after 20 tries
+/// let backoff = Backoff::exponential(Duration::from_millis(100), Duration::from_secs(30))
+///   .set_jitter(0.2)
+///   .set_max_attempts(20);
+/// ```
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backoff {
+  /// Delay before the first retry
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub initial: Duration,
+  /// Delay never grows past this
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub max: Duration,
+  /// Delay is multiplied by this after every failed attempt, `1.0` keeps it fixed
+  pub multiplier: f64,
+  /// Fraction of the delay that is randomly taken off, between `0.0` and `1.0`,
+  /// keeps many listeners from retrying at the exact same time
+  pub jitter: f64,
+  /// Attempts in a row before the source gives up and emits
+  /// [MediaEvent::SourceGaveUp](crate::MediaEvent::SourceGaveUp), [None] retries forever
+  pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+  fn default() -> Self {
+    Self::fixed(Duration::from_millis(1000))
+  }
+}
+
+impl Backoff {
+  /// Always waits `delay`
+  pub fn fixed(delay: Duration) -> Self {
+    Self {
+      initial: delay,
+      max: delay,
+      multiplier: 1.0,
+      jitter: 0.0,
+      max_attempts: None,
+    }
+  }
+
+  /// Starts at `initial` and doubles every attempt up to `max`
+  pub fn exponential(initial: Duration, max: Duration) -> Self {
+    Self {
+      initial,
+      max,
+      multiplier: 2.0,
+      jitter: 0.0,
+      max_attempts: None,
+    }
+  }
+
+  pub fn set_multiplier(self, multiplier: f64) -> Self {
+    Self { multiplier, ..self }
+  }
+
+  pub fn set_jitter(self, jitter: f64) -> Self {
+    Self {
+      jitter: jitter.clamp(0.0, 1.0),
+      ..self
+    }
+  }
+
+  pub fn set_max_attempts(self, max_attempts: u32) -> Self {
+    Self {
+      max_attempts: Some(max_attempts),
+      ..self
+    }
+  }
+
+  /// Delay before retry number `attempt` (starting at 0), without jitter
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let factor = self
+      .multiplier
+      .max(1.0)
+      .powi(attempt.min(i32::MAX as u32) as i32);
+    let delay = self.initial.as_secs_f64() * factor;
+
+    match Duration::try_from_secs_f64(delay) {
+      Ok(delay) => delay.min(self.max),
+      Err(_) => self.max,
+    }
+  }
+}
+
+/// Counts the failed attempts of one source
+#[derive(Debug)]
+pub(crate) struct Retry {
+  backoff: Backoff,
+  attempt: u32,
+}
+
+impl Retry {
+  pub(crate) fn new(backoff: Backoff) -> Self {
+    Self {
+      backoff,
+      attempt: 0,
+    }
+  }
+
+  /// How long to wait before trying again, [None] once [Backoff::max_attempts] is used up
+  pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+    if self
+      .backoff
+      .max_attempts
+      .is_some_and(|max| self.attempt >= max)
+    {
+      return None;
+    }
+
+    let delay = self.backoff.delay(self.attempt);
+    self.attempt = self.attempt.saturating_add(1);
+
+    Some(delay.mul_f64(1.0 - self.backoff.jitter.clamp(0.0, 1.0) * random()))
+  }
+
+  /// The connection came up, so the next failure starts over at [Backoff::initial]
+  pub(crate) fn reset(&mut self) {
+    self.attempt = 0;
+  }
+}
+
+/// Number in `0.0..1.0`, good enough for jitter without pulling in a rng crate
+fn random() -> f64 {
+  let mut hasher = RandomState::new().build_hasher();
+  hasher.write_u64(0);
+
+  (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,53 @@
+use std::net::SocketAddr;
+
+use currently_playing::listener::WebsocketAddr;
+use currently_playing::ws::conformance::{self, ConformanceConfig};
+
+const USAGE: &str = "\
+usage: currently-playing-conformance [--port <port> | --addr <ip:port>]
+
+listens where the media client connects to (127.0.0.1:19532 by default) in place of the listener,
+stop the listener first, then start the client and keep something playing until the report is printed";
+
+fn main() {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+  let addr = match args.as_slice() {
+    [] => WebsocketAddr::Default,
+    ["--port", port] => WebsocketAddr::Local(port.parse().unwrap_or_else(|_| exit(USAGE, 2))),
+    ["--addr", addr] => WebsocketAddr::Addr(
+      addr
+        .parse::<SocketAddr>()
+        .unwrap_or_else(|_| exit(USAGE, 2)),
+    ),
+    ["-h" | "--help"] => {
+      println!("{USAGE}");
+      return;
+    }
+    _ => exit(USAGE, 2),
+  };
+
+  let cfg = ConformanceConfig::default().set_addr(addr);
+
+  eprintln!(
+    "waiting for a media client on ws://{}/ws",
+    addr.socket_addr()
+  );
+
+  match conformance::run(&cfg) {
+    Ok(report) => {
+      println!("{report}");
+
+      if !report.passed() {
+        std::process::exit(1);
+      }
+    }
+    Err(err) => exit(err, 2),
+  }
+}
+
+fn exit(message: impl std::fmt::Display, code: i32) -> ! {
+  eprintln!("{message}");
+  std::process::exit(code)
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use currently_playing::listener::{MediaListener, MediaSource, MediaSourceConfig};
+use currently_playing::pipeline::PollMode;
+use currently_playing::platform;
+use currently_playing::{Error, MediaEvent};
+
+const USAGE: &str = "\
+usage: currently-playing get [--json | --template <template>]
+       currently-playing watch [--json | --template <template>]
+       currently-playing control <play|pause|next|previous>
+       currently-playing dump-raw
+
+templates fill in {key} placeholders like {title}, {artists}, {album}, {state}, {elapsed} and {duration}";
+
+const DEFAULT_TEMPLATE: &str = "{artists} - {title}";
+
+/// How long `get` and `control` wait for the players to answer
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How media is printed
+enum Output {
+  /// A [MediaMetadata](currently_playing::MediaMetadata) per line for `get`, a [MediaEvent] per line for `watch`
+  Json,
+  Template(String),
+}
+
+fn main() {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+  match args.as_slice() {
+    ["get", output @ ..] => get(parse_output(output)),
+    ["watch", output @ ..] => watch(parse_output(output)),
+    ["control", command] => control(command),
+    ["dump-raw"] => dump_raw(),
+    ["-h" | "--help"] => println!("{USAGE}"),
+    _ => exit(USAGE, 2),
+  }
+}
+
+fn parse_output(args: &[&str]) -> Output {
+  match args {
+    [] => Output::Template(DEFAULT_TEMPLATE.into()),
+    ["--json"] => Output::Json,
+    ["--template", template] => Output::Template(template.to_string()),
+    _ => exit(USAGE, 2),
+  }
+}
+
+fn exit(message: impl std::fmt::Display, code: i32) -> ! {
+  eprintln!("{message}");
+  std::process::exit(code)
+}
+
+/// Listener on the system's players, `poll_mode` decides if it keeps up with them in the background,
+/// covers are only read if they are printed
+fn listener(poll_mode: PollMode, covers: bool) -> MediaListener {
+  let mut cfg = MediaSourceConfig::new()
+    .set_poll_mode(poll_mode)
+    .set_covers(covers);
+  cfg.timeout = TIMEOUT;
+
+  let listener = MediaListener::builder()
+    .with_config(cfg.clone())
+    .with_system(cfg)
+    .build();
+
+  match listener {
+    Ok(listener) => listener,
+    Err(Error::NotEnabled) => exit("this build can't read the system's players", 1),
+    Err(err) => exit(err, 1),
+  }
+}
+
+/// Prints what is playing once, exits with 1 when nothing is
+fn get(output: Output) {
+  // the platform is asked right away instead of waiting for the background thread to catch up
+  let listener = listener(
+    PollMode::on_demand(Duration::ZERO),
+    matches!(output, Output::Json),
+  );
+
+  let media = match listener.poll() {
+    Ok(media) if !media.title.is_empty() => media,
+    Ok(_) | Err(Error::NotExist) => exit("nothing is playing", 1),
+    Err(err) => exit(err, 1),
+  };
+
+  match output {
+    Output::Json => print_json(&media),
+    Output::Template(template) => println!("{}", media.format(&template)),
+  }
+}
+
+/// Prints a line for every change until it's killed, with a template only when the line would change
+fn watch(output: Output) {
+  let listener = listener(PollMode::Background, matches!(output, Output::Json));
+  let mut printed = None;
+
+  loop {
+    let event = match listener.next() {
+      Ok(event) => event,
+      Err(err) if err.is_recoverable() => continue,
+      Err(err) => exit(err, 1),
+    };
+
+    match &output {
+      Output::Json => print_json(&event),
+      Output::Template(template) => {
+        let media = match event {
+          MediaEvent::MediaChanged(media) => media,
+          MediaEvent::StateChanged(_) => listener.poll().unwrap_or_default(),
+          _ => continue,
+        };
+
+        let line = media.format(template).to_string();
+
+        if printed.as_ref() != Some(&line) {
+          println!("{line}");
+          printed = Some(line);
+        }
+      }
+    }
+  }
+}
+
+fn control(command: &str) {
+  let listener = listener(PollMode::on_demand(Duration::ZERO), false);
+
+  // picks the player to control like `get` picks the one to show
+  let _ = listener.poll();
+
+  let result = match command {
+    "play" => listener.play(),
+    "pause" => listener.pause(),
+    "next" => listener.next_track(),
+    "previous" => listener.previous_track(),
+    _ => exit(USAGE, 2),
+  };
+
+  if let Err(err) = result {
+    exit(err, 1);
+  }
+}
+
+/// Prints what the player the listener would pick reports, before it's mapped, for bug reports
+fn dump_raw() {
+  match platform::dump_raw(&MediaSourceConfig::new()) {
+    Ok(raw) => match serde_json::to_string_pretty(&raw) {
+      Ok(json) => println!("{json}"),
+      Err(err) => exit(err, 1),
+    },
+    Err(Error::NotExist) => exit("nothing is playing", 1),
+    Err(err) => exit(err, 1),
+  }
+}
+
+fn print_json(value: &impl serde::Serialize) {
+  match serde_json::to_string(value) {
+    Ok(json) => println!("{json}"),
+    Err(err) => exit(err, 1),
+  }
+}
@@ -0,0 +1,580 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use currently_playing::listener::{
+  ListenerStatus, MediaListener, MediaSource, MediaSourceConfig, Preset, WebsocketAddr,
+};
+use currently_playing::osc::{OscConfig, OscSink};
+#[cfg(all(
+  unix,
+  not(any(target_os = "macos", target_os = "ios", target_os = "android")),
+  feature = "mpris-export"
+))]
+use currently_playing::platform::mpris_export::MprisExportSink;
+#[cfg(all(windows, feature = "smtc-export"))]
+use currently_playing::platform::smtc_export::SmtcExportSink;
+use currently_playing::sink::{FileSink, FileSinkConfig, MediaSink};
+use currently_playing::webhook::{WebhookConfig, WebhookSink};
+use currently_playing::{
+  Error, MediaCommand, MediaEvent, MediaMetadata, RemoteControl, Result, SourceKind,
+};
+
+const USAGE: &str = "\
+usage: currently-playingd [--config <file>]
+       currently-playingd [--config <file>] ctl <status|reload|pause-sinks|resume-sinks|shutdown>";
+
+/// How long the daemon waits for an event before it looks at control requests again
+const TICK: Duration = Duration::from_millis(250);
+
+/// Longest control request that is read, anything longer fails to parse
+const MAX_REQUEST: u64 = 64 * 1024;
+
+/// Settings of the daemon, read from the JSON file given with `--config`, anything left out is defaulted
+///
+/// ```json
+/// {
+///   "preset": "StatusBar",
+///   "websocket": { "Local": 19532 },
+///   "sinks": [{ "File": { "path": "now-playing.txt", "template": "{artists} - {title}" } }]
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct DaemonConfig {
+  /// Applied before the rest of the settings
+  preset: Option<Preset>,
+  /// Reads media from the players of the system
+  system: bool,
+  /// Where browser extensions and other websocket clients connect, [None] turns the websocket source off
+  websocket: Option<WebsocketAddr>,
+  priority: Vec<SourceKind>,
+  persist_path: Option<PathBuf>,
+  /// Path of the control socket, a loopback address where there are no unix sockets,
+  /// it stays where it is when the config is reloaded
+  control: String,
+  /// Lets websocket clients with this token control the daemon, see [MediaSourceConfig::control_token]
+  control_token: Option<String>,
+  sinks: Vec<SinkConfig>,
+}
+
+impl Default for DaemonConfig {
+  fn default() -> Self {
+    Self {
+      preset: None,
+      system: true,
+      websocket: Some(WebsocketAddr::Default),
+      priority: MediaSourceConfig::default().priority,
+      persist_path: None,
+      control: control::default_addr(),
+      control_token: None,
+      sinks: Vec::new(),
+    }
+  }
+}
+
+impl DaemonConfig {
+  fn load(path: Option<&Path>) -> Result<Self> {
+    let Some(path) = path else {
+      return Ok(Self::default());
+    };
+
+    let data = std::fs::read(path)?;
+
+    serde_json::from_slice(&data)
+      .map_err(|err| Error::InvalidConfig(vec![format!("{}: {err}", path.display())]))
+  }
+
+  fn listener(&self) -> Result<MediaListener> {
+    let mut cfg = MediaSourceConfig::default().set_priority(self.priority.clone());
+    cfg.timeout = TICK;
+
+    if let Some(preset) = self.preset {
+      cfg = cfg.set_preset(preset);
+    }
+
+    if let Some(path) = &self.persist_path {
+      cfg = cfg.set_persist_path(path);
+    }
+
+    if let Some(token) = &self.control_token {
+      cfg = cfg.set_control_token(token);
+    }
+
+    let mut builder = MediaListener::builder().with_config(cfg.clone());
+
+    if self.system {
+      builder = builder.with_system(cfg.clone());
+    }
+
+    if let Some(addr) = self.websocket {
+      builder = builder.with_websocket(cfg.enable_websocket(addr));
+    }
+
+    builder.build()
+  }
+
+  /// `commands` is where sinks that can control playback send their commands
+  #[cfg_attr(
+    not(any(feature = "mpris-export", feature = "smtc-export")),
+    allow(unused_variables)
+  )]
+  fn sinks(&self, commands: &Sender<Command>) -> Result<Vec<Box<dyn MediaSink>>> {
+    let mut sinks = Vec::<Box<dyn MediaSink>>::new();
+
+    for sink in &self.sinks {
+      match sink {
+        SinkConfig::File(cfg) => sinks.push(Box::new(FileSink::from_config(cfg.clone()))),
+        SinkConfig::Osc(cfg) => sinks.push(Box::new(OscSink::new(cfg.clone())?)),
+        SinkConfig::Webhook(cfg) => sinks.push(Box::new(WebhookSink::new(cfg.clone()))),
+        #[cfg(all(
+          unix,
+          not(any(target_os = "macos", target_os = "ios", target_os = "android")),
+          feature = "mpris-export"
+        ))]
+        SinkConfig::Mpris => {
+          let commands = commands.clone();
+
+          // nobody waits for the answer, MPRIS clients don't look at it either
+          let sink = MprisExportSink::new(move |command| {
+            let (reply, _) = std::sync::mpsc::channel();
+            let _ = commands.send((ControlRequest::Control(command), reply));
+            Ok(())
+          })?;
+
+          sinks.push(Box::new(sink));
+        }
+        #[cfg(all(windows, feature = "smtc-export"))]
+        SinkConfig::Smtc => {
+          let commands = commands.clone();
+
+          let sink = SmtcExportSink::new(move |command| {
+            let (reply, _) = std::sync::mpsc::channel();
+            let _ = commands.send((ControlRequest::Control(command), reply));
+            Ok(())
+          })?;
+
+          sinks.push(Box::new(sink));
+        }
+      }
+    }
+
+    Ok(sinks)
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SinkConfig {
+  File(FileSinkConfig),
+  Osc(OscConfig),
+  Webhook(WebhookConfig),
+  /// Shows the media as an MPRIS player and passes its controls on to the players
+  #[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android")),
+    feature = "mpris-export"
+  ))]
+  Mpris,
+  /// Shows the media in the Windows media flyout and passes the media keys on to the players
+  #[cfg(all(windows, feature = "smtc-export"))]
+  Smtc,
+}
+
+/// What `ctl` sends over the control socket, one JSON value per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum ControlRequest {
+  Status,
+  /// Reads the config file again and starts over with it
+  Reload,
+  PauseSinks,
+  ResumeSinks,
+  /// Passed on to the player the listener gets media from
+  Control(MediaCommand),
+  /// Sends controls to this source, [None] to the one the listener gets media from
+  ControlSource(Option<SourceKind>),
+  /// Hands what is playing to the sinks again, so they show its cover again
+  RefreshCover,
+  Shutdown,
+}
+
+impl From<RemoteControl> for ControlRequest {
+  fn from(control: RemoteControl) -> Self {
+    match control {
+      RemoteControl::Command(command) => Self::Control(command),
+      RemoteControl::ControlSource(source) => Self::ControlSource(source),
+      RemoteControl::RefreshCover => Self::RefreshCover,
+    }
+  }
+}
+
+/// Answer to a [ControlRequest], one JSON value per line
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum ControlReply {
+  Status(DaemonStatus),
+  Done,
+  Error(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DaemonStatus {
+  /// [None] when the last reload left the daemon without a working listener
+  listener: Option<ListenerStatus>,
+  media: Option<MediaMetadata>,
+  sinks: usize,
+  sinks_paused: bool,
+  last_sink_error: Option<String>,
+}
+
+struct Daemon {
+  config_path: Option<PathBuf>,
+  commands: Sender<Command>,
+  listener: Option<MediaListener>,
+  sinks: Vec<Box<dyn MediaSink>>,
+  sinks_paused: bool,
+  last_sink_error: Option<String>,
+}
+
+impl Daemon {
+  fn start(
+    config_path: Option<PathBuf>,
+    commands: Sender<Command>,
+    cfg: &DaemonConfig,
+  ) -> Result<Self> {
+    Ok(Self {
+      config_path,
+      listener: Some(cfg.listener()?),
+      sinks: cfg.sinks(&commands)?,
+      commands,
+      sinks_paused: false,
+      last_sink_error: None,
+    })
+  }
+
+  fn reload(&mut self) -> Result<()> {
+    // a broken config is reported without touching what's running
+    let cfg = DaemonConfig::load(self.config_path.as_deref())?;
+
+    // dropped first so the new listener can have the websocket port and the sinks their names
+    self.listener = None;
+    self.sinks = Vec::new();
+    self.last_sink_error = None;
+    self.sinks = cfg.sinks(&self.commands)?;
+    self.listener = Some(cfg.listener()?);
+
+    Ok(())
+  }
+
+  fn handle(&mut self, request: ControlRequest) -> ControlReply {
+    match request {
+      ControlRequest::Status => ControlReply::Status(DaemonStatus {
+        listener: self.listener.as_ref().map(MediaListener::status),
+        media: self
+          .listener
+          .as_ref()
+          .and_then(|listener| listener.poll().ok()),
+        sinks: self.sinks.len(),
+        sinks_paused: self.sinks_paused,
+        last_sink_error: self.last_sink_error.clone(),
+      }),
+      ControlRequest::Reload => match self.reload() {
+        Ok(()) => ControlReply::Done,
+        Err(err) => ControlReply::Error(err.to_string()),
+      },
+      ControlRequest::PauseSinks | ControlRequest::ResumeSinks => {
+        self.sinks_paused = request == ControlRequest::PauseSinks;
+        ControlReply::Done
+      }
+      ControlRequest::Control(command) => {
+        let result = match &self.listener {
+          Some(listener) => listener.control(command),
+          None => Err(Error::NotEnabled),
+        };
+
+        match result {
+          Ok(()) => ControlReply::Done,
+          Err(err) => ControlReply::Error(err.to_string()),
+        }
+      }
+      ControlRequest::ControlSource(source) => match (&self.listener, source) {
+        (Some(listener), Some(source)) => {
+          listener.control_source(source);
+          ControlReply::Done
+        }
+        (Some(listener), None) => {
+          listener.clear_control_source();
+          ControlReply::Done
+        }
+        (None, _) => ControlReply::Error(Error::NotEnabled.to_string()),
+      },
+      ControlRequest::RefreshCover => {
+        let media = self
+          .listener
+          .as_ref()
+          .and_then(|listener| listener.poll().ok());
+
+        match media {
+          Some(media) => {
+            self.dispatch(Some((&MediaEvent::MediaChanged(media.clone()), &media)));
+            ControlReply::Done
+          }
+          None => ControlReply::Error(Error::NotExist.to_string()),
+        }
+      }
+      ControlRequest::Shutdown => ControlReply::Done,
+    }
+  }
+
+  /// Waits for the next event and hands it to the sinks, ticks them either way
+  fn step(&mut self) {
+    let Some(listener) = &self.listener else {
+      std::thread::sleep(TICK);
+      return;
+    };
+
+    let event = match listener.next() {
+      Ok(event) => Some(event),
+      Err(err) if err.is_recoverable() => None,
+      Err(_) => {
+        std::thread::sleep(TICK);
+        None
+      }
+    };
+
+    // remote control from websocket clients goes through the same requests as the control socket
+    if let Some(MediaEvent::ControlRequested(control)) = event {
+      if let ControlReply::Error(err) = self.handle(control.into()) {
+        eprintln!("remote control error: {err}");
+      }
+
+      return;
+    }
+
+    let media = event.and_then(|event| Some((event, listener.poll().ok()?)));
+    self.dispatch(media.as_ref().map(|(event, media)| (event, media)));
+  }
+
+  /// Hands `event` to the sinks, or ticks them without one
+  fn dispatch(&mut self, event: Option<(&MediaEvent, &MediaMetadata)>) {
+    if self.sinks_paused {
+      return;
+    }
+
+    for sink in &mut self.sinks {
+      let result = match event {
+        Some((event, media)) => sink.offer(event, media),
+        None => sink.tick(),
+      };
+
+      if let Err(err) = result {
+        eprintln!("sink error: {err}");
+        self.last_sink_error = Some(err.to_string());
+      }
+    }
+  }
+}
+
+type Command = (ControlRequest, Sender<ControlReply>);
+
+fn main() {
+  let mut args = std::env::args().skip(1);
+  let mut config_path = None;
+  let mut ctl = None;
+
+  while let Some(arg) = args.next() {
+    match (arg.as_str(), args.next()) {
+      ("--config", Some(path)) => config_path = Some(PathBuf::from(path)),
+      ("ctl", Some(command)) => ctl = Some(command),
+      ("-h" | "--help", _) => {
+        println!("{USAGE}");
+        return;
+      }
+      _ => exit(USAGE, 2),
+    }
+  }
+
+  let cfg = match DaemonConfig::load(config_path.as_deref()) {
+    Ok(cfg) => cfg,
+    Err(err) => exit(err, 1),
+  };
+
+  match ctl {
+    Some(command) => send(&cfg, &command),
+    None => run(config_path, &cfg),
+  }
+}
+
+fn exit(message: impl std::fmt::Display, code: i32) -> ! {
+  eprintln!("{message}");
+  std::process::exit(code)
+}
+
+fn run(config_path: Option<PathBuf>, cfg: &DaemonConfig) {
+  let control = match control::bind(&cfg.control) {
+    Ok(control) => control,
+    Err(err) => exit(format_args!("failed to bind {}: {err}", cfg.control), 1),
+  };
+
+  let (commands, requests) = std::sync::mpsc::channel::<Command>();
+
+  let mut daemon = match Daemon::start(config_path, commands.clone(), cfg) {
+    Ok(daemon) => daemon,
+    Err(err) => exit(err, 1),
+  };
+
+  std::thread::spawn(move || {
+    for stream in control.incoming().flatten() {
+      let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+      answer(stream, &commands);
+    }
+  });
+
+  loop {
+    if serve_requests(&mut daemon, &requests) {
+      control::remove(&cfg.control);
+      return;
+    }
+
+    daemon.step();
+  }
+}
+
+/// Answers the requests that came in since the last event, true if the daemon should stop
+fn serve_requests(daemon: &mut Daemon, requests: &Receiver<Command>) -> bool {
+  for (request, reply) in requests.try_iter() {
+    let shutdown = request == ControlRequest::Shutdown;
+    let _ = reply.send(daemon.handle(request));
+
+    if shutdown {
+      return true;
+    }
+  }
+
+  false
+}
+
+/// Reads a request from a control client and forwards it to the daemon, clients are served one at a time
+fn answer<S: Read + Write>(mut stream: S, commands: &Sender<Command>) {
+  let mut line = String::new();
+
+  if BufReader::new((&mut stream).take(MAX_REQUEST))
+    .read_line(&mut line)
+    .is_err()
+  {
+    return;
+  }
+
+  let reply = match serde_json::from_str(&line) {
+    Ok(request) => {
+      let (send, recv) = std::sync::mpsc::channel();
+      let _ = commands.send((request, send));
+      recv
+        .recv()
+        .unwrap_or(ControlReply::Error("daemon stopped".into()))
+    }
+    Err(err) => ControlReply::Error(format!("malformed request: {err}")),
+  };
+
+  if let Ok(reply) = serde_json::to_string(&reply) {
+    let _ = writeln!(stream, "{reply}");
+  }
+}
+
+/// `ctl`, sends a request to a running daemon and prints its answer
+fn send(cfg: &DaemonConfig, command: &str) {
+  let request = match command {
+    "status" => ControlRequest::Status,
+    "reload" => ControlRequest::Reload,
+    "pause-sinks" => ControlRequest::PauseSinks,
+    "resume-sinks" => ControlRequest::ResumeSinks,
+    "shutdown" => ControlRequest::Shutdown,
+    _ => exit(USAGE, 2),
+  };
+
+  let reply = control::connect(&cfg.control).and_then(|mut stream| {
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    Ok(serde_json::from_str(&line)?)
+  });
+
+  match reply {
+    Ok(ControlReply::Status(status)) => match serde_json::to_string_pretty(&status) {
+      Ok(status) => println!("{status}"),
+      Err(err) => exit(err, 1),
+    },
+    Ok(ControlReply::Done) => {}
+    Ok(ControlReply::Error(message)) => exit(message, 1),
+    Err(err) => exit(
+      format_args!("failed to reach the daemon at {}: {err}", cfg.control),
+      1,
+    ),
+  }
+}
+
+#[cfg(unix)]
+mod control {
+  use std::io::ErrorKind;
+  use std::os::unix::fs::PermissionsExt;
+  use std::os::unix::net::{UnixListener, UnixStream};
+  use std::path::PathBuf;
+
+  pub fn default_addr() -> String {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+
+    dir
+      .join("currently-playingd.sock")
+      .to_string_lossy()
+      .into_owned()
+  }
+
+  /// Takes over a socket left behind by a daemon that didn't shut down, but not one that is still running
+  pub fn bind(path: &str) -> std::io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+      return Err(std::io::Error::new(
+        ErrorKind::AddrInUse,
+        "another daemon is running",
+      ));
+    }
+
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+
+    // only the user running the daemon gets to control it
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+  }
+
+  pub fn connect(path: &str) -> std::io::Result<UnixStream> {
+    UnixStream::connect(path)
+  }
+
+  pub fn remove(path: &str) {
+    let _ = std::fs::remove_file(path);
+  }
+}
+
+#[cfg(not(unix))]
+mod control {
+  use std::net::{TcpListener, TcpStream};
+
+  pub fn default_addr() -> String {
+    "127.0.0.1:19533".into()
+  }
+
+  pub fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    TcpListener::bind(addr)
+  }
+
+  pub fn connect(addr: &str) -> std::io::Result<TcpStream> {
+    TcpStream::connect(addr)
+  }
+
+  pub fn remove(_addr: &str) {}
+}
@@ -0,0 +1,276 @@
+#![cfg(all(target_arch = "wasm32", feature = "wasm-client"))]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CloseEvent, MessageEvent, WebSocket};
+
+use crate::{
+  ClientEnvelope, ClientMessage, CloseReason, CoverChunk, EventKind, MediaCapabilities, MediaCommand,
+  MediaEvent, MediaImage, MediaMessage, ProtocolError, RemoteControl,
+};
+
+/// Websocket client for media clients written in rust and compiled to wasm,
+/// the counterpart of [WebsocketMediaSource](crate::ws::WebsocketMediaSource)
+///
+/// Examples
+/// --------
+///
+/// ```rs
+/// let client = MediaClient::connect_default()?;
+///
+/// client.on_progress_interval(|interval| {
+///   // restart the progress timer with the new interval
+/// });
+///
+/// client.send(&MediaEvent::StateChanged(MediaState::Playing))?;
+/// ```
+pub struct MediaClient {
+  ws: WebSocket,
+  state: Rc<RefCell<ClientState>>,
+  _on_message: Closure<dyn FnMut(MessageEvent)>,
+  _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+struct ClientState {
+  subscribed: EventKind,
+  progress_interval: Option<Duration>,
+  on_progress_interval: Option<Box<dyn FnMut(Duration)>>,
+  on_cover_rejected: Option<Box<dyn FnMut(u64)>>,
+  on_protocol_error: Option<Box<dyn FnMut(ProtocolError)>>,
+  on_command: Option<Box<dyn FnMut(MediaCommand)>>,
+  on_close: Option<Box<dyn FnMut(Option<CloseReason>)>>,
+}
+
+impl MediaClient {
+  /// Connects to ws://127.0.0.1:19532/ws, the default address of the websocket source
+  pub fn connect_default() -> Result<Self, JsValue> {
+    Self::connect("ws://127.0.0.1:19532/ws")
+  }
+
+  pub fn connect(url: &str) -> Result<Self, JsValue> {
+    let ws = WebSocket::new(url)?;
+
+    let state = Rc::new(RefCell::new(ClientState {
+      subscribed: EventKind::ALL,
+      progress_interval: None,
+      on_progress_interval: None,
+      on_cover_rejected: None,
+      on_protocol_error: None,
+      on_command: None,
+      on_close: None,
+    }));
+
+    let handle = state.clone();
+    let socket = ws.clone();
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+      let Some(text) = event.data().as_string() else {
+        return;
+      };
+
+      let Ok(message) = serde_json::from_str::<MediaMessage>(&text) else {
+        return;
+      };
+
+      match message {
+        MediaMessage::ProgressUpdateInterval(ms) => {
+          let interval = Duration::from_millis(ms);
+
+          let callback = {
+            let mut state = handle.borrow_mut();
+            state.progress_interval = Some(interval);
+            state.on_progress_interval.take()
+          };
+
+          // called without holding the borrow so it can use the client
+          if let Some(mut callback) = callback {
+            callback(interval);
+            handle
+              .borrow_mut()
+              .on_progress_interval
+              .get_or_insert(callback);
+          }
+        }
+        MediaMessage::Subscribe(events) => handle.borrow_mut().subscribed = events,
+        MediaMessage::Ping(sent) => {
+          let pong = ClientEnvelope::new(ClientMessage::Pong {
+            sent,
+            received: web_sys::js_sys::Date::now() as u64,
+          });
+
+          if let Ok(text) = serde_json::to_string(&pong) {
+            let _ = socket.send_with_str(&text);
+          }
+        }
+        MediaMessage::CoverRejected(id) => {
+          let callback = handle.borrow_mut().on_cover_rejected.take();
+
+          if let Some(mut callback) = callback {
+            callback(id);
+            handle
+              .borrow_mut()
+              .on_cover_rejected
+              .get_or_insert(callback);
+          }
+        }
+        MediaMessage::ProtocolError(error) => {
+          let callback = handle.borrow_mut().on_protocol_error.take();
+
+          if let Some(mut callback) = callback {
+            callback(error);
+            handle
+              .borrow_mut()
+              .on_protocol_error
+              .get_or_insert(callback);
+          }
+        }
+        message => {
+          let Some(command) = message.command() else {
+            return;
+          };
+
+          let callback = handle.borrow_mut().on_command.take();
+
+          if let Some(mut callback) = callback {
+            callback(command);
+            handle.borrow_mut().on_command.get_or_insert(callback);
+          }
+        }
+      }
+    });
+
+    ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+    let handle = state.clone();
+    let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+      let callback = handle.borrow_mut().on_close.take();
+
+      if let Some(mut callback) = callback {
+        callback(CloseReason::from_code(event.code()));
+        handle.borrow_mut().on_close.get_or_insert(callback);
+      }
+    });
+
+    ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+    Ok(Self {
+      ws,
+      state,
+      _on_message: on_message,
+      _on_close: on_close,
+    })
+  }
+
+  /// Returns true once the connection is open and events can be sent
+  pub fn is_open(&self) -> bool {
+    self.ws.ready_state() == WebSocket::OPEN
+  }
+
+  /// Sends `event` to the websocket source,
+  /// events the source didn't [subscribe](crate::ws::MediaConnection::subscribe) to are skipped
+  pub fn send(&self, event: &MediaEvent) -> Result<(), JsValue> {
+    self.send_player("", event)
+  }
+
+  /// Types of events the websocket source asked for
+  pub fn subscribed(&self) -> EventKind {
+    self.state.borrow().subscribed
+  }
+
+  /// How often the websocket source asked for progress updates, if it did
+  pub fn progress_interval(&self) -> Option<Duration> {
+    self.state.borrow().progress_interval
+  }
+
+  /// Called whenever the websocket source asks for a different progress update interval
+  pub fn on_progress_interval(&self, f: impl FnMut(Duration) + 'static) {
+    self.state.borrow_mut().on_progress_interval = Some(Box::new(f));
+  }
+
+  /// Tells the websocket source what this client supports, pings are answered automatically
+  pub fn send_capabilities(&self, capabilities: &MediaCapabilities) -> Result<(), JsValue> {
+    self.send_message(&ClientMessage::Capabilities(capabilities.clone()))
+  }
+
+  /// Same as [MediaClient::send], but for one of several players, see [ClientMessage::Player]
+  pub fn send_player(&self, player_id: &str, event: &MediaEvent) -> Result<(), JsValue> {
+    if !self.state.borrow().subscribed.contains(event.kind()) {
+      return Ok(());
+    }
+
+    self.send_message(&ClientMessage::Player {
+      player_id: player_id.to_string(),
+      event: event.clone(),
+    })
+  }
+
+  /// Tells the websocket source a player sent with [MediaClient::send_player] went away
+  pub fn remove_player(&self, player_id: &str) -> Result<(), JsValue> {
+    self.send_message(&ClientMessage::PlayerRemoved(player_id.to_string()))
+  }
+
+  /// Asks the listener to do something, see [ClientMessage::Control]
+  pub fn control(&self, token: &str, request: RemoteControl) -> Result<(), JsValue> {
+    self.send_message(&ClientMessage::Control {
+      token: token.to_string(),
+      request,
+    })
+  }
+
+  /// Sends `message` in a [ClientEnvelope]
+  fn send_message(&self, message: &ClientMessage) -> Result<(), JsValue> {
+    let envelope = ClientEnvelope::new(message.clone());
+    let text =
+      serde_json::to_string(&envelope).map_err(|err| JsValue::from_str(&err.to_string()))?;
+
+    self.ws.send_with_str(&text)
+  }
+
+  /// Sends `cover` as [CoverChunk]s of at most `chunk_size` bytes,
+  /// call it after the [MediaEvent::MediaChanged] the cover belongs to
+  pub fn send_cover(&self, id: u64, cover: &MediaImage, chunk_size: usize) -> Result<(), JsValue> {
+    for chunk in CoverChunk::split(id, cover, chunk_size) {
+      self.send(&MediaEvent::CoverChunk(chunk))?;
+    }
+
+    Ok(())
+  }
+
+  /// Called when the websocket source dropped a cover sent with [MediaClient::send_cover],
+  /// with the id it was sent with
+  pub fn on_cover_rejected(&self, f: impl FnMut(u64) + 'static) {
+    self.state.borrow_mut().on_cover_rejected = Some(Box::new(f));
+  }
+
+  /// Called when the websocket source rejected a message, with what was wrong with it
+  pub fn on_protocol_error(&self, f: impl FnMut(ProtocolError) + 'static) {
+    self.state.borrow_mut().on_protocol_error = Some(Box::new(f));
+  }
+
+  /// Called when the websocket source asks the player to play, pause, skip, seek or change its volume,
+  /// only asked after sending [MediaCapabilities] with [MediaCapabilities::control] set
+  pub fn on_command(&self, f: impl FnMut(MediaCommand) + 'static) {
+    self.state.borrow_mut().on_command = Some(Box::new(f));
+  }
+
+  /// Called when the connection closed, with why the websocket source closed it,
+  /// [None] if the connection was lost, see [CloseReason::reconnect_after] for when to connect again
+  pub fn on_close(&self, f: impl FnMut(Option<CloseReason>) + 'static) {
+    self.state.borrow_mut().on_close = Some(Box::new(f));
+  }
+
+  pub fn close(&self) -> Result<(), JsValue> {
+    self.ws.close()
+  }
+}
+
+impl Drop for MediaClient {
+  fn drop(&mut self) {
+    self.ws.set_onmessage(None);
+    self.ws.set_onclose(None);
+    let _ = self.ws.close();
+  }
+}
@@ -0,0 +1,171 @@
+use std::cell::Cell;
+use std::fmt::{Formatter, Write};
+use std::time::Duration;
+
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::{DeserializeAs, SerializeAs};
+
+/// How durations like [MediaMetadata::duration](crate::MediaMetadata::duration) are written on the wire
+///
+/// Reading is lenient regardless of the format: floats are always seconds and strings are always
+/// ISO-8601, only integers depend on it, they're seconds for [DurationFormat::Seconds]
+/// and milliseconds otherwise
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum DurationFormat {
+  /// Whole milliseconds, e.g. `213500`
+  #[default]
+  Milliseconds,
+  /// Fractional seconds, e.g. `213.5`
+  Seconds,
+  /// ISO-8601 duration, e.g. `"PT3M33.5S"`
+  Iso8601,
+}
+
+thread_local! {
+  static FORMAT: Cell<DurationFormat> = const { Cell::new(DurationFormat::Milliseconds) };
+}
+
+impl DurationFormat {
+  /// Runs `f` with durations (de)serialized in this format on the current thread
+  ///
+  /// ```rs
+  /// let json = DurationFormat::Seconds.scope(|| serde_json::to_string(&event))?;
+  /// ```
+  pub fn scope<T>(self, f: impl FnOnce() -> T) -> T {
+    struct Restore(DurationFormat);
+
+    impl Drop for Restore {
+      fn drop(&mut self) {
+        FORMAT.set(self.0);
+      }
+    }
+
+    let _restore = Restore(FORMAT.replace(self));
+
+    f()
+  }
+
+  /// Format used by the current thread, [DurationFormat::Milliseconds] outside of [DurationFormat::scope]
+  pub fn current() -> Self {
+    FORMAT.get()
+  }
+}
+
+/// `serde_as` adapter writing durations in [DurationFormat::current]
+pub(crate) struct WireDuration;
+
+impl SerializeAs<Duration> for WireDuration {
+  fn serialize_as<S: Serializer>(source: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    match DurationFormat::current() {
+      DurationFormat::Milliseconds => serializer.serialize_u64(source.as_millis() as u64),
+      DurationFormat::Seconds => serializer.serialize_f64(source.as_secs_f64()),
+      DurationFormat::Iso8601 => serializer.serialize_str(&to_iso8601(*source)),
+    }
+  }
+}
+
+impl<'de> DeserializeAs<'de, Duration> for WireDuration {
+  fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    deserializer.deserialize_any(WireDurationVisitor)
+  }
+}
+
+struct WireDurationVisitor;
+
+impl Visitor<'_> for WireDurationVisitor {
+  type Value = Duration;
+
+  fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+    formatter.write_str("milliseconds, seconds as a float or an ISO-8601 duration")
+  }
+
+  fn visit_u64<E: Error>(self, v: u64) -> Result<Duration, E> {
+    match DurationFormat::current() {
+      DurationFormat::Seconds => Ok(Duration::from_secs(v)),
+      _ => Ok(Duration::from_millis(v)),
+    }
+  }
+
+  fn visit_i64<E: Error>(self, v: i64) -> Result<Duration, E> {
+    match u64::try_from(v) {
+      Ok(v) => self.visit_u64(v),
+      Err(_) => Err(E::custom("duration can't be negative")),
+    }
+  }
+
+  fn visit_f64<E: Error>(self, v: f64) -> Result<Duration, E> {
+    Duration::try_from_secs_f64(v).map_err(E::custom)
+  }
+
+  fn visit_str<E: Error>(self, v: &str) -> Result<Duration, E> {
+    from_iso8601(v).ok_or_else(|| E::custom(format!("invalid ISO-8601 duration: {v}")))
+  }
+}
+
+/// Writes `PT#H#M#S`, leaving out zero parts
+fn to_iso8601(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  let millis = duration.subsec_millis();
+  let mut out = String::from("PT");
+
+  if secs >= 3600 {
+    let _ = write!(out, "{}H", secs / 3600);
+  }
+
+  if secs % 3600 >= 60 {
+    let _ = write!(out, "{}M", secs % 3600 / 60);
+  }
+
+  if !secs.is_multiple_of(60) || millis != 0 || secs == 0 {
+    match millis {
+      0 => {
+        let _ = write!(out, "{}S", secs % 60);
+      }
+      _ => {
+        let fraction = format!("{millis:03}");
+        let _ = write!(out, "{}.{}S", secs % 60, fraction.trim_end_matches('0'));
+      }
+    }
+  }
+
+  out
+}
+
+/// Reads `P#DT#H#M#S`, any part can be left out and the last one can be fractional,
+/// years, months and weeks aren't supported since they don't have a fixed length
+fn from_iso8601(text: &str) -> Option<Duration> {
+  let rest = text.strip_prefix('P')?;
+  let mut secs = 0.0;
+  let mut in_time = false;
+  let mut number = String::new();
+  let mut parts = 0;
+
+  for char in rest.chars() {
+    let unit = match char {
+      '0'..='9' | '.' | ',' => {
+        number.push(if char == ',' { '.' } else { char });
+        continue;
+      }
+      'T' if !in_time && number.is_empty() => {
+        in_time = true;
+        continue;
+      }
+      'D' if !in_time => 86400.0,
+      'H' if in_time => 3600.0,
+      'M' if in_time => 60.0,
+      'S' if in_time => 1.0,
+      _ => return None,
+    };
+
+    secs += number.parse::<f64>().ok()? * unit;
+    number.clear();
+    parts += 1;
+  }
+
+  if !number.is_empty() || parts == 0 {
+    return None;
+  }
+
+  Duration::try_from_secs_f64(secs).ok()
+}
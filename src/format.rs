@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write};
+use std::time::Duration;
+
+use crate::{MediaMetadata, MediaState};
+
+/// Keys of [MediaMetadata::to_map] besides the `extra.<key>` ones
+const KEYS: &[&str] = &[
+  "uid",
+  "uri",
+  "album",
+  "cover_url",
+  "background_url",
+  "app",
+  "title",
+  "state",
+  "playing",
+  "duration",
+  "duration_ms",
+  "elapsed",
+  "elapsed_ms",
+  "progress",
+  "has_cover",
+  "has_background",
+  "queue_length",
+  "artist",
+  "artists",
+  "featured_artists",
+  "next_title",
+  "next_artists",
+];
+
+/// Displays a duration as `m:ss`, or `h:mm:ss` from an hour on, without allocating
+///
+/// ```rs
+/// assert_eq!(Timestamp(Duration::from_secs(3725)).to_string(), "1:02:05");
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Timestamp(pub Duration);
+
+impl Display for Timestamp {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let secs = self.0.as_secs();
+
+    match secs / 3600 {
+      0 => write!(f, "{}:{:02}", secs / 60, secs % 60),
+      hours => write!(f, "{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+  }
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour on, use [Timestamp] to write it somewhere without allocating
+pub fn format_timestamp(duration: Duration) -> String {
+  Timestamp(duration).to_string()
+}
+
+/// [MediaMetadata] rendered through a template, see [MediaMetadata::format]
+#[derive(Debug, Copy, Clone)]
+pub struct MetadataFormat<'a> {
+  metadata: &'a MediaMetadata,
+  template: &'a str,
+}
+
+impl Display for MetadataFormat<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let mut rest = self.template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+      f.write_str(&rest[..start])?;
+
+      let brace = rest.as_bytes()[start] as char;
+      rest = &rest[start + 1..];
+
+      // doubled braces are literal, so is a stray closing one
+      if rest.starts_with(brace) || brace == '}' {
+        f.write_char(brace)?;
+        rest = rest.strip_prefix(brace).unwrap_or(rest);
+        continue;
+      }
+
+      let Some(end) = rest.find('}') else {
+        f.write_char(brace)?;
+        break;
+      };
+
+      if let Some(result) = self.metadata.write_value(rest[..end].trim(), f) {
+        result?;
+      }
+
+      rest = &rest[end + 1..];
+    }
+
+    f.write_str(rest)
+  }
+}
+
+impl MediaMetadata {
+  /// Fills `{key}` placeholders in `template` with the values of [MediaMetadata::to_map],
+  /// missing values and unknown keys are left empty and `{{` and `}}` are literal braces
+  ///
+  /// Values are written straight into the output, nothing is collected in between
+  ///
+  /// ```rs
+  /// let line = metadata.format("{artists} - {title} [{elapsed}/{duration}]").to_string();
+  /// ```
+  pub fn format<'a>(&'a self, template: &'a str) -> MetadataFormat<'a> {
+    MetadataFormat {
+      metadata: self,
+      template,
+    }
+  }
+
+  /// Flattens the metadata into strings for templates, missing values are left out
+  ///
+  /// Keys are the field names, plus:
+  /// - `artists` and `featured_artists` joined with `, `, `artist` is the first artist
+  /// - `duration` and `elapsed` as `m:ss` (or `h:mm:ss`), `duration_ms` and `elapsed_ms` in milliseconds
+  /// - `progress` as a whole percentage
+  /// - `state` in lowercase, `playing` as `true` or `false`
+  /// - `has_cover` and `has_background` as `true` or `false`
+  /// - `queue_length`, and `next_title` and `next_artists` for the first queue entry
+  /// - every [MediaMetadata::extra] value as `extra.<key>`, strings as is and everything else as json
+  pub fn to_map(&self) -> BTreeMap<String, String> {
+    let extra = self.extra.keys().map(|key| format!("extra.{key}"));
+    let keys = KEYS.iter().map(|key| key.to_string()).chain(extra);
+
+    keys
+      .filter_map(|key| {
+        let mut value = String::new();
+
+        match self.write_value(&key, &mut value)? {
+          Ok(()) => Some((key, value)),
+          Err(_) => None,
+        }
+      })
+      .collect()
+  }
+
+  /// Writes the value of `key` as described in [MediaMetadata::to_map], [None] if it's missing
+  fn write_value(&self, key: &str, out: &mut impl Write) -> Option<std::fmt::Result> {
+    let result = match key {
+      "uid" => out.write_str(self.uid.as_deref()?),
+      "uri" => out.write_str(self.uri.as_deref()?),
+      "album" => out.write_str(self.album.as_deref()?),
+      "cover_url" => out.write_str(self.cover_url.as_deref()?),
+      "background_url" => out.write_str(self.background_url.as_deref()?),
+      "app" => out.write_str(self.app.as_deref()?),
+      "title" => out.write_str(&self.title),
+      "state" => out.write_str(match self.state {
+        MediaState::Playing => "playing",
+        MediaState::Paused => "paused",
+        MediaState::Stopped => "stopped",
+      }),
+      "playing" => write!(out, "{}", self.state == MediaState::Playing),
+      "duration" => write!(out, "{}", Timestamp(self.duration)),
+      "duration_ms" => write!(out, "{}", self.duration.as_millis()),
+      "elapsed" => write!(out, "{}", Timestamp(self.elapsed)),
+      "elapsed_ms" => write!(out, "{}", self.elapsed.as_millis()),
+      "progress" if !self.duration.is_zero() => {
+        write!(out, "{}", (self.progress_fraction() * 100.0).round() as u64)
+      }
+      "has_cover" => write!(out, "{}", self.cover.is_some()),
+      "has_background" => write!(out, "{}", self.background.is_some()),
+      "queue_length" => write!(out, "{}", self.queue.len()),
+      "artist" => out.write_str(self.artists.first()?),
+      "artists" if !self.artists.is_empty() => write_joined(out, &self.artists),
+      "featured_artists" if !self.featured_artists.is_empty() => {
+        write_joined(out, &self.featured_artists)
+      }
+      "next_title" => out.write_str(&self.queue.first()?.title),
+      "next_artists" => write_joined(out, &self.queue.first()?.artists),
+      _ => match self.extra.get(key.strip_prefix("extra.")?)? {
+        serde_json::Value::String(value) => out.write_str(value),
+        value => write!(out, "{value}"),
+      },
+    };
+
+    Some(result)
+  }
+}
+
+fn write_joined(out: &mut impl Write, values: &[String]) -> std::fmt::Result {
+  for (index, value) in values.iter().enumerate() {
+    if index > 0 {
+      out.write_str(", ")?;
+    }
+
+    out.write_str(value)?;
+  }
+
+  Ok(())
+}
@@ -0,0 +1,88 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::{Error, Result};
+
+/// Plain HTTP/1.1 request to a server on the local network, there's no tls,
+/// returns the whole response, see [parse_response]
+pub(crate) fn request(
+  addr: &SocketAddr,
+  method: &str,
+  path: &str,
+  headers: &[(&str, &str)],
+  body: Option<&str>,
+  timeout: Duration,
+) -> Result<Vec<u8>> {
+  let mut stream =
+    TcpStream::connect_timeout(addr, timeout).map_err(|err| Error::Connection(err.into()))?;
+
+  stream.set_read_timeout(Some(timeout))?;
+  stream.set_write_timeout(Some(timeout))?;
+
+  let mut head = format!(
+    "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nAccept: application/json\r\nConnection: close\r\n"
+  );
+
+  for (name, value) in headers {
+    head.push_str(&format!("{name}: {value}\r\n"));
+  }
+
+  if let Some(body) = body {
+    head.push_str(&format!(
+      "Content-Type: application/json\r\nContent-Length: {}\r\n",
+      body.len()
+    ));
+  }
+
+  head.push_str("\r\n");
+  head.push_str(body.unwrap_or_default());
+
+  stream.write_all(head.as_bytes())?;
+
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response)?;
+
+  Ok(response)
+}
+
+/// Splits a response into its status and body, undoing chunked transfer encoding
+pub(crate) fn parse_response(response: &[u8]) -> Option<(u16, Vec<u8>)> {
+  let end = response
+    .windows(4)
+    .position(|window| window == b"\r\n\r\n")?;
+
+  let head = std::str::from_utf8(&response[..end]).ok()?;
+  let body = &response[end + 4..];
+  let mut lines = head.lines();
+
+  let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+
+  let chunked = lines.any(|line| {
+    line.split_once(':').is_some_and(|(name, value)| {
+      name.trim().eq_ignore_ascii_case("transfer-encoding")
+        && value.trim().eq_ignore_ascii_case("chunked")
+    })
+  });
+
+  if !chunked {
+    return Some((status, body.to_vec()));
+  }
+
+  let mut rest = body;
+  let mut decoded = Vec::new();
+
+  loop {
+    let line_end = rest.windows(2).position(|window| window == b"\r\n")?;
+    let size = std::str::from_utf8(&rest[..line_end]).ok()?;
+    let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+
+    if size == 0 {
+      return Some((status, decoded));
+    }
+
+    let chunk = rest.get(line_end + 2..line_end + 2 + size)?;
+    decoded.extend_from_slice(chunk);
+    rest = rest.get(line_end + 4 + size..)?;
+  }
+}
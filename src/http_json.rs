@@ -0,0 +1,345 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLockReadGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::DeserializeAs;
+
+use crate::duration::WireDuration;
+use crate::http_client::{self, parse_response};
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::polled::PolledSource;
+use crate::{DurationFormat, Error, MediaEvent, MediaMetadata, MediaState, Result};
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpJsonError {
+  #[error("unexpected status {0}: {1}")]
+  Status(u16, String),
+  #[error("malformed http response")]
+  MalformedResponse,
+}
+
+impl From<HttpJsonError> for Error {
+  fn from(value: HttpJsonError) -> Self {
+    match value {
+      HttpJsonError::MalformedResponse => Self::Protocol(value.into()),
+      _ => Self::Connection(value.into()),
+    }
+  }
+}
+
+/// Where in the response each field of [MediaMetadata] is, as JSON pointers like `/player/track/title`
+/// (see [Value::pointer]), fields without a pointer or whose pointer doesn't match are left empty
+///
+/// - `artists` can point to a string or an array of strings
+/// - `state` can point to a bool (playing or paused), a number (playing unless it's 0, like a playback speed)
+///   or a string (`playing`, `paused`, anything else is stopped), without it media with a title is playing
+/// - `duration` and `elapsed` are read as [HttpJsonConfig::duration_format]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonMapping {
+  pub title: Option<String>,
+  pub artists: Option<String>,
+  pub album: Option<String>,
+  pub uid: Option<String>,
+  pub uri: Option<String>,
+  pub cover_url: Option<String>,
+  pub app: Option<String>,
+  pub state: Option<String>,
+  pub duration: Option<String>,
+  pub elapsed: Option<String>,
+}
+
+impl JsonMapping {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_title(self, pointer: impl Into<String>) -> Self {
+    Self {
+      title: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_artists(self, pointer: impl Into<String>) -> Self {
+    Self {
+      artists: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_album(self, pointer: impl Into<String>) -> Self {
+    Self {
+      album: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_uid(self, pointer: impl Into<String>) -> Self {
+    Self {
+      uid: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_uri(self, pointer: impl Into<String>) -> Self {
+    Self {
+      uri: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_cover_url(self, pointer: impl Into<String>) -> Self {
+    Self {
+      cover_url: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_app(self, pointer: impl Into<String>) -> Self {
+    Self {
+      app: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_state(self, pointer: impl Into<String>) -> Self {
+    Self {
+      state: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_duration(self, pointer: impl Into<String>) -> Self {
+    Self {
+      duration: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  pub fn set_elapsed(self, pointer: impl Into<String>) -> Self {
+    Self {
+      elapsed: Some(pointer.into()),
+      ..self
+    }
+  }
+
+  /// Reads the mapped fields out of `json`
+  pub fn apply(&self, json: &Value, duration_format: DurationFormat) -> MediaMetadata {
+    let get = |pointer: &Option<String>| {
+      pointer
+        .as_deref()
+        .and_then(|pointer| json.pointer(pointer))
+        .filter(|value| !value.is_null())
+    };
+
+    let string = |pointer: &Option<String>| get(pointer).and_then(text);
+
+    let duration = |pointer: &Option<String>| {
+      get(pointer)
+        .and_then(|value| duration_format.scope(|| WireDuration::deserialize_as(value).ok()))
+        .unwrap_or_default()
+    };
+
+    let artists = match get(&self.artists) {
+      Some(Value::Array(artists)) => artists.iter().filter_map(text).collect(),
+      Some(artist) => text(artist).into_iter().collect(),
+      None => Vec::new(),
+    };
+
+    let title = string(&self.title).unwrap_or_default();
+
+    let state = match get(&self.state) {
+      Some(Value::Bool(true)) => MediaState::Playing,
+      Some(Value::Bool(false)) => MediaState::Paused,
+      Some(Value::Number(speed)) => match speed.as_f64() {
+        Some(speed) if speed != 0.0 => MediaState::Playing,
+        _ => MediaState::Paused,
+      },
+      Some(Value::String(state)) => match state.to_ascii_lowercase().as_str() {
+        "playing" | "play" => MediaState::Playing,
+        "paused" | "pause" => MediaState::Paused,
+        _ => MediaState::Stopped,
+      },
+      _ if title.is_empty() => MediaState::Stopped,
+      _ => MediaState::Playing,
+    };
+
+    MediaMetadata {
+      uid: string(&self.uid),
+      uri: string(&self.uri),
+      state,
+      duration: duration(&self.duration),
+      elapsed: duration(&self.elapsed),
+      title,
+      album: string(&self.album),
+      artists,
+      cover_url: string(&self.cover_url),
+      app: string(&self.app),
+      ..MediaMetadata::default()
+    }
+  }
+}
+
+/// Strings as they are, numbers and bools written out
+fn text(value: &Value) -> Option<String> {
+  match value {
+    Value::String(text) => Some(text.clone()),
+    Value::Number(number) => Some(number.to_string()),
+    Value::Bool(bool) => Some(bool.to_string()),
+    _ => None,
+  }
+}
+
+/// JSON endpoint of a player on the local network and how to read media out of it
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct HttpJsonConfig {
+  pub addr: SocketAddr,
+  /// Path of the endpoint, including its query
+  pub path: String,
+  /// Sent with every request, like an `Authorization` header
+  pub headers: Vec<(String, String)>,
+  /// How often the endpoint is fetched
+  pub poll_interval: Duration,
+  /// How the endpoint writes durations, integers are milliseconds unless this is [DurationFormat::Seconds]
+  pub duration_format: DurationFormat,
+  pub mapping: JsonMapping,
+}
+
+impl HttpJsonConfig {
+  pub fn new(addr: SocketAddr, path: impl Into<String>, mapping: JsonMapping) -> Self {
+    Self {
+      addr,
+      path: path.into(),
+      headers: Vec::new(),
+      poll_interval: Duration::from_millis(1000),
+      duration_format: DurationFormat::default(),
+      mapping,
+    }
+  }
+
+  pub fn add_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+    self.headers.push((name.into(), value.into()));
+    self
+  }
+
+  pub fn set_poll_interval(self, poll_interval: Duration) -> Self {
+    Self {
+      poll_interval,
+      ..self
+    }
+  }
+
+  pub fn set_duration_format(self, duration_format: DurationFormat) -> Self {
+    Self {
+      duration_format,
+      ..self
+    }
+  }
+
+  fn fetch(&self) -> Result<MediaMetadata> {
+    let headers = self
+      .headers
+      .iter()
+      .map(|(name, value)| (name.as_str(), value.as_str()))
+      .collect::<Vec<_>>();
+
+    let response = http_client::request(
+      &self.addr,
+      "GET",
+      &self.path,
+      &headers,
+      None,
+      Duration::from_secs(5),
+    )?;
+
+    let (status, body) = parse_response(&response).ok_or(HttpJsonError::MalformedResponse)?;
+
+    if !(200..=299).contains(&status) {
+      let body = String::from_utf8_lossy(&body).into_owned();
+      return Err(HttpJsonError::Status(status, body).into());
+    }
+
+    let json: Value = serde_json::from_slice(&body).map_err(|err| Error::Protocol(err.into()))?;
+
+    Ok(self.mapping.apply(&json, self.duration_format))
+  }
+}
+
+/// Source for players with a JSON api of their own, fetched every
+/// [HttpJsonConfig::poll_interval] and read with a [JsonMapping]
+///
+/// ```rs
+/// let mapping = JsonMapping::new()
+///   .set_title("/track/title")
+///   .set_artists("/track/artists")
+///   .set_state("/playing")
+///   .set_elapsed("/position");
+///
+/// let endpoint = HttpJsonConfig::new(([127, 0, 0, 1], 8080).into(), "/api/now-playing", mapping)
+///   .set_duration_format(DurationFormat::Seconds);
+///
+/// let cfg = MediaSourceConfig::default().set_http_json(endpoint);
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(HttpJsonMediaSource::NAME, HttpJsonMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct HttpJsonMediaSource(PolledSource);
+
+impl HttpJsonMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "http_json";
+}
+
+impl MediaSource for HttpJsonMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(endpoint) = cfg.http_json.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let poll_interval = endpoint.poll_interval;
+
+    PolledSource::create(cfg, Self::NAME, poll_interval, move || endpoint.fetch()).map(Self)
+  }
+
+  fn is_closed(&self) -> bool {
+    self.0.is_closed()
+  }
+
+  fn is_running(&self) -> bool {
+    self.0.is_running()
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    self.0.poll_guarded()
+  }
+
+  fn is_stale(&self) -> bool {
+    self.0.is_stale()
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.0.last_error()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    self.0.next()
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.0.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.0.set_notifier(notifier)
+  }
+}
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, EventSender, Heartbeat,
+};
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result, SourceKind};
+
+/// Largest response head the source reads before giving up on the server
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum IcyError {
+  /// Only plain `http://` stream urls are supported
+  #[error("unsupported stream url {0}, only http:// is supported")]
+  UnsupportedUrl(String),
+  #[error("unexpected status {0}")]
+  Status(u16),
+  /// The server answered without `icy-metaint`, so the stream carries no titles
+  #[error("stream has no icy metadata")]
+  NoMetadata,
+  #[error("malformed icy response")]
+  MalformedResponse,
+}
+
+impl From<IcyError> for Error {
+  fn from(value: IcyError) -> Self {
+    match value {
+      IcyError::MalformedResponse => Self::Protocol(value.into()),
+      _ => Self::Connection(value.into()),
+    }
+  }
+}
+
+/// Icecast or Shoutcast stream to read titles from
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct IcyConfig {
+  /// `http://` url of the stream itself, not of a playlist (`.m3u` or `.pls`) pointing to it
+  pub url: String,
+  /// Separates the artist from the title in `StreamTitle`, titles without it are reported without artist
+  pub separator: String,
+}
+
+impl Default for IcyConfig {
+  fn default() -> Self {
+    Self {
+      url: String::new(),
+      separator: " - ".into(),
+    }
+  }
+}
+
+impl IcyConfig {
+  pub fn new(url: impl Into<String>) -> Self {
+    Self {
+      url: url.into(),
+      ..Self::default()
+    }
+  }
+
+  pub fn set_separator(self, separator: impl Into<String>) -> Self {
+    Self {
+      separator: separator.into(),
+      ..self
+    }
+  }
+
+  /// Host, port and path of [IcyConfig::url]
+  fn target(&self) -> Result<(String, u16, String), IcyError> {
+    let unsupported = || IcyError::UnsupportedUrl(self.url.clone());
+    let rest = self.url.strip_prefix("http://").ok_or_else(unsupported)?;
+
+    let (authority, path) = match rest.find('/') {
+      Some(index) => rest.split_at(index),
+      None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+      Some((host, port)) if !port.ends_with(']') => {
+        (host, port.parse().map_err(|_| unsupported())?)
+      }
+      _ => (authority, 80),
+    };
+
+    if host.is_empty() {
+      return Err(unsupported());
+    }
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok((host.into(), port, path.into()))
+  }
+}
+
+/// Station info from the `icy-*` response headers
+#[derive(Debug, Default)]
+struct Station {
+  /// Audio bytes in between metadata blocks
+  metaint: usize,
+  name: Option<String>,
+  genre: Option<String>,
+  url: Option<String>,
+}
+
+/// Reads the response head, the stream itself follows it
+fn read_head(stream: &mut TcpStream, cancel_token: &AtomicBool) -> Result<Station> {
+  let mut head = Vec::new();
+
+  while !head.ends_with(b"\r\n\r\n") {
+    if head.len() >= MAX_HEAD_SIZE {
+      return Err(IcyError::MalformedResponse.into());
+    }
+
+    let mut byte = [0];
+    read_exact(stream, &mut byte, cancel_token)?;
+    head.push(byte[0]);
+  }
+
+  let head = String::from_utf8_lossy(&head);
+  let mut lines = head.lines();
+
+  // `ICY 200 OK` from shoutcast, `HTTP/1.x 200 OK` from everything else
+  let status = lines
+    .next()
+    .and_then(|line| line.split(' ').nth(1))
+    .and_then(|status| status.parse().ok())
+    .ok_or(IcyError::MalformedResponse)?;
+
+  if status != 200 {
+    return Err(IcyError::Status(status).into());
+  }
+
+  let mut station = Station::default();
+
+  for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+    let value = value.trim();
+
+    match name.trim().to_ascii_lowercase().as_str() {
+      "icy-metaint" => station.metaint = value.parse().map_err(|_| IcyError::MalformedResponse)?,
+      "icy-name" => station.name = Some(value.into()),
+      "icy-genre" => station.genre = Some(value.into()),
+      "icy-url" => station.url = Some(value.into()),
+      _ => {}
+    }
+  }
+
+  if station.metaint == 0 {
+    return Err(IcyError::NoMetadata.into());
+  }
+
+  Ok(station)
+}
+
+/// [Read::read_exact] that gives up once the source is dropped, the stream has a read timeout
+/// so this doesn't block forever on a stalled server
+///
+/// Returns [Error::Closed] when cancelled
+fn read_exact(stream: &mut TcpStream, mut buf: &mut [u8], cancel_token: &AtomicBool) -> Result<()> {
+  while !buf.is_empty() {
+    if cancel_token.load(Ordering::SeqCst) {
+      return Err(Error::Closed);
+    }
+
+    match stream.read(buf) {
+      Ok(0) => return Err(Error::Connection("stream ended".into())),
+      Ok(read) => buf = &mut buf[read..],
+      Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+      Err(err) if err.kind() == ErrorKind::Interrupted => {}
+      Err(err) => return Err(err.into()),
+    }
+  }
+
+  Ok(())
+}
+
+/// `StreamTitle` out of a metadata block like `StreamTitle='Artist - Title';StreamUrl='';`
+fn stream_title(block: &[u8]) -> Option<String> {
+  let block = String::from_utf8_lossy(block);
+  let block = block.trim_end_matches('\0');
+  let start = block.find("StreamTitle='")? + "StreamTitle='".len();
+  let rest = &block[start..];
+
+  // titles can contain quotes themselves, so it ends at the quote before the next field
+  let end = rest.find("';").or_else(|| rest.rfind('\''))?;
+
+  Some(rest[..end].trim().into())
+}
+
+impl Station {
+  fn metadata(&self, title: &str, separator: &str) -> MediaMetadata {
+    let (artists, title) = match title.split_once(separator) {
+      Some((artist, title)) if !separator.is_empty() => (vec![artist.trim().into()], title.trim()),
+      _ => (Vec::new(), title),
+    };
+
+    let mut extra = BTreeMap::new();
+
+    for (key, value) in [
+      ("station", &self.name),
+      ("genre", &self.genre),
+      ("station_url", &self.url),
+    ] {
+      if let Some(value) = value {
+        extra.insert(key.into(), value.clone().into());
+      }
+    }
+
+    MediaMetadata {
+      state: MediaState::Playing,
+      title: title.into(),
+      artists,
+      app: self.name.clone(),
+      extra,
+      ..MediaMetadata::default()
+    }
+  }
+}
+
+/// Source for the titles of an Icecast or Shoutcast stream, for webradio that is played
+/// somewhere the system media apis can't see, like a browser without media session support
+///
+/// The stream is read like a player would (so it costs the same bandwidth), only its titles are kept,
+/// [MediaEvent::MediaChanged] is emitted whenever the title changes
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_icy(IcyConfig::new("http://radio.example.com:8000/live"));
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(IcyMediaSource::NAME, IcyMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct IcyMediaSource {
+  timeout: Duration,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  _background_task: JoinHandle<()>,
+}
+
+impl IcyMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "icy";
+}
+
+impl MediaSource for IcyMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(icy) = cfg.icy.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let cfg = cfg.for_source(&SourceKind::Custom(IcyMediaSource::NAME.into()));
+
+    // a bad url won't get better by retrying
+    icy.target()?;
+
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Custom(IcyMediaSource::NAME.into()));
+
+    let _background_task = spawn_background_task(
+      cfg.clone(),
+      icy,
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      last_error.clone(),
+      send,
+    )?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      _background_task,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.cancel_token.load(Ordering::SeqCst)
+  }
+
+  fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for IcyMediaSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_background_task(
+  cfg: MediaSourceConfig,
+  icy: IcyConfig,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn(IcyMediaSource::NAME, move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+    let source = SourceKind::Custom(IcyMediaSource::NAME.into());
+
+    loop {
+      let result = catch_panic(&source, &metadata, || {
+        background_task(
+          &cfg,
+          &icy,
+          &cancel_token,
+          &is_running,
+          &metadata,
+          &heartbeat,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) | Err(Error::Closed) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+
+          events.send(MediaEvent::SourceError {
+            source: source.clone(),
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(source));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
+      }
+    }
+  })
+}
+
+fn background_task(
+  cfg: &MediaSourceConfig,
+  icy: &IcyConfig,
+  cancel_token: &AtomicBool,
+  is_running: &AtomicBool,
+  metadata: &RwLock<MediaMetadata>,
+  heartbeat: &Heartbeat,
+  send: ChannelSender,
+) -> Result<()> {
+  let mut send = EventSender::new(send, cfg);
+  let (host, port, path) = icy.target()?;
+
+  let addr = (host.as_str(), port)
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| Error::Io(ErrorKind::NotFound.into()))?;
+
+  let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))
+    .map_err(|err| Error::Connection(err.into()))?;
+
+  // short so a dropped source is noticed, [read_exact] keeps waiting past it
+  stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+  stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+  let host = match port {
+    80 => host,
+    _ => format!("{host}:{port}"),
+  };
+
+  let request = format!(
+    "GET {path} HTTP/1.0\r\nHost: {host}\r\nIcy-MetaData: 1\r\nUser-Agent: currently_playing\r\nConnection: close\r\n\r\n"
+  );
+
+  stream.write_all(request.as_bytes())?;
+
+  let station = read_head(&mut stream, cancel_token)?;
+  let mut audio = vec![0; station.metaint];
+  let mut last_title = None;
+
+  is_running.store(true, Ordering::SeqCst);
+
+  loop {
+    read_exact(&mut stream, &mut audio, cancel_token)?;
+
+    let mut length = [0];
+    read_exact(&mut stream, &mut length, cancel_token)?;
+
+    let restore = heartbeat.beat();
+
+    if let Some(state) = restore {
+      metadata.write().unwrap().state = state;
+    }
+
+    // an empty block means the title didn't change
+    if length[0] == 0 {
+      continue;
+    }
+
+    let mut block = vec![0; length[0] as usize * 16];
+    read_exact(&mut stream, &mut block, cancel_token)?;
+
+    let Some(title) = stream_title(&block) else {
+      continue;
+    };
+
+    if last_title.as_ref() == Some(&title) {
+      continue;
+    }
+
+    let mut new_metadata = station.metadata(&title, &icy.separator);
+    cfg.sanitize.apply(&mut new_metadata);
+    cfg.apply_zone(&mut new_metadata);
+
+    last_title = Some(title);
+    *metadata.write().unwrap() = new_metadata.clone();
+
+    send.send(MediaEvent::MediaChanged(new_metadata));
+    send.flush();
+  }
+}
@@ -0,0 +1,64 @@
+use std::fmt::{Debug, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::MediaMetadata;
+
+/// Decides if two [MediaMetadata] are different tracks,
+/// which is what [MediaEvent::MediaChanged](crate::MediaEvent::MediaChanged) gets emitted on
+///
+/// Implemented for [IdentityStrategy] and for closures,
+/// so fuzzy matching or anything else can be plugged in
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default()
+///   .set_identity(|old: &MediaMetadata, new: &MediaMetadata| old.title != new.title);
+/// ```
+pub trait TrackIdentity: Send + Sync {
+  fn is_different(&self, old: &MediaMetadata, new: &MediaMetadata) -> bool;
+}
+
+impl Debug for dyn TrackIdentity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str("dyn TrackIdentity")
+  }
+}
+
+impl<F> TrackIdentity for F
+where
+  F: Fn(&MediaMetadata, &MediaMetadata) -> bool + Send + Sync,
+{
+  fn is_different(&self, old: &MediaMetadata, new: &MediaMetadata) -> bool {
+    self(old, new)
+  }
+}
+
+/// Built-in ways of comparing tracks
+#[derive(
+  Default, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum IdentityStrategy {
+  /// Uses [MediaMetadata::is_different], UID or URI if available, otherwise title and artists
+  #[default]
+  Default,
+  /// Only compares the UID, for players with stable track ids
+  Uid,
+  /// Only compares the URI
+  Uri,
+  /// Compares title and artists, for radio streams that keep the same URI for every song
+  TitleArtists,
+  /// Compares title and album, for players with unstable track ids
+  TitleAlbum,
+}
+
+impl TrackIdentity for IdentityStrategy {
+  fn is_different(&self, old: &MediaMetadata, new: &MediaMetadata) -> bool {
+    match self {
+      Self::Default => old.is_different(new),
+      Self::Uid => old.uid != new.uid,
+      Self::Uri => old.uri != new.uri,
+      Self::TitleArtists => old.title != new.title || old.artists != new.artists,
+      Self::TitleAlbum => old.title != new.title || old.album != new.album,
+    }
+  }
+}
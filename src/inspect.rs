@@ -0,0 +1,82 @@
+use std::io::Cursor;
+
+use image::ImageReader;
+
+use crate::{Error, ImageFormat, MediaImage, Result};
+
+impl ImageFormat {
+  fn to_image_format(&self) -> Option<image::ImageFormat> {
+    match self {
+      Self::PNG => Some(image::ImageFormat::Png),
+      Self::JPEG => Some(image::ImageFormat::Jpeg),
+      Self::WEBP => Some(image::ImageFormat::WebP),
+      Self::Other(mime) => image::ImageFormat::from_mime_type(mime),
+    }
+  }
+}
+
+impl MediaImage {
+  /// Width and height from the image header, nothing else is decoded
+  ///
+  /// Fails with [Error::InvalidImage] if the header can't be read,
+  /// the format is taken from the data and only falls back to [MediaImage::format]
+  pub fn dimensions(&self) -> Result<(u32, u32)> {
+    let format = ImageFormat::detect(&self.data)
+      .as_ref()
+      .unwrap_or(&self.format)
+      .to_image_format()
+      .ok_or_else(|| Error::InvalidImage(format!("unsupported format {:?}", self.format)))?;
+
+    ImageReader::with_format(Cursor::new(&self.data), format)
+      .into_dimensions()
+      .map_err(|err| Error::InvalidImage(err.to_string()))
+  }
+
+  /// Checks the image is safe to hand to a decoder without decoding it,
+  /// the header has to be readable, the data has to match [MediaImage::format]
+  /// and PNG, JPEG and GIF images have to end like they should, which catches truncated ones
+  ///
+  /// ```rs
+  /// if let Some(cover) = metadata.cover.as_ref().filter(|cover| cover.validate().is_ok()) {
+  ///   show(cover);
+  /// }
+  /// ```
+  pub fn validate(&self) -> Result<()> {
+    if let Some(detected) = ImageFormat::detect(&self.data) {
+      if detected != self.format {
+        return Err(Error::InvalidImage(format!(
+          "data is {detected:?} but the format says {:?}",
+          self.format
+        )));
+      }
+    }
+
+    let (width, height) = self.dimensions()?;
+
+    if width == 0 || height == 0 {
+      return Err(Error::InvalidImage(format!(
+        "empty image ({width}x{height})"
+      )));
+    }
+
+    // encoders sometimes pad the end with zeros
+    let end = self
+      .data
+      .iter()
+      .rposition(|byte| *byte != 0)
+      .map_or(&[][..], |last| &self.data[..=last]);
+
+    let complete = match &self.format {
+      ImageFormat::PNG => end.ends_with(b"IEND\xAE\x42\x60\x82"),
+      ImageFormat::JPEG => end.ends_with(&[0xFF, 0xD9]),
+      ImageFormat::Other(mime) if mime == "image/gif" => end.ends_with(b";"),
+      _ => true,
+    };
+
+    if !complete {
+      return Err(Error::InvalidImage("image is truncated".into()));
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,703 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tungstenite::{Message, WebSocket};
+
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result, SourceKind};
+
+/// Port of Kodi's JSON-RPC over TCP and websockets unless changed in `advancedsettings.xml`
+pub const DEFAULT_PORT: u16 = 9090;
+
+/// Port of Kodi's web server, which serves the artwork
+pub const DEFAULT_HTTP_PORT: u16 = 8080;
+
+#[derive(thiserror::Error, Debug)]
+pub enum KodiError {
+  /// Kodi answered a request with a JSON-RPC error
+  #[error("json-rpc error {code}: {message}")]
+  Rpc { code: i64, message: String },
+}
+
+impl From<KodiError> for Error {
+  fn from(value: KodiError) -> Self {
+    Self::Protocol(value.into())
+  }
+}
+
+/// Where to find [Kodi](https://kodi.tv)'s JSON-RPC websocket
+///
+/// Enable "Allow remote control from applications on this/other systems" in Kodi's settings
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct KodiConfig {
+  pub addr: SocketAddr,
+  /// Port of Kodi's web server on the same host, artwork that Kodi has cached locally is linked through it,
+  /// [None] only reports artwork that has a http(s) url of its own
+  pub http_port: Option<u16>,
+  /// How often the playing item is fetched, notifications from Kodi fetch it right away
+  pub poll_interval: Duration,
+}
+
+impl Default for KodiConfig {
+  fn default() -> Self {
+    Self {
+      addr: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+      http_port: Some(DEFAULT_HTTP_PORT),
+      poll_interval: Duration::from_millis(1000),
+    }
+  }
+}
+
+impl KodiConfig {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_addr(self, addr: SocketAddr) -> Self {
+    Self { addr, ..self }
+  }
+
+  pub fn set_http_port(self, http_port: u16) -> Self {
+    Self {
+      http_port: Some(http_port),
+      ..self
+    }
+  }
+
+  pub fn set_poll_interval(self, poll_interval: Duration) -> Self {
+    Self {
+      poll_interval,
+      ..self
+    }
+  }
+
+  /// Url of a Kodi `image://` path, the original url if it has one, otherwise through the web server
+  fn art_url(&self, image: &str) -> Option<String> {
+    if image.is_empty() {
+      return None;
+    }
+
+    if let Some(inner) = image.strip_prefix("image://") {
+      let inner = percent_decode(inner.trim_end_matches('/'));
+
+      if inner.starts_with("http://") || inner.starts_with("https://") {
+        return Some(inner);
+      }
+    }
+
+    let web_server = SocketAddr::new(self.addr.ip(), self.http_port?);
+
+    Some(format!(
+      "http://{web_server}/image/{}",
+      percent_encode(image)
+    ))
+  }
+}
+
+fn percent_encode(text: &str) -> String {
+  let mut encoded = String::with_capacity(text.len());
+
+  for byte in text.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+        encoded.push(byte as char)
+      }
+      _ => encoded.push_str(&format!("%{byte:02X}")),
+    }
+  }
+
+  encoded
+}
+
+fn percent_decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    let escaped = bytes
+      .get(i + 1..i + 3)
+      .filter(|_| bytes[i] == b'%')
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[derive(Debug, Serialize)]
+struct Request<'a, P> {
+  jsonrpc: &'static str,
+  id: u64,
+  method: &'a str,
+  params: P,
+}
+
+/// Answer to a request (with `id`) or a notification (with `method`)
+#[derive(Debug, Deserialize)]
+struct Incoming {
+  id: Option<u64>,
+  method: Option<String>,
+  result: Option<Value>,
+  error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+  code: i64,
+  message: String,
+}
+
+/// Notifications that change what is playing or its state
+const PLAYER_NOTIFICATIONS: [&str; 6] = [
+  "Player.OnPlay",
+  "Player.OnResume",
+  "Player.OnPause",
+  "Player.OnStop",
+  "Player.OnSeek",
+  "Player.OnAVStart",
+];
+
+/// JSON-RPC connection to Kodi
+struct Rpc {
+  socket: WebSocket<TcpStream>,
+  next_id: u64,
+  /// A notification about the player came in while waiting for an answer
+  notified: bool,
+}
+
+impl Rpc {
+  fn connect(kodi: &KodiConfig) -> Result<Self> {
+    let stream = TcpStream::connect_timeout(&kodi.addr, Duration::from_secs(5))
+      .map_err(|err| Error::Connection(err.into()))?;
+
+    let (socket, _) = tungstenite::client(format!("ws://{}/jsonrpc", kodi.addr), stream).map_err(
+      |err| match err {
+        tungstenite::HandshakeError::Failure(err) => Error::from(err),
+        tungstenite::HandshakeError::Interrupted(_) => Error::Io(ErrorKind::WouldBlock.into()),
+      },
+    )?;
+
+    Ok(Self {
+      socket,
+      next_id: 0,
+      notified: false,
+    })
+  }
+
+  fn call<P: Serialize, T: DeserializeOwned>(&mut self, method: &str, params: P) -> Result<T> {
+    self.next_id += 1;
+
+    let request = Request {
+      jsonrpc: "2.0",
+      id: self.next_id,
+      method,
+      params,
+    };
+
+    let request = serde_json::to_string(&request).map_err(|err| Error::Protocol(err.into()))?;
+    self.socket.send(Message::text(request))?;
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    loop {
+      let Some(incoming) = self.read(deadline)? else {
+        return Err(Error::Io(ErrorKind::TimedOut.into()));
+      };
+
+      if incoming.id != Some(self.next_id) {
+        continue;
+      }
+
+      if let Some(RpcError { code, message }) = incoming.error {
+        return Err(KodiError::Rpc { code, message }.into());
+      }
+
+      let result = incoming.result.unwrap_or_default();
+
+      return serde_json::from_value(result).map_err(|err| Error::Protocol(err.into()));
+    }
+  }
+
+  /// Next message from Kodi, [None] if nothing came in before `deadline`
+  fn read(&mut self, deadline: Instant) -> Result<Option<Incoming>> {
+    loop {
+      let timeout = deadline.saturating_duration_since(Instant::now());
+
+      if timeout.is_zero() {
+        return Ok(None);
+      }
+
+      self.socket.get_mut().set_read_timeout(Some(timeout))?;
+
+      let message = match self.socket.read() {
+        Ok(message) => message,
+        Err(tungstenite::Error::Io(err))
+          if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+          return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
+      };
+
+      let Message::Text(text) = message else {
+        continue;
+      };
+
+      let incoming: Incoming =
+        serde_json::from_str(&text).map_err(|err| Error::Protocol(err.into()))?;
+
+      if incoming
+        .method
+        .as_deref()
+        .is_some_and(|method| PLAYER_NOTIFICATIONS.contains(&method))
+      {
+        self.notified = true;
+      }
+
+      return Ok(Some(incoming));
+    }
+  }
+
+  /// What the first audio or video player is playing
+  fn fetch(&mut self, kodi: &KodiConfig) -> Result<MediaMetadata> {
+    #[derive(Serialize)]
+    struct NoParams {}
+
+    #[derive(Serialize)]
+    struct PlayerParams {
+      playerid: i64,
+      properties: &'static [&'static str],
+    }
+
+    #[derive(Deserialize)]
+    struct ItemResult {
+      item: KodiItem,
+    }
+
+    let players: Vec<KodiPlayer> = self.call("Player.GetActivePlayers", NoParams {})?;
+
+    let Some(player) = players.into_iter().find(|player| player.kind != "picture") else {
+      return Ok(MediaMetadata::default());
+    };
+
+    let properties: KodiProperties = self.call(
+      "Player.GetProperties",
+      PlayerParams {
+        playerid: player.playerid,
+        properties: &["speed", "time", "totaltime"],
+      },
+    )?;
+
+    let ItemResult { item } = self.call(
+      "Player.GetItem",
+      PlayerParams {
+        playerid: player.playerid,
+        properties: &[
+          "title",
+          "album",
+          "artist",
+          "duration",
+          "thumbnail",
+          "fanart",
+          "file",
+          "showtitle",
+          "season",
+          "episode",
+        ],
+      },
+    )?;
+
+    Ok(item.into_metadata(properties, kodi))
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct KodiPlayer {
+  playerid: i64,
+  /// `audio`, `video` or `picture`
+  #[serde(rename = "type")]
+  kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KodiProperties {
+  /// 0 while paused, negative while rewinding
+  speed: i32,
+  time: KodiTime,
+  totaltime: KodiTime,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct KodiTime {
+  hours: u64,
+  minutes: u64,
+  seconds: u64,
+  milliseconds: u64,
+}
+
+impl From<KodiTime> for Duration {
+  fn from(time: KodiTime) -> Self {
+    Duration::from_secs(time.hours * 3600 + time.minutes * 60 + time.seconds)
+      + Duration::from_millis(time.milliseconds)
+  }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct KodiItem {
+  /// Library id, missing for files that aren't in the library
+  id: Option<i64>,
+  /// `song`, `movie`, `episode`, `musicvideo`, `channel` or `unknown`
+  #[serde(rename = "type")]
+  kind: String,
+  label: String,
+  title: String,
+  album: String,
+  artist: Vec<String>,
+  /// Seconds
+  duration: u64,
+  thumbnail: String,
+  fanart: String,
+  file: String,
+  showtitle: String,
+  season: i32,
+  episode: i32,
+}
+
+impl KodiItem {
+  fn into_metadata(self, properties: KodiProperties, kodi: &KodiConfig) -> MediaMetadata {
+    let state = match properties.speed {
+      0 => MediaState::Paused,
+      _ => MediaState::Playing,
+    };
+
+    let duration = match Duration::from(properties.totaltime) {
+      Duration::ZERO => Duration::from_secs(self.duration),
+      duration => duration,
+    };
+
+    // episodes are credited to their show
+    let artists = match self.artist.is_empty() && !self.showtitle.is_empty() {
+      true => vec![self.showtitle.clone()],
+      false => self.artist,
+    };
+
+    let mut extra = BTreeMap::new();
+    extra.insert("media_type".into(), self.kind.clone().into());
+
+    if !self.showtitle.is_empty() {
+      extra.insert("show".into(), self.showtitle.into());
+    }
+
+    for (key, value) in [("season", self.season), ("episode", self.episode)] {
+      if value > 0 {
+        extra.insert(key.into(), value.into());
+      }
+    }
+
+    MediaMetadata {
+      uid: self.id.map(|id| format!("{}:{id}", self.kind)),
+      uri: Some(self.file).filter(|file| !file.is_empty()),
+      state,
+      duration,
+      elapsed: properties.time.into(),
+      title: match self.title.is_empty() {
+        true => self.label,
+        false => self.title,
+      },
+      album: Some(self.album).filter(|album| !album.is_empty()),
+      artists,
+      featured_artists: Vec::new(),
+      cover_url: kodi.art_url(&self.thumbnail),
+      cover: None,
+      background_url: kodi.art_url(&self.fanart),
+      background: None,
+      app: Some("Kodi".into()),
+      zone: None,
+      extra,
+      queue: Vec::new(),
+    }
+  }
+}
+
+/// Source for [Kodi](https://kodi.tv)'s JSON-RPC websocket, for HTPCs whose playback
+/// should show up next to everything else
+///
+/// Player notifications update it right away, the playing item is also fetched every
+/// [KodiConfig::poll_interval] to keep the progress current
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_kodi(KodiConfig::new());
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(KodiMediaSource::NAME, KodiMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct KodiMediaSource {
+  timeout: Duration,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  _background_task: JoinHandle<()>,
+}
+
+impl KodiMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "kodi";
+}
+
+impl MediaSource for KodiMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(kodi) = cfg.kodi.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let cfg = cfg.for_source(&SourceKind::Custom(KodiMediaSource::NAME.into()));
+
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Custom(KodiMediaSource::NAME.into()));
+
+    let _background_task = spawn_background_task(
+      cfg.clone(),
+      kodi,
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      last_error.clone(),
+      send,
+    )?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      _background_task,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.cancel_token.load(Ordering::SeqCst)
+  }
+
+  fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for KodiMediaSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_background_task(
+  cfg: MediaSourceConfig,
+  kodi: KodiConfig,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn(KodiMediaSource::NAME, move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+    let source = SourceKind::Custom(KodiMediaSource::NAME.into());
+
+    loop {
+      let result = catch_panic(&source, &metadata, || {
+        background_task(
+          &cfg,
+          &kodi,
+          &cancel_token,
+          &is_running,
+          &metadata,
+          &heartbeat,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+
+          events.send(MediaEvent::SourceError {
+            source: source.clone(),
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(source));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
+      }
+    }
+  })
+}
+
+fn background_task(
+  cfg: &MediaSourceConfig,
+  kodi: &KodiConfig,
+  cancel_token: &AtomicBool,
+  is_running: &AtomicBool,
+  metadata: &RwLock<MediaMetadata>,
+  heartbeat: &Heartbeat,
+  send: ChannelSender,
+) -> Result<()> {
+  let mut send = EventSender::new(send, cfg);
+  let mut track_end = TrackEndDetector::default();
+  let mut rpc = Rpc::connect(kodi)?;
+
+  is_running.store(true, Ordering::SeqCst);
+
+  loop {
+    if cancel_token.load(Ordering::SeqCst) {
+      let _ = rpc.socket.close(None);
+      return Ok(());
+    }
+
+    rpc.notified = false;
+
+    let mut new_metadata = rpc.fetch(kodi)?;
+    cfg.sanitize.apply(&mut new_metadata);
+    cfg.apply_zone(&mut new_metadata);
+
+    let restore = heartbeat.beat();
+    let mut metadata = metadata.write().unwrap();
+    let mut events = Vec::new();
+
+    if let Some(state) = restore {
+      metadata.state = state;
+    }
+
+    let state = new_metadata.state;
+    let media_changed = cfg.identity.is_different(&metadata, &new_metadata);
+
+    match () {
+      _ if media_changed => events.push(MediaEvent::MediaChanged(new_metadata.clone())),
+      _ if metadata.state != state => events.push(MediaEvent::StateChanged(state)),
+      _ if state == MediaState::Playing => {
+        events.push(MediaEvent::ProgressChanged(new_metadata.elapsed))
+      }
+      _ => {}
+    };
+
+    if let Some(ended) = events.first().and_then(|e| track_end.detect(&metadata, e)) {
+      events.insert(0, ended);
+    }
+
+    *metadata = new_metadata;
+    drop(metadata);
+
+    for event in events {
+      send.send(event);
+    }
+
+    send.flush();
+
+    // waits for the next poll, unless Kodi says something changed first
+    let due = Instant::now() + kodi.poll_interval;
+
+    while !rpc.notified && !cancel_token.load(Ordering::SeqCst) {
+      if rpc.read(due)?.is_none() {
+        break;
+      }
+    }
+  }
+}
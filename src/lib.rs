@@ -1,26 +1,97 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
+use std::net::SocketAddr;
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio_tungstenite::tungstenite;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod backoff;
+pub mod client;
+mod duration;
+mod format;
+#[cfg(all(feature = "icy", not(target_arch = "wasm32")))]
+pub mod icy;
+#[cfg(all(feature = "http-json", not(target_arch = "wasm32")))]
+pub mod http_json;
+pub mod identity;
+#[cfg(all(
+  any(feature = "ytmd", feature = "media-server", feature = "http-json", feature = "plugins", feature = "ws"),
+  not(target_arch = "wasm32")
+))]
+mod http_client;
+#[cfg(feature = "image")]
+mod inspect;
+#[cfg(all(feature = "kodi", not(target_arch = "wasm32")))]
+pub mod kodi;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod listener;
+#[cfg(all(feature = "media-server", not(target_arch = "wasm32")))]
+pub mod media_server;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod metrics;
+#[cfg(all(feature = "osc", not(target_arch = "wasm32")))]
+pub mod osc;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipeline;
+#[cfg(all(feature = "plugins", not(target_arch = "wasm32")))]
+pub mod plugin;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod persist;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod platform;
+#[cfg(all(any(feature = "media-server", feature = "http-json", feature = "plugins", feature = "ws"), not(target_arch = "wasm32")))]
+mod polled;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod power;
+#[cfg(all(feature = "cover-prefetch", not(target_arch = "wasm32")))]
+pub mod prefetch;
+pub mod redact;
+pub mod sanitize;
+pub mod share;
+#[cfg(all(feature = "shairport", unix))]
+pub mod shairport;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sink;
+pub mod spotify;
+#[cfg(feature = "ticker")]
+pub mod ticker;
+#[cfg(all(feature = "webhook", not(target_arch = "wasm32")))]
+pub mod webhook;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod widget;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod ws;
+#[cfg(all(feature = "ytmd", not(target_arch = "wasm32")))]
+pub mod ytmd;
+
+pub use duration::DurationFormat;
+pub use format::{format_timestamp, MetadataFormat, Timestamp};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, Error)]
-#[error(transparent)]
 pub enum Error {
-  #[cfg(windows)]
-  Platform(windows::core::Error),
+  /// The platform's media API failed (MPRIS over D-Bus on linux and the BSDs, GSMTC on windows)
+  #[cfg(all(windows, feature = "system"))]
+  #[error("Platform error: {0}")]
+  Platform(#[source] windows::core::Error),
 
-  #[cfg(target_os = "linux")]
+  /// The platform's media API failed (MPRIS over D-Bus on linux and the BSDs, GSMTC on windows)
+  #[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")), feature = "system"))]
+  #[error("Platform error: {0}")]
   Platform(#[from] platform::linux::MprisError),
 
+  /// The platform's media API isn't available at all, like GSMTC on some server editions of windows
+  /// or in sandboxes without access to it, [MediaListener](listener::MediaListener) carries on without
+  /// the system source if it has the websocket source
+  #[error("Platform media API unsupported: {0}")]
+  PlatformUnsupported(String),
+
   #[error("No media found or is currently opened")]
   NotExist,
 
@@ -30,18 +101,136 @@ pub enum Error {
   #[error("Closed")]
   Closed,
 
+  /// The source can't do what was asked, like controlling playback
+  #[error("Not supported by the source")]
+  Unsupported,
+
+  /// Returned by [MediaMetadataBuilder::build] when the fields contradict each other
+  #[error("Invalid metadata: {0}")]
+  InvalidMetadata(String),
+
+  /// Returned by [MediaImage::validate] and [MediaImage::dimensions] for corrupt or truncated images
+  #[error("Invalid image: {0}")]
+  InvalidImage(String),
+
+  #[cfg(not(target_arch = "wasm32"))]
+  #[error("Timed out waiting for an event: {0}")]
   Timeout(#[from] std::sync::mpsc::RecvTimeoutError),
 
+  /// The connection to a media client was lost or never came up
+  #[error("Connection error: {0}")]
+  Connection(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+  /// A media client sent something that doesn't follow the protocol
+  #[error("Protocol error: {0}")]
+  Protocol(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+  #[error("IO error: {0}")]
   Io(#[from] std::io::Error),
 
-  Tungstenite(#[from] tungstenite::Error),
+  /// Websocket errors that are neither connection nor protocol errors, like a bad handshake
+  #[cfg(any(feature = "ws", feature = "kodi"))]
+  #[error("Websocket error: {0}")]
+  Tungstenite(#[source] Box<tungstenite::Error>),
 
+  #[error(transparent)]
   Other(#[from] anyhow::Error),
 
-  #[error("{}, {}", 0.0, 0.1)]
+  #[error("{}, {}", .0.0, .0.1)]
   FailedToCreateListener((Box<Self>, Box<Self>)),
+
+  /// Returned by [MediaSourceConfig::validate](listener::MediaSourceConfig::validate), every problem it found
+  #[error("Invalid config: {}", .0.join(", "))]
+  InvalidConfig(Vec<String>),
+
+  /// Returned by [MediaListener::add_source](listener::MediaListener::add_source) when a source
+  /// with that name was already added
+  #[error("A source named {0} already exists")]
+  DuplicateSource(String),
+  /// Another listener already serves the websocket source's address,
+  /// see [InstancePolicy](listener::InstancePolicy)
+  #[error("Another listener is already serving {0}")]
+  InstanceRunning(SocketAddr),
+
+  /// The background task of the source in `kind` panicked, it's restarted according to
+  /// [MediaSourceConfig::backoff](listener::MediaSourceConfig::backoff) like after any other error
+  #[error("Background task of the {kind} source panicked: {message}")]
+  BackgroundPanic { kind: SourceKind, message: String },
+}
+
+impl Error {
+  /// If trying again later might succeed, like when the player was closed or a client disconnected,
+  /// `false` means it's a configuration or programming error and retrying is pointless
+  pub fn is_recoverable(&self) -> bool {
+    match self {
+      #[cfg(all(any(windows, all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))), feature = "system"))]
+      Self::Platform(_) => true,
+      Self::PlatformUnsupported(_) => false,
+      Self::NotExist => true,
+      Self::NotEnabled => false,
+      Self::Closed => false,
+      Self::Unsupported => false,
+      Self::InvalidMetadata(_) => false,
+      Self::InvalidImage(_) => false,
+      #[cfg(not(target_arch = "wasm32"))]
+      Self::Timeout(err) => *err == std::sync::mpsc::RecvTimeoutError::Timeout,
+      Self::Connection(_) => true,
+      Self::Protocol(_) => true,
+      Self::Io(err) => !matches!(
+        err.kind(),
+        std::io::ErrorKind::PermissionDenied
+          | std::io::ErrorKind::AddrNotAvailable
+          | std::io::ErrorKind::InvalidInput
+          | std::io::ErrorKind::Unsupported
+      ),
+      #[cfg(any(feature = "ws", feature = "kodi"))]
+      Self::Tungstenite(err) => !matches!(**err, tungstenite::Error::Url(_)),
+      Self::Other(_) => false,
+      Self::FailedToCreateListener((a, b)) => a.is_recoverable() || b.is_recoverable(),
+      Self::InvalidConfig(_) => false,
+      Self::DuplicateSource(_) => false,
+      Self::InstanceRunning(_) => false,
+      Self::BackgroundPanic { .. } => true,
+    }
+  }
+
+  /// Which source the error came from, [None] if it isn't tied to one
+  pub fn source_kind(&self) -> Option<SourceKind> {
+    match self {
+      #[cfg(all(any(windows, all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))), feature = "system"))]
+      Self::Platform(_) => Some(SourceKind::System),
+      Self::PlatformUnsupported(_) => Some(SourceKind::System),
+      Self::BackgroundPanic { kind, .. } => Some(kind.clone()),
+      Self::Connection(_) | Self::Protocol(_) | Self::InstanceRunning(_) => {
+        Some(SourceKind::Websocket)
+      }
+      #[cfg(any(feature = "ws", feature = "kodi"))]
+      Self::Tungstenite(_) => Some(SourceKind::Websocket),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(any(feature = "ws", feature = "kodi"))]
+impl From<tungstenite::Error> for Error {
+  fn from(value: tungstenite::Error) -> Self {
+    use tungstenite::Error as WsError;
+
+    match value {
+      WsError::ConnectionClosed | WsError::AlreadyClosed => Self::Connection(Box::new(value)),
+      WsError::Io(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+        Self::Protocol(Box::new(err))
+      }
+      WsError::Io(err) => Self::Connection(Box::new(err)),
+      WsError::Protocol(_) | WsError::Utf8 | WsError::Capacity(_) | WsError::AttackAttempt => {
+        Self::Protocol(Box::new(value))
+      }
+      value => Self::Tungstenite(Box::new(value)),
+    }
+  }
 }
-#[cfg(windows)]
+
+#[cfg(all(windows, feature = "system"))]
 #[allow(overflowing_literals)]
 impl From<windows::core::Error> for Error {
   fn from(value: windows::core::Error) -> Self {
@@ -87,6 +276,22 @@ pub struct MediaImage {
   pub data: Vec<u8>,
 }
 
+impl MediaImage {
+  /// Image with the format detected from the data, see [ImageFormat::detect]
+  pub fn from_bytes(data: impl Into<Vec<u8>>) -> Self {
+    Self::from_reported(ImageFormat::Other("application/octet-stream".into()), data)
+  }
+
+  /// Image whose format was reported by a platform or client, which isn't always right
+  /// (GSMTC reports `image/jpeg` for some PNG thumbnails), the format detected from the data wins
+  pub fn from_reported(reported: ImageFormat, data: impl Into<Vec<u8>>) -> Self {
+    let data = data.into();
+    let format = ImageFormat::detect(&data).unwrap_or(reported);
+
+    Self { format, data }
+  }
+}
+
 impl Debug for MediaImage {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
     f.debug_struct("MediaImage")
@@ -107,20 +312,43 @@ impl Display for ImageFormat {
   }
 }
 
+impl ImageFormat {
+  /// Detects the format from the magic bytes at the start of the image,
+  /// formats without a variant of their own come back as [ImageFormat::Other] with their mime type
+  /// (`image/gif`, `image/bmp` and `image/avif`)
+  pub fn detect(data: &[u8]) -> Option<Self> {
+    match data {
+      [0x89, b'P', b'N', b'G', ..] => Some(Self::PNG),
+      [0xFF, 0xD8, 0xFF, ..] => Some(Self::JPEG),
+      [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::WEBP),
+      [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Self::Other("image/gif".into())),
+      [b'B', b'M', ..] if data.len() >= 26 => Some(Self::Other("image/bmp".into())),
+      [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => {
+        Some(Self::Other("image/avif".into()))
+      }
+      _ => None,
+    }
+  }
+}
+
 impl From<String> for ImageFormat {
   fn from(value: String) -> Self {
-    match value.as_str() {
+    // content types can come with parameters and in any case
+    let mime = value.split(';').next().unwrap_or_default().trim();
+
+    match mime.to_ascii_lowercase().as_str() {
       "image/png" => Self::PNG,
       "image/jpg" | "image/jpeg" => Self::JPEG,
       "image/webp" => Self::WEBP,
-      _ => Self::Other(value),
+      "" => Self::Other(value),
+      mime => Self::Other(mime.into()),
     }
   }
 }
 
 /// Metadata of what is currently playing
 #[serde_with::serde_as]
-#[derive(Default, Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MediaMetadata {
   /// UID of what is currently playing if available
   pub uid: Option<String>,
@@ -129,9 +357,9 @@ pub struct MediaMetadata {
   /// State of what is currently playing
   pub state: MediaState,
   /// Duration of what is currently playing
-  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  #[serde_as(as = "duration::WireDuration")]
   pub duration: Duration,
-  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  #[serde_as(as = "duration::WireDuration")]
   /// Elapsed duration of what is currently playing
   pub elapsed: Duration,
   /// Title of what is currently playing
@@ -140,6 +368,11 @@ pub struct MediaMetadata {
   pub album: Option<String>,
   /// Artists of what is currently playing
   pub artists: Vec<String>,
+  /// Featured artists of what is currently playing,
+  /// only filled in when [SanitizeConfig::split_artists](sanitize::SanitizeConfig::split_artists) is enabled
+  /// or when the source provides them separately
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub featured_artists: Vec<String>,
   /// Cover art url of what is currently playing if available
   pub cover_url: Option<String>,
   /// Cover art image data of what is currently playing if available
@@ -150,6 +383,140 @@ pub struct MediaMetadata {
   /// Background art image data of what is currently playing if available
   /// (when you hit the "full screen" thing in the bottom-right corner of spotify)
   pub background: Option<MediaImage>,
+  /// Name or id of the app that is playing if available (e.g. `Spotify` on MPRIS)
+  pub app: Option<String>,
+  /// Room or device it's playing in if known (e.g. `Living Room`), from the source
+  /// or its [MediaSourceConfig::zone](listener::MediaSourceConfig::zone)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub zone: Option<String>,
+  /// Any other metadata the source provided that doesn't have a dedicated field,
+  /// keyed by the source's own names (e.g. `xesam:composer` or `xesam:userRating` on MPRIS)
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub extra: BTreeMap<String, serde_json::Value>,
+  /// What is going to play after the current media if the source knows about it ("up next")
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub queue: Vec<QueueEntry>,
+}
+
+/// Ordered by its fields in the order they're declared, like it was derived,
+/// [MediaMetadata::extra] can't derive it so its values are compared with [cmp_json]
+impl Ord for MediaMetadata {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self
+      .uid
+      .cmp(&other.uid)
+      .then_with(|| self.uri.cmp(&other.uri))
+      .then_with(|| self.state.cmp(&other.state))
+      .then_with(|| self.duration.cmp(&other.duration))
+      .then_with(|| self.elapsed.cmp(&other.elapsed))
+      .then_with(|| self.title.cmp(&other.title))
+      .then_with(|| self.album.cmp(&other.album))
+      .then_with(|| self.artists.cmp(&other.artists))
+      .then_with(|| self.featured_artists.cmp(&other.featured_artists))
+      .then_with(|| self.cover_url.cmp(&other.cover_url))
+      .then_with(|| self.cover.cmp(&other.cover))
+      .then_with(|| self.background_url.cmp(&other.background_url))
+      .then_with(|| self.background.cmp(&other.background))
+      .then_with(|| self.app.cmp(&other.app))
+      .then_with(|| self.zone.cmp(&other.zone))
+      .then_with(|| {
+        cmp_by(&self.extra, &other.extra, |(a_key, a), (b_key, b)| {
+          a_key.cmp(b_key).then_with(|| cmp_json(a, b))
+        })
+      })
+      .then_with(|| self.queue.cmp(&other.queue))
+  }
+}
+
+impl PartialOrd for MediaMetadata {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// Total order of json values that agrees with their [Eq], values of different types
+/// are ordered null, bools, numbers, strings, arrays then objects
+fn cmp_json(a: &serde_json::Value, b: &serde_json::Value) -> Ordering {
+  use serde_json::Value;
+
+  fn rank(value: &Value) -> u8 {
+    match value {
+      Value::Null => 0,
+      Value::Bool(_) => 1,
+      Value::Number(_) => 2,
+      Value::String(_) => 3,
+      Value::Array(_) => 4,
+      Value::Object(_) => 5,
+    }
+  }
+
+  match (a, b) {
+    (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+    // numbers that are equal as floats but not as json, like `1` and `1.0`, are told apart by how they're written
+    (Value::Number(a), Value::Number(b)) => {
+      let as_float = |number: &serde_json::Number| number.as_f64().unwrap_or_default();
+
+      as_float(a)
+        .total_cmp(&as_float(b))
+        .then_with(|| a.to_string().cmp(&b.to_string()))
+    }
+    (Value::String(a), Value::String(b)) => a.cmp(b),
+    (Value::Array(a), Value::Array(b)) => cmp_by(a, b, cmp_json),
+    // sorted first, maps that keep insertion order are still equal in any order
+    (Value::Object(a), Value::Object(b)) => {
+      let mut a = a.iter().collect::<Vec<_>>();
+      let mut b = b.iter().collect::<Vec<_>>();
+      a.sort_by_key(|(key, _)| *key);
+      b.sort_by_key(|(key, _)| *key);
+
+      cmp_by(a, b, |(a_key, a), (b_key, b)| {
+        a_key.cmp(b_key).then_with(|| cmp_json(a, b))
+      })
+    }
+    _ => rank(a).cmp(&rank(b)),
+  }
+}
+
+/// Compares `a` and `b` item by item with `cmp` like slices are, the shorter one first if it's where they differ
+fn cmp_by<T>(
+  a: impl IntoIterator<Item = T>,
+  b: impl IntoIterator<Item = T>,
+  cmp: impl Fn(T, T) -> Ordering,
+) -> Ordering {
+  let mut a = a.into_iter();
+  let mut b = b.into_iter();
+
+  loop {
+    match (a.next(), b.next()) {
+      (Some(a), Some(b)) => match cmp(a, b) {
+        Ordering::Equal => {}
+        ordering => return ordering,
+      },
+      (a, b) => return a.is_some().cmp(&b.is_some()),
+    }
+  }
+}
+
+/// Entry in the queue of what is going to play next
+#[serde_with::serde_as]
+#[derive(Default, Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueEntry {
+  /// UID of the queued media if available
+  pub uid: Option<String>,
+  /// URI of the queued media if available
+  pub uri: Option<String>,
+  /// Duration of the queued media
+  #[serde_as(as = "duration::WireDuration")]
+  pub duration: Duration,
+  /// Title of the queued media
+  pub title: String,
+  /// Album of the queued media if available
+  pub album: Option<String>,
+  /// Artists of the queued media
+  pub artists: Vec<String>,
+  /// Cover art url of the queued media if available
+  pub cover_url: Option<String>,
 }
 
 impl MediaMetadata {
@@ -179,11 +546,54 @@ impl MediaMetadata {
       } else {
         self.artists
       },
+      featured_artists: if self.featured_artists.is_empty() {
+        fallback.featured_artists
+      } else {
+        self.featured_artists
+      },
       cover_url: self.cover_url.or(fallback.cover_url),
       cover: self.cover.or(fallback.cover),
       background_url: self.background_url.or(fallback.background_url),
       background: self.background.or(fallback.background),
+      app: self.app.or(fallback.app),
+      zone: self.zone.or(fallback.zone),
+      extra: {
+        let mut extra = fallback.extra;
+        extra.extend(self.extra);
+        extra
+      },
+      queue: if self.queue.is_empty() {
+        fallback.queue
+      } else {
+        self.queue
+      },
+    }
+  }
+
+  /// How far into the track playback is, from `0.0` to `1.0`,
+  /// `0.0` if the duration is unknown like for live streams
+  pub fn progress_fraction(&self) -> f64 {
+    if self.duration.is_zero() {
+      return 0.0;
     }
+
+    (self.elapsed.as_secs_f64() / self.duration.as_secs_f64()).clamp(0.0, 1.0)
+  }
+
+  /// Time left until the end of the track, zero if the duration is unknown
+  /// or the elapsed time already went past it
+  pub fn remaining(&self) -> Duration {
+    self.duration.saturating_sub(self.elapsed)
+  }
+
+  /// If at most `threshold` is left of the track, always `false` if the duration is unknown
+  pub fn is_near_end(&self, threshold: Duration) -> bool {
+    !self.duration.is_zero() && self.remaining() <= threshold
+  }
+
+  /// Builder for when only a few fields are known, see [MediaMetadataBuilder]
+  pub fn builder() -> MediaMetadataBuilder {
+    MediaMetadataBuilder::default()
   }
 
   pub fn is_different(&self, other: &Self) -> bool {
@@ -196,9 +606,510 @@ impl MediaMetadata {
   }
 }
 
+/// Builds [MediaMetadata] without spelling out every field
+///
+/// ```rs
+/// let metadata = MediaMetadata::builder()
+///   .with_title("Never Gonna Give You Up")
+///   .with_artists(["Rick Astley"])
+///   .with_state(MediaState::Playing)
+///   .with_duration(Duration::from_secs(213))
+///   .with_cover_bytes(png)
+///   .build()?;
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct MediaMetadataBuilder(MediaMetadata);
+
+impl MediaMetadataBuilder {
+  pub fn with_uid(mut self, uid: impl Into<String>) -> Self {
+    self.0.uid = Some(uid.into());
+    self
+  }
+
+  pub fn with_uri(mut self, uri: impl Into<String>) -> Self {
+    self.0.uri = Some(uri.into());
+    self
+  }
+
+  pub fn with_state(mut self, state: MediaState) -> Self {
+    self.0.state = state;
+    self
+  }
+
+  pub fn with_duration(mut self, duration: Duration) -> Self {
+    self.0.duration = duration;
+    self
+  }
+
+  pub fn with_elapsed(mut self, elapsed: Duration) -> Self {
+    self.0.elapsed = elapsed;
+    self
+  }
+
+  pub fn with_title(mut self, title: impl Into<String>) -> Self {
+    self.0.title = title.into();
+    self
+  }
+
+  pub fn with_album(mut self, album: impl Into<String>) -> Self {
+    self.0.album = Some(album.into());
+    self
+  }
+
+  pub fn with_artists<I, S>(mut self, artists: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self.0.artists = artists.into_iter().map(Into::into).collect();
+    self
+  }
+
+  pub fn with_featured_artists<I, S>(mut self, featured_artists: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self.0.featured_artists = featured_artists.into_iter().map(Into::into).collect();
+    self
+  }
+
+  pub fn with_cover_url(mut self, cover_url: impl Into<String>) -> Self {
+    self.0.cover_url = Some(cover_url.into());
+    self
+  }
+
+  pub fn with_cover(mut self, cover: MediaImage) -> Self {
+    self.0.cover = Some(cover);
+    self
+  }
+
+  /// Cover with the format guessed from the data, see [ImageFormat::detect]
+  pub fn with_cover_bytes(self, data: impl Into<Vec<u8>>) -> Self {
+    self.with_cover(MediaImage::from_bytes(data))
+  }
+
+  pub fn with_background_url(mut self, background_url: impl Into<String>) -> Self {
+    self.0.background_url = Some(background_url.into());
+    self
+  }
+
+  pub fn with_background(mut self, background: MediaImage) -> Self {
+    self.0.background = Some(background);
+    self
+  }
+
+  /// Background with the format guessed from the data, see [ImageFormat::detect]
+  pub fn with_background_bytes(self, data: impl Into<Vec<u8>>) -> Self {
+    self.with_background(MediaImage::from_bytes(data))
+  }
+
+  pub fn with_app(mut self, app: impl Into<String>) -> Self {
+    self.0.app = Some(app.into());
+    self
+  }
+
+  pub fn with_zone(mut self, zone: impl Into<String>) -> Self {
+    self.0.zone = Some(zone.into());
+    self
+  }
+
+  pub fn with_extra(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+    self.0.extra.insert(key.into(), value.into());
+    self
+  }
+
+  pub fn with_queue(mut self, queue: impl IntoIterator<Item = QueueEntry>) -> Self {
+    self.0.queue = queue.into_iter().collect();
+    self
+  }
+
+  /// Fails with [Error::InvalidMetadata] if elapsed is past the duration (when the duration is known)
+  pub fn build(self) -> Result<MediaMetadata> {
+    let metadata = self.0;
+
+    if !metadata.duration.is_zero() && metadata.elapsed > metadata.duration {
+      return Err(Error::InvalidMetadata(format!(
+        "elapsed ({:?}) is past the duration ({:?})",
+        metadata.elapsed, metadata.duration
+      )));
+    }
+
+    Ok(metadata)
+  }
+}
+
+/// Kind of source that [MediaListener](listener::MediaListener) is currently getting media from
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum SourceKind {
+  Websocket,
+  System,
+  /// Source added with [MediaListenerBuilder::with_source](listener::MediaListenerBuilder::with_source)
+  Custom(String),
+}
+
+impl SourceKind {
+  /// `websocket`, `system` or the custom source's name
+  pub fn name(&self) -> &str {
+    match self {
+      Self::Websocket => "websocket",
+      Self::System => "system",
+      Self::Custom(name) => name,
+    }
+  }
+}
+
+impl Display for SourceKind {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+/// Message to send to media client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MediaMessage {
+  /// Updates the progress update interval from the media client
+  ProgressUpdateInterval(u64),
+  /// Tells the media client which types of events to send, see [EventKind] for the bits
+  Subscribe(EventKind),
+  /// Tells the media client the cover sent in chunks with this id was dropped,
+  /// because a chunk was missing, it didn't match the checksum or it was too large
+  CoverRejected(u64),
+  /// Asks the media client to answer with [ClientMessage::Pong], only sent to clients with
+  /// [MediaCapabilities::ping], value is when it was sent in milliseconds since the unix epoch
+  Ping(u64),
+  /// Tells the media client its last message was rejected and why
+  ProtocolError(ProtocolError),
+  /// Asks the media client to resume playback, see [MediaCommand]
+  Play,
+  /// Asks the media client to pause playback
+  Pause,
+  NextTrack,
+  PreviousTrack,
+  /// Asks the media client to seek to this many milliseconds into the media
+  SeekTo(u64),
+  /// Asks the media client to set its volume, from 0 to 1
+  SetVolume(f64),
+}
+
+/// Playback control, see [MediaListener::control](listener::MediaListener::control)
+#[serde_with::serde_as]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum MediaCommand {
+  Play,
+  Pause,
+  NextTrack,
+  PreviousTrack,
+  SeekTo(#[serde_as(as = "duration::WireDuration")] Duration),
+  /// From 0 to 1
+  SetVolume(f64),
+}
+
+/// What a trusted client asks the listener to do, sent with [ClientMessage::Control]
+/// or to `POST /control` on the websocket port, see [MediaSourceConfig::control_token](listener::MediaSourceConfig::control_token)
+///
+/// The listener passes it on as [MediaEvent::ControlRequested] for the app to carry out
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum RemoteControl {
+  /// Passed on to the player, see [MediaListener::control](listener::MediaListener::control)
+  Command(MediaCommand),
+  /// Sends controls to this source from now on, [None] sends them to the active source again,
+  /// see [MediaListener::control_source](listener::MediaListener::control_source)
+  ControlSource(Option<SourceKind>),
+  /// Passes the cover of what is playing on again, for clients that missed or dropped it
+  RefreshCover,
+}
+
+impl MediaMessage {
+  /// The [MediaCommand] this message asks for, if it's one
+  pub fn command(&self) -> Option<MediaCommand> {
+    match *self {
+      Self::Play => Some(MediaCommand::Play),
+      Self::Pause => Some(MediaCommand::Pause),
+      Self::NextTrack => Some(MediaCommand::NextTrack),
+      Self::PreviousTrack => Some(MediaCommand::PreviousTrack),
+      Self::SeekTo(ms) => Some(MediaCommand::SeekTo(Duration::from_millis(ms))),
+      Self::SetVolume(volume) => Some(MediaCommand::SetVolume(volume)),
+      _ => None,
+    }
+  }
+}
+
+impl From<MediaCommand> for MediaMessage {
+  fn from(command: MediaCommand) -> Self {
+    match command {
+      MediaCommand::Play => Self::Play,
+      MediaCommand::Pause => Self::Pause,
+      MediaCommand::NextTrack => Self::NextTrack,
+      MediaCommand::PreviousTrack => Self::PreviousTrack,
+      MediaCommand::SeekTo(position) => Self::SeekTo(position.as_millis() as u64),
+      MediaCommand::SetVolume(volume) => Self::SetVolume(volume.clamp(0.0, 1.0)),
+    }
+  }
+}
+
+/// Piece of a cover too large to send in one message, see [MediaEvent::CoverChunk]
+///
+/// Chunks are sent in order after the [MediaEvent::MediaChanged] they belong to,
+/// the last one has [CoverChunk::end] set
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct CoverChunk {
+  /// Same for every chunk of one cover, a new id starts a new cover
+  pub id: u64,
+  /// Position of this chunk, starting at 0
+  pub index: u32,
+  pub data: Vec<u8>,
+  /// Only set on the last chunk
+  pub end: Option<CoverChunkEnd>,
+}
+
+/// What the receiver needs to check and finish the cover
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct CoverChunkEnd {
+  pub format: ImageFormat,
+  /// CRC-32 (IEEE) of the whole image, see [CoverChunk::checksum]
+  pub checksum: u32,
+}
+
+impl CoverChunk {
+  /// Splits `image` into chunks of at most `chunk_size` bytes
+  pub fn split(id: u64, image: &MediaImage, chunk_size: usize) -> Vec<Self> {
+    let checksum = Self::checksum(&image.data);
+    let mut chunks = image
+      .data
+      .chunks(chunk_size.max(1))
+      .enumerate()
+      .map(|(index, data)| Self {
+        id,
+        index: index as u32,
+        data: data.to_vec(),
+        end: None,
+      })
+      .collect::<Vec<_>>();
+
+    if chunks.is_empty() {
+      chunks.push(Self {
+        id,
+        index: 0,
+        data: Vec::new(),
+        end: None,
+      });
+    }
+
+    if let Some(last) = chunks.last_mut() {
+      last.end = Some(CoverChunkEnd {
+        format: image.format.clone(),
+        checksum,
+      });
+    }
+
+    chunks
+  }
+
+  /// CRC-32 (IEEE), the same one zip and png use
+  pub fn checksum(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+      let mut table = [0; 256];
+      let mut i = 0;
+
+      while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+          crc = if crc & 1 == 1 {
+            (crc >> 1) ^ 0xEDB8_8320
+          } else {
+            crc >> 1
+          };
+          bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+      }
+
+      table
+    };
+
+    !data.iter().fold(!0, |crc, byte| {
+      TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+  }
+}
+
+/// What a media client supports, sent as `{"Capabilities": {...}}` before its first [MediaEvent]
+///
+/// Clients that never send it are assumed to use the defaults
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaCapabilities {
+  /// How the client writes durations, see [DurationFormat]
+  pub duration_format: DurationFormat,
+  /// Client answers [MediaMessage::Ping], so the websocket source can measure its latency
+  /// and correct the progress it reports
+  pub ping: bool,
+  /// Client handles [MediaMessage::Play] and the other playback controls,
+  /// [MediaListener::play](listener::MediaListener::play) and co fail with [Error::Unsupported] otherwise
+  pub control: bool,
+}
+
+/// Message from a media client, a plain [MediaEvent] is also accepted
+/// and counts as coming from the player with an empty id
+///
+/// Clients reporting several players (like a browser extension with many tabs)
+/// send [ClientMessage::Player] instead, the websocket source picks which player is passed on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum ClientMessage {
+  /// See [MediaCapabilities]
+  Capabilities(MediaCapabilities),
+  /// Event from one of the client's players
+  Player { player_id: String, event: MediaEvent },
+  /// Player with this id went away, like when its tab was closed
+  PlayerRemoved(String),
+  /// Answer to [MediaMessage::Ping], times are milliseconds since the unix epoch
+  Pong {
+    /// Value of the ping
+    sent: u64,
+    /// When the client received the ping, by its own clock
+    received: u64,
+  },
+  /// Asks the listener to do something, rejected with [ProtocolErrorKind::Unauthorized] unless
+  /// `token` is [MediaSourceConfig::control_token](listener::MediaSourceConfig::control_token),
+  /// the connection is then closed with [CloseReason::Unauthorized]
+  Control { token: String, request: RemoteControl },
+}
+
+/// Message from an event consumer connected to `/events` on the websocket port,
+/// which is sent the websocket source's [MediaEvent]s with the covers left out
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsumerMessage {
+  /// Only sends the consumer these types of events from now on, it starts out with [EventKind::ALL]
+  Subscribe(EventKind),
+}
+
+/// Version of the websocket protocol this crate speaks, see [ClientEnvelope]
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// [ClientMessage] tagged with the protocol version of the media client,
+/// sent as `{"version": 1, "message": {...}}`
+///
+/// Bare [ClientMessage]s and [MediaEvent]s are still accepted and count as version 1,
+/// fields the websocket source doesn't know are ignored so newer clients can add to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientEnvelope {
+  pub version: u32,
+  pub message: ClientMessage,
+}
+
+impl ClientEnvelope {
+  /// Tags `message` with [PROTOCOL_VERSION]
+  pub fn new(message: ClientMessage) -> Self {
+    Self {
+      version: PROTOCOL_VERSION,
+      message,
+    }
+  }
+}
+
+/// Why the websocket source rejected a message, sent back as [MediaMessage::ProtocolError]
+#[derive(Debug, Clone, Eq, PartialEq, Error, Serialize, Deserialize)]
+#[error("{kind:?}: {message}")]
+pub struct ProtocolError {
+  pub kind: ProtocolErrorKind,
+  /// What was wrong, meant for whoever writes the client
+  pub message: String,
+  /// [PROTOCOL_VERSION] of the websocket source
+  pub version: u32,
+}
+
+impl ProtocolError {
+  pub fn new(kind: ProtocolErrorKind, message: impl Into<String>) -> Self {
+    Self {
+      kind,
+      message: message.into(),
+      version: PROTOCOL_VERSION,
+    }
+  }
+}
+
+/// What kind of [ProtocolError] it is
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ProtocolErrorKind {
+  /// Not json, or it isn't any of the messages the websocket source accepts
+  Malformed,
+  /// [ClientEnvelope::version] is newer than [ProtocolError::version]
+  UnsupportedVersion,
+  /// Message is larger than [MessageLimits::max_size](listener::MessageLimits::max_size)
+  TooLarge,
+  /// A string or a list in the message is longer than [MessageLimits](listener::MessageLimits) allows
+  LimitExceeded,
+  /// [ClientMessage::Control] with the wrong token, or remote control isn't turned on
+  Unauthorized,
+}
+
+/// Why the websocket source closed the connection, sent as the close frame's code
+/// with [CloseReason::reason] as its reason
+///
+/// Clients should wait at least [CloseReason::reconnect_after] before connecting again
+/// and double the wait after every connection that fails, up to a minute
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CloseReason {
+  /// The listener shut down or another listener took over the address, `1001`
+  Shutdown,
+  /// The client broke the websocket protocol, like an invalid frame, `1002`
+  ProtocolError,
+  /// Another client connected, see [ConnectionPolicy::ReplaceOld](listener::ConnectionPolicy::ReplaceOld), `4000`
+  Replaced,
+  /// [ClientMessage::Control] with the wrong token, `4001`
+  Unauthorized,
+}
+
+impl CloseReason {
+  pub const fn code(self) -> u16 {
+    match self {
+      Self::Shutdown => 1001,
+      Self::ProtocolError => 1002,
+      Self::Replaced => 4000,
+      Self::Unauthorized => 4001,
+    }
+  }
+
+  /// [None] for codes the websocket source doesn't send
+  pub const fn from_code(code: u16) -> Option<Self> {
+    match code {
+      1001 => Some(Self::Shutdown),
+      1002 => Some(Self::ProtocolError),
+      4000 => Some(Self::Replaced),
+      4001 => Some(Self::Unauthorized),
+      _ => None,
+    }
+  }
+
+  pub const fn reason(self) -> &'static str {
+    match self {
+      Self::Shutdown => "shutdown",
+      Self::ProtocolError => "protocol error",
+      Self::Replaced => "replaced by another client",
+      Self::Unauthorized => "unauthorized",
+    }
+  }
+
+  /// Least time to wait before connecting again, [None] if the client shouldn't reconnect on its own,
+  /// it would only take the connection back from the client that replaced it or be refused again
+  pub const fn reconnect_after(self) -> Option<Duration> {
+    match self {
+      Self::Shutdown => Some(Duration::from_secs(1)),
+      Self::ProtocolError => Some(Duration::from_secs(5)),
+      Self::Replaced | Self::Unauthorized => None,
+    }
+  }
+}
+
 /// Media Events
 #[serde_with::serde_as]
-#[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum MediaEvent {
   /// Event for when media changed (like going to next song)
@@ -208,5 +1119,167 @@ pub enum MediaEvent {
   /// Event for when progress is updated, usually called on a set interval
   ///
   /// value is a percentage of the duration
-  ProgressChanged(#[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")] Duration),
+  ProgressChanged(#[serde_as(as = "duration::WireDuration")] Duration),
+  /// Event for when the queue of what is going to play next changed
+  QueueChanged(Vec<QueueEntry>),
+  /// Event for when media finished playing, either by reaching the end
+  /// or by changing to something else when it was almost done
+  ///
+  /// value is the metadata of what finished playing
+  TrackEnded(MediaMetadata),
+  /// Event for when [MediaListener](listener::MediaListener) switched
+  /// which source it gets media from
+  ActiveSourceChanged(SourceKind),
+  /// Event for when a media client connected to the websocket source
+  ClientConnected(SocketAddr),
+  /// Event for when a media client disconnected from the websocket source,
+  /// metadata stays at whatever the client last sent
+  ClientDisconnected(SocketAddr),
+  /// Event for when a source hasn't produced an update in a while, its state is
+  /// reported as [MediaState::Stopped] until it does
+  SourceStale(SourceKind),
+  /// Event for when a source failed [Backoff::max_attempts](backoff::Backoff::max_attempts) times in a row
+  /// and stopped trying to set up its connection
+  SourceGaveUp(SourceKind),
+  /// Event for when a source's background task failed, it restarts according to
+  /// [MediaSourceConfig::backoff](listener::MediaSourceConfig::backoff)
+  SourceError { source: SourceKind, message: String },
+  /// Event for when the system source started (true) or stopped (false) throttling
+  /// because the computer runs on battery, see
+  /// [MediaSourceConfig::battery_throttle](listener::MediaSourceConfig::battery_throttle)
+  Throttled(bool),
+  /// Event for when more than one source is playing and they aren't playing the same track,
+  /// [MediaListener](listener::MediaListener) still only reports one of them
+  ///
+  /// Sent again whenever the set of conflicting sources changes
+  ConflictDetected { sources: Vec<SourceKind> },
+  /// Event for when a source was added to a running [MediaListener](listener::MediaListener),
+  /// see [MediaListener::add_source](listener::MediaListener::add_source)
+  SourceAdded(SourceKind),
+  /// Event for when a source was removed from a running [MediaListener](listener::MediaListener)
+  /// and shut down, see [MediaListener::remove_source](listener::MediaListener::remove_source)
+  SourceRemoved(SourceKind),
+  /// Piece of a cover sent by a media client, the websocket source puts the pieces back together
+  /// and sends a [MediaEvent::MediaChanged] with the cover instead of passing these on
+  ///
+  /// Counts as [EventKind::MEDIA_CHANGED] for subscriptions
+  CoverChunk(CoverChunk),
+  /// Event for when the current track has been playing for
+  /// [MediaSourceConfig::listen_threshold](listener::MediaSourceConfig::listen_threshold) of its duration,
+  /// only counting the time it was playing, sent once per track
+  ///
+  /// `listened` is how long it has been playing so far
+  ListenThresholdReached {
+    metadata: MediaMetadata,
+    #[serde_as(as = "duration::WireDuration")]
+    listened: Duration,
+  },
+  /// Event for when a trusted client asked the listener to do something, see [RemoteControl],
+  /// it's up to whoever gets it to carry it out
+  ControlRequested(RemoteControl),
+}
+
+/// Set of [MediaEvent] types, used to pick which events get delivered
+///
+/// Combine them with `|`, e.g. `EventKind::MEDIA_CHANGED | EventKind::STATE_CHANGED`,
+/// the bits are part of the websocket protocol so they never change
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EventKind(u32);
+
+impl EventKind {
+  pub const NONE: Self = Self(0);
+  pub const MEDIA_CHANGED: Self = Self(1 << 0);
+  pub const STATE_CHANGED: Self = Self(1 << 1);
+  pub const PROGRESS_CHANGED: Self = Self(1 << 2);
+  pub const QUEUE_CHANGED: Self = Self(1 << 3);
+  pub const TRACK_ENDED: Self = Self(1 << 4);
+  pub const ACTIVE_SOURCE_CHANGED: Self = Self(1 << 5);
+  pub const CLIENT_CONNECTED: Self = Self(1 << 6);
+  pub const CLIENT_DISCONNECTED: Self = Self(1 << 7);
+  pub const SOURCE_STALE: Self = Self(1 << 8);
+  pub const SOURCE_GAVE_UP: Self = Self(1 << 9);
+  pub const SOURCE_ERROR: Self = Self(1 << 10);
+  pub const THROTTLED: Self = Self(1 << 11);
+  pub const CONFLICT_DETECTED: Self = Self(1 << 12);
+  pub const SOURCE_ADDED: Self = Self(1 << 13);
+  pub const SOURCE_REMOVED: Self = Self(1 << 14);
+  pub const LISTEN_THRESHOLD_REACHED: Self = Self(1 << 15);
+  pub const CONTROL_REQUESTED: Self = Self(1 << 16);
+  pub const ALL: Self = Self(u32::MAX);
+
+  pub const fn bits(self) -> u32 {
+    self.0
+  }
+
+  pub const fn from_bits(bits: u32) -> Self {
+    Self(bits)
+  }
+
+  /// Returns true if every type in `other` is also in `self`
+  pub const fn contains(self, other: Self) -> bool {
+    self.0 & other.0 == other.0
+  }
+}
+
+impl Default for EventKind {
+  fn default() -> Self {
+    Self::ALL
+  }
+}
+
+impl BitOr for EventKind {
+  type Output = Self;
+
+  fn bitor(self, rhs: Self) -> Self {
+    Self(self.0 | rhs.0)
+  }
+}
+
+impl BitOrAssign for EventKind {
+  fn bitor_assign(&mut self, rhs: Self) {
+    self.0 |= rhs.0;
+  }
+}
+
+impl BitAnd for EventKind {
+  type Output = Self;
+
+  fn bitand(self, rhs: Self) -> Self {
+    Self(self.0 & rhs.0)
+  }
+}
+
+impl Not for EventKind {
+  type Output = Self;
+
+  fn not(self) -> Self {
+    Self(!self.0)
+  }
+}
+
+impl MediaEvent {
+  /// Type of this event
+  pub fn kind(&self) -> EventKind {
+    match self {
+      Self::MediaChanged(_) => EventKind::MEDIA_CHANGED,
+      Self::StateChanged(_) => EventKind::STATE_CHANGED,
+      Self::ProgressChanged(_) => EventKind::PROGRESS_CHANGED,
+      Self::QueueChanged(_) => EventKind::QUEUE_CHANGED,
+      Self::TrackEnded(_) => EventKind::TRACK_ENDED,
+      Self::ActiveSourceChanged(_) => EventKind::ACTIVE_SOURCE_CHANGED,
+      Self::ClientConnected(_) => EventKind::CLIENT_CONNECTED,
+      Self::ClientDisconnected(_) => EventKind::CLIENT_DISCONNECTED,
+      Self::SourceStale(_) => EventKind::SOURCE_STALE,
+      Self::SourceGaveUp(_) => EventKind::SOURCE_GAVE_UP,
+      Self::SourceError { .. } => EventKind::SOURCE_ERROR,
+      Self::Throttled(_) => EventKind::THROTTLED,
+      Self::ConflictDetected { .. } => EventKind::CONFLICT_DETECTED,
+      Self::SourceAdded(_) => EventKind::SOURCE_ADDED,
+      Self::SourceRemoved(_) => EventKind::SOURCE_REMOVED,
+      Self::CoverChunk(_) => EventKind::MEDIA_CHANGED,
+      Self::ListenThresholdReached { .. } => EventKind::LISTEN_THRESHOLD_REACHED,
+      Self::ControlRequested(_) => EventKind::CONTROL_REQUESTED,
+    }
+  }
 }
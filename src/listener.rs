@@ -1,12 +1,53 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt::{Debug, Formatter};
 use std::net::SocketAddr;
-use std::sync::{Arc, RwLock, RwLockReadGuard};
-use std::time::Duration;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "ws-tokio")]
+use tokio::runtime::{Builder, Handle, Runtime};
 
+use crate::backoff::Backoff;
+use crate::duration::WireDuration;
+use crate::identity::{IdentityStrategy, TrackIdentity};
+use crate::metrics::{source_name, Metrics};
+use crate::persist::PersistedState;
+use crate::power::BatteryThrottle;
+use crate::pipeline::{
+  BackpressurePolicy, CoalesceConfig, ListenTracker, PollMode, ProgressThreshold, RateLimit,
+};
 use crate::platform::SystemMediaSource;
-use crate::ws::WebsocketMediaSourceBackground;
-use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result};
+use crate::redact::{RedactionRule, Redactor};
+use crate::sanitize::SanitizeConfig;
+#[cfg(feature = "http-json")]
+use crate::http_json::HttpJsonConfig;
+#[cfg(feature = "icy")]
+use crate::icy::IcyConfig;
+#[cfg(feature = "kodi")]
+use crate::kodi::KodiConfig;
+#[cfg(feature = "media-server")]
+use crate::media_server::{JellyfinConfig, PlexConfig};
+#[cfg(feature = "plugins")]
+use crate::plugin::PluginConfig;
+#[cfg(all(feature = "shairport", unix))]
+use crate::shairport::ShairportConfig;
+#[cfg(feature = "ytmd")]
+use crate::ytmd::YtmdConfig;
+#[cfg(feature = "ws")]
+use crate::ws::{InstanceClient, WebsocketMediaSourceBackground};
+
+/// No websocket source without the `ws` feature
+#[cfg(not(feature = "ws"))]
+type WebsocketMediaSourceBackground = DisabledMediaSource;
+use crate::{Error, EventKind, MediaCommand, MediaEvent, MediaMetadata, MediaState, Result};
+
+pub use crate::SourceKind;
 
 #[derive(
   Default, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize,
@@ -17,38 +58,563 @@ pub enum WebsocketAddr {
 
   #[default]
   Default,
+  /// Socket systemd passed to the process (`LISTEN_FDS`), so it can be started when a client connects,
+  /// binds like [WebsocketAddr::Default] when it wasn't started that way
+  Activated,
+}
+
+impl WebsocketAddr {
+  /// Address the websocket source binds to
+  pub fn socket_addr(self) -> SocketAddr {
+    match self {
+      Self::Local(port) => SocketAddr::from(([127, 0, 0, 1], port)),
+      Self::Addr(addr) => addr,
+      Self::Default | Self::Activated => SocketAddr::from(([127, 0, 0, 1], 19532)),
+    }
+  }
+}
+
+/// Why the websocket source couldn't bind its address, see [BindErrorHandler]
+#[derive(Debug)]
+pub struct BindError<'a> {
+  pub addr: SocketAddr,
+  pub error: &'a std::io::Error,
+  /// How long until it tries again, [None] if it gave up
+  pub retry_in: Option<Duration>,
+}
+
+/// Called every time the websocket source fails to bind, like when another instance already has the port
+///
+/// Implemented for closures
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default()
+///   .set_on_bind_error(|err: &BindError| eprintln!("can't listen on {}: {}", err.addr, err.error));
+/// ```
+pub trait BindErrorHandler: Send + Sync {
+  fn on_bind_error(&self, error: &BindError);
+}
+
+impl Debug for dyn BindErrorHandler {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str("dyn BindErrorHandler")
+  }
+}
+
+impl<F> BindErrorHandler for F
+where
+  F: Fn(&BindError) + Send + Sync,
+{
+  fn on_bind_error(&self, error: &BindError) {
+    self(error)
+  }
+}
+
+/// Events a source dropped, see [DroppedEventsHandler]
+#[derive(Debug)]
+pub struct DroppedEvents<'a> {
+  pub source: &'a SourceKind,
+  /// Latest event that got dropped
+  pub event: &'a MediaEvent,
+  /// How many events the source dropped so far
+  pub total: u64,
+}
+
+/// Called every [MediaSourceConfig::dropped_events_threshold] events a source drops
+/// because they weren't consumed fast enough, see [MediaSourceConfig::backpressure]
+///
+/// Implemented for closures
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default()
+///   .set_on_dropped_events(|dropped: &DroppedEvents| {
+///     eprintln!("{:?} dropped {} events", dropped.source, dropped.total)
+///   });
+/// ```
+pub trait DroppedEventsHandler: Send + Sync {
+  fn on_dropped_events(&self, dropped: &DroppedEvents);
+}
+
+impl Debug for dyn DroppedEventsHandler {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str("dyn DroppedEventsHandler")
+  }
+}
+
+impl<F> DroppedEventsHandler for F
+where
+  F: Fn(&DroppedEvents) + Send + Sync,
+{
+  fn on_dropped_events(&self, dropped: &DroppedEvents) {
+    self(dropped)
+  }
+}
+
+/// How large messages from websocket clients can get, anything larger is rejected
+/// with a [ProtocolError](crate::ProtocolError) sent back to the client
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct MessageLimits {
+  /// Largest message in bytes
+  pub max_size: usize,
+  /// Longest string (titles, names, urls and player ids) in bytes
+  pub max_string_len: usize,
+  /// Most entries in a list (artists and the queue) and in [MediaMetadata::extra]
+  pub max_list_len: usize,
+}
+
+impl Default for MessageLimits {
+  fn default() -> Self {
+    Self {
+      max_size: 16 * 1024 * 1024,
+      max_string_len: 64 * 1024,
+      max_list_len: 1024,
+    }
+  }
+}
+
+impl MessageLimits {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_max_size(self, max_size: usize) -> Self {
+    Self { max_size, ..self }
+  }
+
+  pub fn set_max_string_len(self, max_string_len: usize) -> Self {
+    Self {
+      max_string_len,
+      ..self
+    }
+  }
+
+  pub fn set_max_list_len(self, max_list_len: usize) -> Self {
+    Self {
+      max_list_len,
+      ..self
+    }
+  }
+}
+
+/// Options of the websocket source's sockets, see [MediaSourceConfig::socket]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SocketOptions {
+  /// `SO_REUSEADDR` on the listening socket, so a restarted bridge can bind while connections
+  /// of the old one are still closing, on by default except on windows where it lets
+  /// other programs bind the same port
+  pub reuse_address: bool,
+  /// `TCP_NODELAY` on client connections, small frames like progress updates are sent right away
+  /// instead of waiting to be batched
+  pub nodelay: bool,
+  /// How long a client connection is idle before TCP keepalive probes are sent,
+  /// finds clients that vanished without closing the connection, [None] turns it off
+  pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+  fn default() -> Self {
+    Self {
+      reuse_address: !cfg!(windows),
+      nodelay: true,
+      keepalive: None,
+    }
+  }
+}
+
+/// What the websocket source does when a client connects while
+/// [MediaSourceConfig::max_connections] clients are already connected
+#[derive(
+  Default, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize,
+)]
+pub enum ConnectionPolicy {
+  /// The new client is disconnected right away
+  RejectNew,
+  /// The connected client is closed and the new one takes over,
+  /// handy for clients that reconnect before the old connection timed out
+  #[default]
+  ReplaceOld,
 }
 
+/// What the websocket source does when another listener, like a daemon or a widget,
+/// already serves its [MediaSourceConfig::addr]
 #[derive(
   Default, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize,
 )]
-pub enum MediaSourcePriority {
+pub enum InstancePolicy {
+  /// Keeps trying to bind according to [MediaSourceConfig::bind_backoff], without checking what holds the address
   #[default]
-  Websocket,
-  System,
+  Retry,
+  /// Creating the source fails with [Error::InstanceRunning]
+  FailFast,
+  /// Asks the other listener to shut its websocket source down and binds the address once it's free
+  TakeOver,
+  /// Follows the media the other listener reports instead, through its `GET /now-playing`,
+  /// the source shows up as [SourceKind::Custom] named `instance` and can't be controlled
+  Client,
+}
+
+/// Settings for common uses of the listener, see [MediaSourceConfig::set_preset]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Preset {
+  /// Stream overlays, 60 updates a second with covers
+  Overlay,
+  /// Scrobblers, one update a second and every 30 seconds while nothing is playing, no covers
+  /// and [MediaEvent::ListenThresholdReached] at half of a track
+  Scrobbler,
+  /// Status bars, one update a second without covers
+  StatusBar,
+}
+
+/// Setup of the threads and runtime the crate creates itself,
+/// every source runs on its own thread and the websocket source also builds a runtime
+/// unless [MediaSourceConfig::runtime] is set
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ThreadConfig {
+  /// Worker threads of the websocket runtime, 0 runs it on the source's own thread,
+  /// the `ws-smol` backend always runs on the source's own thread
+  pub worker_threads: usize,
+  /// Prefix of thread names, threads are named like `{prefix}-ws` or `{prefix}-mpris`
+  pub name_prefix: String,
+  /// Stack size of every thread in bytes, uses the platform default if not set
+  pub stack_size: Option<usize>,
+}
+
+impl Default for ThreadConfig {
+  fn default() -> Self {
+    Self {
+      worker_threads: 4,
+      name_prefix: "currently-playing".into(),
+      stack_size: None,
+    }
+  }
+}
+
+impl ThreadConfig {
+  /// Spawns a thread named `{prefix}-{name}`
+  #[cfg_attr(not(any(feature = "system", feature = "ws")), allow(dead_code))]
+  pub(crate) fn spawn<F>(&self, name: &str, f: F) -> std::io::Result<JoinHandle<()>>
+  where
+    F: FnOnce() + Send + 'static,
+  {
+    let mut builder = std::thread::Builder::new().name(format!("{}-{name}", self.name_prefix));
+
+    if let Some(stack_size) = self.stack_size {
+      builder = builder.stack_size(stack_size);
+    }
+
+    builder.spawn(f)
+  }
+
+  /// Builds a runtime whose workers are named `{prefix}-{name}-worker`
+  #[cfg(feature = "ws-tokio")]
+  pub(crate) fn runtime(&self, name: &str) -> std::io::Result<Runtime> {
+    let mut builder = match self.worker_threads {
+      0 => Builder::new_current_thread(),
+      worker_threads => {
+        let mut builder = Builder::new_multi_thread();
+        builder.worker_threads(worker_threads);
+        builder
+      }
+    };
+
+    builder
+      .enable_all()
+      .thread_name(format!("{}-{name}-worker", self.name_prefix));
+
+    if let Some(stack_size) = self.stack_size {
+      builder.thread_stack_size(stack_size);
+    }
+
+    builder.build()
+  }
+}
+
+/// Which source's values win when [MediaSourceConfig::hybrid] merges sources,
+/// [None] keeps the active source's values, gaps are always filled in from the other sources
+///
+/// By default the system source owns the timeline (GSMTC and MPRIS know exactly where playback is)
+/// and the websocket source owns the art (media clients usually have better covers)
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FieldOwnership {
+  /// `state`, `elapsed` and `duration`
+  pub timeline: Option<SourceKind>,
+  /// `uid`, `uri`, `title`, `album`, `artists`, `featured_artists`, `app`, `extra` and `queue`
+  pub info: Option<SourceKind>,
+  /// `cover_url`, `cover`, `background_url` and `background`
+  pub art: Option<SourceKind>,
+}
+
+impl Default for FieldOwnership {
+  fn default() -> Self {
+    Self {
+      timeline: Some(SourceKind::System),
+      info: None,
+      art: Some(SourceKind::Websocket),
+    }
+  }
+}
+
+/// Settings a single source uses instead of the ones of the [MediaSourceConfig] it's created with,
+/// see [MediaSourceConfig::set_source_overrides]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SourceOverrides {
+  pub timeout: Option<Duration>,
+  pub update_rate: Option<u64>,
+  pub stale_after: Option<Duration>,
+  pub zone: Option<String>,
+  /// Replaces [MediaSourceConfig::sanitize], like to leave alone websocket clients that send clean
+  /// metadata while cleaning up after sources that only have a browser tab's title
+  pub sanitize: Option<SanitizeConfig>,
+}
+
+impl SourceOverrides {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_timeout(self, timeout: Duration) -> Self {
+    Self {
+      timeout: Some(timeout),
+      ..self
+    }
+  }
+
+  pub fn set_update_rate(self, update_rate: u64) -> Self {
+    Self {
+      update_rate: Some(update_rate),
+      ..self
+    }
+  }
+
+  pub fn set_stale_after(self, stale_after: Duration) -> Self {
+    Self {
+      stale_after: Some(stale_after),
+      ..self
+    }
+  }
+
+  pub fn set_zone(self, zone: impl Into<String>) -> Self {
+    Self {
+      zone: Some(zone.into()),
+      ..self
+    }
+  }
+
+  pub fn set_sanitize(self, sanitize: SanitizeConfig) -> Self {
+    Self {
+      sanitize: Some(sanitize),
+      ..self
+    }
+  }
 }
 
 #[derive(Debug, Clone)]
 pub struct MediaSourceConfig {
   pub addr: WebsocketAddr,
-  pub priority: MediaSourcePriority,
+  /// Most websocket clients connected at once, clients are served one at a time for now
+  /// so anything above 1 acts like 1
+  pub max_connections: usize,
+  /// What happens to websocket clients connecting past [MediaSourceConfig::max_connections]
+  pub connection_policy: ConnectionPolicy,
+  /// What happens when another listener already serves [MediaSourceConfig::addr]
+  pub instance_policy: InstancePolicy,
+  /// Options of the websocket source's listening socket and client connections
+  pub socket: SocketOptions,
+  /// Which source wins when more than one is playing, from most to least preferred,
+  /// sources that aren't listed come after
+  pub priority: Vec<SourceKind>,
+  /// How long another source has to be the better pick before it takes over from the active one,
+  /// keeps the active source from flapping when players keep toggling
+  pub hysteresis: Duration,
   pub timeout: Duration,
   pub update_rate: u64,
+  /// Whether sources read covers and backgrounds, otherwise they're left out of events
+  /// and only their urls are reported, for uses that never show them like scrobbling
+  pub covers: bool,
+  /// Fills in the active source's metadata from other sources playing the same media,
+  /// see [MediaSourceConfig::field_ownership]
   pub hybrid: bool,
+  /// Which source's values win per field when [MediaSourceConfig::hybrid] merges sources
+  pub field_ownership: FieldOwnership,
   pub websocket_enabled: bool,
   pub system_enabled: bool,
+  /// Player names (MPRIS identity or bus name) to prefer when more than one player is available,
+  /// ordered from most to least preferred
+  pub preferred_players: Vec<String>,
+  /// How bursts of events are coalesced before they are delivered
+  pub coalesce: CoalesceConfig,
+  /// How much elapsed has to change before another progress event is emitted
+  pub progress_threshold: ProgressThreshold,
+  /// Which types of events get delivered, websocket clients are asked to only send these
+  ///
+  /// Applies to everything the listener hands out, sinks pick their own with [MediaSink::subscribe](crate::sink::MediaSink::subscribe)
+  /// and consumers on `/events` of the websocket port with [ConsumerMessage::Subscribe](crate::ConsumerMessage::Subscribe)
+  pub events: EventKind,
+  /// How long a source can go without producing an update before it is considered stale,
+  /// stale sources report [MediaState::Stopped] so other sources take priority
+  pub stale_after: Option<Duration>,
+  /// File the last played media is saved to, so a new listener shows it (as stopped and stale)
+  /// until a source reports something
+  pub persist_path: Option<PathBuf>,
+  /// How sources decide if media changed
+  pub identity: Arc<dyn TrackIdentity>,
+  /// [MediaEvent::MediaChanged] for the same track as the last one is dropped within this long of it,
+  /// for players that announce the same track again after buffering, [None] passes every one on
+  pub dedup_window: Option<Duration>,
+  /// How long a gapless or crossfaded track change gets to settle, the pause, seek or stale progress
+  /// players send around it is dropped instead of passed on, [None] passes every one on
+  pub transition_window: Option<Duration>,
+  /// Fraction of a track, between 0 and 1, it has to have been playing for before
+  /// [MediaEvent::ListenThresholdReached] is sent, like 0.5 for scrobbling, [None] never sends it
+  pub listen_threshold: Option<f64>,
+  /// Cleanup passes applied to metadata before it is compared or emitted,
+  /// can be set per source with [SourceOverrides::sanitize]
+  pub sanitize: SanitizeConfig,
+  /// Room or device the sources play in, like `Living Room`, put in [MediaMetadata::zone]
+  /// when the media doesn't name one itself, usually set per source with [SourceOverrides::zone]
+  pub zone: Option<String>,
+  /// Rules for hiding media from events, checked in order before events are delivered,
+  /// polling still returns the unredacted metadata
+  pub redaction: Vec<RedactionRule>,
+  /// How many events each source queues up before [MediaSourceConfig::backpressure] kicks in, at least 1
+  pub channel_capacity: usize,
+  /// What happens when events aren't consumed fast enough
+  pub backpressure: BackpressurePolicy,
+  /// Called when a source dropped another [MediaSourceConfig::dropped_events_threshold] events,
+  /// the counts are in [Metrics] and [SourceStatus::dropped_events] either way
+  pub on_dropped_events: Option<Arc<dyn DroppedEventsHandler>>,
+  /// How many dropped events it takes to call [MediaSourceConfig::on_dropped_events] again, at least 1
+  pub dropped_events_threshold: u64,
+  /// Runtime for the websocket source to spawn its tasks on,
+  /// if not set, it builds its own runtime on a dedicated thread
+  #[cfg(feature = "ws-tokio")]
+  pub runtime: Option<Handle>,
+  /// How the threads and runtime the crate creates itself are set up
+  pub threads: ThreadConfig,
+  /// Where the listener and its sources record their counters, share it to read them from elsewhere
+  pub metrics: Arc<Metrics>,
+  /// How long sources wait before reconnecting after they failed, and when they give up
+  pub backoff: Backoff,
+  /// Replaces [MediaSourceConfig::backoff] for the websocket source binding its address
+  pub bind_backoff: Option<Backoff>,
+  /// Called when the websocket source fails to bind its address,
+  /// it also emits [MediaEvent::SourceError] either way
+  pub on_bind_error: Option<Arc<dyn BindErrorHandler>>,
+  /// When the system source asks the platform for updates
+  pub poll_mode: PollMode,
+  /// Time between updates of the system source while nothing is playing, replaces
+  /// [MediaSourceConfig::update_rate] until playback resumes, [None] keeps the full rate
+  ///
+  /// Keep [MediaSourceConfig::stale_after] above it, otherwise paused sources go stale
+  pub idle_interval: Option<Duration>,
+  /// What the system source cuts back on while the computer runs on battery,
+  /// [None] doesn't check the power supply
+  pub battery_throttle: Option<BatteryThrottle>,
+  /// Most events each websocket client can send, [None] allows any amount
+  pub rate_limit: Option<RateLimit>,
+  /// How large messages from websocket clients can get
+  pub message_limits: MessageLimits,
+  /// How often websocket clients with [MediaCapabilities::ping](crate::MediaCapabilities::ping)
+  /// are pinged to measure their latency, [None] turns it off
+  pub ping_interval: Option<Duration>,
+  /// Token clients send along with a [RemoteControl](crate::RemoteControl), over the websocket
+  /// or as `Authorization: Bearer <token>` to `POST /control`, [None] turns remote control off
+  pub control_token: Option<String>,
+  /// Settings that differ per source, like a shorter [MediaSourceConfig::stale_after]
+  /// for a websocket client that sends updates several times a second
+  pub source_overrides: BTreeMap<SourceKind, SourceOverrides>,
+  /// Companion server of the YouTube Music Desktop App, [YtmdMediaSource](crate::ytmd::YtmdMediaSource)
+  /// isn't enabled without it
+  #[cfg(feature = "ytmd")]
+  pub ytmd: Option<YtmdConfig>,
+  /// Kodi's JSON-RPC websocket, [KodiMediaSource](crate::kodi::KodiMediaSource) isn't enabled without it
+  #[cfg(feature = "kodi")]
+  pub kodi: Option<KodiConfig>,
+  /// Plex Media Server, [PlexMediaSource](crate::media_server::PlexMediaSource) isn't enabled without it
+  #[cfg(feature = "media-server")]
+  pub plex: Option<PlexConfig>,
+  /// Jellyfin server, [JellyfinMediaSource](crate::media_server::JellyfinMediaSource) isn't enabled without it
+  #[cfg(feature = "media-server")]
+  pub jellyfin: Option<JellyfinConfig>,
+  /// Icecast or Shoutcast stream, [IcyMediaSource](crate::icy::IcyMediaSource) isn't enabled without it
+  #[cfg(feature = "icy")]
+  pub icy: Option<IcyConfig>,
+  /// JSON endpoint of a player, [HttpJsonMediaSource](crate::http_json::HttpJsonMediaSource) isn't enabled without it
+  #[cfg(feature = "http-json")]
+  pub http_json: Option<HttpJsonConfig>,
+  /// Plugin script, [PluginMediaSource](crate::plugin::PluginMediaSource) isn't enabled without it
+  #[cfg(feature = "plugins")]
+  pub plugin: Option<PluginConfig>,
+  /// Metadata pipe of shairport-sync, [ShairportMediaSource](crate::shairport::ShairportMediaSource) isn't enabled without it
+  #[cfg(all(feature = "shairport", unix))]
+  pub shairport: Option<ShairportConfig>,
 }
 
 impl Default for MediaSourceConfig {
   fn default() -> Self {
     Self {
       addr: WebsocketAddr::Default,
-      priority: MediaSourcePriority::Websocket,
+      max_connections: 1,
+      connection_policy: ConnectionPolicy::default(),
+      instance_policy: InstancePolicy::default(),
+      socket: SocketOptions::default(),
+      priority: vec![SourceKind::Websocket, SourceKind::System],
+      hysteresis: Duration::ZERO,
       timeout: Duration::from_millis(5000),
       update_rate: 30,
+      covers: true,
       hybrid: true,
+      field_ownership: FieldOwnership::default(),
       websocket_enabled: true,
       system_enabled: true,
+      preferred_players: Vec::new(),
+      coalesce: CoalesceConfig::default(),
+      progress_threshold: ProgressThreshold::default(),
+      events: EventKind::ALL,
+      stale_after: None,
+      persist_path: None,
+      identity: Arc::new(IdentityStrategy::Default),
+      dedup_window: None,
+      transition_window: None,
+      listen_threshold: None,
+      sanitize: SanitizeConfig::default(),
+      zone: None,
+      redaction: Vec::new(),
+      channel_capacity: 64,
+      backpressure: BackpressurePolicy::default(),
+      on_dropped_events: None,
+      dropped_events_threshold: 100,
+      #[cfg(feature = "ws-tokio")]
+      runtime: None,
+      threads: ThreadConfig::default(),
+      metrics: Arc::new(Metrics::default()),
+      backoff: Backoff::default(),
+      bind_backoff: None,
+      on_bind_error: None,
+      poll_mode: PollMode::default(),
+      idle_interval: Some(Duration::from_secs(5)),
+      battery_throttle: None,
+      rate_limit: Some(RateLimit::default()),
+      message_limits: MessageLimits::default(),
+      ping_interval: Some(Duration::from_secs(5)),
+      control_token: None,
+      source_overrides: BTreeMap::new(),
+      #[cfg(feature = "ytmd")]
+      ytmd: None,
+      #[cfg(feature = "kodi")]
+      kodi: None,
+      #[cfg(feature = "media-server")]
+      plex: None,
+      #[cfg(feature = "media-server")]
+      jellyfin: None,
+      #[cfg(feature = "icy")]
+      icy: None,
+      #[cfg(feature = "http-json")]
+      http_json: None,
+      #[cfg(feature = "plugins")]
+      plugin: None,
+      #[cfg(all(feature = "shairport", unix))]
+      shairport: None,
     }
   }
 }
@@ -63,259 +629,1938 @@ impl MediaSourceConfig {
     }
   }
 
-  pub fn set_priority(self, priority: MediaSourcePriority) -> Self {
-    Self { priority, ..self }
+  pub fn set_max_connections(self, max_connections: usize) -> Self {
+    Self {
+      max_connections,
+      ..self
+    }
   }
 
-  pub fn set_update_rate(self, update_rate: u64) -> Self {
+  pub fn set_connection_policy(self, connection_policy: ConnectionPolicy) -> Self {
     Self {
-      update_rate,
+      connection_policy,
       ..self
     }
   }
 
-  pub fn set_hybrid(self, hybrid: bool) -> Self {
-    Self { hybrid, ..self }
+  pub fn set_instance_policy(self, instance_policy: InstancePolicy) -> Self {
+    Self {
+      instance_policy,
+      ..self
+    }
   }
 
-  pub fn enable_system(self) -> Self {
+  pub fn set_socket_options(self, socket: SocketOptions) -> Self {
+    Self { socket, ..self }
+  }
+
+  pub fn set_priority(self, priority: impl IntoIterator<Item = SourceKind>) -> Self {
     Self {
-      system_enabled: true,
+      priority: priority.into_iter().collect(),
       ..self
     }
   }
 
-  pub fn enable_websocket(self, addr: WebsocketAddr) -> Self {
+  pub fn set_hysteresis(self, hysteresis: Duration) -> Self {
+    Self { hysteresis, ..self }
+  }
+
+  pub fn set_update_rate(self, update_rate: u64) -> Self {
     Self {
-      addr,
-      websocket_enabled: true,
+      update_rate,
       ..self
     }
   }
-}
 
-#[derive(Debug)]
-pub struct MediaListener {
-  system: Option<SystemMediaSource>,
-  websocket: Option<WebsocketMediaSourceBackground>,
-  last_played: Arc<RwLock<LastPlayed>>,
-  cfg: MediaSourceConfig,
-}
-
-#[derive(Debug, Copy, Clone)]
-enum LastPlayed {
-  Websocket,
-  System,
-}
+  pub fn set_covers(self, covers: bool) -> Self {
+    Self { covers, ..self }
+  }
 
-impl MediaSource for MediaListener {
-  fn create(cfg: MediaSourceConfig) -> Result<Self> {
-    if !cfg.system_enabled && !cfg.websocket_enabled {
-      return Err(Error::NotEnabled);
+  /// Overwrites the settings `preset` is about, set anything else after it
+  ///
+  /// ```rs
+  /// let cfg = MediaSourceConfig::default().set_preset(Preset::StatusBar);
+  /// ```
+  pub fn set_preset(self, preset: Preset) -> Self {
+    match preset {
+      Preset::Overlay => Self {
+        update_rate: 60,
+        covers: true,
+        ..self
+      },
+      Preset::Scrobbler => Self {
+        update_rate: 1,
+        idle_interval: Some(Duration::from_secs(30)),
+        covers: false,
+        listen_threshold: Some(0.5),
+        ..self
+      },
+      Preset::StatusBar => Self {
+        update_rate: 1,
+        covers: false,
+        ..self
+      },
     }
+  }
 
-    let system = match cfg.system_enabled {
-      true => {
-        let source = SystemMediaSource::create(cfg.clone())?;
-        Some(source)
-      }
-      false => None,
-    };
+  /// Replaces the overrides of `source`
+  ///
+  /// ```rs
+  /// let cfg = MediaSourceConfig::default()
+  ///   .set_source_overrides(SourceKind::Websocket, SourceOverrides::new().set_stale_after(Duration::from_secs(1)));
+  /// ```
+  pub fn set_source_overrides(mut self, source: SourceKind, overrides: SourceOverrides) -> Self {
+    self.source_overrides.insert(source, overrides);
+    self
+  }
 
-    let websocket = match cfg.websocket_enabled {
-      true => {
-        let source = WebsocketMediaSourceBackground::create(cfg.clone())?;
-        Some(source)
-      }
-      false => None,
-    };
+  /// This config with the [MediaSourceConfig::source_overrides] of `source` applied,
+  /// the sources of the crate do this themselves when they're created
+  pub fn for_source(&self, source: &SourceKind) -> Self {
+    let mut cfg = self.clone();
 
-    let last_played = match cfg.priority {
-      MediaSourcePriority::Websocket => LastPlayed::Websocket,
-      MediaSourcePriority::System => LastPlayed::System,
+    let Some(overrides) = self.source_overrides.get(source) else {
+      return cfg;
     };
 
-    let last_played = Arc::new(RwLock::new(last_played));
+    if let Some(timeout) = overrides.timeout {
+      cfg.timeout = timeout;
+    }
 
-    Ok(Self {
-      system,
-      websocket,
-      last_played,
-      cfg,
-    })
+    if let Some(update_rate) = overrides.update_rate {
+      cfg.update_rate = update_rate;
+    }
+
+    if let Some(stale_after) = overrides.stale_after {
+      cfg.stale_after = Some(stale_after);
+    }
+
+    if let Some(zone) = &overrides.zone {
+      cfg.zone = Some(zone.clone());
+    }
+
+    if let Some(sanitize) = &overrides.sanitize {
+      cfg.sanitize = sanitize.clone();
+    }
+
+    cfg
   }
 
-  fn is_closed(&self) -> bool {
-    let system = self
-      .system
-      .as_ref()
-      .map(|s| s.is_closed())
-      .unwrap_or_default();
+  pub fn set_hybrid(self, hybrid: bool) -> Self {
+    Self { hybrid, ..self }
+  }
 
-    let websocket = self
-      .websocket
-      .as_ref()
-      .map(|s| s.is_closed())
-      .unwrap_or_default();
+  pub fn set_field_ownership(self, field_ownership: FieldOwnership) -> Self {
+    Self {
+      field_ownership,
+      ..self
+    }
+  }
+
+  pub fn set_preferred_players<I, S>(self, players: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    Self {
+      preferred_players: players.into_iter().map(Into::into).collect(),
+      ..self
+    }
+  }
 
-    system && websocket
+  pub fn set_coalesce(self, coalesce: CoalesceConfig) -> Self {
+    Self { coalesce, ..self }
   }
 
-  fn is_running(&self) -> bool {
-    let system = self
-      .system
-      .as_ref()
-      .map(|s| s.is_running())
-      .unwrap_or_default();
+  pub fn set_progress_threshold(self, progress_threshold: ProgressThreshold) -> Self {
+    Self {
+      progress_threshold,
+      ..self
+    }
+  }
 
-    let websocket = self
-      .websocket
-      .as_ref()
-      .map(|s| s.is_running())
-      .unwrap_or_default();
+  pub fn set_events(self, events: EventKind) -> Self {
+    Self { events, ..self }
+  }
 
-    system || websocket
+  pub fn set_stale_after(self, stale_after: Duration) -> Self {
+    Self {
+      stale_after: Some(stale_after),
+      ..self
+    }
   }
 
-  fn poll(&self) -> Result<MediaMetadata> {
-    match (self.cfg.priority, &self.system, &self.websocket) {
-      (MediaSourcePriority::System, Some(system), Some(websocket)) => {
-        let system = system.poll()?;
-        let websocket = websocket.poll()?;
-
-        match (system.state, websocket.state) {
-          (MediaState::Playing, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          (MediaState::Stopped | MediaState::Paused, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::Websocket;
-            Ok(websocket)
-          },
-          (MediaState::Playing, MediaState::Stopped | MediaState::Paused) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          _ => match *self.last_played.read().unwrap() {
-            LastPlayed::Websocket => Ok(websocket),
-            LastPlayed::System => Ok(system),
-          }
-        }
-      }
-      (MediaSourcePriority::System, Some(system), None) => system.poll(),
-      (MediaSourcePriority::System, None, Some(websocket)) => websocket.poll(),
-      (MediaSourcePriority::Websocket, Some(system), Some(websocket)) => {
-        let system = system.poll()?;
-        let websocket = websocket.poll()?;
-
-        match (system.state, websocket.state) {
-          (MediaState::Playing, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::Websocket;
-            Ok(websocket)
-          },
-          (MediaState::Playing, MediaState::Stopped | MediaState::Paused) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          (MediaState::Stopped | MediaState::Paused, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::Websocket;
-            Ok(websocket)
-          },
-          _ => match *self.last_played.read().unwrap() {
-            LastPlayed::Websocket => Ok(websocket),
-            LastPlayed::System => Ok(system),
-          }
-        }
-      }
-      (MediaSourcePriority::Websocket, None, Some(websocket)) => websocket.poll(),
-      (MediaSourcePriority::Websocket, Some(system), None) => system.poll(),
-      _ => unreachable!(),
-    }
-  }
-
-  fn poll_guarded(&self) -> Result<RwLockReadGuard<MediaMetadata>> {
-    match (self.cfg.priority, &self.system, &self.websocket) {
-      (MediaSourcePriority::System, Some(system), Some(websocket)) => {
-        let system = system.poll_guarded()?;
-        let websocket = websocket.poll_guarded()?;
-
-        match (system.state, websocket.state) {
-          (MediaState::Playing, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          (MediaState::Stopped | MediaState::Paused, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::Websocket;
-            Ok(websocket)
-          },
-          (MediaState::Playing, MediaState::Stopped | MediaState::Paused) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          _ => match *self.last_played.read().unwrap() {
-            LastPlayed::Websocket => Ok(websocket),
-            LastPlayed::System => Ok(system),
-          }
-        }
-      }
-      (MediaSourcePriority::System, Some(system), None) => system.poll_guarded(),
-      (MediaSourcePriority::System, None, Some(websocket)) => websocket.poll_guarded(),
-      (MediaSourcePriority::Websocket, Some(system), Some(websocket)) => {
-        let system = system.poll_guarded()?;
-        let websocket = websocket.poll_guarded()?;
-
-        match (system.state, websocket.state) {
-          (MediaState::Playing, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          (MediaState::Playing, MediaState::Stopped | MediaState::Paused) => {
-            *self.last_played.write().unwrap() = LastPlayed::System;
-            Ok(system)
-          },
-          (MediaState::Stopped | MediaState::Paused, MediaState::Playing) => {
-            *self.last_played.write().unwrap() = LastPlayed::Websocket;
-            Ok(websocket)
-          },
-          _ => match *self.last_played.read().unwrap() {
-            LastPlayed::Websocket => Ok(websocket),
-            LastPlayed::System => Ok(system),
-          }
-        }
-      }
-      (MediaSourcePriority::Websocket, None, Some(websocket)) => websocket.poll_guarded(),
-      (MediaSourcePriority::Websocket, Some(system), None) => system.poll_guarded(),
-      _ => unreachable!(),
+  pub fn set_persist_path(self, persist_path: impl Into<PathBuf>) -> Self {
+    Self {
+      persist_path: Some(persist_path.into()),
+      ..self
     }
   }
 
-  fn next(&self) -> Result<MediaEvent> {
-    match (self.cfg.priority, &self.system, &self.websocket) {
-      (MediaSourcePriority::System, Some(system), Some(websocket)) => {
-        system.next().or_else(|_| websocket.next())
-      }
-      (MediaSourcePriority::System, Some(system), None) => system.next(),
-      (MediaSourcePriority::System, None, Some(websocket)) => websocket.next(),
-      (MediaSourcePriority::Websocket, Some(system), Some(websocket)) => {
-        websocket.next().or_else(|_| system.next())
-      }
-      (MediaSourcePriority::Websocket, None, Some(websocket)) => websocket.next(),
-      (MediaSourcePriority::Websocket, Some(system), None) => system.next(),
-      _ => unreachable!(),
+  pub fn set_identity(self, identity: impl TrackIdentity + 'static) -> Self {
+    Self {
+      identity: Arc::new(identity),
+      ..self
     }
   }
-}
 
-pub trait MediaSource: Send + Sync + Sized {
-  fn create(cfg: MediaSourceConfig) -> Result<Self>;
+  pub fn set_dedup_window(self, dedup_window: Duration) -> Self {
+    Self {
+      dedup_window: Some(dedup_window),
+      ..self
+    }
+  }
 
-  fn is_closed(&self) -> bool;
+  pub fn set_transition_window(self, transition_window: Duration) -> Self {
+    Self {
+      transition_window: Some(transition_window),
+      ..self
+    }
+  }
 
-  fn is_running(&self) -> bool;
+  pub fn set_listen_threshold(self, listen_threshold: f64) -> Self {
+    Self {
+      listen_threshold: Some(listen_threshold),
+      ..self
+    }
+  }
 
-  fn poll(&self) -> Result<MediaMetadata>;
+  pub fn set_sanitize(self, sanitize: SanitizeConfig) -> Self {
+    Self { sanitize, ..self }
+  }
 
-  fn poll_guarded(&self) -> Result<RwLockReadGuard<MediaMetadata>>;
+  pub fn set_zone(self, zone: impl Into<String>) -> Self {
+    Self {
+      zone: Some(zone.into()),
+      ..self
+    }
+  }
 
-  fn next(&self) -> Result<MediaEvent>;
+  /// Puts [MediaSourceConfig::zone] in `metadata` if it doesn't name a zone itself
+  #[cfg_attr(not(any(feature = "system", feature = "ws")), allow(dead_code))]
+  pub(crate) fn apply_zone(&self, metadata: &mut MediaMetadata) {
+    if metadata.zone.is_none() {
+      metadata.zone = self.zone.clone();
+    }
+  }
+
+  pub fn set_redaction(self, redaction: impl IntoIterator<Item = RedactionRule>) -> Self {
+    Self {
+      redaction: redaction.into_iter().collect(),
+      ..self
+    }
+  }
+
+  pub fn set_channel_capacity(self, channel_capacity: usize) -> Self {
+    Self {
+      channel_capacity,
+      ..self
+    }
+  }
+
+  pub fn set_backpressure(self, backpressure: BackpressurePolicy) -> Self {
+    Self {
+      backpressure,
+      ..self
+    }
+  }
+
+  pub fn set_on_dropped_events(
+    self,
+    on_dropped_events: impl DroppedEventsHandler + 'static,
+  ) -> Self {
+    Self {
+      on_dropped_events: Some(Arc::new(on_dropped_events)),
+      ..self
+    }
+  }
+
+  pub fn set_dropped_events_threshold(self, dropped_events_threshold: u64) -> Self {
+    Self {
+      dropped_events_threshold,
+      ..self
+    }
+  }
+
+  #[cfg(feature = "ws-tokio")]
+  pub fn set_runtime(self, runtime: Handle) -> Self {
+    Self {
+      runtime: Some(runtime),
+      ..self
+    }
+  }
+
+  pub fn set_threads(self, threads: ThreadConfig) -> Self {
+    Self { threads, ..self }
+  }
+
+  pub fn set_metrics(self, metrics: Arc<Metrics>) -> Self {
+    Self { metrics, ..self }
+  }
+
+  pub fn set_backoff(self, backoff: Backoff) -> Self {
+    Self { backoff, ..self }
+  }
+
+  pub fn set_bind_backoff(self, bind_backoff: Backoff) -> Self {
+    Self {
+      bind_backoff: Some(bind_backoff),
+      ..self
+    }
+  }
+
+  pub fn set_on_bind_error(self, on_bind_error: impl BindErrorHandler + 'static) -> Self {
+    Self {
+      on_bind_error: Some(Arc::new(on_bind_error)),
+      ..self
+    }
+  }
+
+  pub fn set_poll_mode(self, poll_mode: PollMode) -> Self {
+    Self { poll_mode, ..self }
+  }
+
+  pub fn set_idle_interval(self, idle_interval: Duration) -> Self {
+    Self {
+      idle_interval: Some(idle_interval),
+      ..self
+    }
+  }
+
+  pub fn set_battery_throttle(self, battery_throttle: BatteryThrottle) -> Self {
+    Self {
+      battery_throttle: Some(battery_throttle),
+      ..self
+    }
+  }
+
+  /// Time between updates of a system source whose media is in `state`
+  #[cfg_attr(not(any(feature = "system", all(feature = "shairport", unix))), allow(dead_code))]
+  pub(crate) fn update_interval(&self, state: MediaState) -> Duration {
+    let full_rate = Duration::from_millis(1000u64.checked_div(self.update_rate).unwrap_or(1));
+
+    match self.idle_interval {
+      Some(idle) if state != MediaState::Playing => idle,
+      _ => full_rate,
+    }
+  }
+
+  pub fn set_rate_limit(self, rate_limit: RateLimit) -> Self {
+    Self {
+      rate_limit: Some(rate_limit),
+      ..self
+    }
+  }
+
+  pub fn set_message_limits(self, message_limits: MessageLimits) -> Self {
+    Self {
+      message_limits,
+      ..self
+    }
+  }
+
+  pub fn set_ping_interval(self, ping_interval: Duration) -> Self {
+    Self {
+      ping_interval: Some(ping_interval),
+      ..self
+    }
+  }
+
+  pub fn set_control_token(self, control_token: impl Into<String>) -> Self {
+    Self {
+      control_token: Some(control_token.into()),
+      ..self
+    }
+  }
+
+  /// Fails with [Error::InvalidConfig] for settings that don't work, instead of the sources
+  /// quietly making do with them, [MediaListener::create] checks this itself
+  pub fn validate(&self) -> Result<()> {
+    let mut problems = Vec::new();
+
+    if !self.system_enabled && !self.websocket_enabled {
+      problems.push("the system and websocket sources are both disabled".to_string());
+    }
+
+    if self.channel_capacity == 0 {
+      problems.push("channel_capacity is 0".to_string());
+    }
+
+    if self.idle_interval.is_some_and(|idle| idle.is_zero()) {
+      problems.push("idle_interval is 0".to_string());
+    }
+
+    if self.ping_interval.is_some_and(|ping| ping.is_zero()) {
+      problems.push("ping_interval is 0".to_string());
+    }
+
+    let backoffs = [("backoff", Some(&self.backoff)), ("bind_backoff", self.bind_backoff.as_ref())];
+
+    for (name, backoff) in backoffs {
+      let Some(backoff) = backoff else {
+        continue;
+      };
+
+      if backoff.initial > backoff.max {
+        problems.push(format!("{name}.initial is more than {name}.max"));
+      }
+
+      if backoff.multiplier < 1.0 {
+        problems.push(format!("{name}.multiplier is below 1"));
+      }
+
+      if !(0.0..=1.0).contains(&backoff.jitter) {
+        problems.push(format!("{name}.jitter isn't between 0 and 1"));
+      }
+    }
+
+    if self
+      .listen_threshold
+      .is_some_and(|threshold| !(0.0..=1.0).contains(&threshold))
+    {
+      problems.push("listen_threshold isn't between 0 and 1".to_string());
+    }
+
+    if self.update_rate == 0 {
+      problems.push("update_rate is 0".to_string());
+    }
+
+    if self.stale_after.is_some_and(|stale| stale.is_zero()) {
+      problems.push("stale_after is 0".to_string());
+    }
+
+    for (source, overrides) in &self.source_overrides {
+      let name = source_name(source);
+
+      if overrides.update_rate == Some(0) {
+        problems.push(format!("update_rate of {name} is 0"));
+      }
+
+      if overrides.stale_after.is_some_and(|stale| stale.is_zero()) {
+        problems.push(format!("stale_after of {name} is 0"));
+      }
+    }
+
+    match problems.is_empty() {
+      true => Ok(()),
+      false => Err(Error::InvalidConfig(problems)),
+    }
+  }
+
+  /// Defaults resolved, for showing the config as it's used
+  fn resolved(&self) -> Self {
+    // where an activated socket is bound only shows in SourceStatus::local_addr
+    let addr = match self.addr {
+      WebsocketAddr::Activated => WebsocketAddr::Activated,
+      addr => WebsocketAddr::Addr(addr.socket_addr()),
+    };
+
+    Self {
+      addr,
+      ..self.clone()
+    }
+  }
+
+  #[cfg(feature = "ytmd")]
+  pub fn set_ytmd(self, ytmd: YtmdConfig) -> Self {
+    Self {
+      ytmd: Some(ytmd),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "kodi")]
+  pub fn set_kodi(self, kodi: KodiConfig) -> Self {
+    Self {
+      kodi: Some(kodi),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "media-server")]
+  pub fn set_plex(self, plex: PlexConfig) -> Self {
+    Self {
+      plex: Some(plex),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "media-server")]
+  pub fn set_jellyfin(self, jellyfin: JellyfinConfig) -> Self {
+    Self {
+      jellyfin: Some(jellyfin),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "icy")]
+  pub fn set_icy(self, icy: IcyConfig) -> Self {
+    Self {
+      icy: Some(icy),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "http-json")]
+  pub fn set_http_json(self, http_json: HttpJsonConfig) -> Self {
+    Self {
+      http_json: Some(http_json),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "plugins")]
+  pub fn set_plugin(self, plugin: PluginConfig) -> Self {
+    Self {
+      plugin: Some(plugin),
+      ..self
+    }
+  }
+
+  #[cfg(all(feature = "shairport", unix))]
+  pub fn set_shairport(self, shairport: ShairportConfig) -> Self {
+    Self {
+      shairport: Some(shairport),
+      ..self
+    }
+  }
+
+  pub fn enable_system(self) -> Self {
+    Self {
+      system_enabled: true,
+      ..self
+    }
+  }
+
+  pub fn enable_websocket(self, addr: WebsocketAddr) -> Self {
+    Self {
+      addr,
+      websocket_enabled: true,
+      ..self
+    }
+  }
+}
+
+/// Health of a single source, see [MediaListener::status]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SourceStatus {
+  pub running: bool,
+  pub stale: bool,
+  pub last_error: Option<String>,
+  /// Events the source dropped because they weren't consumed fast enough,
+  /// only counted for the sources that come with the crate
+  pub dropped_events: u64,
+  /// Where the source accepts connections, see [MediaSource::local_addr]
+  pub local_addr: Option<SocketAddr>,
+}
+
+impl SourceStatus {
+  fn of(source: &dyn DynMediaSource, dropped_events: u64) -> Self {
+    Self {
+      running: source.is_running(),
+      stale: source.is_stale(),
+      last_error: source.last_error(),
+      dropped_events,
+      local_addr: source.local_addr(),
+    }
+  }
+}
+
+/// Health of the listener and its sources, [None] for sources that aren't enabled
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ListenerStatus {
+  /// Source the listener currently gets media from
+  pub active: SourceKind,
+  pub system: Option<SourceStatus>,
+  pub websocket: Option<SourceStatus>,
+  /// Sources added with [MediaListenerBuilder::with_source] or [MediaListener::add_source], by name
+  pub custom: BTreeMap<String, SourceStatus>,
+  /// Reported media was restored from [MediaSourceConfig::persist_path], no source has reported anything yet
+  pub restored: bool,
+}
+
+/// Configuration a [MediaListener] runs with, see [MediaListener::config]
+#[derive(Debug, Clone)]
+pub struct EffectiveConfig {
+  /// Settings of the listener itself, with [MediaSourceConfig::priority] in the order the sources are in
+  pub listener: MediaSourceConfig,
+  /// Settings of the system and websocket sources, with their [MediaSourceConfig::source_overrides] applied,
+  /// custom sources are created with configs of your own so they aren't listed
+  pub sources: BTreeMap<SourceKind, MediaSourceConfig>,
+}
+
+/// Everything [MediaListener] knows at one point in time, see [MediaListener::snapshot]
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListenerSnapshot {
+  /// Source the listener reports
+  pub active: SourceKind,
+  /// Why it reports [ListenerSnapshot::active]
+  pub reason: SelectionReason,
+  /// Every source in priority order
+  pub sources: Vec<SourceSnapshot>,
+  /// Reported media was restored from [MediaSourceConfig::persist_path], no source has reported anything yet
+  pub restored: bool,
+  /// How long the reported track has been playing, see [MediaSourceConfig::listen_threshold]
+  #[serde_as(as = "WireDuration")]
+  pub listened: Duration,
+}
+
+/// A single source in a [ListenerSnapshot]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceSnapshot {
+  pub kind: SourceKind,
+  /// Zone of the source's media, or the [MediaSourceConfig::zone] it's configured with
+  /// when there's no media, for showing what plays where in multi-room setups
+  pub zone: Option<String>,
+  /// [None] if the source couldn't be polled
+  pub metadata: Option<MediaMetadata>,
+  pub running: bool,
+  pub closed: bool,
+  pub stale: bool,
+  pub last_error: Option<String>,
+}
+
+/// Why [MediaListener] reports the source it does
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum SelectionReason {
+  /// It's the only source
+  Only,
+  /// It's the most preferred source that is playing
+  Playing,
+  /// Nothing is playing, it's the source that played last
+  LastPlayed,
+  /// A more preferred source is playing, but not for [MediaSourceConfig::hysteresis] yet
+  Hysteresis,
+}
+
+/// Wakes a [MediaListener] waiting for events, so it can wait on all of its sources at once
+/// instead of on one after another, see [MediaSource::set_notifier]
+#[derive(Debug, Default)]
+pub struct EventNotifier {
+  /// Bumped on every notification, so one that came in before waiting isn't missed
+  generation: Mutex<u64>,
+  changed: Condvar,
+}
+
+impl EventNotifier {
+  /// Tells the listener a source has an event ready or closed
+  pub fn notify(&self) {
+    *self.generation.lock().unwrap() += 1;
+    self.changed.notify_all();
+  }
+
+  fn generation(&self) -> u64 {
+    *self.generation.lock().unwrap()
+  }
+
+  /// Waits up to `timeout` for a notification after `seen`, see [EventNotifier::generation]
+  fn wait(&self, seen: u64, timeout: Duration) {
+    let generation = self.generation.lock().unwrap();
+
+    let _ = self
+      .changed
+      .wait_timeout_while(generation, timeout, |generation| *generation == seen)
+      .unwrap();
+  }
+}
+
+/// Object safe part of [MediaSource], so the listener can hold any mix of sources
+trait DynMediaSource: Send + Sync {
+  fn is_closed(&self) -> bool;
+
+  fn is_running(&self) -> bool;
+
+  fn poll(&self) -> Result<MediaMetadata>;
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>>;
+
+  fn is_stale(&self) -> bool;
+
+  fn last_error(&self) -> Option<String>;
+
+  fn next(&self) -> Result<MediaEvent>;
+
+  fn drain(&self) -> Vec<MediaEvent>;
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool;
+
+  fn control(&self, command: MediaCommand) -> Result<()>;
+
+  fn local_addr(&self) -> Option<SocketAddr>;
+}
+
+impl<T: MediaSource> DynMediaSource for T {
+  fn is_closed(&self) -> bool {
+    MediaSource::is_closed(self)
+  }
+
+  fn is_running(&self) -> bool {
+    MediaSource::is_running(self)
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    MediaSource::poll(self)
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    MediaSource::poll_guarded(self)
+  }
+
+  fn is_stale(&self) -> bool {
+    MediaSource::is_stale(self)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    MediaSource::last_error(self)
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    MediaSource::next(self)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    MediaSource::drain(self)
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    MediaSource::set_notifier(self, notifier)
+  }
+
+  fn control(&self, command: MediaCommand) -> Result<()> {
+    MediaSource::control(self, command)
+  }
+
+  fn local_addr(&self) -> Option<SocketAddr> {
+    MediaSource::local_addr(self)
+  }
+}
+
+/// Source the listener gets media from
+struct ListenerSource {
+  kind: SourceKind,
+  source: Box<dyn DynMediaSource>,
+  /// Set once it's removed or the listener is dropped, stops the thread waiting on it if it has one
+  removed: AtomicBool,
+}
+
+impl ListenerSource {
+  fn new(kind: SourceKind, source: Box<dyn DynMediaSource>) -> Self {
+    Self {
+      kind,
+      source,
+      removed: AtomicBool::new(false),
+    }
+  }
+}
+
+impl Debug for ListenerSource {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ListenerSource")
+      .field("kind", &self.kind)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Builds a [MediaListener] out of the system source, the websocket source and any number of custom sources
+///
+/// ```rs
+/// let listener = MediaListener::builder()
+///   .with_system(MediaSourceConfig::default())
+///   .with_websocket(MediaSourceConfig::default())
+///   .with_source("mpd", MpdMediaSource::create(cfg)?)
+///   .priority([SourceKind::Custom("mpd".into()), SourceKind::System])
+///   .build()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct MediaListenerBuilder {
+  cfg: MediaSourceConfig,
+  system: Option<MediaSourceConfig>,
+  websocket: Option<MediaSourceConfig>,
+  custom: Vec<ListenerSource>,
+  priority: Option<Vec<SourceKind>>,
+}
+
+impl MediaListenerBuilder {
+  /// Settings of the listener itself, like which events it delivers and where metrics are recorded,
+  /// the system and websocket sources record into the same metrics
+  pub fn with_config(self, cfg: MediaSourceConfig) -> Self {
+    Self { cfg, ..self }
+  }
+
+  /// Left out if this build has no system source, see [SYSTEM_SOURCE_AVAILABLE](crate::platform::SYSTEM_SOURCE_AVAILABLE)
+  pub fn with_system(self, cfg: MediaSourceConfig) -> Self {
+    Self {
+      system: Some(cfg.enable_system()),
+      ..self
+    }
+  }
+
+  /// Left out if this build has no websocket backend
+  pub fn with_websocket(self, cfg: MediaSourceConfig) -> Self {
+    Self {
+      websocket: Some(MediaSourceConfig {
+        websocket_enabled: true,
+        ..cfg
+      }),
+      ..self
+    }
+  }
+
+  /// Adds a source of your own, it shows up as [SourceKind::Custom] with the given name
+  pub fn with_source(mut self, name: impl Into<String>, source: impl MediaSource + 'static) -> Self {
+    self.custom.push(ListenerSource::new(
+      SourceKind::Custom(name.into()),
+      Box::new(source),
+    ));
+
+    self
+  }
+
+  /// Which source wins when more than one is playing, from most to least preferred,
+  /// sources that aren't listed come after in the order they were added (system, websocket, then custom),
+  /// defaults to [MediaSourceConfig::priority] of [MediaListenerBuilder::with_config]
+  pub fn priority(self, priority: impl IntoIterator<Item = SourceKind>) -> Self {
+    Self {
+      priority: Some(priority.into_iter().collect()),
+      ..self
+    }
+  }
+
+  /// Fails with [Error::InvalidConfig] if the configs of the system or websocket source don't
+  /// [validate](MediaSourceConfig::validate)
+  pub fn build(self) -> Result<MediaListener> {
+    let mut sources = Vec::new();
+    let mut configs = BTreeMap::new();
+    let mut unsupported = None;
+
+    // sources this build doesn't have are left out, so the same code works everywhere
+    if let Some(cfg) = self.system {
+      let cfg = cfg.set_metrics(self.cfg.metrics.clone());
+      cfg.validate()?;
+
+      match SystemMediaSource::create(cfg.clone()) {
+        Err(Error::NotEnabled) => {}
+        // carries on with just the websocket source, retrying would never get anywhere
+        Err(Error::PlatformUnsupported(message)) if self.websocket.is_some() => {
+          unsupported = Some(message);
+        }
+        source => {
+          sources.push(ListenerSource::new(SourceKind::System, Box::new(source?)));
+
+          configs.insert(SourceKind::System, cfg.for_source(&SourceKind::System).resolved());
+        }
+      }
+    }
+
+    if let Some(cfg) = self.websocket {
+      let cfg = cfg.set_metrics(self.cfg.metrics.clone());
+      cfg.validate()?;
+
+      match WebsocketMediaSourceBackground::create(cfg.clone()) {
+        Err(Error::NotEnabled) => {}
+        #[cfg(feature = "ws")]
+        Err(Error::InstanceRunning(_)) if cfg.instance_policy == InstancePolicy::Client => {
+          sources.push(ListenerSource::new(
+            SourceKind::Custom(InstanceClient::NAME.into()),
+            Box::new(InstanceClient::create(cfg.clone())?),
+          ));
+        }
+        source => {
+          sources.push(ListenerSource::new(
+            SourceKind::Websocket,
+            Box::new(source?),
+          ));
+
+          configs.insert(SourceKind::Websocket, cfg.for_source(&SourceKind::Websocket).resolved());
+        }
+      }
+    }
+
+    sources.extend(self.custom);
+
+    if sources.is_empty() {
+      return Err(Error::NotEnabled);
+    }
+
+    let priority = self.priority.unwrap_or_else(|| self.cfg.priority.clone());
+
+    // stable, so unlisted sources keep the order they were added in
+    sources.sort_by_key(|source| rank(&priority, &source.kind));
+
+    let restored = self
+      .cfg
+      .persist_path
+      .as_ref()
+      .and_then(|path| PersistedState::load(path).ok());
+
+    let last_played = match &restored {
+      Some(restored) if sources.iter().any(|s| s.kind == restored.active) => restored.active.clone(),
+      _ => sources[0].kind.clone(),
+    };
+
+    let last_played = Arc::new(RwLock::new(last_played));
+    let pending = Arc::new(PendingEvents::new(&self.cfg));
+    let stale = Arc::new(Mutex::new(Vec::new()));
+    let candidate = Mutex::new(None);
+
+    let state = ListenerState {
+      sources: RwLock::new(sources.into_iter().map(Arc::new).collect()),
+      priority,
+      configs,
+      last_played,
+      candidate,
+      control_source: RwLock::new(None),
+      conflict: Mutex::new(Vec::new()),
+      stored: RwLock::default(),
+      restored: Mutex::new(restored.map(|restored| restored.metadata)),
+      saved: Mutex::new(None),
+      listening: Mutex::default(),
+      pending,
+      stale,
+      cfg: self.cfg,
+    };
+
+    for source in state.sources() {
+      state.watch(&source)?;
+    }
+
+    if let Some(message) = unsupported {
+      state.push_pending(MediaEvent::SourceError {
+        source: SourceKind::System,
+        message,
+      });
+      state.push_pending(MediaEvent::SourceGaveUp(SourceKind::System));
+    }
+
+    Ok(MediaListener {
+      state: Arc::new(state),
+    })
+  }
+}
+
+/// Reports media from whichever of its sources is playing
+///
+/// Cheap to clone, clones share the sources and everything else so any thread or widget can poll it
+/// and wait for events without an [Arc] around it, each event is handed to whichever clone asks first
+#[derive(Debug, Clone)]
+pub struct MediaListener {
+  state: Arc<ListenerState>,
+}
+
+/// Everything a [MediaListener] and its clones share
+#[derive(Debug)]
+struct ListenerState {
+  /// Sources in priority order, shared so the ones being polled or waited on
+  /// stay alive if they're removed in the meantime
+  sources: RwLock<Vec<Arc<ListenerSource>>>,
+  /// Where [MediaListener::add_source] puts new sources, see [MediaListenerBuilder::priority]
+  priority: Vec<SourceKind>,
+  /// Configs the system and websocket sources were created with, see [MediaListener::config]
+  configs: BTreeMap<SourceKind, MediaSourceConfig>,
+  last_played: Arc<RwLock<SourceKind>>,
+  /// Source that would take over and since when, for [MediaSourceConfig::hysteresis]
+  candidate: Mutex<Option<(SourceKind, Instant)>>,
+  /// Source that gets playback controls no matter which one is active, see [MediaListener::control_source]
+  control_source: RwLock<Option<SourceKind>>,
+  /// Sources that were last found playing different tracks, see [MediaEvent::ConflictDetected]
+  conflict: Mutex<Vec<SourceKind>>,
+  /// Copy of what was last reported, so [MediaListener::poll_guarded] can hand out a guard
+  /// that doesn't hold on to a source that may be removed
+  stored: RwLock<MediaMetadata>,
+  /// Metadata from [MediaSourceConfig::persist_path], reported until a source has something
+  restored: Mutex<Option<MediaMetadata>>,
+  /// Metadata that was last saved to [MediaSourceConfig::persist_path]
+  saved: Mutex<Option<MediaMetadata>>,
+  /// Time the reported track has been playing
+  listening: Mutex<ListenTracker>,
+  /// Events produced by the listener itself, handed out by [MediaListener::next] before source events
+  pending: Arc<PendingEvents>,
+  /// Sources that are currently stale
+  stale: Arc<Mutex<Vec<SourceKind>>>,
+  cfg: MediaSourceConfig,
+}
+
+/// Events handed out by [MediaListener::next] before the sources are asked for more,
+/// shared with the threads waiting on sources that can't [notify](MediaSource::set_notifier) the listener
+#[derive(Debug)]
+struct PendingEvents {
+  queue: Mutex<VecDeque<MediaEvent>>,
+  /// Past this many the oldest are dropped, [MediaSourceConfig::channel_capacity]
+  capacity: usize,
+  /// Only these are queued, [MediaSourceConfig::events]
+  events: EventKind,
+  metrics: Arc<Metrics>,
+  /// Notified by every source and whenever something is queued, see [ListenerState::next]
+  notifier: Arc<EventNotifier>,
+}
+
+impl PendingEvents {
+  fn new(cfg: &MediaSourceConfig) -> Self {
+    Self {
+      queue: Mutex::default(),
+      capacity: cfg.channel_capacity.max(1),
+      events: cfg.events,
+      metrics: cfg.metrics.clone(),
+      notifier: Arc::default(),
+    }
+  }
+
+  /// Queues `event` unless it's filtered out, the oldest is dropped when it's full
+  /// like with [BackpressurePolicy::DropOldest], since it may only ever be polled
+  fn push(&self, event: MediaEvent) {
+    if !self.events.contains(event.kind()) {
+      return;
+    }
+
+    self.metrics.event_emitted(&event);
+
+    let mut queue = self.queue.lock().unwrap();
+
+    if queue.len() >= self.capacity {
+      queue.pop_front();
+    }
+
+    queue.push_back(event);
+    drop(queue);
+
+    self.notifier.notify();
+  }
+
+  fn pop(&self) -> Option<MediaEvent> {
+    self.queue.lock().unwrap().pop_front()
+  }
+}
+
+impl MediaListener {
+  pub fn builder() -> MediaListenerBuilder {
+    MediaListenerBuilder::default()
+  }
+
+  /// Counters and gauges of the listener and its sources
+  pub fn metrics(&self) -> &Arc<Metrics> {
+    &self.state.cfg.metrics
+  }
+
+  pub fn status(&self) -> ListenerStatus {
+    self.state.status()
+  }
+
+  /// Configuration as the listener and its sources use it, for diagnostics
+  pub fn config(&self) -> EffectiveConfig {
+    let priority = self.state.sources().iter().map(|s| s.kind.clone()).collect();
+
+    EffectiveConfig {
+      listener: MediaSourceConfig {
+        priority,
+        ..self.state.cfg.resolved()
+      },
+      sources: self.state.configs.clone(),
+    }
+  }
+
+  /// Polls every source once and picks the active one the same way [MediaListener::poll] does,
+  /// for debug overlays and the like that need all of it to line up
+  pub fn snapshot(&self) -> ListenerSnapshot {
+    self.state.snapshot()
+  }
+
+  /// Sends playback controls to `source` from now on, instead of the source that is currently active
+  pub fn control_source(&self, source: SourceKind) {
+    *self.state.control_source.write().unwrap() = Some(source);
+  }
+
+  /// Sends playback controls to the active source again, undoes [MediaListener::control_source]
+  pub fn clear_control_source(&self) {
+    *self.state.control_source.write().unwrap() = None;
+  }
+
+  /// What the source named `name` reports, whether or not it's the active one,
+  /// the system and websocket sources are named `system` and `websocket`
+  pub fn poll_source(&self, name: &str) -> Result<MediaMetadata> {
+    self.state.named(name)?.source.poll()
+  }
+
+  /// Next event of the source named `name`, see [MediaListener::poll_source],
+  /// events of the listener itself like [MediaEvent::ActiveSourceChanged] aren't included
+  pub fn next_from(&self, name: &str) -> Result<MediaEvent> {
+    self.state.named(name)?.source.next()
+  }
+
+  pub fn play(&self) -> Result<()> {
+    self.state.control(MediaCommand::Play)
+  }
+
+  pub fn pause(&self) -> Result<()> {
+    self.state.control(MediaCommand::Pause)
+  }
+
+  pub fn next_track(&self) -> Result<()> {
+    self.state.control(MediaCommand::NextTrack)
+  }
+
+  pub fn previous_track(&self) -> Result<()> {
+    self.state.control(MediaCommand::PreviousTrack)
+  }
+
+  pub fn seek_to(&self, position: Duration) -> Result<()> {
+    self.state.control(MediaCommand::SeekTo(position))
+  }
+
+  /// From 0 to 1
+  pub fn set_volume(&self, volume: f64) -> Result<()> {
+    self.state.control(MediaCommand::SetVolume(volume))
+  }
+
+  /// Adds a source while the listener is running, like [MediaListenerBuilder::with_source],
+  /// it goes where [MediaListenerBuilder::priority] puts it and [MediaEvent::SourceAdded] is sent
+  pub fn add_source(&self, name: impl Into<String>, source: impl MediaSource + 'static) -> Result<()> {
+    self.state.add(name.into(), Box::new(source))
+  }
+
+  /// Removes a custom source and shuts it down once calls that are using it return,
+  /// events it already produced are still handed out, followed by [MediaEvent::SourceRemoved]
+  pub fn remove_source(&self, name: &str) -> Result<()> {
+    self.state.remove(&SourceKind::Custom(name.into()))
+  }
+}
+
+/// Sources that could be polled and what they returned, in the same order,
+/// fails with the first error if none could be or [Error::NotEnabled] if there are no sources
+fn polled<'a, T>(
+  sources: &'a [Arc<ListenerSource>],
+  poll: impl Fn(&'a dyn DynMediaSource) -> Result<T>,
+) -> Result<(Vec<Arc<ListenerSource>>, Vec<T>)> {
+  let mut first_err = None;
+  let mut ok = (Vec::new(), Vec::new());
+
+  for source in sources {
+    match poll(source.source.as_ref()) {
+      Ok(polled) => {
+        ok.0.push(source.clone());
+        ok.1.push(polled);
+      }
+      Err(err) => {
+        first_err.get_or_insert(err);
+      }
+    }
+  }
+
+  match (ok.1.is_empty(), first_err) {
+    (true, Some(err)) => Err(err),
+    (true, None) => Err(Error::NotEnabled),
+    (false, _) => Ok(ok),
+  }
+}
+
+/// Position of `kind` in `priority`, sources that aren't listed come last
+fn rank(priority: &[SourceKind], kind: &SourceKind) -> usize {
+  priority
+    .iter()
+    .position(|listed| listed == kind)
+    .unwrap_or(usize::MAX)
+}
+
+impl ListenerState {
+  /// Sources as they are right now, they can be added and removed at any time
+  fn sources(&self) -> Vec<Arc<ListenerSource>> {
+    self.sources.read().unwrap().clone()
+  }
+
+  fn named(&self, name: &str) -> Result<Arc<ListenerSource>> {
+    self
+      .sources()
+      .into_iter()
+      .find(|s| source_name(&s.kind) == name)
+      .ok_or(Error::NotExist)
+  }
+
+  fn add(&self, name: String, source: Box<dyn DynMediaSource>) -> Result<()> {
+    let kind = SourceKind::Custom(name.clone());
+    let mut sources = self.sources.write().unwrap();
+
+    if sources.iter().any(|s| s.kind == kind) {
+      return Err(Error::DuplicateSource(name));
+    }
+
+    let wanted = rank(&self.priority, &kind);
+
+    let index = sources
+      .iter()
+      .position(|s| rank(&self.priority, &s.kind) > wanted)
+      .unwrap_or(sources.len());
+
+    let source = Arc::new(ListenerSource::new(kind.clone(), source));
+    self.watch(&source)?;
+
+    sources.insert(index, source);
+    let first = sources.len() == 1;
+    drop(sources);
+
+    self.push_pending(MediaEvent::SourceAdded(kind.clone()));
+
+    if first {
+      self.set_last_played(&kind);
+    }
+
+    Ok(())
+  }
+
+  fn remove(&self, kind: &SourceKind) -> Result<()> {
+    let mut sources = self.sources.write().unwrap();
+
+    let Some(index) = sources.iter().position(|s| s.kind == *kind) else {
+      return Err(Error::NotExist);
+    };
+
+    let removed = sources.remove(index);
+    removed.removed.store(true, Ordering::SeqCst);
+    let fallback = sources.first().map(|s| s.kind.clone());
+    drop(sources);
+
+    self.stale.lock().unwrap().retain(|stale| stale != kind);
+
+    {
+      let mut candidate = self.candidate.lock().unwrap();
+
+      if candidate.as_ref().is_some_and(|(candidate, _)| candidate == kind) {
+        *candidate = None;
+      }
+    }
+
+    let events = removed.source.drain();
+    self.pending.queue.lock().unwrap().extend(events);
+    self.push_pending(MediaEvent::SourceRemoved(kind.clone()));
+
+    let was_active = *self.last_played.read().unwrap() == *kind;
+
+    if let (true, Some(fallback)) = (was_active, fallback) {
+      self.set_last_played(&fallback);
+    }
+
+    Ok(())
+  }
+
+  fn status(&self) -> ListenerStatus {
+    let mut status = ListenerStatus {
+      active: self.last_played.read().unwrap().clone(),
+      system: None,
+      websocket: None,
+      custom: BTreeMap::new(),
+      restored: self.restored.lock().unwrap().is_some(),
+    };
+
+    for source in self.sources() {
+      let ListenerSource { kind, source, .. } = &*source;
+      let dropped_events = self.cfg.metrics.dropped_events(kind);
+      let source = SourceStatus::of(source.as_ref(), dropped_events);
+
+      match kind {
+        SourceKind::System => status.system = Some(source),
+        SourceKind::Websocket => status.websocket = Some(source),
+        SourceKind::Custom(name) => {
+          status.custom.insert(name.clone(), source);
+        }
+      }
+    }
+
+    status
+  }
+
+  fn snapshot(&self) -> ListenerSnapshot {
+    let sources = self.sources();
+
+    let snapshots = sources
+      .iter()
+      .map(|s| {
+        let metadata = s.source.poll().ok();
+
+        SourceSnapshot {
+          kind: s.kind.clone(),
+          zone: metadata
+            .as_ref()
+            .and_then(|metadata| metadata.zone.clone())
+            .or_else(|| self.cfg.for_source(&s.kind).zone),
+          metadata,
+          running: s.source.is_running(),
+          closed: s.source.is_closed(),
+          stale: s.source.is_stale(),
+          last_error: s.source.last_error(),
+        }
+      })
+      .collect::<Vec<_>>();
+
+    // sources that can't be polled count as stopped, like when picking where controls go
+    let states = snapshots
+      .iter()
+      .map(|s| s.metadata.as_ref().map_or(MediaState::Stopped, |m| m.state))
+      .collect::<Vec<_>>();
+
+    let (active, reason) = self.arbitrate(&sources, &states);
+
+    let active = match sources.get(active) {
+      Some(source) => source.kind.clone(),
+      None => self.last_played.read().unwrap().clone(),
+    };
+
+    ListenerSnapshot {
+      active,
+      reason,
+      sources: snapshots,
+      restored: self.restored.lock().unwrap().is_some(),
+      listened: self.listening.lock().unwrap().listened(),
+    }
+  }
+
+  fn set_last_played(&self, kind: &SourceKind) {
+    let mut last_played = self.last_played.write().unwrap();
+
+    if *last_played != *kind {
+      *last_played = kind.clone();
+      self.cfg.metrics.source_activated(kind);
+
+      let event = MediaEvent::ActiveSourceChanged(kind.clone());
+      self.push_pending(event);
+    }
+  }
+
+  /// Which of the polled sources to report, the first one that is playing,
+  /// otherwise the one that played last
+  fn select(&self, sources: &[Arc<ListenerSource>], states: &[MediaState]) -> usize {
+    self.arbitrate(sources, states).0
+  }
+
+  /// [ListenerState::select] and why it picked that source
+  fn arbitrate(
+    &self,
+    sources: &[Arc<ListenerSource>],
+    states: &[MediaState],
+  ) -> (usize, SelectionReason) {
+    if states.len() == 1 {
+      return (0, SelectionReason::Only);
+    }
+
+    let active = {
+      let last_played = self.last_played.read().unwrap();
+
+      sources
+        .iter()
+        .position(|source| source.kind == *last_played)
+        .unwrap_or_default()
+    };
+
+    let Some(wanted) = states.iter().position(|state| *state == MediaState::Playing) else {
+      return (active, SelectionReason::LastPlayed);
+    };
+
+    if wanted == active {
+      *self.candidate.lock().unwrap() = None;
+      return (active, SelectionReason::Playing);
+    }
+
+    let kind = &sources[wanted].kind;
+
+    if !self.should_switch(kind) {
+      return (active, SelectionReason::Hysteresis);
+    }
+
+    self.set_last_played(kind);
+    (wanted, SelectionReason::Playing)
+  }
+
+  /// Emits [MediaEvent::ConflictDetected] if sources started playing different tracks
+  fn detect_conflict(&self, sources: &[Arc<ListenerSource>], polled: &[&MediaMetadata]) {
+    let playing = sources
+      .iter()
+      .zip(polled)
+      .filter(|(_, metadata)| metadata.state == MediaState::Playing)
+      .collect::<Vec<_>>();
+
+    let conflicting = match playing.split_first() {
+      Some(((_, first), rest))
+        if rest
+          .iter()
+          .any(|(_, other)| self.cfg.identity.is_different(first, other)) =>
+      {
+        playing
+          .iter()
+          .map(|(source, _)| source.kind.clone())
+          .collect()
+      }
+      _ => Vec::new(),
+    };
+
+    let mut conflict = self.conflict.lock().unwrap();
+
+    if *conflict == conflicting {
+      return;
+    }
+
+    *conflict = conflicting;
+
+    if !conflict.is_empty() {
+      let sources = conflict.clone();
+      drop(conflict);
+
+      self.push_pending(MediaEvent::ConflictDetected { sources });
+    }
+  }
+
+  /// Metadata restored from [MediaSourceConfig::persist_path], until a source reports something
+  fn restored(&self, current: &MediaMetadata) -> Option<MediaMetadata> {
+    let mut restored = self.restored.lock().unwrap();
+
+    if !current.title.is_empty() {
+      *restored = None;
+    }
+
+    restored.clone()
+  }
+
+  /// Saves `current` to [MediaSourceConfig::persist_path] if it's a different track than last time
+  fn persist(&self, current: &MediaMetadata) {
+    let Some(path) = &self.cfg.persist_path else {
+      return;
+    };
+
+    if current.title.is_empty() {
+      return;
+    }
+
+    let mut saved = self.saved.lock().unwrap();
+
+    if let Some(saved) = &*saved {
+      if !self.cfg.identity.is_different(saved, current) {
+        return;
+      }
+    }
+
+    *saved = Some(current.clone());
+
+    let active = self.last_played.read().unwrap().clone();
+    let _ = PersistedState::new(active, current.clone()).save(path);
+  }
+
+  /// The active source's metadata merged with the other sources playing the same media,
+  /// see [MediaSourceConfig::hybrid]
+  fn merge(
+    &self,
+    sources: &[Arc<ListenerSource>],
+    polled: &[&MediaMetadata],
+    active: usize,
+  ) -> MediaMetadata {
+    let current = polled[active];
+
+    let same = sources
+      .iter()
+      .zip(polled)
+      .filter(|(_, metadata)| same_media(current, metadata))
+      .map(|(source, metadata)| (&source.kind, *metadata))
+      .collect::<Vec<_>>();
+
+    let merged = same
+      .iter()
+      .fold(current.clone(), |merged, (_, metadata)| {
+        merged.merge((*metadata).clone())
+      });
+
+    // the owner's values, with its gaps still filled in from the merge
+    let owned = |owner: &Option<SourceKind>| {
+      let owner = owner.as_ref()?;
+      let (_, metadata) = same.iter().find(|(kind, _)| *kind == owner)?;
+      Some((*metadata).clone().merge(merged.clone()))
+    };
+
+    let ownership = &self.cfg.field_ownership;
+    let timeline = owned(&ownership.timeline);
+    let info = owned(&ownership.info);
+    let art = owned(&ownership.art);
+
+    let mut merged = merged;
+
+    if let Some(timeline) = timeline {
+      merged.state = timeline.state;
+      merged.elapsed = timeline.elapsed;
+      merged.duration = timeline.duration;
+    }
+
+    if let Some(info) = info {
+      merged = MediaMetadata {
+        uid: info.uid,
+        uri: info.uri,
+        title: info.title,
+        album: info.album,
+        artists: info.artists,
+        featured_artists: info.featured_artists,
+        app: info.app,
+        zone: info.zone,
+        extra: info.extra,
+        queue: info.queue,
+        ..merged
+      };
+    }
+
+    if let Some(art) = art {
+      merged = MediaMetadata {
+        cover_url: art.cover_url,
+        cover: art.cover,
+        background_url: art.background_url,
+        background: art.background,
+        ..merged
+      };
+    }
+
+    merged
+  }
+
+  /// If `kind` has been the better pick for at least [MediaSourceConfig::hysteresis]
+  fn should_switch(&self, kind: &SourceKind) -> bool {
+    if self.cfg.hysteresis.is_zero() {
+      return true;
+    }
+
+    let mut candidate = self.candidate.lock().unwrap();
+
+    let since = match &*candidate {
+      Some((candidate, since)) if candidate == kind => *since,
+      _ => {
+        *candidate = Some((kind.clone(), Instant::now()));
+        return false;
+      }
+    };
+
+    if since.elapsed() < self.cfg.hysteresis {
+      return false;
+    }
+
+    *candidate = None;
+    true
+  }
+
+  fn check_stale(&self, sources: &[Arc<ListenerSource>]) {
+    let mut stale = self.stale.lock().unwrap();
+
+    for source in sources {
+      let ListenerSource { kind, source, .. } = &**source;
+      let is_stale = source.is_stale();
+      let was_stale = stale.contains(kind);
+
+      if is_stale && !was_stale {
+        stale.push(kind.clone());
+        self.push_pending(MediaEvent::SourceStale(kind.clone()));
+      } else if !is_stale && was_stale {
+        stale.retain(|stale| stale != kind);
+      }
+    }
+  }
+
+  /// Updates the time the reported track has been playing, see [MediaSourceConfig::listen_threshold]
+  fn observe_listening(&self, current: &MediaMetadata) {
+    let event = self.listening.lock().unwrap().observe(
+      current,
+      &*self.cfg.identity,
+      self.cfg.listen_threshold,
+    );
+
+    // metadata from polling isn't redacted yet
+    let event = event.and_then(|event| Redactor::new(self.cfg.redaction.clone()).redact(event));
+
+    if let Some(event) = event {
+      self.push_pending(event);
+    }
+  }
+
+  /// [ListenerState::observe_listening] with the source that played last,
+  /// without picking the active source again like polling does
+  fn observe_last_played(&self) {
+    let sources = self.sources();
+    let last_played = self.last_played.read().unwrap().clone();
+
+    if let Some(source) = sources.iter().find(|s| s.kind == last_played) {
+      if let Ok(current) = source.source.poll_guarded() {
+        self.observe_listening(&current);
+      }
+    }
+  }
+
+  fn push_pending(&self, event: MediaEvent) {
+    self.pending.push(event);
+  }
+
+  /// Has `source` notify the listener of its events, sources that can't get a thread
+  /// that waits on them and moves their events to the pending ones
+  fn watch(&self, source: &Arc<ListenerSource>) -> Result<()> {
+    if source.source.set_notifier(self.pending.notifier.clone()) {
+      return Ok(());
+    }
+
+    let name = format!("{}-events", source_name(&source.kind));
+    let source = source.clone();
+    let pending = self.pending.clone();
+
+    self.cfg.threads.spawn(&name, move || {
+      while !source.removed.load(Ordering::SeqCst) {
+        match source.source.next() {
+          Ok(event) => pending.push(event),
+          Err(err) if err.is_recoverable() => {}
+          // closed, nothing more will come
+          Err(_) if source.source.is_closed() => break,
+          // so a source that keeps failing right away doesn't spin
+          Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+      }
+
+      pending.notifier.notify();
+    })?;
+
+    Ok(())
+  }
+}
+
+impl MediaSource for MediaListener {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    cfg.validate()?;
+
+    let mut builder = Self::builder().with_config(cfg.clone());
+
+    if cfg.system_enabled {
+      builder = builder.with_system(cfg.clone());
+    }
+
+    if cfg.websocket_enabled {
+      builder = builder.with_websocket(cfg);
+    }
+
+    builder.build()
+  }
+
+  fn is_closed(&self) -> bool {
+    self.state.is_closed()
+  }
+
+  fn is_running(&self) -> bool {
+    self.state.is_running()
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.state.poll()
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    self.state.poll_guarded()
+  }
+
+  fn is_stale(&self) -> bool {
+    self.state.is_stale()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    self.state.next()
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.state.drain()
+  }
+
+  /// Sends `command` to the source the listener currently gets media from
+  fn control(&self, command: MediaCommand) -> Result<()> {
+    self.state.control(command)
+  }
+}
+
+impl ListenerState {
+  /// With no sources left it isn't closed, more can be added
+  fn is_closed(&self) -> bool {
+    let sources = self.sources();
+    !sources.is_empty() && sources.iter().all(|s| s.source.is_closed())
+  }
+
+  fn is_running(&self) -> bool {
+    self.sources().iter().any(|s| s.source.is_running())
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    let sources = self.sources();
+    self.check_stale(&sources);
+
+    self.cfg.metrics.time_poll(|| {
+      // sources that fail are left out, so one broken source doesn't take the others down with it
+      let (sources, mut polled) = polled(&sources, |s| s.poll())?;
+
+      let states = polled.iter().map(|m| m.state).collect::<Vec<_>>();
+      let active = self.select(&sources, &states);
+      self.detect_conflict(&sources, &polled.iter().collect::<Vec<_>>());
+
+      let metadata = match self.cfg.hybrid && polled.len() > 1 {
+        true => self.merge(&sources, &polled.iter().collect::<Vec<_>>(), active),
+        false => polled.swap_remove(active),
+      };
+
+      self.persist(&metadata);
+      self.observe_listening(&metadata);
+
+      Ok(self.restored(&metadata).unwrap_or(metadata))
+    })
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    let sources = self.sources();
+    self.check_stale(&sources);
+
+    self.cfg.metrics.time_poll(|| {
+      let (sources, guards) = polled(&sources, |s| s.poll_guarded())?;
+
+      let polled = guards.iter().map(|m| &**m).collect::<Vec<_>>();
+      let states = polled.iter().map(|m| m.state).collect::<Vec<_>>();
+      let active = self.select(&sources, &states);
+      self.detect_conflict(&sources, &polled);
+
+      let merged = match self.cfg.hybrid && polled.len() > 1 {
+        true => Some(self.merge(&sources, &polled, active)),
+        false => None,
+      };
+
+      let current = merged.as_ref().unwrap_or(polled[active]);
+      self.persist(current);
+      self.observe_listening(current);
+
+      let restored = self.restored(current);
+      let mut stored = self.stored.write().unwrap();
+
+      // compared first so media that didn't change, cover included, isn't copied on every poll
+      match restored.or(merged) {
+        Some(metadata) => *stored = metadata,
+        None if *stored != *polled[active] => *stored = polled[active].clone(),
+        None => {}
+      }
+
+      drop(stored);
+      drop(guards);
+
+      Ok(self.stored.read().unwrap())
+    })
+  }
+
+  fn is_stale(&self) -> bool {
+    self.restored.lock().unwrap().is_some() || self.sources().iter().all(|s| s.source.is_stale())
+  }
+
+  /// Waits on every source at once, the first event any of them has is handed out
+  fn next(&self) -> Result<MediaEvent> {
+    self.observe_last_played();
+
+    let deadline = Instant::now() + self.cfg.timeout;
+
+    let result = loop {
+      // read first so an event that comes in while checking still wakes the wait below
+      let seen = self.pending.notifier.generation();
+
+      if let Some(event) = self.pending.pop() {
+        break Ok(event);
+      }
+
+      let sources = self.sources();
+
+      if sources.is_empty() {
+        break Err(Error::NotEnabled);
+      }
+
+      let events = sources
+        .iter()
+        .flat_map(|s| s.source.drain())
+        .collect::<Vec<_>>();
+
+      if !events.is_empty() {
+        self.pending.queue.lock().unwrap().extend(events);
+        continue;
+      }
+
+      if sources.iter().all(|s| s.source.is_closed()) {
+        break Err(Error::Closed);
+      }
+
+      let now = Instant::now();
+
+      if now >= deadline {
+        break Err(Error::Timeout(RecvTimeoutError::Timeout));
+      }
+
+      self.pending.notifier.wait(seen, deadline - now);
+    };
+
+    // so pausing or changing tracks is counted when it happens rather than on the next poll
+    self.observe_last_played();
+
+    result
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    let mut events = Vec::from(std::mem::take(&mut *self.pending.queue.lock().unwrap()));
+
+    for source in self.sources() {
+      events.extend(source.source.drain());
+    }
+
+    events
+  }
+
+  /// Sends `command` to [MediaListener::control_source] if set, otherwise to whichever source
+  /// [MediaListener::poll] would report right now
+  fn control(&self, command: MediaCommand) -> Result<()> {
+    let sources = self.sources();
+    let kind = self.control_source.read().unwrap().clone();
+
+    let source = match kind {
+      Some(kind) => sources.iter().find(|s| s.kind == kind),
+      None => sources.get(self.active(&sources)),
+    };
+
+    let Some(source) = source else {
+      return Err(Error::NotExist);
+    };
+
+    source.source.control(command)
+  }
+
+  /// Index of the source that is reported, picked the same way as when polling,
+  /// sources that can't be polled count as stopped
+  fn active(&self, sources: &[Arc<ListenerSource>]) -> usize {
+    let states = sources
+      .iter()
+      .map(|s| s.source.poll_guarded().map_or(MediaState::Stopped, |m| m.state))
+      .collect::<Vec<_>>();
+
+    self.select(sources, &states)
+  }
+}
+
+impl Drop for ListenerState {
+  fn drop(&mut self) {
+    for source in self.sources() {
+      source.removed.store(true, Ordering::SeqCst);
+    }
+
+    // saved again so the elapsed is current, while playing it's only saved on track changes
+    if let (Some(path), Ok(metadata)) = (&self.cfg.persist_path, self.poll()) {
+      if !metadata.title.is_empty() {
+        let active = self.last_played.read().unwrap().clone();
+        let _ = PersistedState::new(active, metadata).save(path);
+      }
+    }
+  }
+}
+
+pub trait MediaSource: Send + Sync + Sized {
+  fn create(cfg: MediaSourceConfig) -> Result<Self>;
+
+  fn is_closed(&self) -> bool;
+
+  fn is_running(&self) -> bool;
+
+  fn poll(&self) -> Result<MediaMetadata>;
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>>;
+
+  /// Returns true if the source hasn't produced an update within
+  /// [MediaSourceConfig::stale_after], sources that can't go stale can leave this as is
+  fn is_stale(&self) -> bool {
+    false
+  }
+
+  /// Message of the last error the source's background task ran into, even if it recovered since
+  fn last_error(&self) -> Option<String> {
+    None
+  }
+
+  fn next(&self) -> Result<MediaEvent>;
+
+  /// Every event that is already queued, without waiting for more,
+  /// for apps that handle everything that happened since their last frame at once
+  ///
+  /// Sources that can't tell without blocking leave this as is and return nothing
+  fn drain(&self) -> Vec<MediaEvent> {
+    Vec::new()
+  }
+
+  /// Has the source call [EventNotifier::notify] whenever an event is queued or it closes,
+  /// so [MediaListener::next] can wait on every source at once, returns false if it can't
+  ///
+  /// Sources that leave this as is get a thread that waits on [MediaSource::next] for them
+  fn set_notifier(&self, _notifier: Arc<EventNotifier>) -> bool {
+    false
+  }
+
+  /// Asks the player to do something, sources that can't control playback leave this as is
+  /// and fail with [Error::Unsupported]
+  fn control(&self, _command: MediaCommand) -> Result<()> {
+    Err(Error::Unsupported)
+  }
+
+  /// Address the source accepts connections on, sources that don't leave this as is
+  fn local_addr(&self) -> Option<SocketAddr> {
+    None
+  }
+}
+
+/// Stands in for a source that was compiled out, [MediaSource::create] always fails with [Error::NotEnabled]
+#[derive(Debug)]
+pub struct DisabledMediaSource(Infallible);
+
+impl MediaSource for DisabledMediaSource {
+  fn create(_: MediaSourceConfig) -> Result<Self> {
+    Err(Error::NotEnabled)
+  }
+
+  fn is_closed(&self) -> bool {
+    match self.0 {}
+  }
+
+  fn is_running(&self) -> bool {
+    match self.0 {}
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    match self.0 {}
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    match self.0 {}
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    match self.0 {}
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    match self.0 {}
+  }
+}
+
+/// Sources rarely agree on ids, so media counts as the same when the titles match
+/// and the artists, album and duration don't disagree where both sources report them
+fn same_media(a: &MediaMetadata, b: &MediaMetadata) -> bool {
+  fn normalize(s: &str) -> String {
+    s.trim().to_lowercase()
+  }
+
+  let title = normalize(&a.title);
+  if title.is_empty() || title != normalize(&b.title) {
+    return false;
+  }
+
+  let shares_artist = |x: &String| b.artists.iter().any(|y| normalize(x) == normalize(y));
+  if !a.artists.is_empty() && !b.artists.is_empty() && !a.artists.iter().any(shares_artist) {
+    return false;
+  }
+
+  if let (Some(x), Some(y)) = (&a.album, &b.album) {
+    if !x.trim().is_empty() && !y.trim().is_empty() && normalize(x) != normalize(y) {
+      return false;
+    }
+  }
+
+  // players round durations differently, so they only have to be within a couple of seconds
+  a.duration.is_zero()
+    || b.duration.is_zero()
+    || a.duration.abs_diff(b.duration) <= Duration::from_secs(2)
 }
@@ -0,0 +1,588 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLockReadGuard};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::http_client::{self, parse_response};
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::polled::PolledSource;
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result};
+
+/// Port Plex Media Server listens on unless changed
+pub const PLEX_DEFAULT_PORT: u16 = 32400;
+
+/// Port Jellyfin listens on unless changed
+pub const JELLYFIN_DEFAULT_PORT: u16 = 8096;
+
+#[derive(thiserror::Error, Debug)]
+pub enum MediaServerError {
+  /// The token or api key is missing, wrong or was revoked
+  #[error("unauthorized, check the token")]
+  Unauthorized,
+  #[error("unexpected status {0}: {1}")]
+  Status(u16, String),
+  #[error("malformed http response")]
+  MalformedResponse,
+}
+
+impl From<MediaServerError> for Error {
+  fn from(value: MediaServerError) -> Self {
+    match value {
+      MediaServerError::MalformedResponse => Self::Protocol(value.into()),
+      _ => Self::Connection(value.into()),
+    }
+  }
+}
+
+/// GET request to a media server on the local network, there's no tls
+fn get<T: DeserializeOwned>(addr: &SocketAddr, path: &str, headers: &[(&str, &str)]) -> Result<T> {
+  let response = http_client::request(addr, "GET", path, headers, None, Duration::from_secs(5))?;
+  let (status, body) = parse_response(&response).ok_or(MediaServerError::MalformedResponse)?;
+
+  match status {
+    200..=299 => serde_json::from_slice(&body).map_err(|err| Error::Protocol(err.into())),
+    401 | 403 => Err(MediaServerError::Unauthorized.into()),
+    _ => Err(MediaServerError::Status(status, String::from_utf8_lossy(&body).into_owned()).into()),
+  }
+}
+
+/// Where to find a [Plex Media Server](https://plex.tv) and whose playback to report
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PlexConfig {
+  pub addr: SocketAddr,
+  /// `X-Plex-Token` of an account on the server, only the server owner sees every session
+  ///
+  /// Cover urls carry the token as well, since the server doesn't hand out artwork without it
+  pub token: String,
+  /// Only reports sessions of this Plex user, [None] reports anyone's
+  pub user: Option<String>,
+  /// How often the sessions are fetched
+  pub poll_interval: Duration,
+}
+
+impl Default for PlexConfig {
+  fn default() -> Self {
+    Self {
+      addr: SocketAddr::from(([127, 0, 0, 1], PLEX_DEFAULT_PORT)),
+      token: String::new(),
+      user: None,
+      poll_interval: Duration::from_millis(2000),
+    }
+  }
+}
+
+impl PlexConfig {
+  pub fn new(token: impl Into<String>) -> Self {
+    Self {
+      token: token.into(),
+      ..Self::default()
+    }
+  }
+
+  pub fn set_addr(self, addr: SocketAddr) -> Self {
+    Self { addr, ..self }
+  }
+
+  pub fn set_user(self, user: impl Into<String>) -> Self {
+    Self {
+      user: Some(user.into()),
+      ..self
+    }
+  }
+
+  pub fn set_poll_interval(self, poll_interval: Duration) -> Self {
+    Self {
+      poll_interval,
+      ..self
+    }
+  }
+
+  fn fetch(&self) -> Result<MediaMetadata> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Sessions {
+      media_container: PlexContainer,
+    }
+
+    let Sessions { media_container } = get(
+      &self.addr,
+      "/status/sessions",
+      &[("X-Plex-Token", &self.token)],
+    )?;
+
+    let sessions = media_container.metadata.into_iter().filter(|session| {
+      self.user.is_none() || session.user.as_ref().map(|user| &user.title) == self.user.as_ref()
+    });
+
+    let Some(session) = pick_session(sessions, PlexSession::state) else {
+      return Ok(MediaMetadata::default());
+    };
+
+    Ok(session.into_metadata(self))
+  }
+
+  fn art_url(&self, path: Option<&str>) -> Option<String> {
+    let path = path.filter(|path| !path.is_empty())?;
+
+    Some(format!(
+      "http://{}{path}?X-Plex-Token={}",
+      self.addr, self.token
+    ))
+  }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct PlexContainer {
+  metadata: Vec<PlexSession>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PlexSession {
+  rating_key: Option<String>,
+  key: Option<String>,
+  /// `track`, `episode`, `movie` or `clip`
+  #[serde(rename = "type")]
+  kind: String,
+  title: String,
+  /// Album of a track, season of an episode
+  parent_title: Option<String>,
+  /// Artist of a track, show of an episode
+  grandparent_title: Option<String>,
+  /// Artist of the track itself when it differs from the album artist
+  original_title: Option<String>,
+  /// Milliseconds
+  duration: u64,
+  /// Milliseconds
+  view_offset: u64,
+  thumb: Option<String>,
+  parent_thumb: Option<String>,
+  art: Option<String>,
+  #[serde(rename = "User")]
+  user: Option<PlexUser>,
+  #[serde(rename = "Player")]
+  player: Option<PlexPlayer>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PlexUser {
+  title: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PlexPlayer {
+  /// `playing`, `paused` or `buffering`
+  state: String,
+  title: String,
+  product: Option<String>,
+}
+
+impl PlexSession {
+  fn state(&self) -> MediaState {
+    match self.player.as_ref().map(|player| player.state.as_str()) {
+      Some("paused") => MediaState::Paused,
+      // buffering is only ever a short pause in between playing
+      Some("playing" | "buffering") => MediaState::Playing,
+      _ => MediaState::Stopped,
+    }
+  }
+
+  fn into_metadata(self, plex: &PlexConfig) -> MediaMetadata {
+    let state = self.state();
+    let is_track = self.kind == "track";
+
+    let mut extra = BTreeMap::new();
+    extra.insert("media_type".into(), self.kind.into());
+
+    if let Some(user) = self.user {
+      extra.insert("user".into(), user.title.into());
+    }
+
+    if let Some(player) = &self.player {
+      extra.insert("device".into(), player.title.clone().into());
+    }
+
+    let zone = self
+      .player
+      .as_ref()
+      .map(|player| player.title.clone())
+      .filter(|title| !title.is_empty());
+
+    // tracks credit their own artist before the album artist, episodes their show
+    let artist = match is_track {
+      true => self.original_title.or(self.grandparent_title),
+      false => self.grandparent_title,
+    };
+
+    MediaMetadata {
+      uid: self.rating_key,
+      uri: self.key,
+      state,
+      duration: Duration::from_millis(self.duration),
+      elapsed: Duration::from_millis(self.view_offset),
+      title: self.title,
+      album: self.parent_title.filter(|_| is_track),
+      artists: artist.into_iter().collect(),
+      featured_artists: Vec::new(),
+      cover_url: plex.art_url(self.thumb.as_deref().or(self.parent_thumb.as_deref())),
+      cover: None,
+      background_url: plex.art_url(self.art.as_deref()),
+      background: None,
+      app: Some(
+        self
+          .player
+          .and_then(|player| player.product)
+          .unwrap_or_else(|| "Plex".into()),
+      ),
+      zone,
+      extra,
+      queue: Vec::new(),
+    }
+  }
+}
+
+/// Where to find a [Jellyfin](https://jellyfin.org) server and whose playback to report
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct JellyfinConfig {
+  pub addr: SocketAddr,
+  /// Api key from the dashboard, or the access token of a user
+  pub api_key: String,
+  /// Only reports sessions of this user, by name or id, [None] reports anyone's
+  pub user: Option<String>,
+  /// How often the sessions are fetched
+  pub poll_interval: Duration,
+}
+
+impl Default for JellyfinConfig {
+  fn default() -> Self {
+    Self {
+      addr: SocketAddr::from(([127, 0, 0, 1], JELLYFIN_DEFAULT_PORT)),
+      api_key: String::new(),
+      user: None,
+      poll_interval: Duration::from_millis(2000),
+    }
+  }
+}
+
+impl JellyfinConfig {
+  pub fn new(api_key: impl Into<String>) -> Self {
+    Self {
+      api_key: api_key.into(),
+      ..Self::default()
+    }
+  }
+
+  pub fn set_addr(self, addr: SocketAddr) -> Self {
+    Self { addr, ..self }
+  }
+
+  pub fn set_user(self, user: impl Into<String>) -> Self {
+    Self {
+      user: Some(user.into()),
+      ..self
+    }
+  }
+
+  pub fn set_poll_interval(self, poll_interval: Duration) -> Self {
+    Self {
+      poll_interval,
+      ..self
+    }
+  }
+
+  fn fetch(&self) -> Result<MediaMetadata> {
+    let authorization = format!("MediaBrowser Token=\"{}\"", self.api_key);
+
+    let sessions: Vec<JellyfinSession> = get(
+      &self.addr,
+      "/Sessions?ActiveWithinSeconds=960",
+      &[("Authorization", &authorization)],
+    )?;
+
+    let sessions = sessions.into_iter().filter(|session| {
+      session.now_playing_item.is_some()
+        && self.user.as_ref().is_none_or(|user| {
+          session.user_name.as_ref() == Some(user) || session.user_id.as_ref() == Some(user)
+        })
+    });
+
+    let Some(session) = pick_session(sessions, JellyfinSession::state) else {
+      return Ok(MediaMetadata::default());
+    };
+
+    Ok(session.into_metadata(self))
+  }
+
+  fn image_url(&self, item_id: &str, kind: &str, tag: &str) -> String {
+    format!(
+      "http://{}/Items/{item_id}/Images/{kind}?tag={tag}",
+      self.addr
+    )
+  }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct JellyfinSession {
+  user_id: Option<String>,
+  user_name: Option<String>,
+  client: Option<String>,
+  device_name: Option<String>,
+  now_playing_item: Option<JellyfinItem>,
+  play_state: JellyfinPlayState,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct JellyfinPlayState {
+  /// 100 nanosecond ticks
+  position_ticks: u64,
+  is_paused: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct JellyfinItem {
+  id: String,
+  name: String,
+  /// `Audio`, `Episode`, `Movie`, `MusicVideo` and so on
+  #[serde(rename = "Type")]
+  kind: String,
+  album: Option<String>,
+  album_id: Option<String>,
+  album_primary_image_tag: Option<String>,
+  artists: Vec<String>,
+  album_artist: Option<String>,
+  series_name: Option<String>,
+  /// 100 nanosecond ticks
+  run_time_ticks: u64,
+  image_tags: BTreeMap<String, String>,
+  backdrop_image_tags: Vec<String>,
+}
+
+/// Jellyfin counts time in 100 nanosecond ticks
+fn from_ticks(ticks: u64) -> Duration {
+  Duration::from_nanos(ticks.saturating_mul(100))
+}
+
+impl JellyfinSession {
+  fn state(&self) -> MediaState {
+    match self.play_state.is_paused {
+      true => MediaState::Paused,
+      false => MediaState::Playing,
+    }
+  }
+
+  fn into_metadata(self, jellyfin: &JellyfinConfig) -> MediaMetadata {
+    let state = self.state();
+    let item = self.now_playing_item.unwrap_or_default();
+
+    let mut extra = BTreeMap::new();
+    extra.insert("media_type".into(), item.kind.into());
+
+    let zone = self.device_name.clone();
+
+    for (key, value) in [("user", self.user_name), ("device", self.device_name)] {
+      if let Some(value) = value {
+        extra.insert(key.into(), value.into());
+      }
+    }
+
+    let artists = match (item.artists.is_empty(), item.album_artist, item.series_name) {
+      (false, ..) => item.artists,
+      (true, Some(artist), _) | (true, None, Some(artist)) => vec![artist],
+      (true, None, None) => Vec::new(),
+    };
+
+    let cover_url = match (item.image_tags.get("Primary"), item.album_id) {
+      (Some(tag), _) => Some(jellyfin.image_url(&item.id, "Primary", tag)),
+      (None, Some(album_id)) => item
+        .album_primary_image_tag
+        .map(|tag| jellyfin.image_url(&album_id, "Primary", &tag)),
+      (None, None) => None,
+    };
+
+    let background_url = item
+      .backdrop_image_tags
+      .first()
+      .map(|tag| jellyfin.image_url(&item.id, "Backdrop", tag));
+
+    MediaMetadata {
+      uid: Some(item.id).filter(|id| !id.is_empty()),
+      uri: None,
+      state,
+      duration: from_ticks(item.run_time_ticks),
+      elapsed: from_ticks(self.play_state.position_ticks),
+      title: item.name,
+      album: item.album,
+      artists,
+      featured_artists: Vec::new(),
+      cover_url,
+      cover: None,
+      background_url,
+      background: None,
+      app: Some(self.client.unwrap_or_else(|| "Jellyfin".into())),
+      zone,
+      extra,
+      queue: Vec::new(),
+    }
+  }
+}
+
+/// The first session that is playing, otherwise the first paused one
+fn pick_session<T>(
+  sessions: impl Iterator<Item = T>,
+  state: impl Fn(&T) -> MediaState,
+) -> Option<T> {
+  let mut paused = None;
+
+  for session in sessions {
+    match state(&session) {
+      MediaState::Playing => return Some(session),
+      _ if paused.is_none() => paused = Some(session),
+      _ => {}
+    }
+  }
+
+  paused
+}
+
+/// Source for the sessions of a [Plex Media Server](https://plex.tv), for music and video
+/// played from the server on devices the local system apis never see
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_plex(PlexConfig::new(token).set_user("me"));
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(PlexMediaSource::NAME, PlexMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct PlexMediaSource(PolledSource);
+
+impl PlexMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "plex";
+}
+
+impl MediaSource for PlexMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(plex) = cfg.plex.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let poll_interval = plex.poll_interval;
+
+    PolledSource::create(cfg, Self::NAME, poll_interval, move || plex.fetch()).map(Self)
+  }
+
+  fn is_closed(&self) -> bool {
+    self.0.is_closed()
+  }
+
+  fn is_running(&self) -> bool {
+    self.0.is_running()
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    self.0.poll_guarded()
+  }
+
+  fn is_stale(&self) -> bool {
+    self.0.is_stale()
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.0.last_error()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    self.0.next()
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.0.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.0.set_notifier(notifier)
+  }
+}
+
+/// Source for the sessions of a [Jellyfin](https://jellyfin.org) server, for music and video
+/// played from the server on devices the local system apis never see
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_jellyfin(JellyfinConfig::new(api_key).set_user("me"));
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(JellyfinMediaSource::NAME, JellyfinMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct JellyfinMediaSource(PolledSource);
+
+impl JellyfinMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "jellyfin";
+}
+
+impl MediaSource for JellyfinMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(jellyfin) = cfg.jellyfin.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let poll_interval = jellyfin.poll_interval;
+
+    PolledSource::create(cfg, Self::NAME, poll_interval, move || jellyfin.fetch()).map(Self)
+  }
+
+  fn is_closed(&self) -> bool {
+    self.0.is_closed()
+  }
+
+  fn is_running(&self) -> bool {
+    self.0.is_running()
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    self.0.poll_guarded()
+  }
+
+  fn is_stale(&self) -> bool {
+    self.0.is_stale()
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.0.last_error()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    self.0.next()
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.0.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.0.set_notifier(notifier)
+  }
+}
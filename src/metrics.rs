@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{MediaEvent, SourceKind};
+
+/// Names of the event types in [EventKind](crate::EventKind) bit order, used as metric labels
+const EVENT_KIND_NAMES: [&str; 17] = [
+  "media_changed",
+  "state_changed",
+  "progress_changed",
+  "queue_changed",
+  "track_ended",
+  "active_source_changed",
+  "client_connected",
+  "client_disconnected",
+  "source_stale",
+  "source_gave_up",
+  "source_error",
+  "throttled",
+  "conflict_detected",
+  "source_added",
+  "source_removed",
+  "listen_threshold_reached",
+  "control_requested",
+];
+
+/// Counters and gauges kept by the listener and its sources,
+/// shared through [MediaSourceConfig::metrics](crate::listener::MediaSourceConfig::metrics)
+///
+/// ```rs
+/// let listener = MediaListener::create(cfg)?;
+///
+/// // serve this from your own http server, or scrape `GET /metrics` on the websocket port
+/// let text = listener.metrics().snapshot().to_prometheus();
+/// ```
+#[derive(Debug, Default)]
+pub struct Metrics {
+  events: [AtomicU64; EVENT_KIND_NAMES.len()],
+  /// Events each source dropped because its channel was full, by [source_name]
+  dropped: Mutex<BTreeMap<String, [u64; EVENT_KIND_NAMES.len()]>>,
+  reconnects: AtomicU64,
+  websocket_clients: AtomicU64,
+  /// [source_name] of the source the listener reports
+  active_source: Mutex<Option<String>>,
+  poll: Timing,
+  cover_fetch: Timing,
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+  count: AtomicU64,
+  total_nanos: AtomicU64,
+}
+
+impl Timing {
+  fn record(&self, duration: Duration) {
+    self.count.fetch_add(1, Ordering::Relaxed);
+    self
+      .total_nanos
+      .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+  }
+
+  fn total(&self) -> Duration {
+    Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed))
+  }
+}
+
+impl Metrics {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Copies the current values
+  pub fn snapshot(&self) -> MetricsSnapshot {
+    let events = EVENT_KIND_NAMES
+      .iter()
+      .zip(&self.events)
+      .map(|(name, count)| (name.to_string(), count.load(Ordering::Relaxed)))
+      .collect();
+
+    let dropped = self
+      .dropped
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(source, counts)| {
+        let counts = EVENT_KIND_NAMES
+          .iter()
+          .zip(counts)
+          .filter(|(_, count)| **count > 0)
+          .map(|(name, count)| (name.to_string(), *count))
+          .collect();
+
+        (source.clone(), counts)
+      })
+      .collect();
+
+    MetricsSnapshot {
+      events,
+      dropped,
+      reconnects: self.reconnects.load(Ordering::Relaxed),
+      websocket_clients: self.websocket_clients.load(Ordering::Relaxed),
+      active_source: self.active_source.lock().unwrap().clone(),
+      polls: self.poll.count.load(Ordering::Relaxed),
+      poll_time: self.poll.total(),
+      cover_fetches: self.cover_fetch.count.load(Ordering::Relaxed),
+      cover_fetch_time: self.cover_fetch.total(),
+    }
+  }
+
+  pub(crate) fn event_emitted(&self, event: &MediaEvent) {
+    let index = event.kind().bits().trailing_zeros() as usize;
+
+    if let Some(count) = self.events.get(index) {
+      count.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  /// `event` didn't fit in the channel of `source` and was dropped,
+  /// returns how many events `source` dropped so far
+  #[cfg_attr(not(any(feature = "system", feature = "ws")), allow(dead_code))]
+  pub(crate) fn event_dropped(&self, source: &SourceKind, event: &MediaEvent) -> u64 {
+    let index = event.kind().bits().trailing_zeros() as usize;
+    let mut dropped = self.dropped.lock().unwrap();
+    let counts = dropped.entry(source_name(source).into()).or_default();
+
+    if let Some(count) = counts.get_mut(index) {
+      *count += 1;
+    }
+
+    counts.iter().sum()
+  }
+
+  /// How many events `source` dropped so far
+  pub(crate) fn dropped_events(&self, source: &SourceKind) -> u64 {
+    let dropped = self.dropped.lock().unwrap();
+
+    dropped
+      .get(source_name(source))
+      .map_or(0, |counts| counts.iter().sum())
+  }
+
+  /// A source lost its connection (D-Bus, GSMTC or the websocket listener) and is setting it up again
+  #[cfg_attr(not(any(feature = "system", feature = "ws")), allow(dead_code))]
+  pub(crate) fn reconnected(&self) {
+    self.reconnects.fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[cfg_attr(not(feature = "ws"), allow(dead_code))]
+  pub(crate) fn client_connected(&self) {
+    self.websocket_clients.fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[cfg_attr(not(feature = "ws"), allow(dead_code))]
+  pub(crate) fn client_disconnected(&self) {
+    let _ = self
+      .websocket_clients
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |clients| {
+        clients.checked_sub(1)
+      });
+  }
+
+  /// The listener started reporting the media of `source`
+  pub(crate) fn source_activated(&self, source: &SourceKind) {
+    *self.active_source.lock().unwrap() = Some(source_name(source).into());
+  }
+
+  pub(crate) fn time_poll<T>(&self, poll: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = poll();
+    self.poll.record(started.elapsed());
+    result
+  }
+
+  #[cfg_attr(not(all(windows, feature = "system")), allow(dead_code))]
+  pub(crate) fn record_cover_fetch(&self, duration: Duration) {
+    self.cover_fetch.record(duration);
+  }
+}
+
+/// Values of [Metrics] at one point in time
+#[serde_with::serde_as]
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+  /// Events delivered per type, keyed by the snake_case name of the event
+  pub events: BTreeMap<String, u64>,
+  /// Events dropped because a source produced them faster than they were consumed,
+  /// keyed by source (`system`, `websocket` or the custom source's name), then by event type
+  #[serde(default)]
+  pub dropped: BTreeMap<String, BTreeMap<String, u64>>,
+  pub reconnects: u64,
+  /// Currently connected websocket clients
+  pub websocket_clients: u64,
+  /// Source the listener reports, like in [MetricsSnapshot::dropped], [None] until it picked one
+  #[serde(default)]
+  pub active_source: Option<String>,
+  pub polls: u64,
+  /// Total time spent polling
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub poll_time: Duration,
+  pub cover_fetches: u64,
+  /// Total time spent fetching covers
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub cover_fetch_time: Duration,
+}
+
+impl MetricsSnapshot {
+  /// Renders the snapshot in the Prometheus text exposition format
+  pub fn to_prometheus(&self) -> String {
+    let mut out = String::new();
+
+    // writing to a string can't fail
+    let _ = self.write_prometheus(&mut out);
+
+    out
+  }
+
+  fn write_prometheus(&self, out: &mut String) -> std::fmt::Result {
+    writeln!(
+      out,
+      "# HELP currently_playing_events_total Events delivered per type"
+    )?;
+    writeln!(out, "# TYPE currently_playing_events_total counter")?;
+
+    for (kind, count) in &self.events {
+      writeln!(
+        out,
+        "currently_playing_events_total{{kind=\"{kind}\"}} {count}"
+      )?;
+    }
+
+    writeln!(
+      out,
+      "# HELP currently_playing_events_dropped_total Events dropped per source and type because they weren't consumed fast enough"
+    )?;
+    writeln!(out, "# TYPE currently_playing_events_dropped_total counter")?;
+
+    for (source, counts) in &self.dropped {
+      let source = label(source);
+
+      for (kind, count) in counts {
+        writeln!(
+          out,
+          "currently_playing_events_dropped_total{{source=\"{source}\",kind=\"{kind}\"}} {count}"
+        )?;
+      }
+    }
+
+    writeln!(
+      out,
+      "# HELP currently_playing_reconnects_total Times a source set up its connection again"
+    )?;
+    writeln!(out, "# TYPE currently_playing_reconnects_total counter")?;
+    writeln!(
+      out,
+      "currently_playing_reconnects_total {}",
+      self.reconnects
+    )?;
+
+    writeln!(
+      out,
+      "# HELP currently_playing_websocket_clients Connected websocket clients"
+    )?;
+    writeln!(out, "# TYPE currently_playing_websocket_clients gauge")?;
+    writeln!(
+      out,
+      "currently_playing_websocket_clients {}",
+      self.websocket_clients
+    )?;
+
+    writeln!(
+      out,
+      "# HELP currently_playing_active_source Source whose media the listener reports"
+    )?;
+    writeln!(out, "# TYPE currently_playing_active_source gauge")?;
+
+    if let Some(source) = &self.active_source {
+      writeln!(
+        out,
+        "currently_playing_active_source{{source=\"{}\"}} 1",
+        label(source)
+      )?;
+    }
+
+    write_summary(
+      out,
+      "poll_duration_seconds",
+      "Time spent polling",
+      self.polls,
+      self.poll_time,
+    )?;
+
+    write_summary(
+      out,
+      "cover_fetch_duration_seconds",
+      "Time spent fetching covers",
+      self.cover_fetches,
+      self.cover_fetch_time,
+    )
+  }
+}
+
+/// Label of `source` in [MetricsSnapshot::dropped]
+pub(crate) fn source_name(source: &SourceKind) -> &str {
+  source.name()
+}
+
+/// `value` escaped for a label value, custom source names can be anything
+fn label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+fn write_summary(
+  out: &mut String,
+  name: &str,
+  help: &str,
+  count: u64,
+  sum: Duration,
+) -> std::fmt::Result {
+  writeln!(out, "# HELP currently_playing_{name} {help}")?;
+  writeln!(out, "# TYPE currently_playing_{name} summary")?;
+  writeln!(out, "currently_playing_{name}_sum {}", sum.as_secs_f64())?;
+  writeln!(out, "currently_playing_{name}_count {count}")
+}
@@ -0,0 +1,244 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::sink::MediaSink;
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result};
+
+/// Where VRChat listens for OSC on the same machine
+pub const VRCHAT_ADDR: &str = "127.0.0.1:9000";
+
+/// Longest text VRChat's chatbox shows, anything longer is cut off
+pub const CHATBOX_MAX_CHARS: usize = 144;
+
+/// What an [OscSink] sends
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum OscOutput {
+  /// The formatted media as VRChat's `/chatbox/input`, shown right away without the notification sound
+  Chatbox { template: String },
+  /// The formatted media as the only argument of a message to `address`
+  Text { address: String, template: String },
+  /// A bundle with a message per field under `prefix`, like `{prefix}/title`,
+  /// with `artists` joined by `, `, `playing` as a bool and `elapsed` and `duration` as seconds
+  Fields { prefix: String },
+}
+
+/// Where an [OscSink] sends to and what
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct OscConfig {
+  /// `host:port` the packets are sent to
+  pub addr: String,
+  pub output: OscOutput,
+  /// Packets are sent at most this often, newer media waits and replaces what is still waiting
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub min_interval: Duration,
+}
+
+impl OscConfig {
+  /// VRChat's chatbox, at most every 2 seconds so it doesn't time the chatbox out for spam
+  pub fn vrchat_chatbox(template: impl Into<String>) -> Self {
+    Self {
+      addr: VRCHAT_ADDR.into(),
+      output: OscOutput::Chatbox {
+        template: template.into(),
+      },
+      min_interval: Duration::from_secs(2),
+    }
+  }
+}
+
+/// Sends the media over OSC when the track or the state changes, see [OscOutput]
+///
+/// ```rs
+/// let mut sink = OscSink::new(OscConfig::vrchat_chatbox("🎵 {artists} - {title}"))?;
+/// ```
+#[derive(Debug)]
+pub struct OscSink {
+  cfg: OscConfig,
+  socket: UdpSocket,
+  target: SocketAddr,
+  /// Media the last packet was made from, nothing is sent until it changes
+  queued: Option<MediaMetadata>,
+  /// Packet waiting for [OscConfig::min_interval] to pass
+  pending: Option<Vec<u8>>,
+  last_sent: Option<Instant>,
+}
+
+impl OscSink {
+  /// Fails if [OscConfig::addr] doesn't resolve
+  pub fn new(cfg: OscConfig) -> Result<Self> {
+    let target = cfg
+      .addr
+      .to_socket_addrs()?
+      .next()
+      .ok_or_else(|| Error::InvalidConfig(vec![format!("{} doesn't resolve", cfg.addr)]))?;
+
+    let local = match target {
+      SocketAddr::V4(_) => "0.0.0.0:0",
+      SocketAddr::V6(_) => "[::]:0",
+    };
+
+    Ok(Self {
+      socket: UdpSocket::bind(local)?,
+      target,
+      cfg,
+      queued: None,
+      pending: None,
+      last_sent: None,
+    })
+  }
+
+  fn changed(&self, media: &MediaMetadata) -> bool {
+    let Some(queued) = &self.queued else {
+      return true;
+    };
+
+    match &self.cfg.output {
+      OscOutput::Chatbox { template } | OscOutput::Text { template, .. } => {
+        text(queued, template) != text(media, template)
+      }
+      OscOutput::Fields { .. } => {
+        queued.title != media.title
+          || queued.artists != media.artists
+          || queued.album != media.album
+          || queued.state != media.state
+      }
+    }
+  }
+
+  fn packet(&self, media: &MediaMetadata) -> Vec<u8> {
+    match &self.cfg.output {
+      OscOutput::Chatbox { template } => {
+        let text = text(media, template)
+          .chars()
+          .take(CHATBOX_MAX_CHARS)
+          .collect();
+
+        message(
+          "/chatbox/input",
+          &[
+            OscArg::String(text),
+            OscArg::Bool(true),
+            OscArg::Bool(false),
+          ],
+        )
+      }
+      OscOutput::Text { address, template } => {
+        message(address, &[OscArg::String(text(media, template))])
+      }
+      OscOutput::Fields { prefix } => {
+        let prefix = prefix.trim_end_matches('/');
+        let field = |name: &str, arg: OscArg| message(&format!("{prefix}/{name}"), &[arg]);
+
+        bundle(&[
+          field("title", OscArg::String(media.title.clone())),
+          field("artists", OscArg::String(media.artists.join(", "))),
+          field(
+            "album",
+            OscArg::String(media.album.clone().unwrap_or_default()),
+          ),
+          field("state", OscArg::String(format!("{:?}", media.state))),
+          field("playing", OscArg::Bool(media.state == MediaState::Playing)),
+          field("elapsed", OscArg::Float(media.elapsed.as_secs_f32())),
+          field("duration", OscArg::Float(media.duration.as_secs_f32())),
+        ])
+      }
+    }
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    let ready = self
+      .last_sent
+      .is_none_or(|sent| sent.elapsed() >= self.cfg.min_interval);
+
+    if !ready {
+      return Ok(());
+    }
+
+    if let Some(packet) = self.pending.take() {
+      self.last_sent = Some(Instant::now());
+      self.socket.send_to(&packet, self.target)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl MediaSink for OscSink {
+  fn handle(&mut self, _event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    if self.changed(media) {
+      self.pending = Some(self.packet(media));
+      self.queued = Some(media.clone());
+    }
+
+    self.flush()
+  }
+
+  fn tick(&mut self) -> Result<()> {
+    self.flush()
+  }
+}
+
+/// The formatted media, empty when nothing is playing so the chatbox is cleared instead of showing ` - `
+fn text(media: &MediaMetadata, template: &str) -> String {
+  if media.title.is_empty() {
+    return String::new();
+  }
+
+  media.format(template).to_string()
+}
+
+enum OscArg {
+  String(String),
+  Float(f32),
+  Bool(bool),
+}
+
+/// Appends `bytes` with a nul and pads them to a multiple of 4, like OSC wants strings
+fn push_padded(buf: &mut Vec<u8>, bytes: &[u8]) {
+  buf.extend_from_slice(bytes);
+  buf.push(0);
+  buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+fn message(address: &str, args: &[OscArg]) -> Vec<u8> {
+  let mut tags = String::from(",");
+  let mut data = Vec::new();
+
+  for arg in args {
+    match arg {
+      OscArg::String(value) => {
+        tags.push('s');
+        // a nul would end the string early
+        push_padded(&mut data, value.replace('\0', "").as_bytes());
+      }
+      OscArg::Float(value) => {
+        tags.push('f');
+        data.extend_from_slice(&value.to_be_bytes());
+      }
+      OscArg::Bool(value) => tags.push(if *value { 'T' } else { 'F' }),
+    }
+  }
+
+  let mut buf = Vec::new();
+  push_padded(&mut buf, address.as_bytes());
+  push_padded(&mut buf, tags.as_bytes());
+  buf.extend(data);
+  buf
+}
+
+fn bundle(messages: &[Vec<u8>]) -> Vec<u8> {
+  let mut buf = Vec::new();
+  push_padded(&mut buf, b"#bundle");
+  // time tag 1 means right away
+  buf.extend_from_slice(&1u64.to_be_bytes());
+
+  for message in messages {
+    buf.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    buf.extend_from_slice(message);
+  }
+
+  buf
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, MediaMetadata, MediaState, Result, SourceKind};
+
+/// What [MediaListener](crate::listener::MediaListener) last played,
+/// saved to [MediaSourceConfig::persist_path](crate::listener::MediaSourceConfig::persist_path)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersistedState {
+  pub active: SourceKind,
+  pub metadata: MediaMetadata,
+}
+
+impl PersistedState {
+  /// Image data is left out, it would bloat the file and the urls are usually enough
+  pub fn new(active: SourceKind, metadata: MediaMetadata) -> Self {
+    Self {
+      active,
+      metadata: MediaMetadata {
+        cover: None,
+        background: None,
+        ..metadata
+      },
+    }
+  }
+
+  /// Reads the state back, the metadata is reported as [MediaState::Stopped]
+  /// since nothing is known to be playing yet
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let data = fs::read(path)?;
+
+    let mut state = serde_json::from_slice::<Self>(&data)
+      .map_err(|err| Error::Io(std::io::Error::new(ErrorKind::InvalidData, err)))?;
+
+    state.metadata.state = MediaState::Stopped;
+
+    Ok(state)
+  }
+
+  /// Writes to a temporary file first, so a crash mid-write doesn't leave a broken file behind
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+
+    let data = serde_json::to_vec(self)
+      .map_err(|err| Error::Io(std::io::Error::new(ErrorKind::InvalidData, err)))?;
+
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+  }
+}
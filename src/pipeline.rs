@@ -0,0 +1,1261 @@
+// only used by sources, which can all be compiled out
+#![cfg_attr(not(any(feature = "system", feature = "ws")), allow(dead_code))]
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::mem::{discriminant, Discriminant};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::identity::TrackIdentity;
+use crate::listener::{DroppedEvents, DroppedEventsHandler, EventNotifier, MediaSourceConfig};
+use crate::metrics::Metrics;
+use crate::redact::Redactor;
+use crate::{Error, EventKind, MediaEvent, MediaMetadata, MediaState, Result, SourceKind};
+
+/// How far into a track it has to be for a track change to count as the track having ended
+const TRACK_END_COMPLETION: f64 = 0.9;
+
+/// Runs one attempt of a source's background task, a panic in it becomes [Error::BackgroundPanic]
+/// so the task is restarted like after any other error instead of the source going quiet
+#[cfg_attr(
+  not(any(
+    feature = "system",
+    feature = "icy",
+    feature = "kodi",
+    feature = "ytmd",
+    feature = "media-server",
+    feature = "http-json",
+    feature = "plugins"
+  )),
+  allow(dead_code)
+)]
+pub(crate) fn catch_panic(
+  kind: &SourceKind,
+  metadata: &RwLock<MediaMetadata>,
+  task: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+  std::panic::catch_unwind(AssertUnwindSafe(task))
+    .unwrap_or_else(|payload| Err(panicked(kind, metadata, &*payload)))
+}
+
+/// [Error::BackgroundPanic] for a panic with `payload`, `metadata` is unpoisoned
+/// in case the task panicked while writing it, so polling keeps working
+pub(crate) fn panicked(
+  kind: &SourceKind,
+  metadata: &RwLock<MediaMetadata>,
+  payload: &(dyn Any + Send),
+) -> Error {
+  metadata.clear_poison();
+
+  let message = payload
+    .downcast_ref::<&str>()
+    .map(|message| message.to_string())
+    .or_else(|| payload.downcast_ref::<String>().cloned())
+    .unwrap_or_else(|| "no message".into());
+
+  Error::BackgroundPanic {
+    kind: kind.clone(),
+    message,
+  }
+}
+
+/// Detects when a track finished playing, shared by every source so they all agree on what "ended" means
+#[derive(Debug, Default)]
+pub(crate) struct TrackEndDetector {
+  ended: bool,
+}
+
+impl TrackEndDetector {
+  /// Checks if `event` means the track in `previous` has ended,
+  /// `previous` being the metadata before `event` got applied
+  pub(crate) fn detect(
+    &mut self,
+    previous: &MediaMetadata,
+    event: &MediaEvent,
+  ) -> Option<MediaEvent> {
+    match event {
+      MediaEvent::MediaChanged(_) => {
+        let ended = !self.ended && previous.progress_fraction() > TRACK_END_COMPLETION;
+
+        self.ended = false;
+
+        ended.then(|| MediaEvent::TrackEnded(previous.clone()))
+      }
+      MediaEvent::ProgressChanged(elapsed) => {
+        // seeking back (or a track on repeat) starts it over
+        if *elapsed < previous.elapsed {
+          self.ended = false;
+        }
+
+        if self.ended || previous.duration == Duration::ZERO || *elapsed < previous.duration {
+          return None;
+        }
+
+        self.ended = true;
+
+        Some(MediaEvent::TrackEnded(MediaMetadata {
+          elapsed: previous.duration,
+          ..previous.clone()
+        }))
+      }
+      _ => None,
+    }
+  }
+}
+
+/// How long the current track has actually been playing, pauses and seeks don't count,
+/// see [MediaSourceConfig::listen_threshold]
+#[derive(Debug, Default)]
+pub(crate) struct ListenTracker {
+  /// Track the time is for, without its covers
+  track: Option<MediaMetadata>,
+  /// Time played before [ListenTracker::playing_since]
+  listened: Duration,
+  playing_since: Option<Instant>,
+  reached: bool,
+}
+
+impl ListenTracker {
+  pub(crate) fn listened(&self) -> Duration {
+    self.listened
+      + self
+        .playing_since
+        .map_or(Duration::ZERO, |since| since.elapsed())
+  }
+
+  /// Counts the time since it was last called if it was playing then, starting over for a different track,
+  /// returns [MediaEvent::ListenThresholdReached] the first time `threshold` of the track has been listened to
+  pub(crate) fn observe(
+    &mut self,
+    current: &MediaMetadata,
+    identity: &dyn TrackIdentity,
+    threshold: Option<f64>,
+  ) -> Option<MediaEvent> {
+    let now = Instant::now();
+
+    if let Some(since) = self.playing_since.take() {
+      self.listened += now - since;
+    }
+
+    let same = self
+      .track
+      .as_ref()
+      .is_some_and(|track| !identity.is_different(track, current));
+
+    if !same {
+      *self = Self {
+        track: (!current.title.is_empty()).then(|| MediaMetadata {
+          cover: None,
+          background: None,
+          ..current.clone()
+        }),
+        ..Self::default()
+      };
+    }
+
+    let track = self.track.as_ref()?;
+
+    if current.state == MediaState::Playing {
+      self.playing_since = Some(now);
+    }
+
+    let threshold = threshold?;
+
+    if self.reached
+      || current.duration.is_zero()
+      || self.listened < current.duration.mul_f64(threshold)
+    {
+      return None;
+    }
+
+    self.reached = true;
+
+    Some(MediaEvent::ListenThresholdReached {
+      metadata: MediaMetadata {
+        state: current.state,
+        duration: current.duration,
+        elapsed: current.elapsed,
+        ..track.clone()
+      },
+      listened: self.listened,
+    })
+  }
+}
+
+/// How events of one type are coalesced before being delivered
+#[derive(Default, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum CoalescePolicy {
+  /// Deliver every event right away
+  #[default]
+  Immediate,
+  /// Hold the first event for the given window and only deliver the latest one received during it
+  Latest(Duration),
+  /// Deliver at most one event per window, anything received in between is dropped
+  Throttle(Duration),
+}
+
+/// Coalescing policies for each type of event,
+/// events that aren't listed here are always delivered immediately
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CoalesceConfig {
+  pub media_changed: CoalescePolicy,
+  pub state_changed: CoalescePolicy,
+  pub progress_changed: CoalescePolicy,
+  pub queue_changed: CoalescePolicy,
+  /// Fold state, progress and queue changes into a [MediaEvent::MediaChanged]
+  /// that is still being held instead of delivering them separately
+  pub merge_into_media_changed: bool,
+}
+
+impl CoalesceConfig {
+  fn policy(&self, event: &MediaEvent) -> CoalescePolicy {
+    match event {
+      MediaEvent::MediaChanged(_) => self.media_changed,
+      MediaEvent::StateChanged(_) => self.state_changed,
+      MediaEvent::ProgressChanged(_) => self.progress_changed,
+      MediaEvent::QueueChanged(_) => self.queue_changed,
+      _ => CoalescePolicy::Immediate,
+    }
+  }
+}
+
+/// Coalesces bursts of events according to [CoalesceConfig]
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+  cfg: CoalesceConfig,
+  /// Held events with when they are due, at most one per type
+  pending: Vec<(MediaEvent, Instant)>,
+  last_delivered: HashMap<Discriminant<MediaEvent>, Instant>,
+}
+
+impl Coalescer {
+  pub(crate) fn new(cfg: CoalesceConfig) -> Self {
+    Self {
+      cfg,
+      pending: Vec::new(),
+      last_delivered: HashMap::new(),
+    }
+  }
+
+  /// Takes in a new event, returning the events that should be delivered right now
+  pub(crate) fn push(&mut self, event: MediaEvent, now: Instant) -> Vec<MediaEvent> {
+    let kind = discriminant(&event);
+
+    if let MediaEvent::MediaChanged(_) = event {
+      // new media carries its own state, progress and queue
+      self.pending.retain(|(pending, _)| {
+        !matches!(
+          pending,
+          MediaEvent::StateChanged(_)
+            | MediaEvent::ProgressChanged(_)
+            | MediaEvent::QueueChanged(_)
+        )
+      });
+    }
+
+    if self.cfg.merge_into_media_changed && self.merge(&event) {
+      return Vec::new();
+    }
+
+    let mut ready = self.flush(now);
+
+    match self.cfg.policy(&event) {
+      CoalescePolicy::Immediate => ready.push(event),
+      CoalescePolicy::Latest(window) => {
+        match self
+          .pending
+          .iter_mut()
+          .find(|(pending, _)| discriminant(pending) == kind)
+        {
+          Some((pending, _)) => *pending = event,
+          None => self.pending.push((event, now + window)),
+        }
+      }
+      CoalescePolicy::Throttle(window) => {
+        let throttled = self
+          .last_delivered
+          .get(&kind)
+          .is_some_and(|last| now.duration_since(*last) < window);
+
+        if !throttled {
+          ready.push(event);
+        }
+      }
+    }
+
+    for event in &ready {
+      self.last_delivered.insert(discriminant(event), now);
+    }
+
+    ready
+  }
+
+  /// Returns the held events that are due
+  pub(crate) fn flush(&mut self, now: Instant) -> Vec<MediaEvent> {
+    let mut ready = Vec::new();
+
+    self.pending.retain(|(event, due)| {
+      let is_due = *due <= now;
+
+      if is_due {
+        ready.push(event.clone());
+      }
+
+      !is_due
+    });
+
+    for event in &ready {
+      self.last_delivered.insert(discriminant(event), now);
+    }
+
+    ready
+  }
+
+  /// When the next held event is due
+  #[cfg_attr(not(feature = "ws"), allow(dead_code))]
+  pub(crate) fn next_due(&self) -> Option<Instant> {
+    self.pending.iter().map(|(_, due)| *due).min()
+  }
+
+  /// Folds `event` into a held [MediaEvent::MediaChanged], returns false if there's none
+  fn merge(&mut self, event: &MediaEvent) -> bool {
+    let held = self
+      .pending
+      .iter_mut()
+      .find_map(|(pending, _)| match pending {
+        MediaEvent::MediaChanged(metadata) => Some(metadata),
+        _ => None,
+      });
+
+    let Some(metadata) = held else {
+      return false;
+    };
+
+    match event {
+      MediaEvent::StateChanged(state) => metadata.state = *state,
+      MediaEvent::ProgressChanged(elapsed) => metadata.elapsed = *elapsed,
+      MediaEvent::QueueChanged(queue) => metadata.queue = queue.clone(),
+      _ => return false,
+    }
+
+    true
+  }
+}
+
+/// Minimum change of the elapsed time before another [MediaEvent::ProgressChanged] is emitted,
+/// regardless of how often the source updates internally
+#[derive(Default, Debug, Copy, Clone, PartialOrd, PartialEq)]
+pub enum ProgressThreshold {
+  /// Emit every progress update
+  #[default]
+  None,
+  /// Emit once elapsed moved by at least this much
+  Elapsed(Duration),
+  /// Emit once elapsed moved by at least this percentage (0-100) of the duration
+  Percentage(f64),
+}
+
+/// Drops progress events that moved less than the [ProgressThreshold]
+#[derive(Debug)]
+pub(crate) struct ProgressFilter {
+  threshold: ProgressThreshold,
+  duration: Duration,
+  last_elapsed: Option<Duration>,
+}
+
+impl ProgressFilter {
+  pub(crate) fn new(threshold: ProgressThreshold) -> Self {
+    Self {
+      threshold,
+      duration: Duration::ZERO,
+      last_elapsed: None,
+    }
+  }
+
+  /// Returns if `event` should be passed on
+  pub(crate) fn filter(&mut self, event: &MediaEvent) -> bool {
+    match event {
+      MediaEvent::MediaChanged(metadata) => {
+        self.duration = metadata.duration;
+        self.last_elapsed = Some(metadata.elapsed);
+        true
+      }
+      MediaEvent::ProgressChanged(elapsed) => {
+        let min_delta = match self.threshold {
+          ProgressThreshold::None => Duration::ZERO,
+          ProgressThreshold::Elapsed(delta) => delta,
+          ProgressThreshold::Percentage(percentage) => {
+            self.duration.mul_f64(percentage.clamp(0.0, 100.0) / 100.0)
+          }
+        };
+
+        let passes = match self.last_elapsed {
+          Some(last) => elapsed.abs_diff(last) >= min_delta,
+          None => true,
+        };
+
+        if passes {
+          self.last_elapsed = Some(*elapsed);
+        }
+
+        passes
+      }
+      _ => true,
+    }
+  }
+}
+
+/// Drops [MediaEvent::MediaChanged] for the track that was just announced,
+/// see [MediaSourceConfig::dedup_window]
+#[derive(Debug)]
+pub(crate) struct DedupFilter {
+  window: Option<Duration>,
+  identity: Arc<dyn TrackIdentity>,
+  last: Option<(MediaMetadata, Instant)>,
+}
+
+impl DedupFilter {
+  pub(crate) fn new(cfg: &MediaSourceConfig) -> Self {
+    Self {
+      window: cfg.dedup_window,
+      identity: cfg.identity.clone(),
+      last: None,
+    }
+  }
+
+  /// Returns if `event` should be passed on
+  pub(crate) fn filter(&mut self, event: &MediaEvent, now: Instant) -> bool {
+    let (Some(window), MediaEvent::MediaChanged(metadata)) = (self.window, event) else {
+      return true;
+    };
+
+    let repeated = self.last.as_ref().is_some_and(|(last, announced)| {
+      now.duration_since(*announced) < window && !self.identity.is_different(last, metadata)
+    });
+
+    // the window starts at the first announcement, so a player repeating itself
+    // forever still gets through once per window
+    if !repeated {
+      self.last = Some((metadata.clone(), now));
+    }
+
+    !repeated
+  }
+}
+
+/// Smooths over the burst of events some players send when one track flows into the next,
+/// gapless or crossfaded, see [MediaSourceConfig::transition_window]
+///
+/// Near the end of a track a pause, stop or seek back is held for the window, and dropped
+/// if the next track starts in it, for the window after that the ended track going quiet,
+/// its late progress and a player flipping back to it are dropped
+///
+/// Every track that flows into the next gets exactly one [MediaEvent::TrackEnded],
+/// even when the burst kept the source from noticing it ended
+#[derive(Debug)]
+pub(crate) struct TransitionSmoother {
+  window: Option<Duration>,
+  identity: Arc<dyn TrackIdentity>,
+  /// Track that is playing without its covers, with its elapsed as of `updated`
+  current: Option<MediaMetadata>,
+  updated: Option<Instant>,
+  /// [MediaEvent::TrackEnded] was passed on for the current track
+  ended: bool,
+  /// Track that just ended and until when events about it are dropped
+  previous: Option<(MediaMetadata, Instant)>,
+  /// Events that might only be the player moving on, delivered once `due`
+  held: Vec<MediaEvent>,
+  due: Option<Instant>,
+}
+
+impl TransitionSmoother {
+  pub(crate) fn new(cfg: &MediaSourceConfig) -> Self {
+    Self {
+      window: cfg.transition_window,
+      identity: cfg.identity.clone(),
+      current: None,
+      updated: None,
+      ended: false,
+      previous: None,
+      held: Vec::new(),
+      due: None,
+    }
+  }
+
+  /// Takes in a new event, returning the events that should be passed on right now
+  pub(crate) fn push(&mut self, event: MediaEvent, now: Instant) -> Vec<MediaEvent> {
+    let Some(window) = self.window else {
+      return vec![event];
+    };
+
+    if self
+      .previous
+      .as_ref()
+      .is_some_and(|(_, until)| now >= *until)
+    {
+      self.previous = None;
+    }
+
+    let mut ready = self.flush(now);
+
+    match &event {
+      MediaEvent::MediaChanged(metadata) => {
+        // a player that announced the next track early going back to the one that ended
+        if let Some((previous, _)) = &self.previous {
+          if !self.identity.is_different(previous, metadata) {
+            return ready;
+          }
+        }
+
+        let ending = self.is_ending(now);
+        let ended = ending || !self.held.is_empty();
+
+        // the new track carries its own state and progress
+        self.held.clear();
+        self.due = None;
+
+        if let Some(current) = self.current.as_ref().filter(|_| ending && !self.ended) {
+          ready.push(MediaEvent::TrackEnded(MediaMetadata {
+            elapsed: current.duration,
+            ..current.clone()
+          }));
+        }
+
+        self.ended = false;
+        self.previous = match (ended, self.current.take()) {
+          (true, Some(current)) => Some((current, now + window)),
+          _ => None,
+        };
+
+        self.current = Some(MediaMetadata {
+          cover: None,
+          background: None,
+          ..metadata.clone()
+        });
+        self.updated = Some(now);
+      }
+      MediaEvent::TrackEnded(metadata) => {
+        let current = self
+          .current
+          .as_ref()
+          .is_some_and(|current| !self.identity.is_different(current, metadata));
+
+        // from progress that came in late, the track only just started
+        if current && !self.is_ending(now) {
+          return ready;
+        }
+
+        self.ended |= current;
+      }
+      MediaEvent::StateChanged(MediaState::Playing) => {
+        let paused = self
+          .held
+          .iter()
+          .any(|held| matches!(held, MediaEvent::StateChanged(_)));
+
+        // it only stopped for a moment, so it never stopped as far as anyone can tell
+        if paused {
+          self
+            .held
+            .retain(|held| !matches!(held, MediaEvent::StateChanged(_)));
+          return ready;
+        }
+
+        self.update(now, |current| current.state = MediaState::Playing);
+      }
+      MediaEvent::StateChanged(state) => {
+        if self.previous.is_some() || self.is_ending(now) {
+          self.hold(event, now + window);
+          return ready;
+        }
+
+        let state = *state;
+        self.update(now, |current| current.state = state);
+      }
+      MediaEvent::ProgressChanged(elapsed) => {
+        let expected = self.elapsed(now);
+
+        // progress of the ended track that came in after the new one
+        let late = self.previous.as_ref().is_some_and(|(previous, _)| {
+          *elapsed >= previous.duration.mul_f64(TRACK_END_COMPLETION)
+            && *elapsed > expected + window
+        });
+
+        if late {
+          return ready;
+        }
+
+        if self.is_ending(now) && *elapsed + window < expected {
+          self.hold(event, now + window);
+          return ready;
+        }
+
+        let elapsed = *elapsed;
+        self.update(now, |current| current.elapsed = elapsed);
+      }
+      _ => {}
+    }
+
+    ready.push(event);
+    ready
+  }
+
+  /// Returns the held events once they're due
+  pub(crate) fn flush(&mut self, now: Instant) -> Vec<MediaEvent> {
+    if self.due.is_none_or(|due| due > now) {
+      return Vec::new();
+    }
+
+    self.due = None;
+
+    let held = std::mem::take(&mut self.held);
+
+    for event in &held {
+      match event {
+        MediaEvent::StateChanged(state) => self.update(now, |current| current.state = *state),
+        MediaEvent::ProgressChanged(elapsed) => {
+          self.update(now, |current| current.elapsed = *elapsed)
+        }
+        _ => {}
+      }
+    }
+
+    held
+  }
+
+  /// When the held events are due
+  pub(crate) fn next_due(&self) -> Option<Instant> {
+    self.due
+  }
+
+  fn hold(&mut self, event: MediaEvent, due: Instant) {
+    self.held.push(event);
+    self.due.get_or_insert(due);
+  }
+
+  /// Moves the current track's elapsed up to `now` before applying `f`
+  fn update(&mut self, now: Instant, f: impl FnOnce(&mut MediaMetadata)) {
+    let elapsed = self.elapsed(now);
+
+    if let Some(current) = &mut self.current {
+      current.elapsed = elapsed;
+      f(current);
+      self.updated = Some(now);
+    }
+  }
+
+  /// Elapsed of the current track, counted on from the last update while it's playing
+  fn elapsed(&self, now: Instant) -> Duration {
+    let Some(current) = &self.current else {
+      return Duration::ZERO;
+    };
+
+    match (current.state, self.updated) {
+      (MediaState::Playing, Some(updated)) => current.elapsed + now.duration_since(updated),
+      _ => current.elapsed,
+    }
+  }
+
+  /// Whether the current track is close enough to its end for the next one to start,
+  /// the same as when [TrackEndDetector] counts a track as ended
+  fn is_ending(&self, now: Instant) -> bool {
+    self.current.as_ref().is_some_and(|current| {
+      current.duration > Duration::ZERO
+        && self.elapsed(now).as_secs_f64() / current.duration.as_secs_f64() > TRACK_END_COMPLETION
+    })
+  }
+}
+
+/// Most events a websocket client can send, enforced per connection as a token bucket
+///
+/// Excess [MediaEvent::ProgressChanged] events are coalesced, only the latest one is kept
+/// until the client is allowed to send again, any other excess event is dropped
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+pub struct RateLimit {
+  /// Events per second allowed on average
+  pub per_second: f64,
+  /// Events allowed in a row before the average kicks in
+  pub burst: u32,
+}
+
+impl Default for RateLimit {
+  fn default() -> Self {
+    Self {
+      per_second: 50.0,
+      burst: 100,
+    }
+  }
+}
+
+impl RateLimit {
+  pub fn new(per_second: f64, burst: u32) -> Self {
+    Self { per_second, burst }
+  }
+}
+
+/// When the system source asks the platform for updates
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PollMode {
+  /// A background thread keeps the metadata current and sends events as things change
+  #[default]
+  Background,
+  /// The platform is only asked when the source is polled and the metadata is older than `min_refresh`,
+  /// the background thread sleeps in between, so events are only sent around polls
+  /// and [MediaSourceConfig::stale_after] has no effect
+  OnDemand { min_refresh: Duration },
+}
+
+impl PollMode {
+  pub fn on_demand(min_refresh: Duration) -> Self {
+    Self::OnDemand { min_refresh }
+  }
+}
+
+/// Lets `poll` wake the background task of a source in [PollMode::OnDemand],
+/// does nothing in [PollMode::Background]
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "system"), allow(dead_code))]
+pub(crate) struct Demand {
+  mode: PollMode,
+  inner: Arc<(Mutex<DemandState>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct DemandState {
+  requested: bool,
+  refreshed: Option<Instant>,
+  /// Bumped on every refresh, so a waiting poll knows its request was served
+  refreshes: u64,
+}
+
+/// How often a sleeping background task checks if it was cancelled
+const DEMAND_CANCEL_CHECK: Duration = Duration::from_millis(250);
+
+#[cfg_attr(not(feature = "system"), allow(dead_code))]
+impl Demand {
+  pub(crate) fn new(mode: PollMode) -> Self {
+    Self {
+      mode,
+      inner: Arc::default(),
+    }
+  }
+
+  pub(crate) fn is_on_demand(&self) -> bool {
+    matches!(self.mode, PollMode::OnDemand { .. })
+  }
+
+  /// Called by `poll`, asks for a refresh if the metadata is too old and waits up to `timeout` for it
+  pub(crate) fn request(&self, timeout: Duration) {
+    let PollMode::OnDemand { min_refresh } = self.mode else {
+      return;
+    };
+
+    let (state, changed) = &*self.inner;
+    let mut state = state.lock().unwrap();
+
+    if state
+      .refreshed
+      .is_some_and(|refreshed| refreshed.elapsed() < min_refresh)
+    {
+      return;
+    }
+
+    let refreshes = state.refreshes;
+    state.requested = true;
+    changed.notify_all();
+
+    let _ = changed
+      .wait_timeout_while(state, timeout, |state| state.refreshes == refreshes)
+      .unwrap();
+  }
+
+  /// Called by the background task before asking the platform,
+  /// blocks until a refresh is requested or `cancel_token` is set
+  pub(crate) fn wait(&self, cancel_token: &AtomicBool) {
+    if !self.is_on_demand() {
+      return;
+    }
+
+    let (state, changed) = &*self.inner;
+    let mut state = state.lock().unwrap();
+
+    while !state.requested && !cancel_token.load(Ordering::SeqCst) {
+      state = changed.wait_timeout(state, DEMAND_CANCEL_CHECK).unwrap().0;
+    }
+  }
+
+  /// Called by the background task once the metadata is current
+  pub(crate) fn refreshed(&self) {
+    if !self.is_on_demand() {
+      return;
+    }
+
+    let (state, changed) = &*self.inner;
+    let mut state = state.lock().unwrap();
+
+    state.requested = false;
+    state.refreshed = Some(Instant::now());
+    state.refreshes += 1;
+    changed.notify_all();
+  }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+pub(crate) struct TokenBucket {
+  limit: RateLimit,
+  tokens: f64,
+  refilled: Instant,
+}
+
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+impl TokenBucket {
+  pub(crate) fn new(limit: RateLimit) -> Self {
+    Self {
+      limit,
+      tokens: limit.burst.max(1) as f64,
+      refilled: Instant::now(),
+    }
+  }
+
+  /// Takes a token if there is one
+  pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+    self.refill(now);
+
+    if self.tokens < 1.0 {
+      return false;
+    }
+
+    self.tokens -= 1.0;
+    true
+  }
+
+  /// When the next token is available
+  pub(crate) fn next_token(&self) -> Instant {
+    let missing = (1.0 - self.tokens).max(0.0);
+
+    match Duration::try_from_secs_f64(missing / self.limit.per_second) {
+      Ok(wait) => self.refilled + wait,
+      Err(_) => self.refilled + Duration::from_secs(1),
+    }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+
+    self.tokens =
+      (self.tokens + elapsed * self.limit.per_second.max(0.0)).min(self.limit.burst.max(1) as f64);
+    self.refilled = now;
+  }
+}
+
+/// What happens when a source produces events faster than they are consumed
+#[derive(Default, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum BackpressurePolicy {
+  /// Drop the oldest queued event to make room for the new one
+  #[default]
+  DropOldest,
+  /// Drop the new event
+  DropNewest,
+  /// Block the source until there's room, the source stops updating while blocked
+  ///
+  /// **With `MediaSourceConfig::runtime` set, this blocks one of that runtime's workers**
+  Block,
+}
+
+#[derive(Debug)]
+struct Channel {
+  state: Mutex<ChannelState>,
+  changed: Condvar,
+  capacity: usize,
+  policy: BackpressurePolicy,
+  source: SourceKind,
+  metrics: Arc<Metrics>,
+  on_dropped_events: Option<Arc<dyn DroppedEventsHandler>>,
+  dropped_events_threshold: u64,
+  /// See [ChannelReceiver::set_notifier]
+  notifier: Mutex<Option<Arc<EventNotifier>>>,
+}
+
+impl Channel {
+  /// Wakes the listener waiting on the channel, **must not be called while holding the state lock**
+  fn notify(&self) {
+    if let Some(notifier) = &*self.notifier.lock().unwrap() {
+      notifier.notify();
+    }
+  }
+
+  /// Counts `event` as dropped, **must not be called while holding the state lock**
+  fn dropped(&self, event: &MediaEvent) {
+    let total = self.metrics.event_dropped(&self.source, event);
+
+    let Some(handler) = &self.on_dropped_events else {
+      return;
+    };
+
+    if total.is_multiple_of(self.dropped_events_threshold) {
+      handler.on_dropped_events(&DroppedEvents {
+        source: &self.source,
+        event,
+        total,
+      });
+    }
+  }
+}
+
+#[derive(Debug)]
+struct ChannelState {
+  queue: VecDeque<MediaEvent>,
+  senders: usize,
+  receiver_alive: bool,
+}
+
+/// Creates a bounded event channel using [MediaSourceConfig::channel_capacity] and [MediaSourceConfig::backpressure],
+/// events dropped along the way are counted for `source`
+pub(crate) fn event_channel(
+  cfg: &MediaSourceConfig,
+  source: SourceKind,
+) -> (ChannelSender, ChannelReceiver) {
+  let state = ChannelState {
+    queue: VecDeque::new(),
+    senders: 1,
+    receiver_alive: true,
+  };
+
+  let channel = Arc::new(Channel {
+    state: Mutex::new(state),
+    changed: Condvar::new(),
+    capacity: cfg.channel_capacity.max(1),
+    policy: cfg.backpressure,
+    source,
+    metrics: cfg.metrics.clone(),
+    on_dropped_events: cfg.on_dropped_events.clone(),
+    dropped_events_threshold: cfg.dropped_events_threshold.max(1),
+    notifier: Mutex::new(None),
+  });
+
+  let send = ChannelSender {
+    channel: channel.clone(),
+  };
+
+  (send, ChannelReceiver { channel })
+}
+
+#[derive(Debug)]
+pub(crate) struct ChannelSender {
+  channel: Arc<Channel>,
+}
+
+impl ChannelSender {
+  fn send(&self, event: MediaEvent) {
+    let channel = &self.channel;
+    let mut state = channel.state.lock().unwrap();
+    let mut dropped = None;
+
+    while state.receiver_alive && state.queue.len() >= channel.capacity {
+      match channel.policy {
+        BackpressurePolicy::DropOldest => dropped = state.queue.pop_front(),
+        BackpressurePolicy::DropNewest => {
+          drop(state);
+          channel.dropped(&event);
+          return;
+        }
+        BackpressurePolicy::Block => state = channel.changed.wait(state).unwrap(),
+      }
+    }
+
+    // nobody is listening anymore, that's not worth counting
+    if !state.receiver_alive {
+      return;
+    }
+
+    state.queue.push_back(event);
+    channel.changed.notify_all();
+    drop(state);
+    channel.notify();
+
+    if let Some(dropped) = dropped {
+      channel.dropped(&dropped);
+    }
+  }
+}
+
+impl Clone for ChannelSender {
+  fn clone(&self) -> Self {
+    self.channel.state.lock().unwrap().senders += 1;
+
+    Self {
+      channel: self.channel.clone(),
+    }
+  }
+}
+
+impl Drop for ChannelSender {
+  fn drop(&mut self) {
+    self.channel.state.lock().unwrap().senders -= 1;
+    self.channel.changed.notify_all();
+    self.channel.notify();
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct ChannelReceiver {
+  channel: Arc<Channel>,
+}
+
+impl ChannelReceiver {
+  /// Same as [std::sync::mpsc::Receiver::recv_timeout]
+  pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<MediaEvent, RecvTimeoutError> {
+    let channel = &self.channel;
+    let deadline = Instant::now() + timeout;
+    let mut state = channel.state.lock().unwrap();
+
+    loop {
+      if let Some(event) = state.queue.pop_front() {
+        channel.changed.notify_all();
+        return Ok(event);
+      }
+
+      if state.senders == 0 {
+        return Err(RecvTimeoutError::Disconnected);
+      }
+
+      let now = Instant::now();
+
+      if now >= deadline {
+        return Err(RecvTimeoutError::Timeout);
+      }
+
+      state = channel
+        .changed
+        .wait_timeout(state, deadline - now)
+        .unwrap()
+        .0;
+    }
+  }
+
+  /// Takes every queued event without waiting
+  pub(crate) fn drain(&self) -> Vec<MediaEvent> {
+    let events = self.channel.state.lock().unwrap().queue.drain(..).collect();
+    self.channel.changed.notify_all();
+    events
+  }
+
+  /// Has every event sent from now on wake `notifier`, see [MediaSource::set_notifier](crate::listener::MediaSource::set_notifier)
+  pub(crate) fn set_notifier(&self, notifier: Arc<EventNotifier>) {
+    *self.channel.notifier.lock().unwrap() = Some(notifier);
+  }
+}
+
+impl Drop for ChannelReceiver {
+  fn drop(&mut self) {
+    let mut state = self.channel.state.lock().unwrap();
+    state.receiver_alive = false;
+    state.queue.clear();
+    self.channel.changed.notify_all();
+  }
+}
+
+/// Gets every event an [EventSender] delivers, before [MediaSourceConfig::events] filters them,
+/// for consumers with their own subscriptions
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+pub(crate) trait EventTap: Send + Sync {
+  fn tap(&self, event: &MediaEvent);
+}
+
+impl Debug for dyn EventTap {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str("dyn EventTap")
+  }
+}
+
+/// Sending half of a source's event channel, runs events through the pipeline before sending them
+#[derive(Debug)]
+pub(crate) struct EventSender {
+  send: ChannelSender,
+  tap: Option<Arc<dyn EventTap>>,
+  events: EventKind,
+  /// [MediaSourceConfig::covers]
+  covers: bool,
+  redactor: Redactor,
+  progress: ProgressFilter,
+  dedup: DedupFilter,
+  transitions: TransitionSmoother,
+  coalescer: Coalescer,
+  metrics: Arc<Metrics>,
+}
+
+impl EventSender {
+  pub(crate) fn new(send: ChannelSender, cfg: &MediaSourceConfig) -> Self {
+    Self {
+      send,
+      tap: None,
+      events: cfg.events,
+      covers: cfg.covers,
+      redactor: Redactor::new(cfg.redaction.clone()),
+      progress: ProgressFilter::new(cfg.progress_threshold),
+      dedup: DedupFilter::new(cfg),
+      transitions: TransitionSmoother::new(cfg),
+      coalescer: Coalescer::new(cfg.coalesce.clone()),
+      metrics: cfg.metrics.clone(),
+    }
+  }
+
+  pub(crate) fn send(&mut self, event: MediaEvent) {
+    // redacted first so nothing after it (or any consumer) sees what was hidden
+    let Some(event) = self.redactor.redact(event) else {
+      return;
+    };
+
+    let event = match self.covers {
+      true => event,
+      false => match strip_covers(event) {
+        Some(event) => event,
+        None => return,
+      },
+    };
+
+    let now = Instant::now();
+
+    // before the other stages, so it sees every progress update
+    for event in self.transitions.push(event, now) {
+      self.filter(event, now);
+    }
+  }
+
+  fn filter(&mut self, event: MediaEvent, now: Instant) {
+    if !self.progress.filter(&event) {
+      return;
+    }
+
+    if !self.dedup.filter(&event, now) {
+      return;
+    }
+
+    for event in self.coalescer.push(event, now) {
+      self.deliver(event);
+    }
+  }
+
+  /// Sends any held events that are due, should be called regularly by the source
+  pub(crate) fn flush(&mut self) {
+    let now = Instant::now();
+
+    for event in self.transitions.flush(now) {
+      self.filter(event, now);
+    }
+
+    for event in self.coalescer.flush(now) {
+      self.deliver(event);
+    }
+  }
+
+  /// Hands every delivered event to `tap` as well
+  #[cfg_attr(not(feature = "ws"), allow(dead_code))]
+  pub(crate) fn set_tap(&mut self, tap: Arc<dyn EventTap>) {
+    self.tap = Some(tap);
+  }
+
+  /// Filtering by type happens last so the other stages still see every event
+  fn deliver(&self, event: MediaEvent) {
+    if let Some(tap) = &self.tap {
+      tap.tap(&event);
+    }
+
+    if self.events.contains(event.kind()) {
+      self.metrics.event_emitted(&event);
+      self.send.send(event);
+    }
+  }
+
+  /// When [EventSender::flush] has to be called next
+  #[cfg_attr(not(feature = "ws"), allow(dead_code))]
+  pub(crate) fn next_due(&self) -> Option<Instant> {
+    [self.transitions.next_due(), self.coalescer.next_due()]
+      .into_iter()
+      .flatten()
+      .min()
+  }
+}
+
+/// `event` without the covers and backgrounds in it, [None] for [MediaEvent::CoverChunk]s
+pub(crate) fn strip_covers(mut event: MediaEvent) -> Option<MediaEvent> {
+  match &mut event {
+    MediaEvent::CoverChunk(_) => return None,
+    MediaEvent::MediaChanged(metadata)
+    | MediaEvent::TrackEnded(metadata)
+    | MediaEvent::ListenThresholdReached { metadata, .. } => {
+      metadata.cover = None;
+      metadata.background = None;
+    }
+    _ => {}
+  }
+
+  Some(event)
+}
+
+/// Keeps track of when a source last produced an update,
+/// so a source that went quiet (like a crashed browser tab) doesn't report what it last saw forever
+#[derive(Debug, Clone)]
+pub(crate) struct Heartbeat {
+  stale_after: Option<Duration>,
+  inner: Arc<Mutex<HeartbeatState>>,
+}
+
+#[derive(Debug)]
+struct HeartbeatState {
+  last_beat: Instant,
+  /// State from before the source went stale
+  stale_state: Option<MediaState>,
+}
+
+impl Heartbeat {
+  pub(crate) fn new(stale_after: Option<Duration>) -> Self {
+    let inner = HeartbeatState {
+      last_beat: Instant::now(),
+      stale_state: None,
+    };
+
+    Self {
+      stale_after,
+      inner: Arc::new(Mutex::new(inner)),
+    }
+  }
+
+  /// Called by the background task whenever the source produced an update,
+  /// returns the state to restore if the source was stale
+  ///
+  /// **Must not be called while holding the metadata lock**
+  pub(crate) fn beat(&self) -> Option<MediaState> {
+    let mut inner = self.inner.lock().unwrap();
+    inner.last_beat = Instant::now();
+    inner.stale_state.take()
+  }
+
+  /// Marks `metadata` as stopped if the source hasn't produced an update in a while,
+  /// returns true if the source is stale
+  pub(crate) fn check(&self, metadata: &RwLock<MediaMetadata>) -> bool {
+    let Some(stale_after) = self.stale_after else {
+      return false;
+    };
+
+    let mut inner = self.inner.lock().unwrap();
+
+    if inner.last_beat.elapsed() < stale_after {
+      return false;
+    }
+
+    if inner.stale_state.is_none() {
+      let mut metadata = metadata.write().unwrap();
+      inner.stale_state = Some(metadata.state);
+      metadata.state = MediaState::Stopped;
+    }
+
+    true
+  }
+}
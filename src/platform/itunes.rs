@@ -0,0 +1,506 @@
+#![cfg(windows)]
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use windows::core::{IUnknown, Interface, BSTR, GUID, HSTRING, PCWSTR, VARIANT};
+use windows::Win32::System::Com::{
+  CLSIDFromProgID, CoInitializeEx, CoUninitialize, IDispatch, COINIT_MULTITHREADED, DISPATCH_FLAGS,
+  DISPATCH_METHOD, DISPATCH_PROPERTYGET, DISPPARAMS,
+};
+use windows::Win32::System::Ole::GetActiveObject;
+
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::{Error, MediaEvent, MediaImage, MediaMetadata, MediaState, Result, SourceKind};
+
+const LOCALE_USER_DEFAULT: u32 = 0x0400;
+
+/// How often to check if iTunes was started while it isn't running
+const APP_SCAN_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Source for the legacy iTunes client through its COM automation interface,
+/// unlike GSMTC it reports the duration, the position and the artwork of every track
+///
+/// It never starts iTunes itself, it waits until the user does
+///
+/// ```rs
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(ITunesMediaSource::NAME, ITunesMediaSource::create(cfg)?)
+///   .priority([SourceKind::Custom(ITunesMediaSource::NAME.into()), SourceKind::System])
+///   .build()?;
+/// ```
+//noinspection DuplicatedCode
+#[derive(Debug)]
+pub struct ITunesMediaSource {
+  timeout: Duration,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  _background_task: JoinHandle<()>,
+}
+
+impl ITunesMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "itunes";
+}
+
+//noinspection DuplicatedCode
+impl MediaSource for ITunesMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let cfg = cfg.for_source(&SourceKind::Custom(ITunesMediaSource::NAME.into()));
+
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Custom(ITunesMediaSource::NAME.into()));
+
+    let _background_task = spawn_background_task(
+      cfg.clone(),
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      last_error.clone(),
+      send,
+    )?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      _background_task,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.cancel_token.load(Ordering::SeqCst)
+  }
+
+  fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for ITunesMediaSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
+}
+
+/// Late bound COM object, iTunes only ships a type library so there are no bindings to use
+struct Dispatch(IDispatch);
+
+impl Dispatch {
+  fn invoke(&self, name: &str, flags: DISPATCH_FLAGS, args: &mut [VARIANT]) -> Result<VARIANT> {
+    let name = HSTRING::from(name);
+    let names = [PCWSTR(name.as_ptr())];
+    let mut id = 0;
+
+    let params = DISPPARAMS {
+      rgvarg: args.as_mut_ptr(),
+      rgdispidNamedArgs: std::ptr::null_mut(),
+      cArgs: args.len() as u32,
+      cNamedArgs: 0,
+    };
+
+    let mut result = VARIANT::default();
+
+    unsafe {
+      self.0.GetIDsOfNames(
+        &GUID::zeroed(),
+        names.as_ptr(),
+        1,
+        LOCALE_USER_DEFAULT,
+        &mut id,
+      )?;
+
+      self.0.Invoke(
+        id,
+        &GUID::zeroed(),
+        LOCALE_USER_DEFAULT,
+        flags,
+        &params,
+        Some(&mut result),
+        None,
+        None,
+      )?;
+    }
+
+    Ok(result)
+  }
+
+  fn get(&self, name: &str) -> Result<VARIANT> {
+    self.invoke(name, DISPATCH_PROPERTYGET, &mut [])
+  }
+
+  fn get_i32(&self, name: &str) -> Result<i32> {
+    Ok(i32::try_from(&self.get(name)?)?)
+  }
+
+  fn get_string(&self, name: &str) -> Result<String> {
+    Ok(BSTR::try_from(&self.get(name)?)?.to_string())
+  }
+
+  /// [None] for an empty string
+  fn get_optional_string(&self, name: &str) -> Result<Option<String>> {
+    let value = self.get_string(name)?;
+
+    Ok((!value.is_empty()).then_some(value))
+  }
+
+  /// [None] if the property is empty, like `CurrentTrack` while nothing is loaded
+  fn get_object(&self, name: &str) -> Result<Option<Self>> {
+    Self::from_variant(self.get(name)?)
+  }
+
+  /// `Item(index)` of a collection
+  fn item(&self, index: i32) -> Result<Option<Self>> {
+    Self::from_variant(self.invoke("Item", DISPATCH_PROPERTYGET, &mut [VARIANT::from(index)])?)
+  }
+
+  fn from_variant(variant: VARIANT) -> Result<Option<Self>> {
+    let Ok(unknown) = IUnknown::try_from(&variant) else {
+      return Ok(None);
+    };
+
+    Ok(Some(Self(unknown.cast()?)))
+  }
+}
+
+/// The running instance of iTunes, [None] if it isn't running
+fn active_app(clsid: &GUID) -> Option<Dispatch> {
+  let mut unknown = None;
+
+  unsafe { GetActiveObject(clsid, None, &mut unknown).ok()? };
+
+  unknown?.cast().ok().map(Dispatch)
+}
+
+/// Balances the `CoInitializeEx` of the background thread
+struct ComGuard;
+
+impl Drop for ComGuard {
+  fn drop(&mut self) {
+    unsafe { CoUninitialize() }
+  }
+}
+
+//noinspection DuplicatedCode
+fn spawn_background_task(
+  cfg: MediaSourceConfig,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn(ITunesMediaSource::NAME, move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+    let source = SourceKind::Custom(ITunesMediaSource::NAME.into());
+
+    let _com = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }
+      .ok()
+      .map(|_| ComGuard);
+
+    loop {
+      let result = catch_panic(&source, &metadata, || {
+        background_task(
+          &cfg,
+          &cancel_token,
+          &is_running,
+          &metadata,
+          &heartbeat,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+
+          events.send(MediaEvent::SourceError {
+            source: source.clone(),
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(source));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
+      }
+    }
+  })
+}
+
+//noinspection DuplicatedCode
+fn background_task(
+  cfg: &MediaSourceConfig,
+  cancel_token: &AtomicBool,
+  is_running: &AtomicBool,
+  metadata: &RwLock<MediaMetadata>,
+  heartbeat: &Heartbeat,
+  send: ChannelSender,
+) -> Result<()> {
+  let clsid = unsafe { CLSIDFromProgID(&HSTRING::from("iTunes.Application"))? };
+
+  let mut send = EventSender::new(send, cfg);
+  let mut track_end = TrackEndDetector::default();
+  let mut artwork = Artwork::default();
+
+  loop {
+    if cancel_token.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+
+    let app = active_app(&clsid);
+    is_running.store(app.is_some(), Ordering::SeqCst);
+
+    let mut new_metadata = match &app {
+      Some(app) => metadata_from_app(cfg, app, &mut artwork)?,
+      None => MediaMetadata::default(),
+    };
+
+    cfg.sanitize.apply(&mut new_metadata);
+    cfg.apply_zone(&mut new_metadata);
+
+    let restore = heartbeat.beat();
+    let mut metadata = metadata.write().unwrap();
+
+    if let Some(state) = restore {
+      metadata.state = state;
+    }
+
+    let state = new_metadata.state;
+
+    let event = match () {
+      _ if cfg.identity.is_different(&metadata, &new_metadata) => {
+        Some(MediaEvent::MediaChanged(new_metadata.clone()))
+      }
+      _ if metadata.state != state => Some(MediaEvent::StateChanged(state)),
+      _ if state == MediaState::Playing => Some(MediaEvent::ProgressChanged(new_metadata.elapsed)),
+      _ => None,
+    };
+
+    let ended = event
+      .as_ref()
+      .and_then(|event| track_end.detect(&metadata, event));
+
+    *metadata = new_metadata;
+    drop(metadata);
+
+    send.flush();
+
+    if let Some(ended) = ended {
+      send.send(ended);
+    }
+
+    if let Some(event) = event {
+      send.send(event);
+    }
+
+    std::thread::sleep(match app {
+      Some(_) => cfg.update_interval(state),
+      None => APP_SCAN_INTERVAL,
+    });
+  }
+}
+
+/// Artwork of the last track, it's only saved again once the track changes
+#[derive(Default)]
+struct Artwork {
+  track_id: Option<i32>,
+  image: Option<MediaImage>,
+}
+
+impl Artwork {
+  fn of(&mut self, cfg: &MediaSourceConfig, track: &Dispatch, track_id: i32) -> Option<MediaImage> {
+    if self.track_id != Some(track_id) {
+      let started = Instant::now();
+
+      self.track_id = Some(track_id);
+      // tracks without artwork or artwork that can't be saved just don't get a cover
+      self.image = save_artwork(track).ok().flatten();
+
+      cfg.metrics.record_cover_fetch(started.elapsed());
+    }
+
+    self.image.clone()
+  }
+}
+
+/// iTunes can only hand out artwork by saving it to a file
+fn save_artwork(track: &Dispatch) -> Result<Option<MediaImage>> {
+  let Some(collection) = track.get_object("Artwork")? else {
+    return Ok(None);
+  };
+
+  if collection.get_i32("Count")? == 0 {
+    return Ok(None);
+  }
+
+  // the collection is 1-based
+  let Some(artwork) = collection.item(1)? else {
+    return Ok(None);
+  };
+
+  let extension = match artwork.get_i32("Format")? {
+    2 => "png",
+    3 => "bmp",
+    _ => "jpg",
+  };
+
+  let path = std::env::temp_dir().join(format!(
+    "currently_playing-itunes-{}.{extension}",
+    std::process::id()
+  ));
+
+  artwork.invoke(
+    "SaveArtworkToFile",
+    DISPATCH_METHOD,
+    &mut [VARIANT::from(BSTR::from(path.to_string_lossy().as_ref()))],
+  )?;
+
+  let data = std::fs::read(&path);
+  let _ = std::fs::remove_file(&path);
+
+  Ok(Some(MediaImage::from_bytes(data?)))
+}
+
+fn metadata_from_app(
+  cfg: &MediaSourceConfig,
+  app: &Dispatch,
+  artwork: &mut Artwork,
+) -> Result<MediaMetadata> {
+  let Some(track) = app.get_object("CurrentTrack")? else {
+    return Ok(MediaMetadata::default());
+  };
+
+  // ITPlayerState, iTunes has no paused state, it's stopped with the track still loaded,
+  // fast forward and rewind count as playing
+  let state = match app.get_i32("PlayerState")? {
+    0 => MediaState::Paused,
+    _ => MediaState::Playing,
+  };
+
+  // milliseconds aren't available before iTunes 10
+  let elapsed = match app.get_i32("PlayerPositionMS") {
+    Ok(millis) => Duration::from_millis(millis.max(0) as u64),
+    Err(_) => Duration::from_secs(app.get_i32("PlayerPosition")?.max(0) as u64),
+  };
+
+  let track_id = track.get_i32("TrackDatabaseID")?;
+
+  let mut extra = BTreeMap::new();
+
+  for key in ["Genre", "AlbumArtist", "Composer"] {
+    if let Some(value) = track.get_optional_string(key)? {
+      extra.insert(key.to_lowercase(), value.into());
+    }
+  }
+
+  for key in ["Year", "TrackNumber", "Rating", "PlayedCount"] {
+    let value = track.get_i32(key)?;
+
+    if value != 0 {
+      extra.insert(key.to_lowercase(), value.into());
+    }
+  }
+
+  Ok(MediaMetadata {
+    uid: Some(track_id.to_string()),
+    uri: None,
+    state,
+    duration: Duration::from_secs(track.get_i32("Duration")?.max(0) as u64),
+    elapsed,
+    title: track.get_string("Name")?,
+    album: track.get_optional_string("Album")?,
+    artists: track.get_optional_string("Artist")?.into_iter().collect(),
+    featured_artists: Vec::new(),
+    cover_url: None,
+    cover: cfg
+      .covers
+      .then(|| artwork.of(cfg, &track, track_id))
+      .flatten(),
+    background_url: None,
+    background: None,
+    app: Some("iTunes".into()),
+    zone: None,
+    extra,
+    queue: Vec::new(),
+  })
+}
@@ -1,15 +1,21 @@
-#![cfg(target_os = "linux")]
+#![cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
 
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use mpris::{PlaybackStatus, PlayerFinder};
+use mpris::{Metadata, MetadataValue, PlaybackStatus, Player, PlayerFinder, Progress, TrackList};
 
-use crate::listener::{MediaSource, MediaSourceConfig};
-use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result};
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, Demand, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::platform::raw::{RawMetadata, RawMpris};
+use crate::power::Throttle;
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, QueueEntry, Result, SourceKind};
 
 #[derive(thiserror::Error, Debug)]
 #[error(transparent)]
@@ -21,6 +27,13 @@ pub enum MprisError {
   TrackListError(#[from] mpris::TrackListError),
 }
 
+/// Bus name of [MprisExportSink](crate::platform::mpris_export::MprisExportSink),
+/// the system source leaves that player alone so a listener doesn't read back its own output
+pub const EXPORT_BUS_NAME: &str = "org.mpris.MediaPlayer2.currently_playing";
+
+/// How often to look for another playing player while the current one is paused or stopped
+const PLAYER_SCAN_INTERVAL: Duration = Duration::from_millis(1000);
+
 impl From<PlaybackStatus> for MediaState {
   fn from(value: PlaybackStatus) -> Self {
     match value {
@@ -37,32 +50,50 @@ pub struct MprisMediaSource {
   cancel_token: Arc<AtomicBool>,
   is_running: Arc<AtomicBool>,
   metadata: Arc<RwLock<MediaMetadata>>,
-  recv: Arc<Mutex<Receiver<MediaEvent>>>,
+  heartbeat: Heartbeat,
+  demand: Demand,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
   _background_task: JoinHandle<()>,
 }
 
 impl MediaSource for MprisMediaSource {
   fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let cfg = cfg.for_source(&SourceKind::System);
+
     if !cfg.system_enabled {
       return Err(Error::NotEnabled);
     }
 
-    let update_rate = cfg.update_rate;
     let cancel_token = Arc::new(AtomicBool::new(false));
     let is_running = Arc::new(AtomicBool::new(false));
     let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
-    let (send, recv) = std::sync::mpsc::sync_channel(0);
-
-    let _background_task =
-      spawn_background_task(update_rate, cancel_token.clone(), is_running.clone(), metadata.clone(), send);
-
-    let recv = Arc::new(Mutex::new(recv));
+    let demand = Demand::new(cfg.poll_mode);
+    // nothing is updated in between polls on demand, so it would always look stale
+    let heartbeat = Heartbeat::new(cfg.stale_after.filter(|_| !demand.is_on_demand()));
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::System);
+
+    let _background_task = spawn_background_task(
+      cfg.clone(),
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      demand.clone(),
+      last_error.clone(),
+      send,
+    )?;
 
     Ok(Self {
       timeout: cfg.timeout,
       cancel_token,
       is_running,
       metadata,
+      heartbeat,
+      demand,
+      last_error,
       recv,
       _background_task,
     })
@@ -80,24 +111,43 @@ impl MediaSource for MprisMediaSource {
     self.poll_guarded().map(|v| v.clone())
   }
 
-  fn poll_guarded(&self) -> Result<RwLockReadGuard<MediaMetadata>> {
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
     if self.is_closed() {
       return Err(Error::Closed);
     }
 
+    self.demand.request(self.timeout);
+    self.heartbeat.check(&self.metadata);
+
     Ok(self.metadata.read().unwrap())
   }
 
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
   fn next(&self) -> Result<MediaEvent> {
     if self.is_closed() {
       return Err(Error::Closed);
     }
 
-    let recv = self.recv.lock().unwrap();
-    let event = recv.recv_timeout(self.timeout)?;
+    let event = self.recv.recv_timeout(self.timeout)?;
 
     Ok(event)
   }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
 }
 
 impl Drop for MprisMediaSource {
@@ -106,110 +156,375 @@ impl Drop for MprisMediaSource {
   }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_background_task(
-  update_rate: u64,
+  cfg: MediaSourceConfig,
   cancel_token: Arc<AtomicBool>,
   is_running: Arc<AtomicBool>,
   metadata: Arc<RwLock<MediaMetadata>>,
-  send: SyncSender<MediaEvent>,
-) -> JoinHandle<()> {
-  std::thread::spawn(move || loop {
-    let result = background_task(
-      update_rate,
-      cancel_token.clone(),
-      is_running.clone(),
-      metadata.clone(),
-      send.clone(),
-    );
-
-    match result {
-      Ok(_) => break,
-      Err(_) => {
-        is_running.store(false, Ordering::SeqCst);
-        std::thread::sleep(Duration::from_millis(1000));
-        continue;
+  heartbeat: Heartbeat,
+  demand: Demand,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn("mpris", move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut throttle = Throttle::new(cfg.battery_throttle.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+
+    loop {
+      let result = catch_panic(&SourceKind::System, &metadata, || {
+        background_task(
+          &cfg,
+          cancel_token.clone(),
+          is_running.clone(),
+          metadata.clone(),
+          &heartbeat,
+          &demand,
+          &mut throttle,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+          // polls waiting on a refresh get what there is instead of timing out
+          demand.refreshed();
+
+          events.send(MediaEvent::SourceError {
+            source: SourceKind::System,
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(SourceKind::System));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
       }
     }
   })
 }
 
-#[allow(clippy::await_holding_lock)]
+#[allow(clippy::too_many_arguments)]
 fn background_task(
-  update_rate: u64,
+  cfg: &MediaSourceConfig,
   cancel_token: Arc<AtomicBool>,
   is_running: Arc<AtomicBool>,
   metadata: Arc<RwLock<MediaMetadata>>,
-  send: SyncSender<MediaEvent>,
+  heartbeat: &Heartbeat,
+  demand: &Demand,
+  throttle: &mut Throttle,
+  send: ChannelSender,
 ) -> Result<()> {
   let finder = PlayerFinder::new().map_err(MprisError::from)?;
-  let mut player = finder.find_active().map_err(MprisError::from)?;
+  let mut send = EventSender::new(send, cfg);
 
-  let wait_ms = 1000u64.checked_div(update_rate).unwrap_or(1);
-  let wait = Duration::from_millis(wait_ms);
+  let mut next_player = None;
 
   loop {
     if cancel_token.load(Ordering::SeqCst) {
       break;
     }
 
-    is_running.store(player.is_running(), Ordering::SeqCst);
+    let player = match next_player.take() {
+      Some(player) => player,
+      None => match select_player(&finder, &cfg.preferred_players)? {
+        Some((player, _)) => player,
+        None => {
+          is_running.store(false, Ordering::SeqCst);
+          demand.refreshed();
+
+          match demand.is_on_demand() {
+            true => demand.wait(&cancel_token),
+            false => std::thread::sleep(Duration::from_millis(1000)),
+          }
+
+          continue;
+        }
+      },
+    };
 
-    if !player.is_running() {
-      player = finder.find_active().map_err(MprisError::from)?;
+    // The tracker blocks on D-Bus signals (PropertiesChanged, Seeked, ...) for up to `interval`,
+    // so metadata is only re-fetched when the player tells us something changed,
+    // in between ticks the position is interpolated locally.
+    // Signals still wake it right away, so a long idle interval doesn't delay resuming.
+    let mut interval = throttle.update_interval(cfg, MediaState::Playing);
+    let mut tracker = player
+      .track_progress(interval.as_millis() as u32)
+      .map_err(MprisError::from)?;
+
+    let mut first_tick = true;
+    let mut last_scan = Instant::now();
+    let mut track_end = TrackEndDetector::default();
+
+    loop {
+      demand.wait(&cancel_token);
 
-      if !player.is_running() {
-        std::thread::sleep(Duration::from_millis(1000));
-        continue;
+      if cancel_token.load(Ordering::SeqCst) {
+        return Ok(());
       }
-    };
 
-    let mpris_metadata = player.get_metadata().map_err(MprisError::from)?;
-    let elapsed = player.get_position().map_err(MprisError::from)?;
-    let state = player
-      .get_playback_status()
-      .map(MediaState::from)
-      .map_err(MprisError::from)?;
+      let tick = tracker.tick();
 
-    let new_metadata = MediaMetadata {
-      uid: mpris_metadata.track_id().map(Into::into),
-      uri: mpris_metadata.url().map(Into::into),
-      state,
-      duration: mpris_metadata.length().unwrap_or_default(),
-      elapsed,
-      title: mpris_metadata.title().map(Into::into).unwrap_or_default(),
-      album: mpris_metadata.album_name().map(Into::into),
-      artists: mpris_metadata
-        .artists()
-        .unwrap_or_default()
-        .iter()
-        .map(|s| s.to_string())
-        .collect(),
-      cover_url: mpris_metadata.art_url().map(Into::into),
-      cover: None,
-      background_url: None,
-      background: None,
-    };
+      send.flush();
 
-    let mut metadata = metadata.write().unwrap();
+      // player is gone, pick whatever else is around
+      if tick.player_quit {
+        is_running.store(false, Ordering::SeqCst);
+        break;
+      }
+
+      is_running.store(true, Ordering::SeqCst);
 
-    let event = match () {
-      _ if metadata.is_different(&new_metadata) => {
-        Some(MediaEvent::MediaChanged(new_metadata.clone()))
+      let progress = tick.progress;
+      let state = MediaState::from(progress.playback_status());
+      let elapsed = progress.position();
+
+      let restore = heartbeat.beat();
+      let mut metadata = metadata.write().unwrap();
+      let mut events = Vec::new();
+
+      if let Some(state) = restore {
+        metadata.state = state;
       }
-      _ if metadata.state != state => Some(MediaEvent::StateChanged(state)),
-      _ if state == MediaState::Playing => Some(MediaEvent::ProgressChanged(elapsed)),
-      _ => None,
-    };
 
-    *metadata = new_metadata;
-    drop(metadata);
+      if tick.progress_changed || tick.track_list_changed || first_tick {
+        let mut new_metadata = metadata_from_progress(&player, progress);
+        cfg.sanitize.apply(&mut new_metadata);
+        cfg.apply_zone(&mut new_metadata);
 
-    if let Some(event) = event {
-      let _ = send.try_send(event);
-    }
+        if let Some(track_list) = tick.track_list {
+          new_metadata.queue =
+            queue_from_track_list(&player, track_list, new_metadata.uid.as_deref());
+        }
+
+        let media_changed = cfg.identity.is_different(&metadata, &new_metadata);
+
+        match () {
+          _ if media_changed => events.push(MediaEvent::MediaChanged(new_metadata.clone())),
+          _ if metadata.state != state => events.push(MediaEvent::StateChanged(state)),
+          _ if state == MediaState::Playing => events.push(MediaEvent::ProgressChanged(elapsed)),
+          _ => {}
+        };
+
+        // a new track already carries its queue
+        if !media_changed && metadata.queue != new_metadata.queue {
+          events.push(MediaEvent::QueueChanged(new_metadata.queue.clone()));
+        }
+
+        if let Some(ended) = events.first().and_then(|e| track_end.detect(&metadata, e)) {
+          events.insert(0, ended);
+        }
 
-    std::thread::sleep(wait);
+        *metadata = new_metadata;
+      } else if state == MediaState::Playing {
+        let event = MediaEvent::ProgressChanged(elapsed);
+
+        events.extend(track_end.detect(&metadata, &event));
+        events.push(event);
+
+        metadata.elapsed = elapsed;
+      } else {
+        metadata.elapsed = elapsed;
+      };
+
+      drop(metadata);
+      first_tick = false;
+
+      for event in events {
+        send.send(event);
+      }
+
+      demand.refreshed();
+
+      if let Some(event) = throttle.check() {
+        send.send(event);
+      }
+
+      if throttle.update_interval(cfg, state) != interval {
+        interval = throttle.update_interval(cfg, state);
+        tracker = player
+          .track_progress(interval.as_millis() as u32)
+          .map_err(MprisError::from)?;
+      }
+
+      // while the current player isn't playing, check if another one is
+      if state != MediaState::Playing && last_scan.elapsed() >= PLAYER_SCAN_INTERVAL {
+        last_scan = Instant::now();
+
+        if let Some((candidate, PlaybackStatus::Playing)) =
+          select_player(&finder, &cfg.preferred_players)?
+        {
+          if candidate.unique_name() != player.unique_name() {
+            next_player = Some(candidate);
+            break;
+          }
+        }
+      }
+    }
   }
 
   Ok(())
 }
+
+/// Picks the player that is most likely the one the user cares about,
+/// playing players come first, ties are broken by `preferred_players` order
+fn select_player(
+  finder: &PlayerFinder,
+  preferred_players: &[String],
+) -> Result<Option<(Player, PlaybackStatus)>> {
+  let players = finder.find_all().map_err(MprisError::from)?;
+
+  let player = players
+    .into_iter()
+    .filter(|player| player.bus_name() != EXPORT_BUS_NAME)
+    .filter_map(|player| {
+      let status = player.get_playback_status().ok()?;
+      Some((player, status))
+    })
+    .min_by_key(|(player, status)| {
+      let status = match status {
+        PlaybackStatus::Playing => 0,
+        PlaybackStatus::Paused => 1,
+        PlaybackStatus::Stopped => 2,
+      };
+
+      (status, preference_of(player, preferred_players))
+    });
+
+  Ok(player)
+}
+
+fn preference_of(player: &Player, preferred_players: &[String]) -> usize {
+  preferred_players
+    .iter()
+    .position(|name| {
+      name.eq_ignore_ascii_case(player.identity())
+        || name.eq_ignore_ascii_case(player.bus_name_trimmed())
+    })
+    .unwrap_or(preferred_players.len())
+}
+
+fn metadata_from_progress(player: &Player, progress: &Progress) -> MediaMetadata {
+  raw_mpris(
+    player,
+    progress.playback_status(),
+    progress.position(),
+    progress.metadata(),
+  )
+  .to_metadata()
+}
+
+/// What the player the system source picks reports right now, for bug reports about wrong metadata
+pub fn dump_raw(cfg: &MediaSourceConfig) -> Result<RawMetadata> {
+  let finder = PlayerFinder::new().map_err(MprisError::from)?;
+
+  let Some((player, status)) = select_player(&finder, &cfg.preferred_players)? else {
+    return Err(Error::NotExist);
+  };
+
+  let metadata = player.get_metadata().map_err(MprisError::from)?;
+  // not every player reports a position
+  let position = player.get_position().unwrap_or_default();
+
+  Ok(RawMetadata::Mpris(raw_mpris(
+    &player, status, position, &metadata,
+  )))
+}
+
+fn raw_mpris(
+  player: &Player,
+  status: PlaybackStatus,
+  position: Duration,
+  metadata: &Metadata,
+) -> RawMpris {
+  RawMpris {
+    identity: player.identity().into(),
+    bus_name: player.bus_name().into(),
+    playback_status: format!("{status:?}"),
+    position: position.as_micros() as u64,
+    metadata: metadata
+      .iter()
+      .map(|(key, value)| (key.to_string(), value_to_json(value)))
+      .collect(),
+  }
+}
+
+/// Maps the tracks after `current` in the player's track list to [QueueEntry]s,
+/// if `current` isn't in the list, the whole list is used
+fn queue_from_track_list(
+  player: &Player,
+  track_list: &TrackList,
+  current: Option<&str>,
+) -> Vec<QueueEntry> {
+  let Ok(tracks) = track_list.metadata_iter(player) else {
+    return Vec::new();
+  };
+
+  let tracks = tracks.collect::<Vec<_>>();
+
+  let next = current
+    .and_then(|current| {
+      tracks
+        .iter()
+        .position(|track| track.track_id().is_some_and(|id| id.as_str() == current))
+    })
+    .map(|index| index + 1)
+    .unwrap_or_default();
+
+  tracks
+    .iter()
+    .skip(next)
+    .map(|track| QueueEntry {
+      uid: track.track_id().map(Into::into),
+      uri: track.url().map(Into::into),
+      duration: track.length().unwrap_or_default(),
+      title: track.title().map(Into::into).unwrap_or_default(),
+      album: track.album_name().map(Into::into),
+      artists: track
+        .artists()
+        .unwrap_or_default()
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+      cover_url: track.art_url().map(Into::into),
+    })
+    .collect()
+}
+
+fn value_to_json(value: &MetadataValue) -> serde_json::Value {
+  use serde_json::Value as Json;
+
+  match value {
+    MetadataValue::String(value) => Json::from(value.as_str()),
+    MetadataValue::I16(value) => Json::from(*value),
+    MetadataValue::I32(value) => Json::from(*value),
+    MetadataValue::I64(value) => Json::from(*value),
+    MetadataValue::U8(value) => Json::from(*value),
+    MetadataValue::U16(value) => Json::from(*value),
+    MetadataValue::U32(value) => Json::from(*value),
+    MetadataValue::U64(value) => Json::from(*value),
+    MetadataValue::F64(value) => Json::from(*value),
+    MetadataValue::Bool(value) => Json::from(*value),
+    MetadataValue::Array(values) => values.iter().map(value_to_json).collect(),
+    MetadataValue::Map(values) => values
+      .iter()
+      .map(|(key, value)| (key.clone(), value_to_json(value)))
+      .collect(),
+    MetadataValue::Unsupported => Json::Null,
+  }
+}
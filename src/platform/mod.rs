@@ -3,22 +3,61 @@
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
 use std::sync::RwLockReadGuard;
-#[cfg(windows)]
+#[cfg(all(windows, feature = "system"))]
 pub use windows::*;
+#[cfg(all(windows, feature = "itunes"))]
+pub use itunes::*;
 
-#[cfg(target_os = "linux")]
-pub use linux::*;
 use crate::listener::{MediaSource, MediaSourceConfig};
-use crate::{MediaEvent, MediaMetadata};
+use crate::platform::raw::RawMetadata;
+use crate::{MediaEvent, MediaMetadata, Result};
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")), feature = "system"))]
+pub use linux::*;
 
-#[cfg(windows)]
+#[cfg(all(windows, feature = "system"))]
 pub mod windows;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(windows, feature = "itunes"))]
+pub mod itunes;
+
+/// The listener's media in the Windows media flyout, for players only the listener knows about
+#[cfg(all(windows, feature = "smtc-export"))]
+pub mod smtc_export;
+
+/// MPRIS, for anything with a D-Bus session bus, which is linux and the BSDs
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")), feature = "system"))]
 pub mod linux;
 
-#[cfg(target_os = "linux")]
+/// What the platforms report before it's turned into [MediaMetadata], see [dump_raw]
+pub mod raw;
+
+/// The listener's media as an MPRIS player, for widgets that only speak MPRIS
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")), feature = "mpris-export"))]
+pub mod mpris_export;
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")), feature = "system"))]
 pub type SystemMediaSource = MprisMediaSource;
 
-#[cfg(windows)]
-pub type SystemMediaSource = WindowsMediaSource;
\ No newline at end of file
+#[cfg(all(windows, feature = "system"))]
+pub type SystemMediaSource = WindowsMediaSource;
+
+/// If this build has a system source, without one [SystemMediaSource] is a stub that always fails
+/// with [Error::NotEnabled](crate::Error::NotEnabled) and [MediaListener](crate::listener::MediaListener)
+/// leaves it out
+pub const SYSTEM_SOURCE_AVAILABLE: bool = cfg!(all(
+  any(
+    windows,
+    all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))
+  ),
+  feature = "system"
+));
+
+/// No system source without the `system` feature or on unsupported platforms
+#[cfg(not(all(any(windows, all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))), feature = "system")))]
+pub type SystemMediaSource = crate::listener::DisabledMediaSource;
+
+/// Nothing to dump without the `system` feature or on unsupported platforms, fails with [Error::NotEnabled](crate::Error::NotEnabled)
+#[cfg(not(all(any(windows, all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))), feature = "system")))]
+pub fn dump_raw(_cfg: &MediaSourceConfig) -> Result<RawMetadata> {
+  Err(crate::Error::NotEnabled)
+}
@@ -0,0 +1,451 @@
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::channel::default_reply;
+use dbus::message::MessageType;
+use dbus::Message;
+use mpris::DBusError;
+
+use crate::platform::linux::{MprisError, EXPORT_BUS_NAME};
+use crate::sink::{CommandHandler, MediaSink};
+use crate::{Error, MediaCommand, MediaEvent, MediaMetadata, MediaState, Result};
+
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// Track id of the exported media, there is only ever the one track
+const TRACK_ID: &str = "/org/currently_playing/track";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// How long the D-Bus thread waits for a call before it looks for media to announce again
+const PROCESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How far the elapsed time can be off from where it should be before it's announced as a seek
+const SEEK_TOLERANCE: Duration = Duration::from_millis(1000);
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed" type="a{sv}"/>
+      <arg name="invalidated" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.mpris.MediaPlayer2">
+    <method name="Raise"/>
+    <method name="Quit"/>
+    <property name="CanQuit" type="b" access="read"/>
+    <property name="CanRaise" type="b" access="read"/>
+    <property name="HasTrackList" type="b" access="read"/>
+    <property name="Identity" type="s" access="read"/>
+    <property name="SupportedUriSchemes" type="as" access="read"/>
+    <property name="SupportedMimeTypes" type="as" access="read"/>
+  </interface>
+  <interface name="org.mpris.MediaPlayer2.Player">
+    <method name="Next"/>
+    <method name="Previous"/>
+    <method name="Pause"/>
+    <method name="PlayPause"/>
+    <method name="Stop"/>
+    <method name="Play"/>
+    <method name="Seek"><arg name="offset" type="x" direction="in"/></method>
+    <method name="SetPosition">
+      <arg name="track" type="o" direction="in"/>
+      <arg name="position" type="x" direction="in"/>
+    </method>
+    <method name="OpenUri"><arg name="uri" type="s" direction="in"/></method>
+    <signal name="Seeked"><arg name="position" type="x"/></signal>
+    <property name="PlaybackStatus" type="s" access="read"/>
+    <property name="Rate" type="d" access="read"/>
+    <property name="Metadata" type="a{sv}" access="read"/>
+    <property name="Volume" type="d" access="readwrite"/>
+    <property name="Position" type="x" access="read"/>
+    <property name="MinimumRate" type="d" access="read"/>
+    <property name="MaximumRate" type="d" access="read"/>
+    <property name="CanGoNext" type="b" access="read"/>
+    <property name="CanGoPrevious" type="b" access="read"/>
+    <property name="CanPlay" type="b" access="read"/>
+    <property name="CanPause" type="b" access="read"/>
+    <property name="CanSeek" type="b" access="read"/>
+    <property name="CanControl" type="b" access="read"/>
+  </interface>
+</node>"#;
+
+/// Shows the media it's fed as an MPRIS player named [EXPORT_BUS_NAME] on the session bus,
+/// so every MPRIS widget sees players only the listener knows about, like browser tabs
+///
+/// The system source never picks this player up, so a listener doesn't read back its own output
+#[derive(Debug)]
+pub struct MprisExportSink {
+  media: Arc<Mutex<MediaMetadata>>,
+  cancel_token: Arc<AtomicBool>,
+  background_task: Option<JoinHandle<()>>,
+}
+
+impl MprisExportSink {
+  /// Fails if there's no session bus or another sink already has the name
+  pub fn new(commands: impl CommandHandler + 'static) -> Result<Self> {
+    let dbus_error = |err| MprisError::from(DBusError::from(err));
+
+    let conn = Connection::new_session().map_err(dbus_error)?;
+    let reply = conn
+      .request_name(EXPORT_BUS_NAME, false, false, true)
+      .map_err(dbus_error)?;
+
+    if reply != dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply::PrimaryOwner {
+      return Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::AddrInUse,
+        format!("{EXPORT_BUS_NAME} is already taken"),
+      )));
+    }
+
+    let media = Arc::new(Mutex::new(MediaMetadata::default()));
+    let cancel_token = Arc::new(AtomicBool::new(false));
+
+    let background_task = std::thread::Builder::new()
+      .name("mpris-export".into())
+      .spawn({
+        let media = media.clone();
+        let cancel_token = cancel_token.clone();
+
+        move || serve(conn, &media, &cancel_token, &commands)
+      })?;
+
+    Ok(Self {
+      media,
+      cancel_token,
+      background_task: Some(background_task),
+    })
+  }
+}
+
+impl MediaSink for MprisExportSink {
+  fn handle(&mut self, _event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    let mut exported = self.media.lock().unwrap();
+
+    if *exported != *media {
+      *exported = media.clone();
+    }
+
+    Ok(())
+  }
+}
+
+impl Drop for MprisExportSink {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst);
+
+    // waited for, so the name is free for the next sink once this one is gone
+    if let Some(task) = self.background_task.take() {
+      let _ = task.join();
+    }
+  }
+}
+
+/// Answers calls and announces changed media until cancelled or disconnected
+fn serve(
+  conn: Connection,
+  media: &Mutex<MediaMetadata>,
+  cancel_token: &AtomicBool,
+  commands: &dyn CommandHandler,
+) {
+  let channel = conn.channel();
+  let mut announced = MediaMetadata::default();
+  let mut announced_at = Instant::now();
+
+  while !cancel_token.load(Ordering::SeqCst) {
+    match channel.blocking_pop_message(PROCESS_INTERVAL) {
+      Ok(Some(call)) => {
+        let current = media.lock().unwrap().clone();
+
+        if let Some(reply) = answer(&call, &current, commands) {
+          let _ = channel.send(reply);
+        }
+      }
+      Ok(None) => {}
+      Err(_) => return,
+    }
+
+    let current = media.lock().unwrap();
+
+    if *current == announced {
+      continue;
+    }
+
+    for signal in changes(&announced, announced_at, &current) {
+      let _ = channel.send(signal);
+    }
+
+    announced = current.clone();
+    announced_at = Instant::now();
+  }
+}
+
+/// Signals for what changed since `announced` was announced at `announced_at`
+fn changes(
+  announced: &MediaMetadata,
+  announced_at: Instant,
+  current: &MediaMetadata,
+) -> Vec<Message> {
+  let mut changed = PropMap::new();
+
+  if announced.state != current.state {
+    changed.insert(
+      "PlaybackStatus".into(),
+      variant(playback_status(current.state)),
+    );
+  }
+
+  let track = |media: &MediaMetadata| {
+    let MediaMetadata {
+      title,
+      artists,
+      album,
+      uri,
+      cover_url,
+      duration,
+      ..
+    } = media;
+    (
+      title.clone(),
+      artists.clone(),
+      album.clone(),
+      uri.clone(),
+      cover_url.clone(),
+      *duration,
+    )
+  };
+
+  if track(announced) != track(current) {
+    changed.insert("Metadata".into(), variant(metadata(current)));
+  }
+
+  let mut signals = Vec::new();
+  let properties_changed = !changed.is_empty();
+
+  if properties_changed {
+    signals.extend(
+      Message::new_signal(OBJECT_PATH, PROPERTIES_INTERFACE, "PropertiesChanged")
+        .ok()
+        .map(|signal| signal.append3(PLAYER_INTERFACE, changed, Vec::<String>::new())),
+    );
+  }
+
+  let expected = match announced.state {
+    MediaState::Playing => announced.elapsed + announced_at.elapsed(),
+    _ => announced.elapsed,
+  };
+
+  // clients work out the position themselves from the rate, so jumps have to be announced
+  if !properties_changed && current.elapsed.abs_diff(expected) > SEEK_TOLERANCE {
+    signals.extend(
+      Message::new_signal(OBJECT_PATH, PLAYER_INTERFACE, "Seeked")
+        .ok()
+        .map(|signal| signal.append1(micros(current.elapsed))),
+    );
+  }
+
+  signals
+}
+
+/// Reply to a method call, [None] for anything that isn't one
+fn answer(call: &Message, media: &MediaMetadata, commands: &dyn CommandHandler) -> Option<Message> {
+  if call.msg_type() != MessageType::MethodCall {
+    return None;
+  }
+
+  let interface = call.interface();
+  let member = call.member();
+
+  let reply = match (interface.as_deref(), member.as_deref()) {
+    (Some("org.freedesktop.DBus.Introspectable"), Some("Introspect")) => {
+      call.method_return().append1(INTROSPECTION)
+    }
+    (Some(PROPERTIES_INTERFACE), Some("Get")) => {
+      let (interface, name) = call.read2::<&str, &str>().ok()?;
+
+      match properties(interface, media).remove(name) {
+        Some(value) => call.method_return().append1(value),
+        None => error(call, "org.freedesktop.DBus.Error.UnknownProperty", name),
+      }
+    }
+    (Some(PROPERTIES_INTERFACE), Some("GetAll")) => {
+      let interface = call.read1::<&str>().ok()?;
+      call.method_return().append1(properties(interface, media))
+    }
+    (Some(PROPERTIES_INTERFACE), Some("Set")) => match call.read3::<&str, &str, Variant<f64>>() {
+      Ok((PLAYER_INTERFACE, "Volume", Variant(volume))) => command(
+        call,
+        commands,
+        MediaCommand::SetVolume(volume.clamp(0.0, 1.0)),
+      ),
+      _ => error(
+        call,
+        "org.freedesktop.DBus.Error.PropertyReadOnly",
+        "read only",
+      ),
+    },
+    (Some(ROOT_INTERFACE), Some("Raise" | "Quit")) => call.method_return(),
+    (Some(PLAYER_INTERFACE), Some(member)) => {
+      let playing = media.state == MediaState::Playing;
+
+      let wanted = match member {
+        "Play" => Some(MediaCommand::Play),
+        "Pause" | "Stop" => Some(MediaCommand::Pause),
+        "PlayPause" if playing => Some(MediaCommand::Pause),
+        "PlayPause" => Some(MediaCommand::Play),
+        "Next" => Some(MediaCommand::NextTrack),
+        "Previous" => Some(MediaCommand::PreviousTrack),
+        "Seek" => call.read1::<i64>().ok().map(|offset| {
+          let position = micros(media.elapsed).saturating_add(offset).max(0);
+          MediaCommand::SeekTo(Duration::from_micros(position as u64))
+        }),
+        "SetPosition" => call
+          .read2::<dbus::Path, i64>()
+          .ok()
+          .map(|(_, position)| MediaCommand::SeekTo(Duration::from_micros(position.max(0) as u64))),
+        _ => None,
+      };
+
+      match wanted {
+        Some(wanted) => command(call, commands, wanted),
+        // OpenUri, there's nothing to open media with
+        None => return default_reply(call),
+      }
+    }
+    _ => return default_reply(call),
+  };
+
+  Some(reply)
+}
+
+fn command(call: &Message, commands: &dyn CommandHandler, command: MediaCommand) -> Message {
+  match commands.on_command(command) {
+    Ok(()) => call.method_return(),
+    Err(err) => error(call, "org.freedesktop.DBus.Error.Failed", &err.to_string()),
+  }
+}
+
+fn error(call: &Message, name: &'static str, message: &str) -> Message {
+  let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+  call.error(&name.into(), &message)
+}
+
+fn properties(interface: &str, media: &MediaMetadata) -> PropMap {
+  let mut properties = PropMap::new();
+
+  match interface {
+    ROOT_INTERFACE => {
+      properties.insert("CanQuit".into(), variant(false));
+      properties.insert("CanRaise".into(), variant(false));
+      properties.insert("HasTrackList".into(), variant(false));
+      properties.insert("Identity".into(), variant("Currently Playing".to_string()));
+      properties.insert("SupportedUriSchemes".into(), variant(Vec::<String>::new()));
+      properties.insert("SupportedMimeTypes".into(), variant(Vec::<String>::new()));
+    }
+    PLAYER_INTERFACE => {
+      properties.insert(
+        "PlaybackStatus".into(),
+        variant(playback_status(media.state)),
+      );
+      properties.insert("Metadata".into(), variant(metadata(media)));
+      properties.insert("Position".into(), variant(micros(media.elapsed)));
+      properties.insert("Volume".into(), variant(1.0));
+
+      for rate in ["Rate", "MinimumRate", "MaximumRate"] {
+        properties.insert(rate.into(), variant(1.0));
+      }
+
+      for can in [
+        "CanGoNext",
+        "CanGoPrevious",
+        "CanPlay",
+        "CanPause",
+        "CanSeek",
+        "CanControl",
+      ] {
+        properties.insert(can.into(), variant(true));
+      }
+    }
+    _ => {}
+  }
+
+  properties
+}
+
+fn metadata(media: &MediaMetadata) -> PropMap {
+  let mut metadata = PropMap::new();
+
+  let track_id = match media.title.is_empty() {
+    true => NO_TRACK,
+    false => TRACK_ID,
+  };
+
+  metadata.insert("mpris:trackid".into(), variant(dbus::Path::from(track_id)));
+
+  if media.title.is_empty() {
+    return metadata;
+  }
+
+  metadata.insert("xesam:title".into(), variant(media.title.clone()));
+  metadata.insert("xesam:artist".into(), variant(media.artists.clone()));
+  metadata.insert("mpris:length".into(), variant(micros(media.duration)));
+
+  let optional = [
+    ("xesam:album", &media.album),
+    ("xesam:url", &media.uri),
+    ("mpris:artUrl", &media.cover_url),
+  ];
+
+  for (key, value) in optional {
+    if let Some(value) = value {
+      metadata.insert(key.into(), variant(value.clone()));
+    }
+  }
+
+  metadata
+}
+
+fn playback_status(state: MediaState) -> String {
+  let status = match state {
+    MediaState::Playing => "Playing",
+    MediaState::Paused => "Paused",
+    MediaState::Stopped => "Stopped",
+  };
+
+  status.to_string()
+}
+
+fn micros(duration: Duration) -> i64 {
+  duration.as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+fn variant(value: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+  Variant(Box::new(value))
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{MediaMetadata, MediaState};
+
+/// MPRIS metadata keys that already map to a [MediaMetadata] field
+const MODELED_KEYS: &[&str] = &[
+  "mpris:trackid",
+  "mpris:length",
+  "mpris:artUrl",
+  "xesam:url",
+  "xesam:title",
+  "xesam:album",
+  "xesam:artist",
+];
+
+/// What the system's media API reported before it's turned into [MediaMetadata],
+/// from [dump_raw](super::dump_raw), meant to be attached to bug reports about wrong metadata
+///
+/// Both platforms are mapped the same way on any platform,
+/// so what was dumped on one can be checked on another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RawMetadata {
+  Mpris(RawMpris),
+  Gsmtc(RawGsmtc),
+}
+
+impl RawMetadata {
+  /// [MediaMetadata] the system source makes of it, before [MediaSourceConfig::sanitize](crate::listener::MediaSourceConfig::sanitize)
+  /// and without covers or a queue, those aren't dumped
+  pub fn to_metadata(&self) -> MediaMetadata {
+    match self {
+      Self::Mpris(raw) => raw.to_metadata(),
+      Self::Gsmtc(raw) => raw.to_metadata(),
+    }
+  }
+}
+
+/// Properties of an MPRIS player
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawMpris {
+  /// `Identity`
+  pub identity: String,
+  pub bus_name: String,
+  /// `PlaybackStatus`, `Playing`, `Paused` or `Stopped`
+  pub playback_status: String,
+  /// `Position` in microseconds
+  pub position: u64,
+  /// `Metadata`, with the variants as json
+  pub metadata: BTreeMap<String, Value>,
+}
+
+impl RawMpris {
+  pub fn to_metadata(&self) -> MediaMetadata {
+    let string = |key: &str| self.metadata.get(key).and_then(Value::as_str);
+
+    let state = match self.playback_status.as_str() {
+      "Playing" => MediaState::Playing,
+      "Paused" => MediaState::Paused,
+      _ => MediaState::Stopped,
+    };
+
+    // negative lengths are as good as none
+    let duration = self
+      .metadata
+      .get("mpris:length")
+      .and_then(Value::as_u64)
+      .map(Duration::from_micros)
+      .unwrap_or_default();
+
+    let artists = match self.metadata.get("xesam:artist") {
+      Some(Value::Array(artists)) => artists
+        .iter()
+        .filter_map(Value::as_str)
+        .map(Into::into)
+        .collect(),
+      _ => Vec::new(),
+    };
+
+    MediaMetadata {
+      uid: string("mpris:trackid").map(Into::into),
+      uri: string("xesam:url").map(Into::into),
+      state,
+      duration,
+      elapsed: Duration::from_micros(self.position),
+      title: string("xesam:title").map(Into::into).unwrap_or_default(),
+      album: string("xesam:album").map(Into::into),
+      artists,
+      featured_artists: Vec::new(),
+      cover_url: string("mpris:artUrl").map(Into::into),
+      cover: None,
+      background_url: None,
+      background: None,
+      app: Some(self.identity.clone()),
+      zone: None,
+      extra: self
+        .metadata
+        .iter()
+        .filter(|(key, _)| !MODELED_KEYS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect(),
+      queue: Vec::new(),
+    }
+  }
+}
+
+/// Properties of a GSMTC session, [None] for the ones that couldn't be read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawGsmtc {
+  /// `SourceAppUserModelId`
+  pub app_id: Option<String>,
+  /// `PlaybackStatus`, `Closed`, `Opened`, `Changing`, `Stopped`, `Playing` or `Paused`
+  pub playback_status: String,
+  /// `Position` of the timeline in 100 nanosecond ticks
+  pub position: i64,
+  /// `EndTime` of the timeline in 100 nanosecond ticks
+  pub end_time: i64,
+  pub title: String,
+  pub artist: Option<String>,
+  pub album_title: Option<String>,
+}
+
+impl RawGsmtc {
+  pub fn to_metadata(&self) -> MediaMetadata {
+    let state = match self.playback_status.as_str() {
+      "Playing" => MediaState::Playing,
+      "Paused" | "Changing" => MediaState::Paused,
+      _ => MediaState::Stopped,
+    };
+
+    let ticks = |ticks: i64| Duration::from_nanos(ticks.max(0) as u64 * 100);
+
+    MediaMetadata {
+      uid: None,
+      uri: None,
+      state,
+      duration: ticks(self.end_time),
+      elapsed: ticks(self.position),
+      title: self.title.clone(),
+      album: self.album_title.clone(),
+      artists: self.artist.iter().cloned().collect(),
+      featured_artists: Vec::new(),
+      cover_url: None,
+      cover: None,
+      background_url: None,
+      background: None,
+      app: self.app_id.clone(),
+      zone: None,
+      extra: BTreeMap::new(),
+      queue: Vec::new(),
+    }
+  }
+}
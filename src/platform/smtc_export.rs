@@ -0,0 +1,200 @@
+#![cfg(windows)]
+
+use std::sync::Arc;
+
+use windows::core::HSTRING;
+use windows::Foundation::{TypedEventHandler, Uri};
+use windows::Media::Playback::MediaPlayer;
+use windows::Media::{
+  MediaPlaybackStatus, MediaPlaybackType, PlaybackPositionChangeRequestedEventArgs,
+  SystemMediaTransportControls, SystemMediaTransportControlsButton,
+  SystemMediaTransportControlsButtonPressedEventArgs,
+  SystemMediaTransportControlsTimelineProperties,
+};
+use windows::Storage::Streams::{DataWriter, InMemoryRandomAccessStream, RandomAccessStreamReference};
+
+use crate::sink::{CommandHandler, MediaSink};
+use crate::{MediaCommand, MediaEvent, MediaMetadata, MediaState, Result};
+
+/// Shows the media it's fed in the Windows media flyout and lock screen,
+/// so they show what only the listener knows about, like a browser tab reported by the extension,
+/// and sends the media keys to `commands`
+///
+/// The system source skips the session this creates, so a listener doesn't read back its own output
+#[derive(Debug)]
+pub struct SmtcExportSink {
+  /// The controls belong to the player, it's only kept for them and never plays anything
+  _player: MediaPlayer,
+  controls: SystemMediaTransportControls,
+  button_pressed: i64,
+  position_requested: i64,
+  exported: MediaMetadata,
+}
+
+impl SmtcExportSink {
+  pub fn new(commands: impl CommandHandler + 'static) -> Result<Self> {
+    let player = MediaPlayer::new()?;
+
+    // without this the player answers the media keys itself
+    player.CommandManager()?.SetIsEnabled(false)?;
+
+    let controls = player.SystemMediaTransportControls()?;
+    controls.SetIsEnabled(true)?;
+    controls.SetIsPlayEnabled(true)?;
+    controls.SetIsPauseEnabled(true)?;
+    controls.SetIsStopEnabled(true)?;
+    controls.SetIsNextEnabled(true)?;
+    controls.SetIsPreviousEnabled(true)?;
+
+    let commands = Arc::new(commands);
+    let handler = commands.clone();
+
+    let button_pressed = controls.ButtonPressed(&TypedEventHandler::<
+      SystemMediaTransportControls,
+      SystemMediaTransportControlsButtonPressedEventArgs,
+    >::new(move |_, args| {
+      let Some(args) = args else {
+        return Ok(());
+      };
+
+      let command = match args.Button()? {
+        SystemMediaTransportControlsButton::Play => MediaCommand::Play,
+        SystemMediaTransportControlsButton::Pause | SystemMediaTransportControlsButton::Stop => {
+          MediaCommand::Pause
+        }
+        SystemMediaTransportControlsButton::Next => MediaCommand::NextTrack,
+        SystemMediaTransportControlsButton::Previous => MediaCommand::PreviousTrack,
+        _ => return Ok(()),
+      };
+
+      let _ = handler.on_command(command);
+
+      Ok(())
+    }))?;
+
+    let position_requested = controls.PlaybackPositionChangeRequested(&TypedEventHandler::<
+      SystemMediaTransportControls,
+      PlaybackPositionChangeRequestedEventArgs,
+    >::new(move |_, args| {
+      if let Some(args) = args {
+        let position = args.RequestedPlaybackPosition()?.into();
+        let _ = commands.on_command(MediaCommand::SeekTo(position));
+      }
+
+      Ok(())
+    }))?;
+
+    Ok(Self {
+      _player: player,
+      controls,
+      button_pressed,
+      position_requested,
+      exported: MediaMetadata::default(),
+    })
+  }
+
+  fn update_display(&self, media: &MediaMetadata) -> Result<()> {
+    let updater = self.controls.DisplayUpdater()?;
+
+    if media.title.is_empty() {
+      updater.ClearAll()?;
+      updater.Update()?;
+      return Ok(());
+    }
+
+    updater.SetType(MediaPlaybackType::Music)?;
+
+    let music = updater.MusicProperties()?;
+    music.SetTitle(&HSTRING::from(media.title.as_str()))?;
+    music.SetArtist(&HSTRING::from(media.artists.join(", ")))?;
+    music.SetAlbumTitle(&HSTRING::from(media.album.as_deref().unwrap_or_default()))?;
+
+    // the cover data if there is any, so it doesn't have to be fetched again
+    let thumbnail = match (&media.cover, &media.cover_url) {
+      (Some(cover), _) => Some(thumbnail_from_bytes(&cover.data)?),
+      (None, Some(url)) => {
+        let uri = Uri::CreateUri(&HSTRING::from(url.as_str()))?;
+        Some(RandomAccessStreamReference::CreateFromUri(&uri)?)
+      }
+      (None, None) => None,
+    };
+
+    if let Some(thumbnail) = thumbnail {
+      updater.SetThumbnail(&thumbnail)?;
+    }
+
+    updater.Update()?;
+
+    Ok(())
+  }
+
+  fn update_timeline(&self, media: &MediaMetadata) -> Result<()> {
+    let timeline = SystemMediaTransportControlsTimelineProperties::new()?;
+    timeline.SetStartTime(Default::default())?;
+    timeline.SetMinSeekTime(Default::default())?;
+    timeline.SetEndTime(media.duration.into())?;
+    timeline.SetMaxSeekTime(media.duration.into())?;
+    timeline.SetPosition(media.elapsed.into())?;
+
+    self.controls.UpdateTimelineProperties(&timeline)?;
+
+    Ok(())
+  }
+}
+
+/// Nothing the flyout shows about the track changed
+fn same_track(a: &MediaMetadata, b: &MediaMetadata) -> bool {
+  a.title == b.title
+    && a.artists == b.artists
+    && a.album == b.album
+    && a.cover_url == b.cover_url
+    && a.cover == b.cover
+}
+
+fn thumbnail_from_bytes(data: &[u8]) -> Result<RandomAccessStreamReference> {
+  let stream = InMemoryRandomAccessStream::new()?;
+  let writer = DataWriter::CreateDataWriter(&stream)?;
+
+  writer.WriteBytes(data)?;
+  writer.StoreAsync()?.get()?;
+  writer.FlushAsync()?.get()?;
+  writer.DetachStream()?;
+  stream.Seek(0)?;
+
+  Ok(RandomAccessStreamReference::CreateFromStream(&stream)?)
+}
+
+impl MediaSink for SmtcExportSink {
+  fn handle(&mut self, _event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    if self.exported == *media {
+      return Ok(());
+    }
+
+    if !same_track(&self.exported, media) {
+      self.update_display(media)?;
+    }
+
+    if self.exported.state != media.state {
+      let status = match media.state {
+        MediaState::Playing => MediaPlaybackStatus::Playing,
+        MediaState::Paused => MediaPlaybackStatus::Paused,
+        MediaState::Stopped => MediaPlaybackStatus::Stopped,
+      };
+
+      self.controls.SetPlaybackStatus(status)?;
+    }
+
+    self.update_timeline(media)?;
+    self.exported = media.clone();
+
+    Ok(())
+  }
+}
+
+impl Drop for SmtcExportSink {
+  fn drop(&mut self) {
+    let _ = self.controls.RemoveButtonPressed(self.button_pressed);
+    let _ = self.controls.RemovePlaybackPositionChangeRequested(self.position_requested);
+    let _ = self.controls.SetIsEnabled(false);
+  }
+}
@@ -1,21 +1,45 @@
 #![cfg(windows)]
 
-use crate::listener::{MediaSource, MediaSourceConfig};
-use crate::{Error, MediaEvent, MediaImage, MediaMetadata, MediaState, Result};
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, Demand, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::platform::raw::{RawGsmtc, RawMetadata};
+use crate::power::Throttle;
+use crate::{Error, MediaEvent, MediaImage, MediaMetadata, MediaState, Result, SourceKind};
 use std::fmt::Debug;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use windows::core::HRESULT;
 use windows::Foundation::TypedEventHandler;
 use windows::Media::Control::{
   CurrentSessionChangedEventArgs, GlobalSystemMediaTransportControlsSession,
   GlobalSystemMediaTransportControlsSessionManager,
+  GlobalSystemMediaTransportControlsSessionMediaProperties,
   GlobalSystemMediaTransportControlsSessionPlaybackStatus, TimelinePropertiesChangedEventArgs,
 };
 use windows::Storage::Streams::DataReader;
 
+/// Errors requesting the session manager fails with where GSMTC isn't available,
+/// like on server editions of windows without the media features or in sandboxes
+#[allow(overflowing_literals)]
+const UNSUPPORTED: [HRESULT; 5] = [
+  // REGDB_E_CLASSNOTREG
+  HRESULT(0x80040154),
+  // CLASS_E_CLASSNOTAVAILABLE
+  HRESULT(0x80040111),
+  // E_NOINTERFACE
+  HRESULT(0x80004002),
+  // E_NOTIMPL
+  HRESULT(0x80004001),
+  // E_ACCESSDENIED
+  HRESULT(0x80070005),
+];
+
 //noinspection DuplicatedCode
 #[derive(Debug)]
 pub struct WindowsMediaSource {
@@ -23,38 +47,55 @@ pub struct WindowsMediaSource {
   cancel_token: Arc<AtomicBool>,
   is_running: Arc<AtomicBool>,
   metadata: Arc<RwLock<MediaMetadata>>,
-  recv: Arc<Mutex<Receiver<MediaEvent>>>,
+  heartbeat: Heartbeat,
+  demand: Demand,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
   _background_task: JoinHandle<()>,
 }
 
 //noinspection DuplicatedCode
 impl MediaSource for WindowsMediaSource {
   fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let cfg = cfg.for_source(&SourceKind::System);
+
     if !cfg.system_enabled {
       return Err(Error::NotEnabled);
     }
 
-    let update_rate = cfg.update_rate;
     let cancel_token = Arc::new(AtomicBool::new(false));
     let is_running = Arc::new(AtomicBool::new(false));
     let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
-    let (send, recv) = std::sync::mpsc::sync_channel(0);
+    let demand = Demand::new(cfg.poll_mode);
+    // nothing is updated in between polls on demand, so it would always look stale
+    let heartbeat = Heartbeat::new(cfg.stale_after.filter(|_| !demand.is_on_demand()));
+    let last_error = Arc::new(RwLock::new(None));
+
+    // fails right away instead of restarting the background task over and over
+    request_manager()?;
+
+    let (send, recv) = event_channel(&cfg, SourceKind::System);
 
     let _background_task = spawn_background_task(
-      update_rate,
+      cfg.clone(),
       cancel_token.clone(),
       is_running.clone(),
       metadata.clone(),
+      heartbeat.clone(),
+      demand.clone(),
+      last_error.clone(),
       send,
-    );
-
-    let recv = Arc::new(Mutex::new(recv));
+    )?;
 
     Ok(Self {
       timeout: cfg.timeout,
       cancel_token,
       is_running,
       metadata,
+      heartbeat,
+      demand,
+      last_error,
       recv,
       _background_task,
     })
@@ -72,67 +113,133 @@ impl MediaSource for WindowsMediaSource {
     self.poll_guarded().map(|v| v.clone())
   }
 
-  fn poll_guarded(&self) -> Result<RwLockReadGuard<MediaMetadata>> {
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
     if self.is_closed() {
       return Err(Error::Closed);
     }
 
+    self.demand.request(self.timeout);
+    self.heartbeat.check(&self.metadata);
+
     Ok(self.metadata.read().unwrap())
   }
 
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
   fn next(&self) -> Result<MediaEvent> {
     if self.is_closed() {
       return Err(Error::Closed);
     }
 
-    let recv = self.recv.lock().unwrap();
-    let event = recv.recv_timeout(self.timeout)?;
+    let event = self.recv.recv_timeout(self.timeout)?;
 
     Ok(event)
   }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for WindowsMediaSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
 }
 
 //noinspection DuplicatedCode
+#[allow(clippy::too_many_arguments)]
 fn spawn_background_task(
-  update_rate: u64,
+  cfg: MediaSourceConfig,
   cancel_token: Arc<AtomicBool>,
   is_running: Arc<AtomicBool>,
   metadata: Arc<RwLock<MediaMetadata>>,
-  send: SyncSender<MediaEvent>,
-) -> JoinHandle<()> {
-  std::thread::spawn(move || loop {
-    let result = background_task(
-      update_rate,
-      cancel_token.clone(),
-      is_running.clone(),
-      metadata.clone(),
-      send.clone(),
-    );
-
-    match result {
-      Ok(_) => break,
-      Err(_) => {
-        is_running.store(false, Ordering::SeqCst);
-        std::thread::sleep(Duration::from_millis(100));
-        continue;
+  heartbeat: Heartbeat,
+  demand: Demand,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn("gsmtc", move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut throttle = Throttle::new(cfg.battery_throttle.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+
+    loop {
+      let result = catch_panic(&SourceKind::System, &metadata, || {
+        background_task(
+          &cfg,
+          cancel_token.clone(),
+          is_running.clone(),
+          metadata.clone(),
+          &heartbeat,
+          &demand,
+          &mut throttle,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+          // polls waiting on a refresh get what there is instead of timing out
+          demand.refreshed();
+
+          events.send(MediaEvent::SourceError {
+            source: SourceKind::System,
+            message,
+          });
+
+          let unsupported = matches!(err, Error::PlatformUnsupported(_));
+
+          let Some(delay) = retry.next_delay().filter(|_| !unsupported) else {
+            events.send(MediaEvent::SourceGaveUp(SourceKind::System));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
       }
     }
   })
 }
 
 //noinspection DuplicatedCode
+#[allow(clippy::too_many_arguments)]
 fn background_task(
-  update_rate: u64,
+  cfg: &MediaSourceConfig,
   cancel_token: Arc<AtomicBool>,
   is_running: Arc<AtomicBool>,
   metadata_handle: Arc<RwLock<MediaMetadata>>,
-  send: SyncSender<MediaEvent>,
+  heartbeat: &Heartbeat,
+  demand: &Demand,
+  throttle: &mut Throttle,
+  send: ChannelSender,
 ) -> Result<()> {
-  let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+  let manager = request_manager()?;
 
-  
-  let wait_ms = 1000u64.checked_div(update_rate).unwrap_or(1);
-  let wait = Duration::from_millis(wait_ms);
+  let mut track_end = TrackEndDetector::default();
+  let mut send = EventSender::new(send, cfg);
 
   // let session = Arc::new(RwLock::new(session));
   // 
@@ -159,72 +266,53 @@ fn background_task(
   // manager.CurrentSessionChanged(&event)?;
 
   loop {
+    demand.wait(&cancel_token);
+
     if cancel_token.load(Ordering::SeqCst) {
       break;
     }
     
-    let session = manager.GetCurrentSession()?;
+    let session = current_session(&manager)?;
 
     // let session = session.read().unwrap();
 
     is_running.store(true, Ordering::SeqCst);
 
+    if let Some(state) = heartbeat.beat() {
+      metadata_handle.write().unwrap().state = state;
+    }
+
     let metadata = metadata_handle.read().unwrap();
 
-    let timeline = session.GetTimelineProperties()?;
-    let info = session.GetPlaybackInfo()?;
     let props = session.TryGetMediaPropertiesAsync()?.get()?;
 
-    let state = info.PlaybackStatus()?.into();
-    let elapsed = timeline.Position()?.into();
+    let mut new_metadata = raw_gsmtc(&session, &props)?.to_metadata();
+    let state = new_metadata.state;
+    let elapsed = new_metadata.elapsed;
 
-    let thumbnail = props.Thumbnail();
-    let thumbnail = thumbnail?.OpenReadAsync()?.get()?;
-    let size = thumbnail.Size()?;
-    let pos = thumbnail.Position()?;
-    let stream = thumbnail.GetInputStreamAt(pos)?;
-    let reader = DataReader::CreateDataReader(&stream)?;
+    cfg.sanitize.apply(&mut new_metadata);
+    cfg.apply_zone(&mut new_metadata);
 
-    let mut buf = vec![0u8; size as _];
+    let media_changed = cfg.identity.is_different(&metadata, &new_metadata);
 
-    reader.LoadAsync(size as _)?.get()?;
-    reader.ReadBytes(&mut buf)?;
-
-    thumbnail.Close()?;
-
-    let thumbnail = MediaImage {
-      format: thumbnail.ContentType()?.to_string_lossy().into(),
-      data: buf,
-    };
-
-    let new_metadata = MediaMetadata {
-      uid: None,
-      uri: None,
-      state,
-      duration: timeline.EndTime()?.into(),
-      elapsed,
-      title: props.Title()?.to_string_lossy(),
-      album: props.AlbumTitle().ok().map(|s| s.to_string_lossy()),
-      artists: props
-        .Artist()
-        .map(|s| s.to_string_lossy())
-        .into_iter()
-        .collect(),
-      cover_url: None,
-      cover: Some(thumbnail),
-      background_url: None,
-      background: None,
+    // reading the thumbnail is the expensive part, on battery it's only read for new media
+    new_metadata.cover = match media_changed || throttle.refetch_covers() {
+      _ if !cfg.covers => None,
+      true => Some(read_thumbnail(cfg, &props)?),
+      false => metadata.cover.clone(),
     };
 
     let event = match () {
-      _ if metadata.is_different(&new_metadata) => {
-        Some(MediaEvent::MediaChanged(new_metadata.clone()))
-      }
+      _ if media_changed => Some(MediaEvent::MediaChanged(new_metadata.clone())),
       _ if metadata.state != state => Some(MediaEvent::StateChanged(state)),
       _ if state == MediaState::Playing => Some(MediaEvent::ProgressChanged(elapsed)),
       _ => None,
     };
 
+    let ended = event
+      .as_ref()
+      .and_then(|event| track_end.detect(&metadata, event));
+
     drop(metadata);
 
     let mut metadata = metadata_handle.write().unwrap();
@@ -233,25 +321,131 @@ fn background_task(
 
     drop(metadata);
 
+    send.flush();
+
+    if let Some(ended) = ended {
+      send.send(ended);
+    }
+
     if let Some(event) = event {
-      let _ = send.try_send(event);
+      send.send(event);
+    }
+
+    demand.refreshed();
+
+    if let Some(event) = throttle.check() {
+      send.send(event);
     }
 
-    std::thread::sleep(wait);
+    if !demand.is_on_demand() {
+      std::thread::sleep(throttle.update_interval(cfg, state));
+    }
   }
 
   Ok(())
 }
 
-impl From<GlobalSystemMediaTransportControlsSessionPlaybackStatus> for MediaState {
-  fn from(value: GlobalSystemMediaTransportControlsSessionPlaybackStatus) -> Self {
-    use GlobalSystemMediaTransportControlsSessionPlaybackStatus as Status;
+/// The session manager, [Error::PlatformUnsupported] if GSMTC isn't there to begin with
+fn request_manager() -> Result<GlobalSystemMediaTransportControlsSessionManager> {
+  GlobalSystemMediaTransportControlsSessionManager::RequestAsync()
+    .and_then(|request| request.get())
+    .map_err(|err| match UNSUPPORTED.contains(&err.code()) {
+      true => Error::PlatformUnsupported(err.to_string()),
+      false => err.into(),
+    })
+}
 
-    match value {
-      Status::Stopped | Status::Closed | Status::Opened => Self::Stopped,
-      Status::Paused | Status::Changing => Self::Paused,
-      Status::Playing => Self::Playing,
-      _ => unreachable!(),
+/// The current session, unless it's the one an [SmtcExportSink](super::smtc_export) in this process created,
+/// then whichever other session there is
+fn current_session(
+  manager: &GlobalSystemMediaTransportControlsSessionManager,
+) -> Result<GlobalSystemMediaTransportControlsSession> {
+  // desktop apps without a registered app id are identified by their executable
+  let own_id = std::env::current_exe()
+    .ok()
+    .and_then(|exe| exe.file_name().map(|name| name.to_string_lossy().to_lowercase()));
+
+  let is_own = |session: &GlobalSystemMediaTransportControlsSession| {
+    let id = session.SourceAppUserModelId().map(|id| id.to_string_lossy().to_lowercase());
+    matches!((&own_id, id), (Some(own_id), Ok(id)) if *own_id == id)
+  };
+
+  if let Ok(session) = manager.GetCurrentSession() {
+    if !is_own(&session) {
+      return Ok(session);
     }
   }
+
+  manager
+    .GetSessions()?
+    .into_iter()
+    .find(|session| !is_own(session))
+    .ok_or(Error::NotExist)
+}
+
+fn read_thumbnail(
+  cfg: &MediaSourceConfig,
+  props: &GlobalSystemMediaTransportControlsSessionMediaProperties,
+) -> Result<MediaImage> {
+  let cover_started = Instant::now();
+  let thumbnail = props.Thumbnail();
+  let thumbnail = thumbnail?.OpenReadAsync()?.get()?;
+  let size = thumbnail.Size()?;
+  let pos = thumbnail.Position()?;
+  let stream = thumbnail.GetInputStreamAt(pos)?;
+  let reader = DataReader::CreateDataReader(&stream)?;
+
+  let mut buf = vec![0u8; size as _];
+
+  reader.LoadAsync(size as _)?.get()?;
+  reader.ReadBytes(&mut buf)?;
+
+  thumbnail.Close()?;
+  cfg.metrics.record_cover_fetch(cover_started.elapsed());
+
+  Ok(MediaImage::from_reported(
+    thumbnail.ContentType()?.to_string_lossy().into(),
+    buf,
+  ))
+}
+
+/// What the session the system source picks reports right now, for bug reports about wrong metadata
+pub fn dump_raw(_cfg: &MediaSourceConfig) -> Result<RawMetadata> {
+  let manager = request_manager()?;
+  let session = current_session(&manager)?;
+  let props = session.TryGetMediaPropertiesAsync()?.get()?;
+
+  Ok(RawMetadata::Gsmtc(raw_gsmtc(&session, &props)?))
+}
+
+fn raw_gsmtc(
+  session: &GlobalSystemMediaTransportControlsSession,
+  props: &GlobalSystemMediaTransportControlsSessionMediaProperties,
+) -> Result<RawGsmtc> {
+  use GlobalSystemMediaTransportControlsSessionPlaybackStatus as Status;
+
+  let timeline = session.GetTimelineProperties()?;
+
+  let playback_status = match session.GetPlaybackInfo()?.PlaybackStatus()? {
+    Status::Closed => "Closed",
+    Status::Opened => "Opened",
+    Status::Changing => "Changing",
+    Status::Stopped => "Stopped",
+    Status::Playing => "Playing",
+    Status::Paused => "Paused",
+    _ => "Unknown",
+  };
+
+  Ok(RawGsmtc {
+    app_id: session
+      .SourceAppUserModelId()
+      .ok()
+      .map(|s| s.to_string_lossy()),
+    playback_status: playback_status.into(),
+    position: timeline.Position()?.Duration,
+    end_time: timeline.EndTime()?.Duration,
+    title: props.Title()?.to_string_lossy(),
+    artist: props.Artist().ok().map(|s| s.to_string_lossy()),
+    album_title: props.AlbumTitle().ok().map(|s| s.to_string_lossy()),
+  })
 }
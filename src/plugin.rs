@@ -0,0 +1,395 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib, Table, Value, VmState};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::http_client::{self, parse_response};
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::polled::PolledSource;
+use crate::{Error, MediaEvent, MediaMetadata, Result};
+
+/// Most events a plugin's `next` hook can hand over after a fetch, so one that never returns nil can't hang the source
+const MAX_EVENTS: usize = 64;
+
+/// How many instructions a plugin runs between checks of [PluginConfig::timeout]
+const TIMEOUT_CHECK_INTERVAL: u32 = 1000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PluginError {
+  #[error("plugin didn't answer in time")]
+  Timeout,
+  #[error("plugin didn't return a table with a fetch function")]
+  MissingFetch,
+  #[error("malformed plugin reply: {0}")]
+  MalformedReply(#[source] mlua::Error),
+  #[error("plugin error: {0}")]
+  Plugin(#[source] mlua::Error),
+  #[error("plugin isn't allowed to reach {0}, see PluginConfig::allow_host")]
+  HostNotAllowed(SocketAddr),
+}
+
+impl From<PluginError> for Error {
+  fn from(value: PluginError) -> Self {
+    match value {
+      PluginError::MalformedReply(_) | PluginError::MissingFetch => Self::Protocol(value.into()),
+      _ => Self::Connection(value.into()),
+    }
+  }
+}
+
+/// Lua script that gets media out of a player the crate doesn't support
+///
+/// The script returns a table of hooks, `fetch` is called every [PluginConfig::poll_interval]
+/// and returns a table shaped like a serialized [MediaMetadata], events are worked out from it
+/// like they are for the other sources. The optional `next` is called after every fetch
+/// until it returns nil, for events the plugin knows about itself, returned as tables shaped
+/// like a serialized [MediaEvent]
+///
+/// Plugins are sandboxed, they only get Lua's `table`, `string`, `math` and `utf8` libraries
+/// and can't touch files or processes, on top of that they get
+///
+/// - `args`, the [PluginConfig::args]
+/// - `http_get(addr, path)`, returns the status and body of a plain HTTP GET to one of the [PluginConfig::allow_host] addresses
+/// - `json_decode(text)` and `json_encode(value)`
+///
+/// Every call has [PluginConfig::timeout] and the plugin can't use more than [PluginConfig::memory_limit],
+/// plugins that fail are loaded again from scratch with [MediaSourceConfig::backoff]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PluginConfig {
+  pub path: PathBuf,
+  pub args: Vec<String>,
+  /// Addresses `http_get` is allowed to reach, none by default
+  pub allowed_hosts: Vec<SocketAddr>,
+  /// How often the plugin is asked for media
+  pub poll_interval: Duration,
+  /// How long a hook has to return
+  pub timeout: Duration,
+  /// Most memory the plugin can use in bytes
+  pub memory_limit: usize,
+}
+
+impl PluginConfig {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self {
+      path: path.into(),
+      args: Vec::new(),
+      allowed_hosts: Vec::new(),
+      poll_interval: Duration::from_millis(1000),
+      timeout: Duration::from_millis(5000),
+      memory_limit: 16 * 1024 * 1024,
+    }
+  }
+
+  /// Every `.lua` file in `dir`, sorted by name, plugins aren't looked for in subdirectories
+  pub fn discover(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+    let mut plugins = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+      let path = entry?.path();
+
+      if path.is_file() && path.extension().is_some_and(|ext| ext == "lua") {
+        plugins.push(Self::new(path));
+      }
+    }
+
+    plugins.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(plugins)
+  }
+
+  /// Name of the plugin's file without its extension, what [PluginMediaSource] reports as its source
+  pub fn name(&self) -> String {
+    self
+      .path
+      .file_stem()
+      .unwrap_or(self.path.as_os_str())
+      .to_string_lossy()
+      .into_owned()
+  }
+
+  pub fn add_arg(mut self, arg: impl Into<String>) -> Self {
+    self.args.push(arg.into());
+    self
+  }
+
+  /// Lets `http_get` reach `addr`, like the player's own web interface
+  pub fn allow_host(mut self, addr: SocketAddr) -> Self {
+    self.allowed_hosts.push(addr);
+    self
+  }
+
+  pub fn set_poll_interval(self, poll_interval: Duration) -> Self {
+    Self {
+      poll_interval,
+      ..self
+    }
+  }
+
+  pub fn set_timeout(self, timeout: Duration) -> Self {
+    Self { timeout, ..self }
+  }
+
+  pub fn set_memory_limit(self, memory_limit: usize) -> Self {
+    Self {
+      memory_limit,
+      ..self
+    }
+  }
+
+  fn load(&self) -> Result<Plugin> {
+    let script = std::fs::read_to_string(&self.path)?;
+
+    // no io, os, package or debug, so the plugin can't reach anything it isn't given below
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8;
+    let lua = Lua::new_with(libs, LuaOptions::default()).map_err(PluginError::Plugin)?;
+    lua
+      .set_memory_limit(self.memory_limit)
+      .map_err(PluginError::Plugin)?;
+
+    let deadline = Arc::new(Mutex::new(Instant::now() + self.timeout));
+    let hook_deadline = deadline.clone();
+
+    // also stops plugins stuck in a loop, which never return to be timed out otherwise
+    let triggers = HookTriggers::new().every_nth_instruction(TIMEOUT_CHECK_INTERVAL);
+    lua.set_hook(triggers, move |_, _| {
+      match Instant::now() < *hook_deadline.lock().unwrap() {
+        true => Ok(VmState::Continue),
+        false => Err(mlua::Error::external(PluginError::Timeout)),
+      }
+    });
+
+    self.install(&lua).map_err(PluginError::Plugin)?;
+
+    let mut plugin = Plugin {
+      lua,
+      hooks: None,
+      deadline,
+      timeout: self.timeout,
+    };
+
+    let chunk = plugin.lua.load(script).set_name(self.name());
+    let hooks = plugin.run(|| chunk.eval::<Option<Table>>())?;
+    plugin.hooks = Some(hooks.ok_or(PluginError::MissingFetch)?);
+
+    Ok(plugin)
+  }
+
+  /// Takes away what the base library can reach outside the sandbox and adds what plugins get instead
+  fn install(&self, lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+
+    // load is gone too since it takes precompiled chunks, which can break out of the sandbox
+    for name in ["dofile", "loadfile", "load", "print"] {
+      globals.set(name, Value::Nil)?;
+    }
+
+    globals.set("args", self.args.clone())?;
+
+    let allowed_hosts = self.allowed_hosts.clone();
+    let timeout = self.timeout;
+
+    let http_get = lua.create_function(move |lua, (addr, path): (String, String)| {
+      let addr = addr.parse::<SocketAddr>().map_err(mlua::Error::external)?;
+
+      if !allowed_hosts.contains(&addr) {
+        return Err(mlua::Error::external(PluginError::HostNotAllowed(addr)));
+      }
+
+      let response = http_client::request(&addr, "GET", &path, &[], None, timeout)
+        .map_err(mlua::Error::external)?;
+      let (status, body) =
+        parse_response(&response).ok_or_else(|| mlua::Error::runtime("malformed http response"))?;
+
+      Ok((status, lua.create_string(body)?))
+    })?;
+
+    let json_decode = lua.create_function(|lua, text: String| {
+      let value: serde_json::Value = serde_json::from_str(&text).map_err(mlua::Error::external)?;
+      lua.to_value(&value)
+    })?;
+
+    let json_encode = lua.create_function(|_, value: Value| {
+      serde_json::to_string(&value).map_err(mlua::Error::external)
+    })?;
+
+    globals.set("http_get", http_get)?;
+    globals.set("json_decode", json_decode)?;
+    globals.set("json_encode", json_encode)?;
+
+    Ok(())
+  }
+}
+
+/// Loaded plugin, a fresh one is loaded after it fails
+struct Plugin {
+  lua: Lua,
+  /// What the script returned, only [None] while it's being loaded
+  hooks: Option<Table>,
+  /// When the hook that is running has to return by, checked by the hook set in [PluginConfig::load]
+  deadline: Arc<Mutex<Instant>>,
+  timeout: Duration,
+}
+
+impl Plugin {
+  /// Runs `f` within [PluginConfig::timeout]
+  fn run<T>(&self, f: impl FnOnce() -> mlua::Result<T>) -> Result<T> {
+    *self.deadline.lock().unwrap() = Instant::now() + self.timeout;
+
+    let result = f();
+    let timed_out = Instant::now() >= *self.deadline.lock().unwrap();
+
+    result.map_err(|err| match timed_out {
+      true => PluginError::Timeout.into(),
+      false => PluginError::Plugin(err).into(),
+    })
+  }
+
+  /// Calls the hook called `name`, [None] if the plugin doesn't have it or it returned nil
+  fn call<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+    let Some(hooks) = &self.hooks else {
+      return Err(PluginError::MissingFetch.into());
+    };
+
+    let hook = hooks.get::<Option<Function>>(name);
+
+    let Some(hook) = hook.map_err(PluginError::MalformedReply)? else {
+      return Ok(None);
+    };
+
+    let value = match self.run(|| hook.call::<Value>(()))? {
+      Value::Nil => return Ok(None),
+      value => self.lua.from_value(value),
+    };
+
+    Ok(Some(value.map_err(PluginError::MalformedReply)?))
+  }
+
+  fn fetch(&self) -> Result<(MediaMetadata, Vec<MediaEvent>)> {
+    let metadata = self.call("fetch")?.ok_or(PluginError::MissingFetch)?;
+    let mut events = Vec::new();
+
+    while events.len() < MAX_EVENTS {
+      match self.call("next")? {
+        Some(event) => events.push(event),
+        None => break,
+      }
+    }
+
+    Ok((metadata, events))
+  }
+}
+
+/// Source for a plugin, see [PluginConfig]
+///
+/// ```rs
+/// let mut builder = MediaListener::builder().with_config(cfg.clone());
+///
+/// for plugin in PluginConfig::discover("plugins")? {
+///   let name = plugin.name();
+///   let plugin = plugin.allow_host(([127, 0, 0, 1], 8080).into());
+///   let source = PluginMediaSource::create(cfg.clone().set_plugin(plugin))?;
+///
+///   builder = builder.with_source(name, source);
+/// }
+///
+/// let listener = builder.build()?;
+/// ```
+///
+/// A plugin for a player with a web interface on port 8080
+///
+/// ```lua
+/// local plugin = {}
+///
+/// function plugin.fetch()
+///   local status, body = http_get("127.0.0.1:8080", "/api/status")
+///
+///   if status ~= 200 then
+///     error("player answered with " .. status)
+///   end
+///
+///   local player = json_decode(body)
+///
+///   return {
+///     state = player.playing and "Playing" or "Paused",
+///     title = player.title,
+///     artists = { player.artist },
+///     duration = player.length_ms,
+///     elapsed = player.position_ms,
+///   }
+/// end
+///
+/// return plugin
+/// ```
+#[derive(Debug)]
+pub struct PluginMediaSource(PolledSource);
+
+impl MediaSource for PluginMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(plugin) = cfg.plugin.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let name = plugin.name();
+    let poll_interval = plugin.poll_interval;
+    let loaded = Mutex::new(None);
+
+    PolledSource::create_with_events(cfg, name, poll_interval, move || {
+      let mut loaded = loaded.lock().unwrap();
+
+      let running = match loaded.as_mut() {
+        Some(running) => running,
+        None => loaded.insert(plugin.load()?),
+      };
+
+      let fetched = running.fetch();
+
+      // it timed out, failed or returned something malformed, the next fetch starts from a fresh plugin
+      if fetched.is_err() {
+        *loaded = None;
+      }
+
+      fetched
+    })
+    .map(Self)
+  }
+
+  fn is_closed(&self) -> bool {
+    self.0.is_closed()
+  }
+
+  fn is_running(&self) -> bool {
+    self.0.is_running()
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    self.0.poll_guarded()
+  }
+
+  fn is_stale(&self) -> bool {
+    self.0.is_stale()
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.0.last_error()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    self.0.next()
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.0.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.0.set_notifier(notifier)
+  }
+}
@@ -0,0 +1,240 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, Result, SourceKind};
+
+/// Source that fetches its metadata every so often, the sources built on it only differ in how they fetch it
+#[derive(Debug)]
+pub(crate) struct PolledSource {
+  timeout: Duration,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  _background_task: JoinHandle<()>,
+}
+
+impl PolledSource {
+  #[cfg_attr(
+    not(any(feature = "media-server", feature = "http-json", feature = "ws")),
+    allow(dead_code)
+  )]
+  pub(crate) fn create(
+    cfg: MediaSourceConfig,
+    name: impl Into<String>,
+    poll_interval: Duration,
+    fetch: impl Fn() -> Result<MediaMetadata> + Send + 'static,
+  ) -> Result<Self> {
+    Self::create_with_events(cfg, name, poll_interval, move || {
+      fetch().map(|metadata| (metadata, Vec::new()))
+    })
+  }
+
+  /// [PolledSource::create] for sources that also know about events of their own,
+  /// they're sent after the ones worked out from the metadata
+  pub(crate) fn create_with_events(
+    cfg: MediaSourceConfig,
+    name: impl Into<String>,
+    poll_interval: Duration,
+    fetch: impl Fn() -> Result<(MediaMetadata, Vec<MediaEvent>)> + Send + 'static,
+  ) -> Result<Self> {
+    let name = name.into();
+    let cfg = cfg.for_source(&SourceKind::Custom(name.clone()));
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Custom(name.clone()));
+
+    let task = BackgroundTask {
+      name,
+      poll_interval,
+      fetch: Box::new(fetch),
+      cancel_token: cancel_token.clone(),
+      is_running: is_running.clone(),
+      metadata: metadata.clone(),
+      heartbeat: heartbeat.clone(),
+      last_error: last_error.clone(),
+    };
+
+    let _background_task = task.spawn(cfg.clone(), send)?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      _background_task,
+    })
+  }
+
+  pub(crate) fn is_closed(&self) -> bool {
+    self.cancel_token.load(Ordering::SeqCst)
+  }
+
+  pub(crate) fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  pub(crate) fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  pub(crate) fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  pub(crate) fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  pub(crate) fn next(&self) -> Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  pub(crate) fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  pub(crate) fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for PolledSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
+}
+
+/// State the background thread of a [PolledSource] shares with it
+struct BackgroundTask {
+  name: String,
+  poll_interval: Duration,
+  fetch: Box<dyn Fn() -> Result<(MediaMetadata, Vec<MediaEvent>)> + Send>,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+}
+
+impl BackgroundTask {
+  fn spawn(self, cfg: MediaSourceConfig, send: ChannelSender) -> std::io::Result<JoinHandle<()>> {
+    let threads = cfg.threads.clone();
+    let name = self.name.clone();
+
+    threads.spawn(&name, move || {
+      let mut retry = Retry::new(cfg.backoff.clone());
+      let mut events = EventSender::new(send.clone(), &cfg);
+      let source = SourceKind::Custom(self.name.clone());
+
+      loop {
+        match catch_panic(&source, &self.metadata, || self.run(&cfg, send.clone())) {
+          Ok(_) => break,
+          Err(err) => {
+            // it was up before failing, so this is a new outage
+            if self.is_running.swap(false, Ordering::SeqCst) {
+              retry.reset();
+            }
+
+            let message = err.to_string();
+            *self.last_error.write().unwrap() = Some(message.clone());
+
+            events.send(MediaEvent::SourceError {
+              source: source.clone(),
+              message,
+            });
+
+            let Some(delay) = retry.next_delay() else {
+              events.send(MediaEvent::SourceGaveUp(source));
+              break;
+            };
+
+            cfg.metrics.reconnected();
+            std::thread::sleep(delay);
+          }
+        }
+      }
+    })
+  }
+
+  fn run(&self, cfg: &MediaSourceConfig, send: ChannelSender) -> Result<()> {
+    let mut send = EventSender::new(send, cfg);
+    let mut track_end = TrackEndDetector::default();
+
+    loop {
+      if self.cancel_token.load(Ordering::SeqCst) {
+        return Ok(());
+      }
+
+      let (mut new_metadata, own_events) = (self.fetch)()?;
+      cfg.sanitize.apply(&mut new_metadata);
+      cfg.apply_zone(&mut new_metadata);
+
+      self.is_running.store(true, Ordering::SeqCst);
+
+      let restore = self.heartbeat.beat();
+      let mut metadata = self.metadata.write().unwrap();
+      let mut events = Vec::new();
+
+      if let Some(state) = restore {
+        metadata.state = state;
+      }
+
+      let state = new_metadata.state;
+      let media_changed = cfg.identity.is_different(&metadata, &new_metadata);
+
+      match () {
+        _ if media_changed => events.push(MediaEvent::MediaChanged(new_metadata.clone())),
+        _ if metadata.state != state => events.push(MediaEvent::StateChanged(state)),
+        _ if state == MediaState::Playing => {
+          events.push(MediaEvent::ProgressChanged(new_metadata.elapsed))
+        }
+        _ => {}
+      };
+
+      if let Some(ended) = events.first().and_then(|e| track_end.detect(&metadata, e)) {
+        events.insert(0, ended);
+      }
+
+      *metadata = new_metadata;
+      drop(metadata);
+
+      for event in events.into_iter().chain(own_events) {
+        send.send(event);
+      }
+
+      send.flush();
+      std::thread::sleep(self.poll_interval);
+    }
+  }
+}
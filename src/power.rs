@@ -0,0 +1,216 @@
+use std::time::{Duration, Instant};
+
+use crate::listener::MediaSourceConfig;
+use crate::{MediaEvent, MediaState};
+
+/// What the system source cuts back on while the computer runs on battery,
+/// see [MediaSourceConfig::battery_throttle]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatteryThrottle {
+  /// Shortest time between updates of the system source on battery
+  pub update_interval: Duration,
+  /// Whether covers are fetched on every update on battery,
+  /// otherwise they're only fetched when the media changes
+  pub refetch_covers: bool,
+  /// How often the power supply is checked
+  pub check_interval: Duration,
+}
+
+impl Default for BatteryThrottle {
+  fn default() -> Self {
+    Self {
+      update_interval: Duration::from_secs(1),
+      refetch_covers: false,
+      check_interval: Duration::from_secs(30),
+    }
+  }
+}
+
+impl BatteryThrottle {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_update_interval(self, update_interval: Duration) -> Self {
+    Self {
+      update_interval,
+      ..self
+    }
+  }
+
+  pub fn set_refetch_covers(self, refetch_covers: bool) -> Self {
+    Self {
+      refetch_covers,
+      ..self
+    }
+  }
+
+  pub fn set_check_interval(self, check_interval: Duration) -> Self {
+    Self {
+      check_interval,
+      ..self
+    }
+  }
+}
+
+/// Keeps track of the power supply for a background task, kept across reconnects
+/// so [MediaEvent::Throttled] is only sent when it actually changes
+#[cfg_attr(not(feature = "system"), allow(dead_code))]
+pub(crate) struct Throttle {
+  cfg: Option<BatteryThrottle>,
+  power: Option<PowerSupply>,
+  throttled: bool,
+  last_check: Option<Instant>,
+}
+
+#[cfg_attr(not(feature = "system"), allow(dead_code))]
+impl Throttle {
+  pub(crate) fn new(cfg: Option<BatteryThrottle>) -> Self {
+    let power = cfg.as_ref().and_then(|_| PowerSupply::new());
+
+    Self {
+      cfg,
+      power,
+      throttled: false,
+      last_check: None,
+    }
+  }
+
+  /// Checks the power supply once [BatteryThrottle::check_interval] passed,
+  /// returns [MediaEvent::Throttled] if that started or stopped throttling
+  pub(crate) fn check(&mut self) -> Option<MediaEvent> {
+    let cfg = self.cfg.as_ref()?;
+    let power = self.power.as_ref()?;
+
+    if self
+      .last_check
+      .is_some_and(|last| last.elapsed() < cfg.check_interval)
+    {
+      return None;
+    }
+
+    self.last_check = Some(Instant::now());
+
+    // keep whatever it was if the power supply can't be read
+    let on_battery = power.on_battery()?;
+
+    if on_battery == self.throttled {
+      return None;
+    }
+
+    self.throttled = on_battery;
+
+    Some(MediaEvent::Throttled(on_battery))
+  }
+
+  /// [MediaSourceConfig::update_interval], slowed down to
+  /// [BatteryThrottle::update_interval] on battery
+  pub(crate) fn update_interval(&self, cfg: &MediaSourceConfig, state: MediaState) -> Duration {
+    let interval = cfg.update_interval(state);
+
+    match &self.cfg {
+      Some(throttle) if self.throttled => interval.max(throttle.update_interval),
+      _ => interval,
+    }
+  }
+
+  /// Whether covers of media that didn't change should be fetched again
+  #[cfg_attr(not(all(windows, feature = "system")), allow(dead_code))]
+  pub(crate) fn refetch_covers(&self) -> bool {
+    match &self.cfg {
+      Some(throttle) if self.throttled => throttle.refetch_covers,
+      _ => true,
+    }
+  }
+}
+
+/// UPower's `OnBattery` over the system bus
+#[cfg(all(
+  feature = "system",
+  unix,
+  not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+struct PowerSupply(dbus::blocking::Connection);
+
+#[cfg(all(
+  feature = "system",
+  unix,
+  not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+impl PowerSupply {
+  fn new() -> Option<Self> {
+    dbus::blocking::Connection::new_system().ok().map(Self)
+  }
+
+  fn on_battery(&self) -> Option<bool> {
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+
+    self
+      .0
+      .with_proxy(
+        "org.freedesktop.UPower",
+        "/org/freedesktop/UPower",
+        Duration::from_secs(1),
+      )
+      .get("org.freedesktop.UPower", "OnBattery")
+      .ok()
+  }
+}
+
+/// `GetSystemPowerStatus`
+#[cfg(all(feature = "system", windows))]
+struct PowerSupply;
+
+#[cfg(all(feature = "system", windows))]
+impl PowerSupply {
+  fn new() -> Option<Self> {
+    Some(Self)
+  }
+
+  fn on_battery(&self) -> Option<bool> {
+    use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    let mut status = SYSTEM_POWER_STATUS::default();
+    unsafe { GetSystemPowerStatus(&mut status) }.ok()?;
+
+    // 255 is unknown
+    match status.ACLineStatus {
+      0 => Some(true),
+      1 => Some(false),
+      _ => None,
+    }
+  }
+}
+
+/// Nothing to check without a system source for the platform
+#[cfg(not(all(
+  feature = "system",
+  any(
+    windows,
+    all(
+      unix,
+      not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+  )
+)))]
+struct PowerSupply;
+
+#[cfg(not(all(
+  feature = "system",
+  any(
+    windows,
+    all(
+      unix,
+      not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )
+  )
+)))]
+impl PowerSupply {
+  fn new() -> Option<Self> {
+    None
+  }
+
+  fn on_battery(&self) -> Option<bool> {
+    None
+  }
+}
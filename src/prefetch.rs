@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::sink::MediaSink;
+use crate::{Error, MediaEvent, MediaImage, MediaMetadata, Result};
+
+/// Urls remembered as requested before the failed ones are forgotten
+const MAX_REQUESTED: usize = 256;
+
+/// Bytes read at a time, a fetch that's no longer wanted is cancelled between them
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// How a [CoverPrefetcher] fetches and how much it keeps
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoverPrefetchConfig {
+  /// Covers kept, the oldest is dropped for a new one
+  pub capacity: usize,
+  /// Covers larger than this many bytes are skipped
+  pub max_size: u64,
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub timeout: Duration,
+  /// Covers fetched at the same time
+  pub concurrency: usize,
+  /// Least time between two requests to the same host,
+  /// so skipping through tracks doesn't flood a cover server
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub host_interval: Duration,
+}
+
+impl Default for CoverPrefetchConfig {
+  fn default() -> Self {
+    Self {
+      capacity: 8,
+      max_size: 10 * 1024 * 1024,
+      timeout: Duration::from_secs(10),
+      concurrency: 2,
+      host_interval: Duration::from_millis(250),
+    }
+  }
+}
+
+/// Fetches the covers of what is playing and of the next [queue](MediaMetadata::queue) entry ahead of time,
+/// so the cover is there the moment the track changes, like for overlays that crossfade covers
+///
+/// Sources that only report [MediaMetadata::cover_url], like MPRIS, get their cover
+/// with [CoverPrefetcher::fill], `file://`, `http://` and `https://` urls are fetched
+///
+/// Fetches of covers that aren't wanted anymore, because the track changed before they were done,
+/// are cancelled, at most [CoverPrefetchConfig::concurrency] run at once
+///
+/// ```rs
+/// let mut prefetcher = CoverPrefetcher::new(CoverPrefetchConfig::default());
+///
+/// loop {
+///   let event = listener.next()?;
+///   let mut media = listener.poll()?;
+///
+///   prefetcher.handle(&event, &media)?;
+///   prefetcher.fill(&mut media);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CoverPrefetcher {
+  shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+  cache: Mutex<CoverCache>,
+  /// Notified when a url is queued or the prefetcher is dropped
+  queued: Condvar,
+}
+
+/// Fetched covers by url, oldest first, and the urls waiting to be fetched
+#[derive(Debug, Default)]
+struct CoverCache {
+  capacity: usize,
+  covers: VecDeque<(String, MediaImage)>,
+  /// Urls that are being fetched or failed, they aren't asked for again
+  requested: HashSet<String>,
+  /// Urls waiting for a fetch task
+  queue: VecDeque<String>,
+  /// Urls of the last [CoverPrefetcher::prefetch], fetches of others are cancelled
+  wanted: Vec<String>,
+  /// When each host can be requested again
+  hosts: HashMap<String, Instant>,
+  closed: bool,
+}
+
+impl CoverCache {
+  fn get(&self, url: &str) -> Option<&MediaImage> {
+    self
+      .covers
+      .iter()
+      .find(|(cached, _)| cached == url)
+      .map(|(_, cover)| cover)
+  }
+
+  fn insert(&mut self, url: String, cover: MediaImage) {
+    while self.covers.len() >= self.capacity.max(1) {
+      if let Some((url, _)) = self.covers.pop_front() {
+        self.requested.remove(&url);
+      }
+    }
+
+    self.covers.push_back((url, cover));
+  }
+
+  fn is_wanted(&self, url: &str) -> bool {
+    !self.closed && self.wanted.iter().any(|wanted| wanted == url)
+  }
+
+  /// Forgets a cancelled url so it's fetched again if it's wanted again
+  fn cancel(&mut self, url: &str) {
+    self.requested.remove(url);
+  }
+}
+
+impl CoverPrefetcher {
+  pub fn new(cfg: CoverPrefetchConfig) -> Self {
+    let shared = Arc::new(Shared {
+      cache: Mutex::new(CoverCache {
+        capacity: cfg.capacity,
+        ..CoverCache::default()
+      }),
+      queued: Condvar::new(),
+    });
+
+    // end once the prefetcher is dropped
+    for _ in 0..cfg.concurrency.max(1) {
+      let cfg = cfg.clone();
+      let shared = shared.clone();
+      std::thread::spawn(move || fetch_task(&cfg, &shared));
+    }
+
+    Self { shared }
+  }
+
+  /// Starts fetching the cover of `media` and of the next entry in its queue,
+  /// covers that were fetched or failed already aren't fetched again
+  /// and fetches of any other covers are cancelled
+  pub fn prefetch(&self, media: &MediaMetadata) {
+    let next = media
+      .queue
+      .first()
+      .and_then(|entry| entry.cover_url.as_ref());
+
+    let mut cache = self.shared.cache.lock().unwrap();
+
+    // failed urls are given another chance every now and then
+    if cache.requested.len() > MAX_REQUESTED {
+      let CoverCache {
+        covers, requested, ..
+      } = &mut *cache;
+      requested.retain(|url| covers.iter().any(|(cached, _)| cached == url));
+    }
+
+    cache.wanted = [media.cover_url.as_ref(), next]
+      .into_iter()
+      .flatten()
+      .cloned()
+      .collect();
+
+    let CoverCache {
+      queue,
+      requested,
+      wanted,
+      ..
+    } = &mut *cache;
+
+    queue.retain(|url| {
+      let keep = wanted.contains(url);
+
+      if !keep {
+        requested.remove(url);
+      }
+
+      keep
+    });
+
+    for url in cache.wanted.clone() {
+      if cache.requested.insert(url.clone()) {
+        cache.queue.push_back(url);
+        self.shared.queued.notify_one();
+      }
+    }
+  }
+
+  /// The cover of `url` if it's been fetched
+  pub fn get(&self, url: &str) -> Option<MediaImage> {
+    self.shared.cache.lock().unwrap().get(url).cloned()
+  }
+
+  /// Sets [MediaMetadata::cover] from the cache if it has none, returns if it did
+  pub fn fill(&self, media: &mut MediaMetadata) -> bool {
+    if media.cover.is_some() {
+      return false;
+    }
+
+    media.cover = media.cover_url.as_deref().and_then(|url| self.get(url));
+    media.cover.is_some()
+  }
+}
+
+impl Drop for CoverPrefetcher {
+  fn drop(&mut self) {
+    self.shared.cache.lock().unwrap().closed = true;
+    self.shared.queued.notify_all();
+  }
+}
+
+impl MediaSink for CoverPrefetcher {
+  fn handle(&mut self, event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    if matches!(
+      event,
+      MediaEvent::MediaChanged(_) | MediaEvent::QueueChanged(_)
+    ) {
+      self.prefetch(media);
+    }
+
+    Ok(())
+  }
+}
+
+fn fetch_task(cfg: &CoverPrefetchConfig, shared: &Shared) {
+  let agent = ureq::AgentBuilder::new().timeout(cfg.timeout).build();
+
+  loop {
+    let (url, wait) = {
+      let mut cache = shared.cache.lock().unwrap();
+
+      while cache.queue.is_empty() && !cache.closed {
+        cache = shared.queued.wait(cache).unwrap();
+      }
+
+      let Some(url) = cache.queue.pop_front().filter(|_| !cache.closed) else {
+        return;
+      };
+
+      // the host's next slot is taken now, so other tasks wait for the one after it
+      let wait = host(&url).map_or(Duration::ZERO, |host| {
+        let now = Instant::now();
+        cache.hosts.retain(|_, slot| *slot > now);
+        let slot = cache.hosts.get(host).copied().unwrap_or(now).max(now);
+        cache.hosts.insert(host.into(), slot + cfg.host_interval);
+        slot - now
+      });
+
+      (url, wait)
+    };
+
+    std::thread::sleep(wait);
+
+    let is_wanted = || shared.cache.lock().unwrap().is_wanted(&url);
+
+    match fetch(cfg, &agent, &url, is_wanted) {
+      Ok(cover) => shared.cache.lock().unwrap().insert(url, cover),
+      Err(Error::Closed) => shared.cache.lock().unwrap().cancel(&url),
+      // failed urls stay requested so they aren't tried over and over
+      Err(_) => {}
+    }
+  }
+}
+
+/// Fails with [Error::Closed] once `is_wanted` returns false
+fn fetch(
+  cfg: &CoverPrefetchConfig,
+  agent: &ureq::Agent,
+  url: &str,
+  is_wanted: impl Fn() -> bool,
+) -> Result<MediaImage> {
+  if !is_wanted() {
+    return Err(Error::Closed);
+  }
+
+  let reader: Box<dyn Read> = if let Some(path) = url.strip_prefix("file://") {
+    Box::new(std::fs::File::open(decode_path(path))?)
+  } else if url.starts_with("http://") || url.starts_with("https://") {
+    agent
+      .get(url)
+      .call()
+      .map_err(|err| Error::Connection(err.into()))?
+      .into_reader()
+  } else {
+    return Err(Error::Unsupported);
+  };
+
+  let mut reader = reader.take(cfg.max_size + 1);
+  let mut data = Vec::new();
+  let mut chunk = vec![0; CHUNK_SIZE];
+
+  loop {
+    if !is_wanted() {
+      return Err(Error::Closed);
+    }
+
+    match reader.read(&mut chunk)? {
+      0 => break,
+      read => data.extend_from_slice(&chunk[..read]),
+    }
+  }
+
+  if data.len() as u64 > cfg.max_size {
+    return Err(Error::InvalidImage(format!(
+      "cover is larger than {} bytes",
+      cfg.max_size
+    )));
+  }
+
+  Ok(MediaImage::from_bytes(data))
+}
+
+/// Host of an `http://` or `https://` url, [None] for `file://` urls which aren't rate limited
+fn host(url: &str) -> Option<&str> {
+  let rest = url
+    .strip_prefix("http://")
+    .or_else(|| url.strip_prefix("https://"))?;
+
+  let authority = rest.split(['/', '?', '#']).next()?;
+  Some(authority.rsplit('@').next().unwrap_or(authority))
+}
+
+/// Undoes the percent-encoding of a `file://` url's path
+fn decode_path(path: &str) -> String {
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+
+  String::from_utf8_lossy(&decoded).into_owned()
+}
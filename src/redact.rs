@@ -0,0 +1,289 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{MediaEvent, MediaMetadata, QueueEntry};
+
+/// Which part of the metadata a [RedactionRule] matches against
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum RedactField {
+  Title,
+  /// Any of the artists or featured artists
+  Artist,
+  Album,
+  /// [MediaMetadata::app]
+  App,
+  /// Any of the above
+  #[default]
+  Any,
+}
+
+/// What happens to metadata matched by a [RedactionRule]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum RedactAction {
+  /// Replaces the title with the placeholder and clears everything else that could identify the media
+  Placeholder(String),
+  /// Drops every event about the media until something else plays
+  Suppress,
+}
+
+/// What a [RedactionRule] matches the field against, strings are [RedactPattern::Glob]s
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum RedactPattern {
+  /// Ascii case-insensitive glob matching the whole value,
+  /// `*` matches any amount of characters and `?` a single one
+  Glob(String),
+  /// Regular expression matching anywhere in the value, `(?i)` makes it case-insensitive
+  #[cfg(feature = "regex")]
+  Regex(RegexPattern),
+}
+
+impl RedactPattern {
+  /// Fails if `pattern` isn't a valid regular expression
+  #[cfg(feature = "regex")]
+  pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+    regex::Regex::new(pattern).map(|regex| Self::Regex(RegexPattern(regex)))
+  }
+
+  pub fn matches(&self, value: &str) -> bool {
+    match self {
+      Self::Glob(pattern) => glob(pattern, value),
+      #[cfg(feature = "regex")]
+      Self::Regex(RegexPattern(regex)) => regex.is_match(value),
+    }
+  }
+}
+
+impl From<&str> for RedactPattern {
+  fn from(value: &str) -> Self {
+    Self::Glob(value.into())
+  }
+}
+
+impl From<String> for RedactPattern {
+  fn from(value: String) -> Self {
+    Self::Glob(value)
+  }
+}
+
+/// Compiled regular expression of a [RedactPattern::Regex], compared, hashed and serialized as its source
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct RegexPattern(pub regex::Regex);
+
+#[cfg(feature = "regex")]
+impl PartialEq for RegexPattern {
+  fn eq(&self, other: &Self) -> bool {
+    self.0.as_str() == other.0.as_str()
+  }
+}
+
+#[cfg(feature = "regex")]
+impl Eq for RegexPattern {}
+
+#[cfg(feature = "regex")]
+impl std::hash::Hash for RegexPattern {
+  fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    self.0.as_str().hash(state);
+  }
+}
+
+#[cfg(feature = "regex")]
+impl Serialize for RegexPattern {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.0.as_str())
+  }
+}
+
+#[cfg(feature = "regex")]
+impl<'de> Deserialize<'de> for RegexPattern {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+
+    regex::Regex::new(&pattern)
+      .map(Self)
+      .map_err(serde::de::Error::custom)
+  }
+}
+
+/// User-defined rule for hiding media from events, e.g. for stream overlays
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_redaction([
+///   RedactionRule::new(RedactField::Artist, "*copyrighted*", RedactAction::Suppress),
+///   RedactionRule::new(RedactField::App, "firefox", RedactAction::Placeholder("Something".into())),
+///   // with the regex feature
+///   RedactionRule::new(RedactField::Title, RedactPattern::regex(r"(?i)\bnsfw\b")?, RedactAction::Suppress),
+/// ]);
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct RedactionRule {
+  pub field: RedactField,
+  pub pattern: RedactPattern,
+  pub action: RedactAction,
+}
+
+impl RedactionRule {
+  pub fn new(field: RedactField, pattern: impl Into<RedactPattern>, action: RedactAction) -> Self {
+    Self {
+      field,
+      pattern: pattern.into(),
+      action,
+    }
+  }
+
+  /// Returns true if this rule applies to `metadata`
+  pub fn matches(&self, metadata: &MediaMetadata) -> bool {
+    let artists = metadata.artists.iter().chain(&metadata.featured_artists);
+
+    self.matches_fields(
+      &metadata.title,
+      artists,
+      metadata.album.as_deref(),
+      metadata.app.as_deref(),
+    )
+  }
+
+  /// Returns true if this rule applies to `entry`, queue entries have no app so [RedactField::App] never matches
+  pub fn matches_entry(&self, entry: &QueueEntry) -> bool {
+    self.matches_fields(&entry.title, &entry.artists, entry.album.as_deref(), None)
+  }
+
+  fn matches_fields<'a>(
+    &self,
+    title: &str,
+    artists: impl IntoIterator<Item = &'a String>,
+    album: Option<&str>,
+    app: Option<&str>,
+  ) -> bool {
+    let title = || self.pattern.matches(title);
+    let album = || album.is_some_and(|album| self.pattern.matches(album));
+    let app = || app.is_some_and(|app| self.pattern.matches(app));
+    let mut artists = artists.into_iter();
+
+    match self.field {
+      RedactField::Title => title(),
+      RedactField::Artist => artists.any(|artist| self.pattern.matches(artist)),
+      RedactField::Album => album(),
+      RedactField::App => app(),
+      RedactField::Any => {
+        title() || album() || app() || artists.any(|artist| self.pattern.matches(artist))
+      }
+    }
+  }
+}
+
+/// Applies [RedactionRule]s to events, the first matching rule wins
+#[derive(Debug)]
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub(crate) struct Redactor {
+  rules: Vec<RedactionRule>,
+  /// If the current media is suppressed
+  suppressed: bool,
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+impl Redactor {
+  pub(crate) fn new(rules: Vec<RedactionRule>) -> Self {
+    Self {
+      rules,
+      suppressed: false,
+    }
+  }
+
+  /// Returns the event to pass on, if any
+  pub(crate) fn redact(&mut self, event: MediaEvent) -> Option<MediaEvent> {
+    if self.rules.is_empty() {
+      return Some(event);
+    }
+
+    match event {
+      MediaEvent::MediaChanged(metadata) => {
+        let metadata = self.redact_metadata(metadata);
+        self.suppressed = metadata.is_none();
+        metadata.map(MediaEvent::MediaChanged)
+      }
+      MediaEvent::TrackEnded(metadata) => {
+        self.redact_metadata(metadata).map(MediaEvent::TrackEnded)
+      }
+      MediaEvent::ListenThresholdReached { metadata, listened } => self
+        .redact_metadata(metadata)
+        .map(|metadata| MediaEvent::ListenThresholdReached { metadata, listened }),
+      MediaEvent::StateChanged(_) | MediaEvent::ProgressChanged(_) if self.suppressed => None,
+      MediaEvent::QueueChanged(queue) => Some(MediaEvent::QueueChanged(self.redact_queue(queue))),
+      event => Some(event),
+    }
+  }
+
+  fn redact_metadata(&self, metadata: MediaMetadata) -> Option<MediaMetadata> {
+    let rule = self.rules.iter().find(|rule| rule.matches(&metadata));
+
+    let mut metadata = match rule.map(|rule| &rule.action) {
+      Some(RedactAction::Suppress) => return None,
+      Some(RedactAction::Placeholder(placeholder)) => MediaMetadata {
+        title: placeholder.clone(),
+        app: metadata.app,
+        state: metadata.state,
+        duration: metadata.duration,
+        elapsed: metadata.elapsed,
+        queue: metadata.queue,
+        ..MediaMetadata::default()
+      },
+      None => metadata,
+    };
+
+    metadata.queue = self.redact_queue(metadata.queue);
+
+    Some(metadata)
+  }
+
+  fn redact_queue(&self, queue: Vec<QueueEntry>) -> Vec<QueueEntry> {
+    queue
+      .into_iter()
+      .filter_map(|entry| {
+        let rule = self.rules.iter().find(|rule| rule.matches_entry(&entry));
+
+        match rule.map(|rule| &rule.action) {
+          Some(RedactAction::Suppress) => None,
+          Some(RedactAction::Placeholder(placeholder)) => Some(QueueEntry {
+            title: placeholder.clone(),
+            duration: entry.duration,
+            ..QueueEntry::default()
+          }),
+          None => Some(entry),
+        }
+      })
+      .collect()
+  }
+}
+
+/// Ascii case-insensitive glob match, `*` matches any amount of characters and `?` a single one
+fn glob(pattern: &str, value: &str) -> bool {
+  let pattern = pattern.chars().collect::<Vec<_>>();
+  let value = value.chars().collect::<Vec<_>>();
+
+  let (mut p, mut v) = (0, 0);
+  // position of the last `*` and what it has matched up to so far
+  let mut star = None;
+
+  while v < value.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        star = Some((p, v));
+        p += 1;
+      }
+      Some(c) if *c == '?' || c.eq_ignore_ascii_case(&value[v]) => {
+        p += 1;
+        v += 1;
+      }
+      _ => match star {
+        Some((star_p, star_v)) => {
+          p = star_p + 1;
+          v = star_v + 1;
+          star = Some((star_p, star_v + 1));
+        }
+        None => return false,
+      },
+    }
+  }
+
+  pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::UnicodeNormalization;
+
+use crate::MediaMetadata;
+
+/// Separators between multiple main (or featured) artists in one string
+const ARTIST_SEPARATORS: &[&str] = &[", ", ",", "; ", ";", " & ", " / ", " x "];
+
+/// Markers that start the featured artists part of an artist string, lowercase
+const FEATURE_MARKERS: &[&str] = &[
+  "(feat. ",
+  "(feat ",
+  "(ft. ",
+  "(featuring ",
+  "[feat. ",
+  "[ft. ",
+  " feat. ",
+  " feat ",
+  " ft. ",
+  " featuring ",
+];
+
+/// Bracketed tags in titles that mark a video upload rather than the track itself, lowercase
+const VIDEO_TAGS: &[&str] = &[
+  "official video",
+  "official music video",
+  "official audio",
+  "official lyric video",
+  "official visualizer",
+  "music video",
+  "lyric video",
+  "visualizer",
+];
+
+/// Bracketed tags that are only junk when they are the whole tag, lowercase
+const VIDEO_TAGS_EXACT: &[&str] = &["official", "video", "audio", "hd", "hq", "4k"];
+
+/// Bracketed lyrics tags, lowercase
+const LYRICS_TAGS: &[&str] = &["lyrics", "lyric", "with lyrics"];
+
+/// Suffixes of stream bitrates like `128kbps`, lowercase
+const BITRATE_UNITS: &[&str] = &["kbps", "kb/s", "k"];
+
+/// Characters that don't show up but still make two strings different, zero-width joiners
+/// are kept since emoji sequences are made of them
+const INVISIBLE: &[char] = &['\u{00AD}', '\u{200B}', '\u{2060}', '\u{FEFF}'];
+
+/// Opt-in cleanup passes applied to metadata before sources compare or emit it,
+/// passes left out when it's deserialized are off
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SanitizeConfig {
+  /// Removes invisible characters like zero-width spaces, byte order marks and control characters
+  /// and turns unicode spaces into plain ones, from the title, album and artists, runs first,
+  /// it's not unicode normalization, that's `unicode_normalization`
+  pub strip_invisible: bool,
+  /// Normalizes the title, album and artists, so the same text composed differently
+  /// (like `é` as one character or as `e` and an accent) compares the same, runs after stripping
+  #[cfg(feature = "unicode-normalization")]
+  pub unicode_normalization: Option<NormalizationForm>,
+  /// Splits artist strings like `A, B & C feat. D` into separate artists,
+  /// featured artists are moved to [MediaMetadata::featured_artists]
+  pub split_artists: bool,
+  /// Cleanup filters applied to titles in order, see [TitleFilter::defaults]
+  pub title_filters: Vec<TitleFilter>,
+}
+
+/// Unicode normalization form of [SanitizeConfig::unicode_normalization]
+#[cfg(feature = "unicode-normalization")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum NormalizationForm {
+  /// Canonical composition, only changes how characters are encoded
+  Nfc,
+  /// Compatibility composition, also turns look-alikes like full-width letters or `ﬁ` into the plain ones
+  Nfkc,
+}
+
+#[cfg(feature = "unicode-normalization")]
+impl NormalizationForm {
+  /// Returns `value` normalized to this form
+  pub fn apply(self, value: &str) -> String {
+    match self {
+      Self::Nfc => value.nfc().collect(),
+      Self::Nfkc => value.nfkc().collect(),
+    }
+  }
+}
+
+/// Strips junk that video sites and streams add to titles
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum TitleFilter {
+  /// Bracketed tags like `(Official Video)`, `[Official Music Video]` or `(HD)`
+  VideoTags,
+  /// Bracketed tags like `[Lyrics]`
+  LyricsTags,
+  /// The ` - Topic` suffix of auto-generated YouTube channels, also stripped from artists
+  Topic,
+  /// Trailing stream bitrates like `[128kbps]` or ` - 320 kbps`
+  Bitrate,
+  /// A custom suffix, ascii case-insensitive
+  Suffix(String),
+}
+
+impl TitleFilter {
+  /// Every built-in filter
+  pub fn defaults() -> Vec<Self> {
+    vec![
+      Self::VideoTags,
+      Self::LyricsTags,
+      Self::Topic,
+      Self::Bitrate,
+    ]
+  }
+
+  /// Returns `title` with this filter applied
+  pub fn apply(&self, title: &str) -> String {
+    let cleaned = match self {
+      Self::VideoTags => strip_tags(title, |tag| {
+        VIDEO_TAGS.iter().any(|t| tag.contains(t)) || VIDEO_TAGS_EXACT.contains(&tag)
+      }),
+      Self::LyricsTags => strip_tags(title, |tag| LYRICS_TAGS.contains(&tag)),
+      Self::Topic => strip_suffix_ignore_case(title, " - topic")
+        .unwrap_or(title)
+        .to_string(),
+      Self::Bitrate => strip_bitrate(title).to_string(),
+      Self::Suffix(suffix) => strip_suffix_ignore_case(title.trim_end(), suffix)
+        .unwrap_or(title)
+        .to_string(),
+    };
+
+    if cleaned == title {
+      return cleaned;
+    }
+
+    tidy(&cleaned)
+  }
+}
+
+impl SanitizeConfig {
+  /// Applies every enabled pass to `metadata`
+  pub fn apply(&self, metadata: &mut MediaMetadata) {
+    if self.strip_invisible {
+      map_text(metadata, strip_invisible);
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    if let Some(form) = self.unicode_normalization {
+      map_text(metadata, |value| form.apply(value));
+    }
+
+    for filter in &self.title_filters {
+      metadata.title = filter.apply(&metadata.title);
+
+      if let TitleFilter::Topic = filter {
+        for artist in &mut metadata.artists {
+          *artist = filter.apply(artist);
+        }
+      }
+    }
+
+    if self.split_artists {
+      let (artists, featured) = split_artists(&metadata.artists);
+
+      metadata.artists = artists;
+
+      for artist in featured {
+        if !metadata.featured_artists.contains(&artist) {
+          metadata.featured_artists.push(artist);
+        }
+      }
+    }
+  }
+}
+
+/// Replaces the title, album and artists with what `f` makes of them
+fn map_text(metadata: &mut MediaMetadata, f: impl Fn(&str) -> String) {
+  metadata.title = f(&metadata.title);
+  metadata.album = metadata.album.as_deref().map(&f);
+
+  for artist in metadata
+    .artists
+    .iter_mut()
+    .chain(&mut metadata.featured_artists)
+  {
+    *artist = f(artist);
+  }
+}
+
+/// Splits artist strings into main artists and featured artists
+///
+/// `["A, B & C feat. D"]` becomes `(["A", "B", "C"], ["D"])`
+pub fn split_artists<S: AsRef<str>>(artists: &[S]) -> (Vec<String>, Vec<String>) {
+  let mut main = Vec::new();
+  let mut featured = Vec::new();
+
+  for artist in artists {
+    let artist = artist.as_ref();
+
+    // ascii lowercase keeps byte offsets the same
+    let lowercase = artist.to_ascii_lowercase();
+
+    let feature = FEATURE_MARKERS
+      .iter()
+      .filter_map(|marker| lowercase.find(marker).map(|index| (index, marker.len())))
+      .min();
+
+    let (main_part, featured_part) = match feature {
+      Some((index, len)) => (&artist[..index], Some(&artist[index + len..])),
+      None => (artist, None),
+    };
+
+    push_split(&mut main, main_part);
+
+    if let Some(featured_part) = featured_part {
+      let featured_part = featured_part.trim_end_matches([')', ']']);
+      push_split(&mut featured, featured_part);
+    }
+  }
+
+  (main, featured)
+}
+
+fn push_split(into: &mut Vec<String>, artists: &str) {
+  let mut artists = artists.to_string();
+
+  for separator in ARTIST_SEPARATORS {
+    artists = artists.replace(separator, "\0");
+  }
+
+  for artist in artists.split('\0') {
+    let artist = artist.trim();
+
+    if !artist.is_empty() && !into.iter().any(|a| a == artist) {
+      into.push(artist.to_string());
+    }
+  }
+}
+
+/// Removes every `(...)` or `[...]` group whose lowercase, trimmed content matches `is_junk`
+fn strip_tags(title: &str, is_junk: impl Fn(&str) -> bool) -> String {
+  let mut cleaned = String::with_capacity(title.len());
+  let mut rest = title;
+
+  while let Some(start) = rest.find(['(', '[']) {
+    let close = if rest[start..].starts_with('(') {
+      ')'
+    } else {
+      ']'
+    };
+
+    let Some(len) = rest[start + 1..].find(close) else {
+      break;
+    };
+
+    let end = start + 1 + len;
+    let tag = rest[start + 1..end].trim().to_lowercase();
+
+    cleaned.push_str(&rest[..start]);
+
+    if !is_junk(&tag) {
+      cleaned.push_str(&rest[start..=end]);
+    }
+
+    rest = &rest[end + 1..];
+  }
+
+  cleaned.push_str(rest);
+  cleaned
+}
+
+/// Strips a trailing bitrate, bracketed or not
+fn strip_bitrate(title: &str) -> &str {
+  let trimmed = title.trim_end();
+
+  let (inner, bracketed) = match trimmed.strip_suffix([')', ']']) {
+    Some(inner) => (inner, true),
+    None => (trimmed, false),
+  };
+
+  let Some((number, unit)) = BITRATE_UNITS
+    .iter()
+    .find_map(|unit| strip_suffix_ignore_case(inner, unit).map(|number| (number, *unit)))
+  else {
+    return title;
+  };
+
+  let number = number.trim_end();
+
+  let digits = number.len() - number.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+
+  if digits == 0 {
+    return title;
+  }
+
+  let before = &number[..number.len() - digits];
+
+  let before = match bracketed {
+    true => match before.strip_suffix(['(', '[']) {
+      Some(before) => before,
+      None => return title,
+    },
+    false => before,
+  };
+
+  // don't cut into words like "Blink-182k"
+  if !before.is_empty() && !before.ends_with([' ', '-', '|']) {
+    return title;
+  }
+
+  // a bare "k" needs a separator, otherwise titles like "Take 5k" lose their number
+  if unit == "k" && !bracketed && !before.trim_end().ends_with(['-', '|']) {
+    return title;
+  }
+
+  before
+}
+
+fn strip_suffix_ignore_case<'a>(value: &'a str, suffix: &str) -> Option<&'a str> {
+  let index = value.len().checked_sub(suffix.len())?;
+
+  if !value.is_char_boundary(index) || !value[index..].eq_ignore_ascii_case(suffix) {
+    return None;
+  }
+
+  Some(&value[..index])
+}
+
+/// `value` without invisible characters and with every run of whitespace turned into a single space
+pub fn strip_invisible(value: &str) -> String {
+  value
+    .split(|c: char| c.is_whitespace() || c.is_control())
+    .map(|word| word.replace(INVISIBLE, ""))
+    .filter(|word| !word.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Collapses whitespace left behind and trailing separators like ` -`
+fn tidy(title: &str) -> String {
+  let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+
+  title
+    .trim_end_matches(|c: char| c == '-' || c == '|' || c.is_whitespace())
+    .to_string()
+}
@@ -0,0 +1,606 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::backoff::Retry;
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::{Error, MediaEvent, MediaImage, MediaMetadata, MediaState, Result, SourceKind};
+
+/// How long the source waits before reading the pipe again when there's nothing in it
+const PIPE_POLL: Duration = Duration::from_millis(100);
+
+/// Most bytes buffered for a single item, covers make up the largest ones,
+/// an item that doesn't end before this is thrown away
+const MAX_ITEM_SIZE: usize = 16 * 1024 * 1024;
+
+/// Rate of the RTP timestamps in `prgr` items
+const RTP_RATE: u64 = 44_100;
+
+/// Metadata pipe of shairport-sync to read from
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShairportConfig {
+  /// `metadata.pipe_name` of shairport-sync's config, it has to have `metadata.enabled`
+  /// (and `metadata.include_cover_art` for covers) turned on
+  pub pipe: PathBuf,
+}
+
+impl Default for ShairportConfig {
+  fn default() -> Self {
+    Self {
+      pipe: "/tmp/shairport-sync-metadata".into(),
+    }
+  }
+}
+
+impl ShairportConfig {
+  pub fn new(pipe: impl Into<PathBuf>) -> Self {
+    Self { pipe: pipe.into() }
+  }
+}
+
+/// Single `<item>` of the metadata pipe, like
+/// `<item><type>636f7265</type><code>6d696e6d</code><length>5</length><data encoding="base64">VGl0bGU=</data></item>`
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+  /// `core` for DMAP fields of the track, `ssnc` for shairport-sync's own
+  kind: [u8; 4],
+  code: [u8; 4],
+  data: Vec<u8>,
+}
+
+impl Item {
+  fn parse(item: &str) -> Option<Self> {
+    let kind = four_cc(tag(item, "type")?)?;
+    let code = four_cc(tag(item, "code")?)?;
+
+    let data = match tag(item, "data") {
+      Some(data) => {
+        let data = data
+          .chars()
+          .filter(|c| !c.is_ascii_whitespace())
+          .collect::<String>();
+        STANDARD.decode(data).ok()?
+      }
+      None => Vec::new(),
+    };
+
+    Some(Self { kind, code, data })
+  }
+
+  fn text(&self) -> String {
+    String::from_utf8_lossy(&self.data).into_owned()
+  }
+
+  /// Big endian number, DMAP's integers are 1 to 8 bytes long
+  fn number(&self) -> Option<u64> {
+    match self.data.len() {
+      1..=8 => Some(
+        self
+          .data
+          .iter()
+          .fold(0, |number, byte| number << 8 | *byte as u64),
+      ),
+      _ => None,
+    }
+  }
+}
+
+/// Text in between `<name ...>` and `</name>`
+fn tag<'a>(item: &'a str, name: &str) -> Option<&'a str> {
+  let start = item.find(&format!("<{name}"))?;
+  let start = start + item[start..].find('>')? + 1;
+  let end = start + item[start..].find(&format!("</{name}>"))?;
+
+  Some(&item[start..end])
+}
+
+/// Four character code out of its 8 hex digits
+fn four_cc(hex: &str) -> Option<[u8; 4]> {
+  let hex = hex.trim();
+
+  if hex.len() != 8 {
+    return None;
+  }
+
+  let mut code = [0; 4];
+
+  for (i, byte) in code.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+  }
+
+  Some(code)
+}
+
+/// Takes every complete item off the front of `buffer`, what's left is the start of the next one
+fn take_items(buffer: &mut Vec<u8>) -> Vec<Item> {
+  const END: &[u8] = b"</item>";
+
+  let mut items = Vec::new();
+
+  while let Some(end) = buffer.windows(END.len()).position(|window| window == END) {
+    let raw = buffer.drain(..end + END.len()).collect::<Vec<_>>();
+
+    // anything in front of the item is left over from one that was cut off
+    if let Some(item) = std::str::from_utf8(&raw)
+      .ok()
+      .and_then(|raw| raw.find("<item>").map(|start| &raw[start..]))
+      .and_then(Item::parse)
+    {
+      items.push(item);
+    }
+  }
+
+  if buffer.len() > MAX_ITEM_SIZE {
+    buffer.clear();
+  }
+
+  items
+}
+
+/// What the items so far said about the current track
+#[derive(Debug)]
+struct Session {
+  media: MediaMetadata,
+  /// Track being read in between `mdst` and `mden`
+  next: Option<MediaMetadata>,
+  /// Who is streaming, `snam` and `clip`
+  client: BTreeMap<String, serde_json::Value>,
+  /// Elapsed at the last `prgr` or change of state and when that was, elapsed is counted on from it
+  anchor: (Duration, Instant),
+  last_progress: Instant,
+  track_end: TrackEndDetector,
+}
+
+impl Session {
+  fn new() -> Self {
+    let now = Instant::now();
+
+    Self {
+      media: MediaMetadata {
+        app: Some("AirPlay".into()),
+        ..MediaMetadata::default()
+      },
+      next: None,
+      client: BTreeMap::new(),
+      anchor: (Duration::ZERO, now),
+      last_progress: now,
+      track_end: TrackEndDetector::default(),
+    }
+  }
+
+  fn elapsed(&self) -> Duration {
+    let (elapsed, at) = self.anchor;
+
+    let elapsed = match self.media.state {
+      MediaState::Playing => elapsed + at.elapsed(),
+      _ => elapsed,
+    };
+
+    match self.media.duration.is_zero() {
+      true => elapsed,
+      false => elapsed.min(self.media.duration),
+    }
+  }
+
+  fn set_state(&mut self, state: MediaState) -> Vec<MediaEvent> {
+    if self.media.state == state {
+      return Vec::new();
+    }
+
+    self.media.elapsed = self.elapsed();
+    self.anchor = (self.media.elapsed, Instant::now());
+    self.media.state = state;
+
+    vec![MediaEvent::StateChanged(state)]
+  }
+
+  fn progress(&mut self, elapsed: Duration) -> Vec<MediaEvent> {
+    let event = MediaEvent::ProgressChanged(elapsed);
+    let mut events = self
+      .track_end
+      .detect(&self.media, &event)
+      .into_iter()
+      .collect::<Vec<_>>();
+
+    self.media.elapsed = elapsed;
+    self.last_progress = Instant::now();
+    events.push(event);
+
+    events
+  }
+
+  /// Counts elapsed on while playing, shairport-sync only sends it when playback starts or jumps
+  fn tick(&mut self, interval: Duration) -> Vec<MediaEvent> {
+    if self.media.state != MediaState::Playing || self.last_progress.elapsed() < interval {
+      return Vec::new();
+    }
+
+    self.progress(self.elapsed())
+  }
+
+  fn handle(&mut self, item: Item, cfg: &MediaSourceConfig) -> Vec<MediaEvent> {
+    match (&item.kind, &item.code) {
+      (b"core", code) => {
+        let next = self.next.get_or_insert_with(MediaMetadata::default);
+
+        match code {
+          b"minm" => next.title = item.text(),
+          b"asar" => next.artists = vec![item.text()],
+          b"asal" => next.album = Some(item.text()),
+          b"mper" => next.uid = item.number().map(|id| id.to_string()),
+          b"astm" => next.duration = Duration::from_millis(item.number().unwrap_or_default()),
+          b"asaa" => {
+            next.extra.insert("album_artist".into(), item.text().into());
+          }
+          b"asgn" => {
+            next.extra.insert("genre".into(), item.text().into());
+          }
+          b"ascp" => {
+            next.extra.insert("composer".into(), item.text().into());
+          }
+          _ => {}
+        }
+
+        Vec::new()
+      }
+      (b"ssnc", b"mdst") => {
+        self.next = Some(MediaMetadata::default());
+        Vec::new()
+      }
+      (b"ssnc", b"mden") => match self.next.take() {
+        Some(next) => self.finish(next, cfg),
+        None => Vec::new(),
+      },
+      (b"ssnc", b"PICT") if cfg.covers && !item.data.is_empty() => {
+        self.media.cover = Some(MediaImage::from_bytes(item.data));
+        self.media.elapsed = self.elapsed();
+        vec![MediaEvent::MediaChanged(self.media.clone())]
+      }
+      (b"ssnc", b"prgr") => {
+        let text = item.text();
+        let mut frames = text.trim().split('/').map(|frame| frame.parse::<u32>());
+
+        let (Some(Ok(start)), Some(Ok(current)), Some(Ok(end))) =
+          (frames.next(), frames.next(), frames.next())
+        else {
+          return Vec::new();
+        };
+
+        // rtp timestamps wrap around
+        let frames = |frames: u32| Duration::from_millis(frames as u64 * 1000 / RTP_RATE);
+        let elapsed = frames(current.wrapping_sub(start));
+
+        if self.media.duration.is_zero() {
+          self.media.duration = frames(end.wrapping_sub(start));
+        }
+
+        self.anchor = (elapsed, Instant::now());
+        self.progress(elapsed)
+      }
+      (b"ssnc", b"pbeg" | b"prsm" | b"pffr") => self.set_state(MediaState::Playing),
+      (b"ssnc", b"pfls") => self.set_state(MediaState::Paused),
+      (b"ssnc", b"pend") => self.set_state(MediaState::Stopped),
+      (b"ssnc", b"snam") => {
+        self.client.insert("client".into(), item.text().into());
+        Vec::new()
+      }
+      (b"ssnc", b"clip") => {
+        self.client.insert("client_ip".into(), item.text().into());
+        Vec::new()
+      }
+      _ => Vec::new(),
+    }
+  }
+
+  /// Takes over the track read in between `mdst` and `mden`
+  fn finish(&mut self, mut next: MediaMetadata, cfg: &MediaSourceConfig) -> Vec<MediaEvent> {
+    next.state = self.media.state;
+    next.app = self.media.app.clone();
+    next.extra.extend(self.client.clone());
+
+    // `prgr` can come before the track's metadata
+    if next.duration.is_zero() {
+      next.duration = self.media.duration;
+    }
+
+    cfg.sanitize.apply(&mut next);
+    cfg.apply_zone(&mut next);
+
+    if !cfg.identity.is_different(&self.media, &next) {
+      next.elapsed = self.elapsed();
+      next.cover = self.media.cover.take();
+      self.media = next;
+      return Vec::new();
+    }
+
+    let event = MediaEvent::MediaChanged(next.clone());
+    let mut events = self
+      .track_end
+      .detect(&self.media, &event)
+      .into_iter()
+      .collect::<Vec<_>>();
+
+    self.anchor = (Duration::ZERO, Instant::now());
+    self.media = next;
+    events.push(event);
+
+    events
+  }
+}
+
+/// Source for what is streamed with AirPlay to [shairport-sync](https://github.com/mikebrady/shairport-sync),
+/// like on a Raspberry Pi that is an AirPlay speaker, those have no MPRIS player to read
+///
+/// Reads shairport-sync's metadata pipe, its MQTT output isn't supported.
+/// Elapsed is counted on locally in between the progress shairport-sync sends
+/// and covers are only there with `metadata.include_cover_art` on
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_shairport(ShairportConfig::default());
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(ShairportMediaSource::NAME, ShairportMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct ShairportMediaSource {
+  timeout: Duration,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  _background_task: JoinHandle<()>,
+}
+
+impl ShairportMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "shairport";
+}
+
+impl MediaSource for ShairportMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(shairport) = cfg.shairport.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let cfg = cfg.for_source(&SourceKind::Custom(ShairportMediaSource::NAME.into()));
+
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Custom(ShairportMediaSource::NAME.into()));
+
+    let _background_task = spawn_background_task(
+      cfg.clone(),
+      shairport,
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      last_error.clone(),
+      send,
+    )?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      _background_task,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.cancel_token.load(Ordering::SeqCst)
+  }
+
+  fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for ShairportMediaSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_background_task(
+  cfg: MediaSourceConfig,
+  shairport: ShairportConfig,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn(ShairportMediaSource::NAME, move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+    let source = SourceKind::Custom(ShairportMediaSource::NAME.into());
+
+    loop {
+      let result = catch_panic(&source, &metadata, || {
+        background_task(
+          &cfg,
+          &shairport,
+          &cancel_token,
+          &is_running,
+          &metadata,
+          &heartbeat,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) | Err(Error::Closed) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+
+          events.send(MediaEvent::SourceError {
+            source: source.clone(),
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(source));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
+      }
+    }
+  })
+}
+
+fn background_task(
+  cfg: &MediaSourceConfig,
+  shairport: &ShairportConfig,
+  cancel_token: &AtomicBool,
+  is_running: &AtomicBool,
+  metadata: &RwLock<MediaMetadata>,
+  heartbeat: &Heartbeat,
+  send: ChannelSender,
+) -> Result<()> {
+  let mut send = EventSender::new(send, cfg);
+
+  // non-blocking so opening doesn't wait for shairport-sync and reading doesn't keep a dropped source around,
+  // reads come back empty while shairport-sync isn't running
+  let mut pipe = OpenOptions::new()
+    .read(true)
+    .custom_flags(libc::O_NONBLOCK)
+    .open(&shairport.pipe)?;
+
+  let mut session = Session::new();
+  let mut buffer = Vec::new();
+  let mut chunk = vec![0; 64 * 1024];
+
+  *metadata.write().unwrap() = session.media.clone();
+  is_running.store(true, Ordering::SeqCst);
+
+  loop {
+    if cancel_token.load(Ordering::SeqCst) {
+      return Err(Error::Closed);
+    }
+
+    let read = match pipe.read(&mut chunk) {
+      Ok(read) => read,
+      Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => 0,
+      Err(err) => return Err(err.into()),
+    };
+
+    buffer.extend_from_slice(&chunk[..read]);
+
+    let items = take_items(&mut buffer);
+    let mut events = Vec::new();
+
+    // shairport-sync is quiet for as long as a track plays on, that doesn't make it stale
+    if !items.is_empty() || session.media.state == MediaState::Playing {
+      if let Some(state) = heartbeat.beat() {
+        session.media.state = state;
+      }
+    }
+
+    let changed = !items.is_empty();
+
+    for item in items {
+      events.extend(session.handle(item, cfg));
+    }
+
+    events.extend(session.tick(cfg.update_interval(MediaState::Playing)));
+
+    if changed || !events.is_empty() {
+      *metadata.write().unwrap() = MediaMetadata {
+        elapsed: session.elapsed(),
+        ..session.media.clone()
+      };
+    }
+
+    for event in events {
+      send.send(event);
+    }
+
+    send.flush();
+
+    if read == 0 {
+      std::thread::sleep(PIPE_POLL);
+    }
+  }
+}
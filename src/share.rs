@@ -0,0 +1,227 @@
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::spotify::SpotifyKind;
+use crate::MediaMetadata;
+
+/// Where a [ShareLink] leads
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum ShareService {
+  Spotify,
+  YouTube,
+  YouTubeMusic,
+  AppleMusic,
+  MusicBrainz,
+}
+
+impl ShareService {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Spotify => "Spotify",
+      Self::YouTube => "YouTube",
+      Self::YouTubeMusic => "YouTube Music",
+      Self::AppleMusic => "Apple Music",
+      Self::MusicBrainz => "MusicBrainz",
+    }
+  }
+}
+
+/// Link to the media on a [ShareService], see [MediaMetadata::share_links]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ShareLink {
+  pub service: ShareService,
+  /// Leads straight to the media, otherwise it's a search for the artists and title
+  pub direct: bool,
+  pub url: String,
+}
+
+/// Where searches go, in the order they are returned
+const SEARCHES: [(ShareService, &str); 3] = [
+  (ShareService::Spotify, "https://open.spotify.com/search/"),
+  (
+    ShareService::YouTubeMusic,
+    "https://music.youtube.com/search?q=",
+  ),
+  (
+    ShareService::AppleMusic,
+    "https://music.apple.com/search?term=",
+  ),
+];
+
+/// Keys in [MediaMetadata::extra] sources put MusicBrainz recording ids under
+const MUSICBRAINZ_KEYS: [&str; 3] = [
+  "xesam:musicBrainzTrackID",
+  "musicbrainz_trackid",
+  "musicbrainz_recordingid",
+];
+
+impl MediaMetadata {
+  /// Links to share what is playing, like for chat bots
+  ///
+  /// Direct links come first, from a Spotify [uri](MediaMetadata::uri), a YouTube or Apple Music url
+  /// as the uri, or a MusicBrainz recording id in [MediaMetadata::extra],
+  /// then searches for the artists and title on Spotify, YouTube Music and Apple Music
+  /// for each of them without a direct link, nothing if there's no title
+  ///
+  /// ```rs
+  /// for link in metadata.share_links() {
+  ///   println!("{}: {}", link.service.name(), link.url);
+  /// }
+  /// ```
+  pub fn share_links(&self) -> Vec<ShareLink> {
+    if self.title.is_empty() {
+      return Vec::new();
+    }
+
+    let direct = |service, url| ShareLink {
+      service,
+      direct: true,
+      url,
+    };
+
+    let mut links = Vec::new();
+
+    if let Some(spotify) = self
+      .spotify()
+      .filter(|spotify| matches!(spotify.kind, SpotifyKind::Track | SpotifyKind::Episode))
+    {
+      links.push(direct(ShareService::Spotify, spotify.to_web_url()));
+    }
+
+    links.extend(
+      [&self.uri, &self.uid]
+        .into_iter()
+        .flatten()
+        .find_map(|value| youtube_link(value)),
+    );
+
+    if let Some(url) = [&self.uri, &self.uid]
+      .into_iter()
+      .flatten()
+      .find_map(|value| apple_music_url(value))
+    {
+      links.push(direct(ShareService::AppleMusic, url));
+    }
+
+    if let Some(mbid) = self.musicbrainz_recording() {
+      links.push(direct(
+        ShareService::MusicBrainz,
+        format!("https://musicbrainz.org/recording/{mbid}"),
+      ));
+    }
+
+    let query = encode_query(&format!("{} {}", self.artists.join(" "), self.title));
+
+    for (service, search) in SEARCHES {
+      if !links.iter().any(|link| link.service == service) {
+        links.push(ShareLink {
+          service,
+          direct: false,
+          url: format!("{search}{query}"),
+        });
+      }
+    }
+
+    links
+  }
+
+  /// First MusicBrainz recording id in [MediaMetadata::extra], MPRIS has them in a list
+  fn musicbrainz_recording(&self) -> Option<&str> {
+    MUSICBRAINZ_KEYS
+      .iter()
+      .filter_map(|key| self.extra.get(*key))
+      .find_map(|value| match value {
+        serde_json::Value::String(id) => Some(id.as_str()),
+        serde_json::Value::Array(ids) => ids.first()?.as_str(),
+        _ => None,
+      })
+      .filter(|id| is_mbid(id))
+  }
+}
+
+/// Direct link to a YouTube or YouTube Music video, kept on the site it was played on
+fn youtube_link(value: &str) -> Option<ShareLink> {
+  let rest = strip_scheme(value);
+  let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+  let id = match host {
+    "youtu.be" => path.split(['?', '#']).next()?,
+    "youtube.com" | "www.youtube.com" | "m.youtube.com" | "music.youtube.com" => {
+      let query = path.strip_prefix("watch?")?.split('#').next()?;
+      query.split('&').find_map(|pair| pair.strip_prefix("v="))?
+    }
+    _ => return None,
+  };
+
+  let valid = id.len() == 11
+    && id
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+
+  if !valid {
+    return None;
+  }
+
+  Some(if host == "music.youtube.com" {
+    ShareLink {
+      service: ShareService::YouTubeMusic,
+      direct: true,
+      url: format!("https://music.youtube.com/watch?v={id}"),
+    }
+  } else {
+    ShareLink {
+      service: ShareService::YouTube,
+      direct: true,
+      url: format!("https://www.youtube.com/watch?v={id}"),
+    }
+  })
+}
+
+/// `value` without its query if it's an Apple Music url
+fn apple_music_url(value: &str) -> Option<String> {
+  let path = strip_scheme(value).strip_prefix("music.apple.com/")?;
+
+  // only the `i` parameter picks the song out of an album
+  let (path, query) = path.split_once('?').unwrap_or((path, ""));
+  let song = query.split('&').find(|pair| pair.starts_with("i="));
+
+  Some(match song {
+    Some(song) => format!("https://music.apple.com/{path}?{song}"),
+    None => format!("https://music.apple.com/{path}"),
+  })
+}
+
+fn strip_scheme(value: &str) -> &str {
+  let value = value.trim();
+
+  value
+    .strip_prefix("https://")
+    .or_else(|| value.strip_prefix("http://"))
+    .unwrap_or(value)
+}
+
+/// `8-4-4-4-12` hex digits
+fn is_mbid(id: &str) -> bool {
+  let groups = id.split('-').map(str::len).collect::<Vec<_>>();
+
+  groups == [8, 4, 4, 4, 12]
+    && id
+      .bytes()
+      .all(|byte| byte == b'-' || byte.is_ascii_hexdigit())
+}
+
+/// Percent-encodes everything but unreserved characters, spaces become `%20` which every service takes
+fn encode_query(query: &str) -> String {
+  let mut encoded = String::new();
+
+  for byte in query.trim().bytes() {
+    if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+      encoded.push(byte as char);
+    } else {
+      let _ = write!(encoded, "%{byte:02X}");
+    }
+  }
+
+  encoded
+}
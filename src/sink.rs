@@ -0,0 +1,161 @@
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EventKind, MediaCommand, MediaEvent, MediaMetadata, Result};
+
+/// Somewhere media is sent to, fed with the events of a [MediaListener](crate::listener::MediaListener)
+///
+/// ```rs
+/// let mut sink = FileSink::new("now-playing.txt", "{artists} - {title}");
+///
+/// loop {
+///   if let Ok(event) = listener.next() {
+///     sink.offer(&event, &listener.poll()?)?;
+///   }
+/// }
+/// ```
+pub trait MediaSink: Send {
+  /// Called for every event the sink subscribed to, `media` is what the listener reports once the event happened
+  fn handle(&mut self, event: &MediaEvent, media: &MediaMetadata) -> Result<()>;
+
+  /// Called regularly even when there are no events, for sinks that hold media back, like to rate limit
+  fn tick(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  /// Types of events the sink wants, the others are left out by [MediaSink::offer]
+  fn events(&self) -> EventKind {
+    EventKind::ALL
+  }
+
+  /// Hands `event` to [MediaSink::handle] if the sink subscribed to its type, see [MediaSink::events]
+  fn offer(&mut self, event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    match self.events().contains(event.kind()) {
+      true => self.handle(event, media),
+      false => Ok(()),
+    }
+  }
+
+  /// Narrows the events the sink gets down to `events`, so one that doesn't care about
+  /// progress ticks never sees them
+  ///
+  /// ```rs
+  /// let sink = FileSink::new("now-playing.txt", "{artists} - {title}")
+  ///   .subscribe(EventKind::MEDIA_CHANGED | EventKind::STATE_CHANGED);
+  /// ```
+  fn subscribe(self, events: EventKind) -> Subscribed<Self>
+  where
+    Self: Sized,
+  {
+    Subscribed { sink: self, events }
+  }
+}
+
+/// Sink that only gets some types of events, see [MediaSink::subscribe]
+#[derive(Debug, Clone)]
+pub struct Subscribed<S> {
+  sink: S,
+  events: EventKind,
+}
+
+impl<S: MediaSink> MediaSink for Subscribed<S> {
+  fn handle(&mut self, event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    self.sink.handle(event, media)
+  }
+
+  fn tick(&mut self) -> Result<()> {
+    self.sink.tick()
+  }
+
+  fn events(&self) -> EventKind {
+    self.events & self.sink.events()
+  }
+}
+
+/// Where sinks that take control calls, like media keys on an exported player, send them,
+/// usually [MediaSource::control](crate::listener::MediaSource::control) of the listener feeding the sink
+///
+/// ```rs
+/// let listener = Arc::new(listener);
+/// let controlled = listener.clone();
+///
+/// let sink = MprisExportSink::new(move |command| controlled.control(command))?;
+/// ```
+pub trait CommandHandler: Send + Sync {
+  fn on_command(&self, command: MediaCommand) -> Result<()>;
+}
+
+impl Debug for dyn CommandHandler {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str("dyn CommandHandler")
+  }
+}
+
+impl<F> CommandHandler for F
+where
+  F: Fn(MediaCommand) -> Result<()> + Send + Sync,
+{
+  fn on_command(&self, command: MediaCommand) -> Result<()> {
+    self(command)
+  }
+}
+
+/// Where a [FileSink] writes to and what
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FileSinkConfig {
+  pub path: PathBuf,
+  /// See [MediaMetadata::format]
+  pub template: String,
+}
+
+/// Keeps a file filled with the formatted media, for things like OBS text sources
+#[derive(Debug, Clone)]
+pub struct FileSink {
+  cfg: FileSinkConfig,
+  /// What was last written, the file is only touched when it changes
+  written: Option<String>,
+}
+
+impl FileSink {
+  pub fn new(path: impl Into<PathBuf>, template: impl Into<String>) -> Self {
+    Self::from_config(FileSinkConfig {
+      path: path.into(),
+      template: template.into(),
+    })
+  }
+
+  pub fn from_config(cfg: FileSinkConfig) -> Self {
+    Self { cfg, written: None }
+  }
+}
+
+impl MediaSink for FileSink {
+  fn handle(&mut self, _event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    let text = media.format(&self.cfg.template).to_string();
+
+    if self.written.as_ref() == Some(&text) {
+      return Ok(());
+    }
+
+    let path = &self.cfg.path;
+
+    if let Some(parent) = path
+      .parent()
+      .filter(|parent| !parent.as_os_str().is_empty())
+    {
+      fs::create_dir_all(parent)?;
+    }
+
+    // readers polling the file never see it half written
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &text)?;
+    fs::rename(tmp, path)?;
+
+    self.written = Some(text);
+
+    Ok(())
+  }
+}
@@ -0,0 +1,147 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, MediaMetadata};
+
+/// What a [SpotifyRef] points to
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum SpotifyKind {
+  Track,
+  Album,
+  Artist,
+  Playlist,
+  Episode,
+  Show,
+}
+
+impl SpotifyKind {
+  /// Name used in uris and urls, e.g. `track`
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Self::Track => "track",
+      Self::Album => "album",
+      Self::Artist => "artist",
+      Self::Playlist => "playlist",
+      Self::Episode => "episode",
+      Self::Show => "show",
+    }
+  }
+
+  fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "track" => Some(Self::Track),
+      "album" => Some(Self::Album),
+      "artist" => Some(Self::Artist),
+      "playlist" => Some(Self::Playlist),
+      "episode" => Some(Self::Episode),
+      "show" => Some(Self::Show),
+      _ => None,
+    }
+  }
+}
+
+/// Reference to something on Spotify, parsed from any of
+/// - `spotify:track:4uLU6hMCjMI75M1A2tKUQC`
+/// - `https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=...`, with or without the scheme
+///   or a locale like `/intl-de/`
+/// - `/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC`, the MPRIS track id of the Spotify app
+///
+/// ```rs
+/// let track = SpotifyRef::parse("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc").unwrap();
+///
+/// assert_eq!(track.track_id(), Some("4uLU6hMCjMI75M1A2tKUQC"));
+/// assert_eq!(track.to_uri(), "spotify:track:4uLU6hMCjMI75M1A2tKUQC");
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct SpotifyRef {
+  pub kind: SpotifyKind,
+  /// Base62 id, 22 characters long
+  pub id: String,
+}
+
+impl SpotifyRef {
+  /// [None] if `value` isn't a Spotify uri or url, or the id isn't valid
+  pub fn parse(value: &str) -> Option<Self> {
+    let value = value.trim();
+
+    let (kind, id) = if let Some(rest) = value.strip_prefix("spotify:") {
+      rest.split_once(':')?
+    } else if let Some(rest) = value.strip_prefix("/com/spotify/") {
+      rest.split_once('/')?
+    } else {
+      let rest = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"))
+        .unwrap_or(value)
+        .strip_prefix("open.spotify.com/")?;
+
+      let rest = rest.split(['?', '#']).next().unwrap_or_default();
+      let mut segments = rest
+        .split('/')
+        .filter(|segment| !segment.starts_with("intl-"));
+
+      (segments.next()?, segments.next()?)
+    };
+
+    let kind = SpotifyKind::from_name(kind)?;
+
+    if !is_id(id) {
+      return None;
+    }
+
+    Some(Self {
+      kind,
+      id: id.to_string(),
+    })
+  }
+
+  /// The id if this is a track
+  pub fn track_id(&self) -> Option<&str> {
+    (self.kind == SpotifyKind::Track).then_some(self.id.as_str())
+  }
+
+  /// `https://open.spotify.com/<kind>/<id>`
+  pub fn to_web_url(&self) -> String {
+    format!(
+      "https://open.spotify.com/{}/{}",
+      self.kind.as_str(),
+      self.id
+    )
+  }
+
+  /// `spotify:<kind>:<id>`
+  pub fn to_uri(&self) -> String {
+    format!("spotify:{}:{}", self.kind.as_str(), self.id)
+  }
+}
+
+fn is_id(id: &str) -> bool {
+  id.len() == 22 && id.bytes().all(|byte| byte.is_ascii_alphanumeric())
+}
+
+impl Display for SpotifyRef {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "spotify:{}:{}", self.kind.as_str(), self.id)
+  }
+}
+
+impl FromStr for SpotifyRef {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Error> {
+    Self::parse(value)
+      .ok_or_else(|| Error::InvalidMetadata(format!("not a spotify uri or url: {value}")))
+  }
+}
+
+impl MediaMetadata {
+  /// Spotify reference from [MediaMetadata::uri], or [MediaMetadata::uid] if the uri isn't one
+  pub fn spotify(&self) -> Option<SpotifyRef> {
+    [&self.uri, &self.uid]
+      .into_iter()
+      .flatten()
+      .find_map(|value| SpotifyRef::parse(value))
+  }
+}
@@ -0,0 +1,232 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::sink::MediaSink;
+use crate::{MediaEvent, MediaMetadata, Result};
+
+const ELLIPSIS: &str = "…";
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+/// How a [Ticker] fits text that is wider than it
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum TickerMode {
+  /// Moves the text along by a character every [Ticker::advance], starting over after `separator`
+  Scroll { separator: String },
+  /// Cuts the text off with `…`
+  Ellipsis,
+}
+
+impl Default for TickerMode {
+  fn default() -> Self {
+    Self::Scroll {
+      separator: "   ".into(),
+    }
+  }
+}
+
+/// Frames of the formatted media that are exactly `width` columns wide, for LCDs, status bars
+/// and Stream Deck keys, text that fits is padded with spaces
+///
+/// Text is cut between graphemes, so accents, flags and emoji sequences are never split,
+/// and wide characters like CJK count as two columns
+///
+/// Feed it the media with [Ticker::update], or as a [MediaSink], it starts over when the text changes
+///
+/// ```rs
+/// let mut ticker = Ticker::new("{artists} - {title}", 16);
+///
+/// loop {
+///   ticker.update(&listener.poll()?);
+///   lcd.write(&ticker.advance());
+///   sleep(Duration::from_millis(300));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Ticker {
+  template: String,
+  width: usize,
+  mode: TickerMode,
+  text: String,
+  graphemes: Vec<Grapheme>,
+  /// Grapheme the next scrolling frame starts at
+  offset: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Grapheme {
+  text: String,
+  width: usize,
+}
+
+impl Ticker {
+  /// `template` is filled in like [MediaMetadata::format], scrolls by default
+  pub fn new(template: impl Into<String>, width: usize) -> Self {
+    Self {
+      template: template.into(),
+      width,
+      mode: TickerMode::default(),
+      text: String::new(),
+      graphemes: Vec::new(),
+      offset: 0,
+    }
+  }
+
+  pub fn set_mode(self, mode: TickerMode) -> Self {
+    Self {
+      mode,
+      offset: 0,
+      ..self
+    }
+  }
+
+  /// Formats `media`, returns if the text changed, scrolling starts over when it did
+  pub fn update(&mut self, media: &MediaMetadata) -> bool {
+    let text = if media.title.is_empty() {
+      String::new()
+    } else {
+      media.format(&self.template).to_string()
+    };
+
+    if text == self.text {
+      return false;
+    }
+
+    self.graphemes = graphemes(&text);
+    self.text = text;
+    self.offset = 0;
+
+    true
+  }
+
+  /// The whole text, not fitted to the width
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Current frame, the same until [Ticker::advance]
+  pub fn frame(&self) -> String {
+    let fits = self
+      .graphemes
+      .iter()
+      .map(|grapheme| grapheme.width)
+      .sum::<usize>()
+      <= self.width;
+
+    match &self.mode {
+      _ if fits => fit(self.graphemes.iter(), self.width),
+      TickerMode::Ellipsis => ellipsize(&self.graphemes, self.width),
+      TickerMode::Scroll { separator } => {
+        let separator = graphemes(separator);
+        let cycle = self.graphemes.iter().chain(&separator);
+        let len = self.graphemes.len() + separator.len();
+
+        fit(cycle.cycle().skip(self.offset % len), self.width)
+      }
+    }
+  }
+
+  /// Moves a scrolling ticker along by a grapheme and returns the frame it was on
+  pub fn advance(&mut self) -> String {
+    let frame = self.frame();
+    self.offset = self.offset.wrapping_add(1);
+    frame
+  }
+}
+
+impl MediaSink for Ticker {
+  fn handle(&mut self, _event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    self.update(media);
+    Ok(())
+  }
+}
+
+/// `text` cut off with `…` if it's wider than `width` columns, not padded
+pub fn truncate(text: &str, width: usize) -> String {
+  let graphemes = graphemes(text);
+
+  if graphemes
+    .iter()
+    .map(|grapheme| grapheme.width)
+    .sum::<usize>()
+    <= width
+  {
+    return text.into();
+  }
+
+  ellipsize(&graphemes, width).trim_end().into()
+}
+
+/// Graphemes that fit into `width - 1` columns and `…`, padded to `width`
+fn ellipsize(graphemes: &[Grapheme], width: usize) -> String {
+  if width == 0 {
+    return String::new();
+  }
+
+  let mut frame = fit(graphemes.iter(), width - 1);
+
+  // the ellipsis goes right after the text, the padding after it
+  let text = frame.trim_end().len();
+  let padding = frame.len() - text;
+
+  frame.truncate(text);
+  frame.push_str(ELLIPSIS);
+  frame.extend(std::iter::repeat_n(' ', padding));
+  frame
+}
+
+/// As many of `graphemes` as fit into `width` columns, padded with spaces to exactly `width`
+fn fit<'a>(graphemes: impl Iterator<Item = &'a Grapheme>, width: usize) -> String {
+  let mut frame = String::new();
+  let mut used = 0;
+
+  for grapheme in graphemes {
+    if used + grapheme.width > width {
+      break;
+    }
+
+    frame.push_str(&grapheme.text);
+    used += grapheme.width;
+  }
+
+  frame.extend(std::iter::repeat_n(' ', width - used));
+  frame
+}
+
+/// Splits `text` into unicode's extended grapheme clusters, what is shown as one character,
+/// control characters become spaces
+fn graphemes(text: &str) -> Vec<Grapheme> {
+  text
+    .graphemes(true)
+    .map(|cluster| {
+      if cluster.chars().any(char::is_control) {
+        return Grapheme {
+          text: " ".into(),
+          width: 1,
+        };
+      }
+
+      // the widest part, since the rest of a cluster is drawn on top of or joined to it,
+      // emoji shown as emoji and flags take two columns whatever their parts say
+      let emoji =
+        cluster.contains(EMOJI_PRESENTATION) || cluster.chars().any(is_regional_indicator);
+
+      let width = match emoji {
+        true => 2,
+        false => cluster
+          .chars()
+          .map(|character| character.width().unwrap_or_default())
+          .max()
+          .unwrap_or_default(),
+      };
+
+      Grapheme {
+        text: cluster.into(),
+        width,
+      }
+    })
+    .collect()
+}
+
+fn is_regional_indicator(character: char) -> bool {
+  ('\u{1F1E6}'..='\u{1F1FF}').contains(&character)
+}
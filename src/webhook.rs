@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::backoff::{Backoff, Retry};
+use crate::sink::MediaSink;
+use crate::{Error, EventKind, MediaEvent, MediaMetadata, Result};
+
+/// Payloads waiting to be posted before new ones are dropped
+const QUEUE_SIZE: usize = 32;
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebhookError {
+  /// Only the host is kept, webhook urls tend to have their secret in the path
+  #[error("{host} answered with {status}")]
+  Status { host: String, status: u16 },
+  #[error("posting to {host} failed: {message}")]
+  Transport { host: String, message: String },
+  /// More events came in than could be posted, the newest were dropped
+  #[error("too many payloads waiting to be posted")]
+  QueueFull,
+}
+
+impl From<WebhookError> for Error {
+  fn from(value: WebhookError) -> Self {
+    Self::Connection(value.into())
+  }
+}
+
+/// Where a [WebhookSink] posts to, when and what
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+  /// Every payload is posted to each of them
+  pub urls: Vec<String>,
+  /// Events that are posted, only [EventKind::MEDIA_CHANGED] by default
+  pub events: EventKind,
+  /// JSON to post, every string in it is a template filled like [MediaMetadata::format],
+  /// like `{"content": "Now playing {artists} - {title}"}` for Discord,
+  /// [None] posts `{"event": ..., "media": ...}` with the event and the media without covers
+  pub payload: Option<Value>,
+  /// Sent with every request, like `Authorization`
+  pub headers: BTreeMap<String, String>,
+  /// Delay between attempts after a connection error, a 429 or a 5xx, other statuses aren't retried
+  pub retry: Backoff,
+  /// For each attempt
+  #[serde_as(as = "::serde_with::DurationMilliSeconds<u64>")]
+  pub timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+  fn default() -> Self {
+    Self {
+      urls: Vec::new(),
+      events: EventKind::MEDIA_CHANGED,
+      payload: None,
+      headers: BTreeMap::new(),
+      retry: Backoff::exponential(Duration::from_secs(1), Duration::from_secs(30))
+        .set_max_attempts(5),
+      timeout: Duration::from_secs(10),
+    }
+  }
+}
+
+impl WebhookConfig {
+  pub fn new(url: impl Into<String>) -> Self {
+    Self {
+      urls: vec![url.into()],
+      ..Self::default()
+    }
+  }
+
+  pub fn set_events(self, events: EventKind) -> Self {
+    Self { events, ..self }
+  }
+
+  pub fn set_payload(self, payload: Value) -> Self {
+    Self {
+      payload: Some(payload),
+      ..self
+    }
+  }
+
+  pub fn set_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+    self.headers.insert(name.into(), value.into());
+    self
+  }
+
+  pub fn set_retry(self, retry: Backoff) -> Self {
+    Self { retry, ..self }
+  }
+
+  pub fn set_timeout(self, timeout: Duration) -> Self {
+    Self { timeout, ..self }
+  }
+}
+
+/// Posts JSON to webhooks on the events it's configured for, like Discord, Slack or n8n
+///
+/// Payloads are posted one after another on a thread of their own, so slow or failing webhooks
+/// don't hold the listener up, their errors are returned by the next [MediaSink::handle] or [MediaSink::tick]
+///
+/// ```rs
+/// let sink = WebhookSink::new(
+///   WebhookConfig::new("https://discord.com/api/webhooks/...")
+///     .set_payload(json!({ "content": "Now playing {artists} - {title}" })),
+/// );
+/// ```
+#[derive(Debug)]
+pub struct WebhookSink {
+  events: EventKind,
+  payload: Option<Value>,
+  queue: SyncSender<String>,
+  /// Last error of the thread, taken when it's reported
+  error: Arc<Mutex<Option<WebhookError>>>,
+  cancel_token: Arc<AtomicBool>,
+  task: JoinHandle<()>,
+}
+
+impl WebhookSink {
+  pub fn new(cfg: WebhookConfig) -> Self {
+    let (queue, payloads) = sync_channel(QUEUE_SIZE);
+    let error = Arc::new(Mutex::new(None));
+    let cancel_token = Arc::new(AtomicBool::new(false));
+
+    let events = cfg.events;
+    let payload = cfg.payload.clone();
+    let task_error = error.clone();
+    let task_cancel_token = cancel_token.clone();
+
+    let task = std::thread::spawn(move || {
+      post_task(&cfg, &payloads, &task_error, &task_cancel_token);
+    });
+
+    Self {
+      events,
+      payload,
+      queue,
+      error,
+      cancel_token,
+      task,
+    }
+  }
+
+  fn take_error(&self) -> Result<()> {
+    match self.error.lock().unwrap().take() {
+      Some(err) => Err(err.into()),
+      None => Ok(()),
+    }
+  }
+}
+
+impl MediaSink for WebhookSink {
+  fn handle(&mut self, event: &MediaEvent, media: &MediaMetadata) -> Result<()> {
+    if self.events.contains(event.kind()) {
+      let payload = match &self.payload {
+        Some(template) => fill(template, media),
+        None => {
+          // covers would make every payload huge, the urls are still there
+          let media = MediaMetadata {
+            cover: None,
+            background: None,
+            ..media.clone()
+          };
+
+          serde_json::json!({ "event": event, "media": media })
+        }
+      };
+
+      match self.queue.try_send(payload.to_string()) {
+        Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+        Err(TrySendError::Full(_)) => return Err(WebhookError::QueueFull.into()),
+      }
+    }
+
+    self.take_error()
+  }
+
+  fn tick(&mut self) -> Result<()> {
+    self.take_error()
+  }
+}
+
+impl Drop for WebhookSink {
+  fn drop(&mut self) {
+    // payloads still waiting are dropped, the thread isn't waited for, it stops after the attempt it's on
+    self.cancel_token.store(true, Ordering::SeqCst);
+    self.task.thread().unpark();
+  }
+}
+
+/// `template` with every string in it filled in with `media`
+fn fill(template: &Value, media: &MediaMetadata) -> Value {
+  match template {
+    Value::String(template) => Value::String(media.format(template).to_string()),
+    Value::Array(values) => Value::Array(values.iter().map(|value| fill(value, media)).collect()),
+    Value::Object(values) => Value::Object(
+      values
+        .iter()
+        .map(|(key, value)| (key.clone(), fill(value, media)))
+        .collect(),
+    ),
+    value => value.clone(),
+  }
+}
+
+fn post_task(
+  cfg: &WebhookConfig,
+  payloads: &Receiver<String>,
+  error: &Mutex<Option<WebhookError>>,
+  cancel_token: &AtomicBool,
+) {
+  let agent = ureq::AgentBuilder::new().timeout(cfg.timeout).build();
+
+  while let Ok(payload) = payloads.recv() {
+    for url in &cfg.urls {
+      if let Err(err) = post(cfg, &agent, url, &payload, cancel_token) {
+        *error.lock().unwrap() = Some(err);
+      }
+    }
+  }
+}
+
+/// Posts `payload` until it goes through, [WebhookConfig::retry] gives up or the sink is dropped
+fn post(
+  cfg: &WebhookConfig,
+  agent: &ureq::Agent,
+  url: &str,
+  payload: &str,
+  cancel_token: &AtomicBool,
+) -> Result<(), WebhookError> {
+  let mut retry = Retry::new(cfg.retry.clone());
+
+  loop {
+    if cancel_token.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+
+    let request = cfg
+      .headers
+      .iter()
+      .fold(agent.post(url), |request, (name, value)| {
+        request.set(name, value)
+      })
+      .set("Content-Type", "application/json");
+
+    let err = match request.send_string(payload) {
+      Ok(_) => return Ok(()),
+      Err(ureq::Error::Status(status, _)) => WebhookError::Status {
+        host: host(url).into(),
+        status,
+      },
+      Err(ureq::Error::Transport(err)) => WebhookError::Transport {
+        host: host(url).into(),
+        message: err.kind().to_string(),
+      },
+    };
+
+    let retryable = match err {
+      WebhookError::Status { status, .. } => status == 429 || status >= 500,
+      _ => true,
+    };
+
+    let Some(delay) = retry.next_delay().filter(|_| retryable) else {
+      return Err(err);
+    };
+
+    // woken up early when the sink is dropped
+    std::thread::park_timeout(delay);
+  }
+}
+
+/// Host of `url`, all of it if it isn't one
+fn host(url: &str) -> &str {
+  let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+
+  rest.split(['/', '?', '#']).next().unwrap_or(rest)
+}
@@ -0,0 +1,219 @@
+#![cfg(feature = "egui")]
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use egui::{Image, ImageSource, ProgressBar, Response, Ui, Vec2};
+
+use crate::listener::{MediaListener, MediaSource};
+use crate::{ImageFormat, MediaImage, MediaMetadata, MediaState, Timestamp};
+
+/// How often the widget repaints while playing so the progress bar moves smoothly
+const REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// egui widget showing the cover, title, artists and progress of what is currently playing
+///
+/// Keep it around between frames, it caches the cover and interpolates the progress
+/// between the listener's updates
+///
+/// ```rs
+/// struct App {
+///   listener: MediaListener,
+///   now_playing: NowPlayingWidget,
+/// }
+///
+/// impl eframe::App for App {
+///   fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+///     egui::CentralPanel::default().show(ctx, |ui| {
+///       self.now_playing.show(ui, &self.listener);
+///     });
+///   }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct NowPlayingWidget {
+  cover_size: f32,
+  cover: Option<CachedCover>,
+  progress: Option<ProgressAnchor>,
+}
+
+/// Cover that was handed to egui, so it's only hashed and uploaded once per track
+#[derive(Debug)]
+struct CachedCover {
+  key: u64,
+  uri: String,
+}
+
+/// Last elapsed the listener reported and when, progress is extrapolated from it while playing
+#[derive(Debug)]
+struct ProgressAnchor {
+  elapsed: Duration,
+  at: Instant,
+}
+
+impl Default for NowPlayingWidget {
+  fn default() -> Self {
+    Self {
+      cover_size: 128.0,
+      cover: None,
+      progress: None,
+    }
+  }
+}
+
+impl NowPlayingWidget {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Width and height of the cover in points
+  pub fn cover_size(self, cover_size: f32) -> Self {
+    Self { cover_size, ..self }
+  }
+
+  pub fn show(&mut self, ui: &mut Ui, listener: &MediaListener) -> Response {
+    let Ok(metadata) = listener.poll_guarded() else {
+      self.forget_cover(ui);
+      self.progress = None;
+
+      return ui.label("Nothing is playing");
+    };
+
+    let cover = self.cover_source(ui, &metadata);
+    let elapsed = self.elapsed(&metadata);
+
+    if metadata.state == MediaState::Playing {
+      ui.ctx().request_repaint_after(REPAINT_INTERVAL);
+    }
+
+    ui.horizontal(|ui| {
+      if let Some(cover) = cover {
+        let size = Vec2::splat(self.cover_size);
+        ui.add(Image::new(cover).fit_to_exact_size(size));
+      }
+
+      ui.vertical(|ui| {
+        ui.strong(&metadata.title);
+
+        if !metadata.artists.is_empty() {
+          ui.label(metadata.artists.join(", "));
+        }
+
+        if let Some(album) = &metadata.album {
+          ui.weak(album);
+        }
+
+        let progress = match metadata.duration {
+          Duration::ZERO => 0.0,
+          duration => elapsed.as_secs_f32() / duration.as_secs_f32(),
+        };
+
+        let text = format!(
+          "{} / {}",
+          Timestamp(elapsed),
+          Timestamp(metadata.duration)
+        );
+
+        ui.add(ProgressBar::new(progress).text(text));
+      });
+    })
+    .response
+  }
+
+  /// Elapsed interpolated from the last time the listener's elapsed changed
+  fn elapsed(&mut self, metadata: &MediaMetadata) -> Duration {
+    let now = Instant::now();
+
+    let anchor = self.progress.get_or_insert(ProgressAnchor {
+      elapsed: metadata.elapsed,
+      at: now,
+    });
+
+    if anchor.elapsed != metadata.elapsed {
+      anchor.elapsed = metadata.elapsed;
+      anchor.at = now;
+    }
+
+    let elapsed = match metadata.state {
+      MediaState::Playing => anchor.elapsed + now.duration_since(anchor.at),
+      _ => anchor.elapsed,
+    };
+
+    match metadata.duration {
+      Duration::ZERO => elapsed,
+      duration => elapsed.min(duration),
+    }
+  }
+
+  fn cover_source(&mut self, ui: &Ui, metadata: &MediaMetadata) -> Option<ImageSource<'static>> {
+    let Some(cover) = &metadata.cover else {
+      self.forget_cover(ui);
+
+      return metadata
+        .cover_url
+        .as_ref()
+        .map(|url| ImageSource::Uri(url.clone().into()));
+    };
+
+    let key = cover_key(metadata, cover);
+
+    if self.cover.as_ref().is_some_and(|cached| cached.key == key) {
+      // egui already has the bytes for this uri, no need to hand them over again
+      let uri = &self.cover.as_ref()?.uri;
+      return Some(ImageSource::Uri(uri.clone().into()));
+    }
+
+    self.forget_cover(ui);
+
+    let uri = format!(
+      "bytes://currently_playing/{}.{}",
+      hash(cover),
+      extension(cover)
+    );
+
+    self.cover = Some(CachedCover {
+      key,
+      uri: uri.clone(),
+    });
+
+    Some(ImageSource::Bytes {
+      uri: uri.into(),
+      bytes: cover.data.clone().into(),
+    })
+  }
+
+  /// Drops the cached cover from egui's image cache, otherwise every cover ever shown stays in memory
+  fn forget_cover(&mut self, ui: &Ui) {
+    if let Some(cached) = self.cover.take() {
+      ui.ctx().forget_image(&cached.uri);
+    }
+  }
+}
+
+/// Cheap key for detecting a new cover without hashing the image every frame
+fn cover_key(metadata: &MediaMetadata, cover: &MediaImage) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  metadata.uid.hash(&mut hasher);
+  metadata.uri.hash(&mut hasher);
+  metadata.title.hash(&mut hasher);
+  metadata.album.hash(&mut hasher);
+  cover.data.len().hash(&mut hasher);
+  hasher.finish()
+}
+
+fn hash(cover: &MediaImage) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  cover.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// Image loaders pick the decoder from the extension
+fn extension(cover: &MediaImage) -> &str {
+  match &cover.format {
+    ImageFormat::PNG => "png",
+    ImageFormat::JPEG => "jpg",
+    ImageFormat::WEBP => "webp",
+    ImageFormat::Other(format) => format.rsplit('/').next().unwrap_or("png"),
+  }
+}
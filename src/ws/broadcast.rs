@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+use tungstenite::Error;
+
+use super::backend::Incoming;
+use super::{race, Either, SideRequest};
+use crate::pipeline::{strip_covers, EventTap};
+use crate::{
+  ConsumerMessage, EventKind, MediaEvent, MediaMessage, ProtocolError, ProtocolErrorKind,
+};
+
+/// Most event consumers connected at once, any more are turned away
+pub(super) const MAX_CONSUMERS: usize = 16;
+
+/// Event consumers connected to `/events`, each gets the events it subscribed to with [ConsumerMessage::Subscribe]
+#[derive(Debug)]
+pub(super) struct Consumers {
+  consumers: Mutex<Vec<Arc<Consumer>>>,
+  /// Events queued for a consumer before the oldest are dropped, [MediaSourceConfig::channel_capacity](crate::listener::MediaSourceConfig::channel_capacity)
+  capacity: usize,
+}
+
+#[derive(Debug)]
+struct Consumer {
+  /// Bits of the [EventKind] it subscribed to
+  events: AtomicU32,
+  queue: Mutex<VecDeque<MediaEvent>>,
+  waker: Mutex<Option<Waker>>,
+}
+
+impl Consumers {
+  pub(super) fn new(capacity: usize) -> Self {
+    Self {
+      consumers: Mutex::default(),
+      capacity: capacity.max(1),
+    }
+  }
+
+  pub(super) fn is_full(&self) -> bool {
+    self.consumers.lock().unwrap().len() >= MAX_CONSUMERS
+  }
+
+  fn add(&self) -> Arc<Consumer> {
+    let consumer = Arc::new(Consumer {
+      events: AtomicU32::new(EventKind::ALL.bits()),
+      queue: Mutex::default(),
+      waker: Mutex::default(),
+    });
+
+    self.consumers.lock().unwrap().push(consumer.clone());
+
+    consumer
+  }
+
+  fn remove(&self, consumer: &Arc<Consumer>) {
+    self
+      .consumers
+      .lock()
+      .unwrap()
+      .retain(|other| !Arc::ptr_eq(other, consumer));
+  }
+}
+
+impl EventTap for Consumers {
+  fn tap(&self, event: &MediaEvent) {
+    let consumers = self.consumers.lock().unwrap();
+    let kind = event.kind();
+
+    let mut subscribed = consumers
+      .iter()
+      .filter(|consumer| consumer.subscribed().contains(kind))
+      .peekable();
+
+    if subscribed.peek().is_none() {
+      return;
+    }
+
+    // covers would make every message huge, the urls are still there
+    let Some(event) = strip_covers(event.clone()) else {
+      return;
+    };
+
+    for consumer in subscribed {
+      consumer.push(event.clone(), self.capacity);
+    }
+  }
+}
+
+impl Consumer {
+  fn subscribed(&self) -> EventKind {
+    EventKind::from_bits(self.events.load(Ordering::SeqCst))
+  }
+
+  fn subscribe(&self, events: EventKind) {
+    self.events.store(events.bits(), Ordering::SeqCst);
+    self
+      .queue
+      .lock()
+      .unwrap()
+      .retain(|event| events.contains(event.kind()));
+  }
+
+  fn push(&self, event: MediaEvent, capacity: usize) {
+    let mut queue = self.queue.lock().unwrap();
+
+    // a consumer that can't keep up misses the oldest events
+    if queue.len() == capacity {
+      queue.pop_front();
+    }
+
+    queue.push_back(event);
+    drop(queue);
+
+    if let Some(waker) = self.waker.lock().unwrap().take() {
+      waker.wake();
+    }
+  }
+
+  /// Waits for the next queued event
+  async fn next(&self) -> MediaEvent {
+    poll_fn(|cx| {
+      *self.waker.lock().unwrap() = Some(cx.waker().clone());
+
+      // checked after storing the waker so a concurrent push can't be missed
+      match self.queue.lock().unwrap().pop_front() {
+        Some(event) => Poll::Ready(event),
+        None => Poll::Pending,
+      }
+    })
+    .await
+  }
+}
+
+/// Sends an event consumer its events until it disconnects
+pub(super) async fn consume(incoming: Incoming, consumers: Arc<Consumers>) -> SideRequest {
+  let Ok(mut connection) = incoming.handshake().await else {
+    return SideRequest::Done;
+  };
+
+  let consumer = consumers.add();
+
+  loop {
+    match race(connection.next_text(), consumer.next()).await {
+      Either::Left(Some(Ok(text))) => match serde_json::from_str::<ConsumerMessage>(&text) {
+        Ok(ConsumerMessage::Subscribe(events)) => consumer.subscribe(events),
+        Err(err) => {
+          let err = ProtocolError::new(ProtocolErrorKind::Malformed, err.to_string());
+          let _ = connection
+            .send_message(MediaMessage::ProtocolError(err))
+            .await;
+        }
+      },
+      // like binary messages, which consumers have no use for
+      Either::Left(Some(Err(Error::Io(err)))) if err.kind() == ErrorKind::Unsupported => {}
+      Either::Left(_) => break,
+      Either::Right(event) => {
+        let text = serde_json::to_string(&event).unwrap_or_default();
+
+        if connection.send_text(text).await.is_err() {
+          break;
+        }
+      }
+    }
+  }
+
+  consumers.remove(&consumer);
+
+  SideRequest::Done
+}
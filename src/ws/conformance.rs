@@ -0,0 +1,620 @@
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use tungstenite::error::ProtocolError;
+use tungstenite::{Message, WebSocket};
+
+use super::{close_frame, parse_message, unix_millis};
+use crate::listener::{MessageLimits, WebsocketAddr};
+use crate::{
+  ClientMessage, CloseReason, EventKind, MediaCapabilities, MediaEvent, MediaMessage,
+  ProtocolErrorKind, Result,
+};
+
+/// Events media clients send, the others are the listener's own
+const CLIENT_EVENTS: EventKind = EventKind::from_bits(
+  EventKind::MEDIA_CHANGED.bits()
+    | EventKind::STATE_CHANGED.bits()
+    | EventKind::PROGRESS_CHANGED.bits()
+    | EventKind::QUEUE_CHANGED.bits()
+    | EventKind::TRACK_ENDED.bits(),
+);
+
+/// Size of the frame sent for [Check::OversizedFrame]
+const OVERSIZED: usize = 1024 * 1024;
+
+/// Most problems with the client's messages that are reported, the rest are counted
+const MAX_PROBLEMS: usize = 5;
+
+/// How [run] waits on the client
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ConformanceConfig {
+  /// Where the harness listens, in place of the listener the client usually connects to
+  pub addr: WebsocketAddr,
+  /// How long the client has to connect, and to connect again after it was disconnected
+  pub connect_timeout: Duration,
+  /// How long the client's messages are watched before the harness starts sending it anything
+  pub observe: Duration,
+  /// How long the client has to answer, like a ping
+  pub answer_timeout: Duration,
+  /// Limits the client's messages have to stay within, those of the websocket source by default
+  pub limits: MessageLimits,
+}
+
+impl Default for ConformanceConfig {
+  fn default() -> Self {
+    Self {
+      addr: WebsocketAddr::Default,
+      connect_timeout: Duration::from_secs(60),
+      observe: Duration::from_secs(5),
+      answer_timeout: Duration::from_secs(5),
+      limits: MessageLimits::default(),
+    }
+  }
+}
+
+impl ConformanceConfig {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_addr(self, addr: WebsocketAddr) -> Self {
+    Self { addr, ..self }
+  }
+
+  pub fn set_connect_timeout(self, connect_timeout: Duration) -> Self {
+    Self {
+      connect_timeout,
+      ..self
+    }
+  }
+
+  pub fn set_observe(self, observe: Duration) -> Self {
+    Self { observe, ..self }
+  }
+
+  pub fn set_answer_timeout(self, answer_timeout: Duration) -> Self {
+    Self {
+      answer_timeout,
+      ..self
+    }
+  }
+
+  pub fn set_limits(self, limits: MessageLimits) -> Self {
+    Self { limits, ..self }
+  }
+}
+
+/// What [run] checks, in the order it checks them
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Check {
+  /// The client connects to the address
+  Connect,
+  /// [MediaCapabilities] come before the client's first event
+  Capabilities,
+  /// [MediaMessage::Ping] is answered with a matching [ClientMessage::Pong],
+  /// only for clients with [MediaCapabilities::ping]
+  Ping,
+  /// The client stays connected after a binary frame, text that isn't json and messages it doesn't know
+  WrongTypes,
+  /// The client stays connected after a frame far larger than any message
+  OversizedFrame,
+  /// The client stays connected after its message is rejected with [MediaMessage::ProtocolError]
+  ProtocolError,
+  /// The client connects again after the harness closed the connection
+  Reconnect,
+  /// Every message of the client parses, is within [ConformanceConfig::limits]
+  /// and only has events media clients send
+  Messages,
+}
+
+impl Check {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Connect => "connect",
+      Self::Capabilities => "capabilities",
+      Self::Ping => "ping",
+      Self::WrongTypes => "wrong types",
+      Self::OversizedFrame => "oversized frame",
+      Self::ProtocolError => "protocol error",
+      Self::Reconnect => "reconnect",
+      Self::Messages => "messages",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Outcome {
+  Passed,
+  /// Why it failed
+  Failed(String),
+  /// Why it didn't apply to the client
+  Skipped(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CheckResult {
+  pub check: Check,
+  pub outcome: Outcome,
+}
+
+/// Result of [run], printed as a line per check
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+pub struct ConformanceReport {
+  /// Address the client first connected from, [None] if it never did
+  pub client: Option<SocketAddr>,
+  pub results: Vec<CheckResult>,
+}
+
+impl ConformanceReport {
+  /// No check failed
+  pub fn passed(&self) -> bool {
+    !self
+      .results
+      .iter()
+      .any(|result| matches!(result.outcome, Outcome::Failed(_)))
+  }
+
+  fn push(&mut self, check: Check, outcome: Outcome) {
+    self.results.push(CheckResult { check, outcome });
+  }
+}
+
+impl Display for ConformanceReport {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    for result in &self.results {
+      let name = result.check.name();
+
+      match &result.outcome {
+        Outcome::Passed => writeln!(f, "PASS {name}")?,
+        Outcome::Failed(why) => writeln!(f, "FAIL {name}: {why}")?,
+        Outcome::Skipped(why) => writeln!(f, "SKIP {name}: {why}")?,
+      }
+    }
+
+    let count = |outcome: fn(&Outcome) -> bool| {
+      self
+        .results
+        .iter()
+        .filter(|result| outcome(&result.outcome))
+        .count()
+    };
+
+    write!(
+      f,
+      "{} passed, {} failed, {} skipped",
+      count(|outcome| matches!(outcome, Outcome::Passed)),
+      count(|outcome| matches!(outcome, Outcome::Failed(_))),
+      count(|outcome| matches!(outcome, Outcome::Skipped(_))),
+    )
+  }
+}
+
+/// Checks a media client against the websocket protocol, for whoever writes one
+///
+/// Listens on [ConformanceConfig::addr] in place of the listener, waits for the client to connect
+/// and sends it what the websocket source can send, including what it shouldn't choke on,
+/// the client should be running and playing something to get the most out of it
+///
+/// Fails only if the address can't be bound, everything the client does wrong ends up in the report
+///
+/// ```rs
+/// let report = conformance::run(&ConformanceConfig::default())?;
+///
+/// println!("{report}");
+/// ```
+pub fn run(cfg: &ConformanceConfig) -> Result<ConformanceReport> {
+  let listener = TcpListener::bind(cfg.addr.socket_addr())?;
+  listener.set_nonblocking(true)?;
+
+  let mut report = ConformanceReport::default();
+
+  let Some((socket, addr)) = accept(&listener, cfg) else {
+    report.push(
+      Check::Connect,
+      Outcome::Failed(format!(
+        "no client connected within {:?}",
+        cfg.connect_timeout
+      )),
+    );
+
+    return Ok(report);
+  };
+
+  report.client = Some(addr);
+  report.push(Check::Connect, Outcome::Passed);
+
+  let mut session = Session::new(socket, cfg);
+
+  // what the client sends on its own
+  session.watch(cfg.observe, |_| false);
+
+  let capabilities = match (&session.capabilities, session.capabilities_late) {
+    (_, true) => Outcome::Failed("sent after the first event".into()),
+    (None, _) => Outcome::Skipped("never sent, the defaults are assumed".into()),
+    (Some(_), _) => Outcome::Passed,
+  };
+
+  report.push(Check::Capabilities, capabilities);
+  report.push(Check::Ping, session.ping());
+
+  let wrong_types = [
+    Message::Binary(vec![0, 1, 2, 3]),
+    Message::Text("not json".into()),
+    Message::Text(r#"{"FutureMessage":{"added":"later"}}"#.into()),
+    Message::Text(r#"{"ProgressUpdateInterval":"soon"}"#.into()),
+  ];
+
+  report.push(Check::WrongTypes, session.survives(wrong_types));
+
+  let oversized = serde_json::json!({ "Padding": "x".repeat(OVERSIZED) }).to_string();
+
+  report.push(
+    Check::OversizedFrame,
+    session.survives([Message::Text(oversized)]),
+  );
+
+  let rejected = MediaMessage::ProtocolError(crate::ProtocolError::new(
+    ProtocolErrorKind::Malformed,
+    "sent by the conformance harness, the client's message was fine",
+  ));
+
+  report.push(
+    Check::ProtocolError,
+    session.survives([session.text(&rejected)]),
+  );
+
+  report.push(Check::Reconnect, session.reconnect(&listener));
+  report.push(Check::Messages, session.messages());
+
+  session.close();
+
+  Ok(report)
+}
+
+/// Waits up to [ConformanceConfig::connect_timeout] for a client to connect and finish its handshake
+fn accept(
+  listener: &TcpListener,
+  cfg: &ConformanceConfig,
+) -> Option<(WebSocket<TcpStream>, SocketAddr)> {
+  let due = Instant::now() + cfg.connect_timeout;
+
+  while Instant::now() < due {
+    let (stream, addr) = match listener.accept() {
+      Ok(accepted) => accepted,
+      Err(err) if err.kind() == ErrorKind::WouldBlock => {
+        std::thread::sleep(Duration::from_millis(50));
+        continue;
+      }
+      Err(_) => continue,
+    };
+
+    // anything that isn't a websocket handshake, like a browser opening the address, is skipped
+    let handshake = stream
+      .set_nonblocking(false)
+      .and_then(|()| stream.set_read_timeout(Some(cfg.answer_timeout)));
+
+    if handshake.is_err() {
+      continue;
+    }
+
+    if let Ok(socket) = tungstenite::accept(stream) {
+      return Some((socket, addr));
+    }
+  }
+
+  None
+}
+
+/// How [Session::watch] ended
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Watch {
+  Done,
+  TimedOut,
+  Closed,
+}
+
+/// Connection to the client, what it sent is kept across reconnects
+struct Session {
+  socket: WebSocket<TcpStream>,
+  cfg: ConformanceConfig,
+  connected: bool,
+  capabilities: Option<MediaCapabilities>,
+  /// The client sent an event before its capabilities
+  capabilities_late: bool,
+  /// The client sent an event on this connection
+  sent_event: bool,
+  /// Values of the client's [ClientMessage::Pong]s
+  pongs: Vec<u64>,
+  /// The client answered the last ping frame
+  ponged: bool,
+  received: usize,
+  /// What was wrong with the client's messages, numbered by when they came in
+  problems: Vec<String>,
+}
+
+impl Session {
+  fn new(socket: WebSocket<TcpStream>, cfg: &ConformanceConfig) -> Self {
+    Self {
+      socket,
+      cfg: *cfg,
+      connected: true,
+      capabilities: None,
+      capabilities_late: false,
+      sent_event: false,
+      pongs: Vec::new(),
+      ponged: false,
+      received: 0,
+      problems: Vec::new(),
+    }
+  }
+
+  fn text(&self, message: &MediaMessage) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap_or_default())
+  }
+
+  /// Takes in the client's messages for up to `duration` or until `done`
+  fn watch(&mut self, duration: Duration, mut done: impl FnMut(&Self) -> bool) -> Watch {
+    let due = Instant::now() + duration;
+
+    loop {
+      if !self.connected {
+        return Watch::Closed;
+      }
+
+      if done(self) {
+        return Watch::Done;
+      }
+
+      let remaining = due.saturating_duration_since(Instant::now());
+
+      if remaining.is_zero() {
+        return Watch::TimedOut;
+      }
+
+      if self
+        .socket
+        .get_mut()
+        .set_read_timeout(Some(remaining))
+        .is_err()
+      {
+        self.connected = false;
+        continue;
+      }
+
+      match self.socket.read() {
+        Ok(Message::Text(text)) => self.receive(&text),
+        Ok(Message::Binary(_)) => {
+          self.received += 1;
+          self.problem("binary frame, only text frames are read".into());
+        }
+        Ok(Message::Pong(_)) => self.ponged = true,
+        Ok(Message::Close(_)) => self.connected = false,
+        Ok(_) => {}
+        Err(tungstenite::Error::Io(err))
+          if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+        Err(
+          tungstenite::Error::ConnectionClosed
+          | tungstenite::Error::AlreadyClosed
+          | tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake),
+        ) => {
+          self.connected = false;
+        }
+        Err(err) => {
+          self.problems.push(format!("broke the connection: {err}"));
+          self.connected = false;
+        }
+      }
+    }
+  }
+
+  /// Checks a message like the websocket source does
+  fn receive(&mut self, text: &str) {
+    self.received += 1;
+
+    if text.len() > self.cfg.limits.max_size {
+      self.problem(format!(
+        "{} bytes, at most {} are allowed",
+        text.len(),
+        self.cfg.limits.max_size
+      ));
+
+      return;
+    }
+
+    let format = self
+      .capabilities
+      .as_ref()
+      .map(|capabilities| capabilities.duration_format)
+      .unwrap_or_default();
+
+    let message = format.scope(|| parse_message(text)).and_then(|message| {
+      self.cfg.limits.check_message(&message)?;
+      Ok(message)
+    });
+
+    let message = match message {
+      Ok(message) => message,
+      Err(err) => {
+        self.problem(err.to_string());
+        return;
+      }
+    };
+
+    match message {
+      ClientMessage::Capabilities(capabilities) => {
+        self.capabilities_late |= self.sent_event && self.capabilities.is_none();
+        self.capabilities = Some(capabilities);
+      }
+      ClientMessage::Pong { sent, .. } => self.pongs.push(sent),
+      ClientMessage::Player { event, .. } => {
+        self.sent_event = true;
+
+        if let Some(problem) = check_event(&event) {
+          self.problem(problem);
+        }
+      }
+      ClientMessage::PlayerRemoved(_) | ClientMessage::Control { .. } => {}
+    }
+  }
+
+  fn problem(&mut self, problem: String) {
+    self
+      .problems
+      .push(format!("message {}: {problem}", self.received));
+  }
+
+  fn send(&mut self, message: Message) -> bool {
+    if self.connected && self.socket.send(message).is_err() {
+      self.connected = false;
+    }
+
+    self.connected
+  }
+
+  fn ping(&mut self) -> Outcome {
+    if !self.connected {
+      return Outcome::Skipped("the client disconnected".into());
+    }
+
+    let supported = self
+      .capabilities
+      .as_ref()
+      .is_some_and(|capabilities| capabilities.ping);
+
+    if !supported {
+      return Outcome::Skipped("the client doesn't answer pings".into());
+    }
+
+    let sent = unix_millis();
+
+    if !self.send(self.text(&MediaMessage::Ping(sent))) {
+      return Outcome::Failed("closed the connection".into());
+    }
+
+    match self.watch(self.cfg.answer_timeout, |session| {
+      session.pongs.contains(&sent)
+    }) {
+      Watch::Done => Outcome::Passed,
+      Watch::TimedOut => Outcome::Failed(format!(
+        "no Pong with sent {sent} within {:?}",
+        self.cfg.answer_timeout
+      )),
+      Watch::Closed => Outcome::Failed("closed the connection".into()),
+    }
+  }
+
+  /// Sends `messages` and checks the client still answers ping frames after them
+  fn survives(&mut self, messages: impl IntoIterator<Item = Message>) -> Outcome {
+    if !self.connected {
+      return Outcome::Skipped("the client disconnected".into());
+    }
+
+    for message in messages {
+      if !self.send(message) {
+        return Outcome::Failed("closed the connection".into());
+      }
+    }
+
+    self.ponged = false;
+
+    if !self.send(Message::Ping(b"conformance".to_vec())) {
+      return Outcome::Failed("closed the connection".into());
+    }
+
+    match self.watch(self.cfg.answer_timeout, |session| session.ponged) {
+      Watch::Done => Outcome::Passed,
+      Watch::TimedOut => Outcome::Failed(format!(
+        "stopped answering ping frames for {:?}",
+        self.cfg.answer_timeout
+      )),
+      Watch::Closed => Outcome::Failed("closed the connection".into()),
+    }
+  }
+
+  /// Closes the connection like a restarting listener and waits for the client to come back
+  fn reconnect(&mut self, listener: &TcpListener) -> Outcome {
+    if !self.connected {
+      return Outcome::Skipped("the client disconnected".into());
+    }
+
+    self.close();
+
+    let Some((socket, _)) = accept(listener, &self.cfg) else {
+      return Outcome::Failed(format!(
+        "didn't connect again within {:?}",
+        self.cfg.connect_timeout
+      ));
+    };
+
+    self.socket = socket;
+    self.connected = true;
+    self.sent_event = false;
+
+    // the client is expected to repeat its capabilities and what is playing
+    self.watch(self.cfg.observe, |_| false);
+
+    Outcome::Passed
+  }
+
+  fn messages(&self) -> Outcome {
+    if self.received == 0 {
+      return Outcome::Skipped("the client didn't send anything".into());
+    }
+
+    match self.problems.len() {
+      0 => Outcome::Passed,
+      count if count > MAX_PROBLEMS => Outcome::Failed(format!(
+        "{}, and {} more",
+        self.problems[..MAX_PROBLEMS].join(", "),
+        count - MAX_PROBLEMS
+      )),
+      _ => Outcome::Failed(self.problems.join(", ")),
+    }
+  }
+
+  /// Closes the connection and waits for the client to close its end
+  fn close(&mut self) {
+    if !self.connected {
+      return;
+    }
+
+    let _ = self.socket.close(Some(close_frame(CloseReason::Shutdown)));
+
+    self.watch(self.cfg.answer_timeout, |_| false);
+    self.connected = false;
+  }
+}
+
+/// What is wrong with an event a client sent, if anything
+fn check_event(event: &MediaEvent) -> Option<String> {
+  if !CLIENT_EVENTS.contains(event.kind()) {
+    return Some(format!(
+      "{} is an event of the listener, clients don't send it",
+      variant(event)
+    ));
+  }
+
+  match event {
+    MediaEvent::MediaChanged(metadata) | MediaEvent::TrackEnded(metadata)
+      if !metadata.duration.is_zero() && metadata.elapsed > metadata.duration =>
+    {
+      Some(format!(
+        "elapsed ({:?}) is past the duration ({:?})",
+        metadata.elapsed, metadata.duration
+      ))
+    }
+    _ => None,
+  }
+}
+
+/// Name of the variant of `event`
+fn variant(event: &MediaEvent) -> String {
+  match serde_json::to_value(event) {
+    Ok(serde_json::Value::Object(object)) => object.keys().next().cloned().unwrap_or_default(),
+    Ok(serde_json::Value::String(name)) => name,
+    _ => String::new(),
+  }
+}
@@ -0,0 +1,379 @@
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::backend::{self, Incoming};
+use super::instance;
+#[cfg(feature = "streamdeck")]
+use super::streamdeck;
+use super::{authorize, same_secret, CancelToken, Outbox};
+use crate::listener::MediaSourceConfig;
+use crate::pipeline::EventSender;
+use crate::redact::Redactor;
+use crate::{MediaEvent, MediaMetadata, RemoteControl};
+
+/// Longest request head that is looked at, longer requests get a 431,
+/// the head and body of `POST /control` together get a 413 past it
+const MAX_HEAD: usize = 8 * 1024;
+/// How long a client gets to send its request head, and to take the response
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to wait between looking at a request head that hasn't fully arrived yet
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Route {
+  /// Upgrade to the websocket protocol on `/ws`, or on `/` where clients from before `/ws` connect
+  Websocket,
+  /// Upgrade to the websocket protocol on `/events`, for consumers of the events, see [ConsumerMessage](crate::ConsumerMessage)
+  Events,
+  /// `GET /healthz`
+  Health,
+  /// `GET /now-playing`
+  NowPlaying,
+  /// `GET /metrics`, [MediaSourceConfig::metrics] in the Prometheus text format
+  Metrics,
+  /// `POST /shutdown` with the [instance::secret] as a bearer token, another listener taking over,
+  /// see [InstancePolicy::TakeOver](crate::listener::InstancePolicy::TakeOver)
+  Shutdown,
+  /// `POST /control` with a [RemoteControl] as the body, see [MediaSourceConfig::control_token]
+  Control,
+  /// `GET /streamdeck`
+  #[cfg(feature = "streamdeck")]
+  StreamDeck,
+  /// `POST /streamdeck/<action>`
+  #[cfg(feature = "streamdeck")]
+  StreamDeckAction(streamdeck::Action),
+  NotFound,
+  MethodNotAllowed,
+  HeadTooLarge,
+  BodyTooLarge,
+}
+
+/// Request head of a client, which is left in the stream so the websocket handshake can still read it
+#[derive(Debug)]
+pub(super) struct Request {
+  pub(super) route: Route,
+  /// Length of the head in bytes, and of the body for [Route::Control]
+  pub(super) len: usize,
+  /// Token of an `Authorization: Bearer <token>` header
+  token: Option<String>,
+  /// Only read for [Route::Control]
+  body: Vec<u8>,
+}
+
+/// Response to a plain HTTP request
+pub(super) struct Response {
+  body: Vec<u8>,
+  /// Another listener is taking over, the listener stops once it's been answered
+  take_over: bool,
+}
+
+/// Works out the response to `request`, [None] if it asked for a websocket
+pub(super) fn answer(
+  incoming: &Incoming,
+  request: &Request,
+  cfg: &MediaSourceConfig,
+  metadata: &RwLock<MediaMetadata>,
+  outbox: &Outbox,
+  events: &mut EventSender,
+  client_connected: bool,
+) -> Option<Response> {
+  if matches!(request.route, Route::Websocket | Route::Events) {
+    return None;
+  }
+
+  let peer = incoming.addr;
+  let take_over = request.route == Route::Shutdown
+    && peer.ip().is_loopback()
+    && !instance::secret().is_empty()
+    && same_secret(
+      instance::secret(),
+      request.token.as_deref().unwrap_or_default(),
+    );
+
+  let body = match request.route {
+    Route::Control => control(cfg, request, events),
+    Route::Shutdown if take_over => json(
+      "202 Accepted",
+      &serde_json::json!({ "status": "shutting down" }),
+    ),
+    Route::Shutdown if peer.ip().is_loopback() => {
+      error("403 Forbidden", "taking over needs the listener's secret")
+    }
+    route => respond(
+      route,
+      cfg,
+      &metadata.read().unwrap(),
+      outbox,
+      client_connected,
+    ),
+  };
+
+  Some(Response { body, take_over })
+}
+
+/// Sends `response` and closes the connection, cancels `cancel_token` if another listener is taking over
+pub(super) async fn reply(
+  incoming: Incoming,
+  request: &Request,
+  response: Response,
+  cancel_token: &CancelToken,
+) {
+  send(incoming, request, &response.body).await;
+
+  // after answering, so the other listener knows it's coming
+  if response.take_over {
+    cancel_token.cancel();
+  }
+}
+
+/// Sends `response` and closes the connection
+pub(super) async fn send(incoming: Incoming, request: &Request, response: &[u8]) {
+  let due = Instant::now() + TIMEOUT;
+
+  let _ = backend::timeout_at(due, incoming.respond(request.len, response)).await;
+}
+
+/// Waits for the full request head without taking it out of the stream,
+/// [None] if the client closed the connection or took too long
+pub(super) async fn peek_request(incoming: &Incoming) -> Option<Request> {
+  let due = Instant::now() + TIMEOUT;
+  let mut buf = vec![0; MAX_HEAD];
+
+  loop {
+    let read = backend::timeout_at(due, incoming.peek(&mut buf))
+      .await?
+      .ok()?;
+
+    if read == 0 {
+      return None;
+    }
+
+    if let Some(end) = find_head_end(&buf[..read]) {
+      let head = String::from_utf8_lossy(&buf[..end]);
+      let route = route(&head);
+      let token = header(&head, "authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().into());
+
+      if route != Route::Control {
+        return Some(Request {
+          route,
+          len: end,
+          token,
+          body: Vec::new(),
+        });
+      }
+
+      let len = end + header(&head, "content-length").map_or(0, |len| len.parse().unwrap_or(0));
+
+      if len > buf.len() {
+        return Some(Request {
+          route: Route::BodyTooLarge,
+          len: read,
+          token: None,
+          body: Vec::new(),
+        });
+      }
+
+      if read >= len {
+        return Some(Request {
+          route,
+          len,
+          token,
+          body: buf[end..len].to_vec(),
+        });
+      }
+    }
+
+    if read == buf.len() {
+      return Some(Request {
+        route: Route::HeadTooLarge,
+        len: read,
+        token: None,
+        body: Vec::new(),
+      });
+    }
+
+    // peeking returns right away while the rest of the head is still underway
+    backend::timeout_at(due, backend::sleep(PEEK_INTERVAL)).await?;
+  }
+}
+
+fn find_head_end(data: &[u8]) -> Option<usize> {
+  data
+    .windows(4)
+    .position(|window| window == b"\r\n\r\n")
+    .map(|position| position + 4)
+}
+
+/// Value of the header `name` in `head`, `name` being lowercase
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+  head.lines().skip(1).find_map(|line| {
+    let (header, value) = line.split_once(':')?;
+    header
+      .trim()
+      .eq_ignore_ascii_case(name)
+      .then_some(value.trim())
+  })
+}
+
+fn route(head: &str) -> Route {
+  let mut lines = head.lines();
+  let mut request_line = lines.next().unwrap_or_default().split(' ');
+
+  let method = request_line.next().unwrap_or_default();
+  let target = request_line.next().unwrap_or_default();
+  let path = target.split('?').next().unwrap_or_default();
+
+  let upgrade = lines.any(|line| {
+    let Some((name, value)) = line.split_once(':') else {
+      return false;
+    };
+
+    name.trim().eq_ignore_ascii_case("upgrade") && value.trim().eq_ignore_ascii_case("websocket")
+  });
+
+  if upgrade {
+    match path {
+      "/ws" | "/" => return Route::Websocket,
+      "/events" => return Route::Events,
+      _ => {}
+    }
+  }
+
+  #[cfg(feature = "streamdeck")]
+  if let Some(route) = streamdeck::route(method, path) {
+    return route;
+  }
+
+  match (method, path) {
+    ("GET", "/healthz") => Route::Health,
+    ("GET", "/now-playing") => Route::NowPlaying,
+    ("GET", "/metrics") => Route::Metrics,
+    ("POST", "/shutdown") => Route::Shutdown,
+    ("POST", "/control") => Route::Control,
+    (_, "/healthz" | "/now-playing" | "/metrics" | "/shutdown" | "/control") => {
+      Route::MethodNotAllowed
+    }
+    _ => Route::NotFound,
+  }
+}
+
+#[derive(Serialize)]
+struct Health {
+  status: &'static str,
+  /// Always [instance::SERVICE], tells listeners apart from other servers
+  service: &'static str,
+  client_connected: bool,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+  error: &'a str,
+}
+
+/// Passes the [RemoteControl] of a `POST /control` on as [MediaEvent::ControlRequested]
+fn control(cfg: &MediaSourceConfig, request: &Request, events: &mut EventSender) -> Vec<u8> {
+  if let Err(err) = authorize(cfg, request.token.as_deref().unwrap_or_default()) {
+    return error("401 Unauthorized", &err.message);
+  }
+
+  match serde_json::from_slice::<RemoteControl>(&request.body) {
+    Ok(control) => {
+      events.send(MediaEvent::ControlRequested(control));
+      events.flush();
+
+      json("202 Accepted", &serde_json::json!({ "status": "accepted" }))
+    }
+    Err(err) => error("400 Bad Request", &err.to_string()),
+  }
+}
+
+/// Full response for a route other than [Route::Websocket] and [Route::Control]
+fn respond(
+  route: Route,
+  cfg: &MediaSourceConfig,
+  metadata: &MediaMetadata,
+  #[cfg_attr(not(feature = "streamdeck"), allow(unused_variables))] outbox: &Outbox,
+  client_connected: bool,
+) -> Vec<u8> {
+  match route {
+    Route::Health => json(
+      "200 OK",
+      &Health {
+        status: "ok",
+        service: instance::SERVICE,
+        client_connected,
+      },
+    ),
+    // only listeners on the same computer get to take over, see [serve]
+    Route::Shutdown => error("403 Forbidden", "only local listeners can take over"),
+    Route::NowPlaying => {
+      // covers are left out, they'd make the response huge, the urls are still there
+      let metadata = MediaMetadata {
+        cover: None,
+        background: None,
+        ..metadata.clone()
+      };
+
+      json("200 OK", &redact(cfg, metadata))
+    }
+    Route::Metrics => text(
+      "200 OK",
+      "text/plain; version=0.0.4",
+      &cfg.metrics.snapshot().to_prometheus(),
+    ),
+    #[cfg(feature = "streamdeck")]
+    Route::StreamDeck => {
+      streamdeck::state(&redact(cfg, metadata.clone()), outbox, client_connected)
+    }
+    #[cfg(feature = "streamdeck")]
+    Route::StreamDeckAction(action) => streamdeck::act(action, metadata, outbox, client_connected),
+    Route::NotFound => error("404 Not Found", "not found"),
+    Route::MethodNotAllowed => error("405 Method Not Allowed", "method not allowed"),
+    Route::HeadTooLarge => error(
+      "431 Request Header Fields Too Large",
+      "request header fields too large",
+    ),
+    Route::BodyTooLarge => error("413 Content Too Large", "request body too large"),
+    Route::Websocket | Route::Events | Route::Control => {
+      error("400 Bad Request", "expected a plain http request")
+    }
+  }
+}
+
+/// `metadata` with [MediaSourceConfig::redaction] applied, empty if it's suppressed
+fn redact(cfg: &MediaSourceConfig, metadata: MediaMetadata) -> MediaMetadata {
+  Redactor::new(cfg.redaction.clone())
+    .redact(MediaEvent::MediaChanged(metadata))
+    .and_then(|event| match event {
+      MediaEvent::MediaChanged(metadata) => Some(metadata),
+      _ => None,
+    })
+    .unwrap_or_default()
+}
+
+/// Response for websocket clients turned away by [ConnectionPolicy::RejectNew](crate::listener::ConnectionPolicy::RejectNew)
+pub(super) fn busy() -> Vec<u8> {
+  error("503 Service Unavailable", "another client is connected")
+}
+
+pub(super) fn error(status: &str, message: &str) -> Vec<u8> {
+  json(status, &ErrorBody { error: message })
+}
+
+pub(super) fn json(status: &str, body: &impl Serialize) -> Vec<u8> {
+  let body = serde_json::to_string(body).unwrap_or_default();
+
+  text(status, "application/json", &body)
+}
+
+fn text(status: &str, content_type: &str, body: &str) -> Vec<u8> {
+  format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  )
+  .into_bytes()
+}
@@ -0,0 +1,212 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use crate::http_client::{self, parse_response};
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::polled::PolledSource;
+use crate::{Error, MediaEvent, MediaMetadata, Result};
+
+/// What `GET /healthz` answers with as `service`, so other servers on the port aren't mistaken for a listener
+pub(super) const SERVICE: &str = "currently-playing";
+
+/// How long another listener gets to answer
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// How long another listener gets to let go of the address after being asked to
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often [InstanceClient] asks the other listener what is playing
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Checks if another listener serves `addr`, by asking for its `GET /healthz`
+pub(super) fn is_running(addr: SocketAddr) -> bool {
+  let Ok(response) = http_client::request(&addr, "GET", "/healthz", &[], None, TIMEOUT) else {
+    return false;
+  };
+
+  let Some((200, body)) = parse_response(&response) else {
+    return false;
+  };
+
+  serde_json::from_slice::<serde_json::Value>(&body)
+    .is_ok_and(|health| health["service"] == SERVICE)
+}
+
+/// Asks the listener serving `addr` to shut its websocket source down, with the secret it wrote
+/// to [secret_path], returns once the address is free or fails with [Error::InstanceRunning]
+/// if it doesn't let go of it
+pub(super) fn take_over(addr: SocketAddr) -> Result<()> {
+  let secret = std::fs::read_to_string(secret_path(addr)).unwrap_or_default();
+  let authorization = format!("Bearer {}", secret.trim());
+
+  let response = http_client::request(
+    &addr,
+    "POST",
+    "/shutdown",
+    &[("Authorization", &authorization)],
+    None,
+    TIMEOUT,
+  )?;
+
+  if !matches!(parse_response(&response), Some((202, _))) {
+    return Err(Error::InstanceRunning(addr));
+  }
+
+  let due = Instant::now() + SHUTDOWN_TIMEOUT;
+
+  while is_running(addr) {
+    if Instant::now() >= due {
+      return Err(Error::InstanceRunning(addr));
+    }
+
+    std::thread::sleep(Duration::from_millis(50));
+  }
+
+  Ok(())
+}
+
+/// Secret of this process that `POST /shutdown` has to come with, so only someone who can read
+/// the user's files can take over, not any web page that sends a request to the port
+pub(super) fn secret() -> &'static str {
+  static SECRET: OnceLock<String> = OnceLock::new();
+
+  SECRET.get_or_init(|| {
+    let mut bytes = [0; 32];
+
+    // without randomness there's no secret worth having, takeovers fail until there is
+    if getrandom::getrandom(&mut bytes).is_err() {
+      return String::new();
+    }
+
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+  })
+}
+
+/// Directory of the user's own to keep the secret in, the shared temp dir only if there's none
+fn secret_dir() -> PathBuf {
+  if let Some(runtime) = std::env::var_os("XDG_RUNTIME_DIR") {
+    return PathBuf::from(runtime);
+  }
+
+  let home = match cfg!(windows) {
+    true => std::env::var_os("LOCALAPPDATA").map(PathBuf::from),
+    false => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")),
+  };
+
+  home
+    .map(|dir| dir.join("currently-playing"))
+    .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Where the listener serving `addr` keeps its [secret]
+fn secret_path(addr: SocketAddr) -> PathBuf {
+  secret_dir().join(format!("currently-playing-{}.secret", addr.port()))
+}
+
+/// Writes [secret] for `addr`, only the user running the listener can read it
+///
+/// The file is always created anew, so one another user made first or a symlink planted in its
+/// place makes this fail instead of handing them the secret or overwriting what it points to
+pub(super) fn write_secret(addr: SocketAddr) -> std::io::Result<()> {
+  if secret().is_empty() {
+    return Err(std::io::ErrorKind::Unsupported.into());
+  }
+
+  let path = secret_path(addr);
+
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir)?;
+  }
+
+  // left behind by a listener that didn't shut down cleanly, or a symlink, which is removed itself
+  match std::fs::remove_file(&path) {
+    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+    _ => {}
+  }
+
+  let mut options = std::fs::OpenOptions::new();
+  options.write(true).create_new(true);
+
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+  options.open(path)?.write_all(secret().as_bytes())
+}
+
+/// Removes the secret file of `addr` unless another listener has written its own since
+pub(super) fn remove_secret(addr: SocketAddr) {
+  let path = secret_path(addr);
+
+  if std::fs::read_to_string(&path).is_ok_and(|written| written == secret()) {
+    let _ = std::fs::remove_file(path);
+  }
+}
+
+/// Follows the media of the listener serving [MediaSourceConfig::addr], what the websocket source
+/// turns into with [InstancePolicy::Client](crate::listener::InstancePolicy::Client)
+///
+/// Covers aren't passed on, only their urls
+#[derive(Debug)]
+pub(crate) struct InstanceClient(PolledSource);
+
+impl InstanceClient {
+  pub(crate) const NAME: &'static str = "instance";
+}
+
+impl MediaSource for InstanceClient {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let addr = cfg.addr.socket_addr();
+
+    PolledSource::create(cfg, Self::NAME, POLL_INTERVAL, move || now_playing(addr)).map(Self)
+  }
+
+  fn is_closed(&self) -> bool {
+    self.0.is_closed()
+  }
+
+  fn is_running(&self) -> bool {
+    self.0.is_running()
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    self.0.poll_guarded()
+  }
+
+  fn is_stale(&self) -> bool {
+    self.0.is_stale()
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.0.last_error()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    self.0.next()
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.0.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.0.set_notifier(notifier)
+  }
+}
+
+/// `GET /now-playing` of the listener serving `addr`
+fn now_playing(addr: SocketAddr) -> Result<MediaMetadata> {
+  let response = http_client::request(&addr, "GET", "/now-playing", &[], None, TIMEOUT)?;
+
+  match parse_response(&response) {
+    Some((200, body)) => serde_json::from_slice(&body).map_err(|err| Error::Protocol(err.into())),
+    Some((status, _)) => Err(Error::Connection(
+      format!("{addr} answered with {status}").into(),
+    )),
+    None => Err(Error::Protocol("malformed http response".into())),
+  }
+}
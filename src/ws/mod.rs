@@ -0,0 +1,1565 @@
+#![cfg(feature = "ws")]
+
+#[cfg(not(any(feature = "ws-tokio", feature = "ws-smol")))]
+compile_error!("the `ws` feature needs a backend, enable either `ws-tokio` or `ws-smol`");
+
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::future::{poll_fn, Future};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::Error;
+
+use crate::backoff::Retry;
+use crate::listener::{
+  BindError, ConnectionPolicy, EventNotifier, InstancePolicy, MediaSource, MediaSourceConfig,
+  MessageLimits, SocketOptions, WebsocketAddr,
+};
+use crate::pipeline::{
+  event_channel, panicked, ChannelReceiver, ChannelSender, EventSender, Heartbeat, TokenBucket,
+  TrackEndDetector,
+};
+use crate::{
+  ClientEnvelope, ClientMessage, CloseReason, CoverChunk, EventKind, MediaCapabilities, MediaCommand,
+  MediaEvent, MediaImage, MediaMetadata, MediaState, ProtocolError, ProtocolErrorKind, QueueEntry,
+  SourceKind, PROTOCOL_VERSION,
+};
+
+mod broadcast;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod http;
+mod instance;
+#[cfg(feature = "streamdeck")]
+mod streamdeck;
+#[cfg(feature = "ws-tokio")]
+mod tokio_backend;
+#[cfg(feature = "ws-tokio")]
+use tokio_backend as backend;
+
+#[cfg(all(feature = "ws-smol", not(feature = "ws-tokio")))]
+mod smol_backend;
+#[cfg(all(feature = "ws-smol", not(feature = "ws-tokio")))]
+use smol_backend as backend;
+
+use backend::Incoming;
+use broadcast::Consumers;
+
+pub(crate) use instance::InstanceClient;
+pub use crate::MediaMessage;
+
+pub use backend::MediaConnection;
+/// Listens for media clients, the transport depends on the `ws-tokio` or `ws-smol` feature
+///
+/// Examples
+/// --------
+///
+/// ```rs
+/// use currently_playing::MediaEvent;
+/// use currently_playing::ws::MediaListener;
+///
+/// // Create a listener using local ip and default port
+/// let listener = MediaListener::bind_default().await.unwrap();
+///
+/// // Create a listener using local ip with custom port
+/// let listener = MediaListener::bind_local(69420).await.unwrap();
+///
+/// // Create a listener using a custom address
+/// let listener = MediaListener::bind("127.0.0.1:69420".into()).await.unwrap();
+///
+/// // Listen for incoming connections, if media client closes, the loop keeps listening
+/// while let Ok(mut connection) = listener.get_connection().await {
+///   // handle connection
+/// }
+/// ```
+pub use backend::WebsocketMediaSource;
+
+impl MediaConnection {
+  /// Parses a message, plain events are turned into [ClientMessage::Player] with an empty id,
+  /// [ClientMessage::Capabilities] and [ClientMessage::Pong] are also kept by the connection
+  fn handle_message(&mut self, message: Cow<str>) -> Result<ClientMessage, ProtocolError> {
+    if message.len() > self.limits.max_size {
+      return Err(ProtocolError::new(
+        ProtocolErrorKind::TooLarge,
+        format!(
+          "message is {} bytes, at most {} are allowed",
+          message.len(),
+          self.limits.max_size
+        ),
+      ));
+    }
+
+    let message = self
+      .capabilities
+      .duration_format
+      .scope(|| parse_message(&message))?;
+
+    self.limits.check_message(&message)?;
+
+    match &message {
+      ClientMessage::Capabilities(capabilities) => self.capabilities = capabilities.clone(),
+      ClientMessage::Pong { sent, received } => {
+        self.clock.pong(*sent, *received, unix_millis());
+      }
+      // it would skip the token
+      ClientMessage::Player {
+        event: MediaEvent::ControlRequested(_),
+        ..
+      } => {
+        return Err(ProtocolError::new(
+          ProtocolErrorKind::Unauthorized,
+          "remote control is only accepted as ClientMessage::Control",
+        ));
+      }
+      ClientMessage::Player { .. }
+      | ClientMessage::PlayerRemoved(_)
+      | ClientMessage::Control { .. } => {}
+    }
+
+    Ok(message)
+  }
+
+  /// [MediaConnection::handle_message], the client is sent a [MediaMessage::ProtocolError]
+  /// if its message is rejected
+  async fn receive(&mut self, message: Cow<'_, str>) -> Result<ClientMessage, Error> {
+    match self.handle_message(message) {
+      Ok(message) => Ok(message),
+      Err(err) => {
+        let _ = self
+          .send_message(MediaMessage::ProtocolError(err.clone()))
+          .await;
+
+        Err(Error::Io(std::io::Error::new(ErrorKind::InvalidData, err)))
+      }
+    }
+  }
+
+  /// Messages past `limits` are rejected, defaults to [MessageLimits::default]
+  pub fn set_limits(&mut self, limits: MessageLimits) {
+    self.limits = limits;
+  }
+
+  /// Waits for the next event from any of the client's players, use
+  /// [MediaConnection::next_message] to tell them apart
+  pub async fn next(&mut self) -> Option<Result<MediaEvent, Error>> {
+    loop {
+      return match self.next_message().await? {
+        Ok(ClientMessage::Player { event, .. }) => Some(Ok(event)),
+        Ok(_) => continue,
+        Err(err) => Some(Err(err)),
+      };
+    }
+  }
+
+  /// What the media client said it supports, defaults until it sends [MediaCapabilities]
+  pub fn capabilities(&self) -> &MediaCapabilities {
+    &self.capabilities
+  }
+
+  /// Sends [MediaMessage::Ping], the answer is picked up by [MediaConnection::next_message]
+  /// and updates [MediaConnection::latency] and [MediaConnection::clock_offset]
+  pub async fn ping(&mut self) -> Result<(), Error> {
+    self.send_message(MediaMessage::Ping(unix_millis())).await
+  }
+
+  /// Estimated time messages take from the media client to here, [None] until it answered a ping
+  pub fn latency(&self) -> Option<Duration> {
+    self.clock.best().map(|sample| sample.latency)
+  }
+
+  /// Estimated milliseconds the media client's clock is ahead of this one (negative if behind),
+  /// [None] until it answered a ping
+  pub fn clock_offset(&self) -> Option<i64> {
+    self.clock.best().map(|sample| sample.offset)
+  }
+
+  fn message_to_text(message: MediaMessage) -> String {
+    serde_json::to_string(&message).unwrap_or_else(|_| {
+      // only panics if serialize was implemented incorrectly
+      panic!(
+        "failed to turn {} into a json string",
+        std::any::type_name::<MediaMessage>()
+      )
+    })
+  }
+
+  /// Sets how often it should update the progress
+  ///
+  /// **This might be ignored depending on the media client implementation**
+  pub async fn set_progress_interval(&mut self, interval: Duration) -> Result<(), Error> {
+    let ms = interval.as_millis() as u64;
+
+    self
+      .send_message(MediaMessage::ProgressUpdateInterval(ms))
+      .await
+  }
+
+  /// Asks the media client to only send the given types of events
+  ///
+  /// **This might be ignored depending on the media client implementation,
+  /// [WebsocketMediaSourceBackground] filters them regardless**
+  pub async fn subscribe(&mut self, events: EventKind) -> Result<(), Error> {
+    self.send_message(MediaMessage::Subscribe(events)).await
+  }
+}
+
+impl WebsocketMediaSource {
+  /// Binds to 127.0.0.1:19532
+  pub async fn bind_default() -> std::io::Result<Self> {
+    Self::bind_local(19532).await
+  }
+
+  /// Binds to 127.0.0.1 with a custom port
+  pub async fn bind_local(port: u16) -> std::io::Result<Self> {
+    let ip = format!("127.0.0.1:{port}");
+
+    Self::bind(ip.parse().unwrap()).await
+  }
+
+  /// Binds from [WebsocketAddr]
+  pub async fn bind_from(value: WebsocketAddr) -> std::io::Result<Self> {
+    match value {
+      WebsocketAddr::Local(port) => Self::bind_local(port).await,
+      WebsocketAddr::Addr(addr) => Self::bind(addr).await,
+      WebsocketAddr::Default => Self::bind_default().await,
+      WebsocketAddr::Activated => match activated_listener() {
+        Some(listener) => Self::from_std_listener(listener),
+        None => Self::bind_default().await,
+      },
+    }
+  }
+}
+
+/// Listening socket systemd passed to the process, see sd_listen_fds(3),
+/// only handed out once since whoever takes it owns it
+#[cfg(unix)]
+fn activated_listener() -> Option<std::net::TcpListener> {
+  use std::os::fd::{FromRawFd, IntoRawFd};
+
+  static TAKEN: AtomicBool = AtomicBool::new(false);
+
+  // the first passed socket is always fd 3
+  const SD_LISTEN_FDS_START: i32 = 3;
+
+  let pid = std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+  let fds = std::env::var("LISTEN_FDS").ok()?.parse::<u32>().ok()?;
+
+  if pid != std::process::id() || fds == 0 || TAKEN.swap(true, Ordering::SeqCst) {
+    return None;
+  }
+
+  // systemd passed the fd for this process to own and TAKEN makes sure it's only wrapped once
+  let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+
+  // anything but an ip socket (like a unix socket for something else) is left open and alone
+  if listener.local_addr().is_err() {
+    let _ = listener.into_raw_fd();
+    return None;
+  }
+
+  Some(listener)
+}
+
+#[cfg(not(unix))]
+fn activated_listener() -> Option<std::net::TcpListener> {
+  None
+}
+
+/// Non-blocking listening socket with [SocketOptions::reuse_address] applied, for the backends to wrap
+fn listen(addr: SocketAddr, options: &SocketOptions) -> std::io::Result<std::net::TcpListener> {
+  let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+  socket.set_reuse_address(options.reuse_address)?;
+  socket.bind(&addr.into())?;
+  socket.listen(128)?;
+  socket.set_nonblocking(true)?;
+
+  Ok(socket.into())
+}
+
+/// Applies the [SocketOptions] that are set per client connection
+fn configure(socket: SockRef, options: &SocketOptions) -> std::io::Result<()> {
+  socket.set_tcp_nodelay(options.nodelay)?;
+
+  if let Some(idle) = options.keepalive {
+    socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+  }
+
+  Ok(())
+}
+
+#[derive(Debug)]
+enum Either<L, R> {
+  Left(L),
+  Right(R),
+}
+
+/// Runs both futures until one finishes, `left` goes first if both are ready
+async fn race<L: Future, R: Future>(left: L, right: R) -> Either<L::Output, R::Output> {
+  let mut left = pin!(left);
+  let mut right = pin!(right);
+
+  poll_fn(|cx| {
+    if let Poll::Ready(output) = left.as_mut().poll(cx) {
+      return Poll::Ready(Either::Left(output));
+    }
+
+    right.as_mut().poll(cx).map(Either::Right)
+  })
+  .await
+}
+
+/// Cancellation flag the background task can also wait on,
+/// so dropping the source wakes it up instead of it holding on to the port until the next connection
+#[derive(Debug, Default)]
+struct CancelToken {
+  cancelled: AtomicBool,
+  waker: Mutex<Option<Waker>>,
+}
+
+impl CancelToken {
+  fn cancel(&self) {
+    self.cancelled.store(true, Ordering::SeqCst);
+
+    if let Some(waker) = self.waker.lock().unwrap().take() {
+      waker.wake();
+    }
+  }
+
+  fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst)
+  }
+
+  /// Runs `future` until it finishes, [None] if cancelled before that
+  async fn run<F: Future>(&self, future: F) -> Option<F::Output> {
+    let mut future = pin!(future);
+
+    poll_fn(|cx| {
+      *self.waker.lock().unwrap() = Some(cx.waker().clone());
+
+      // checked after storing the waker so a concurrent cancel can't be missed
+      if self.is_cancelled() {
+        return Poll::Ready(None);
+      }
+
+      future.as_mut().poll(cx).map(Some)
+    })
+    .await
+  }
+}
+
+/// Where a client that isn't the connected media client is at
+enum SideRequest {
+  /// Its head arrived and it can be answered
+  Peeked(Incoming, http::Request),
+  /// It's been answered, the event consumer disconnected or the client went away
+  Done,
+}
+
+/// Plain HTTP requests and event consumers, worked on alongside the media client's messages
+/// so a slow request can't hold them up
+#[derive(Default)]
+struct SideRequests(Vec<Pin<Box<dyn Future<Output = SideRequest> + Send>>>);
+
+impl SideRequests {
+  fn push(&mut self, request: impl Future<Output = SideRequest> + Send + 'static) {
+    self.0.push(Box::pin(request));
+  }
+
+  /// Waits for a request to get to its next step, never finishes while there are none
+  async fn next(&mut self) -> SideRequest {
+    poll_fn(|cx| {
+      for i in 0..self.0.len() {
+        if let Poll::Ready(step) = self.0[i].as_mut().poll(cx) {
+          drop(self.0.swap_remove(i));
+          return Poll::Ready(step);
+        }
+      }
+
+      Poll::Pending
+    })
+    .await
+  }
+}
+
+/// Messages for the connected media client, queued by [MediaSource::control]
+/// and sent by the background task
+#[derive(Debug, Default)]
+struct Outbox {
+  queue: Mutex<VecDeque<MediaMessage>>,
+  waker: Mutex<Option<Waker>>,
+  /// Connected client said it handles playback controls, see [MediaCapabilities::control]
+  control: AtomicBool,
+}
+
+impl Outbox {
+  fn push(&self, message: MediaMessage) {
+    self.queue.lock().unwrap().push_back(message);
+
+    if let Some(waker) = self.waker.lock().unwrap().take() {
+      waker.wake();
+    }
+  }
+
+  /// Drops whatever was meant for a previous client
+  fn clear(&self) {
+    self.queue.lock().unwrap().clear();
+    self.control.store(false, Ordering::SeqCst);
+  }
+
+  /// Waits for the next queued message
+  async fn next(&self) -> MediaMessage {
+    poll_fn(|cx| {
+      *self.waker.lock().unwrap() = Some(cx.waker().clone());
+
+      // checked after storing the waker so a concurrent push can't be missed
+      match self.queue.lock().unwrap().pop_front() {
+        Some(message) => Poll::Ready(message),
+        None => Poll::Pending,
+      }
+    })
+    .await
+  }
+}
+
+/// Answered pings kept to estimate the latency of a media client
+const CLOCK_SAMPLES: usize = 8;
+
+/// Latency and clock offset of a media client, from the pings it answered
+#[derive(Debug, Default)]
+pub(super) struct ClockSync {
+  samples: VecDeque<ClockSample>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ClockSample {
+  round_trip: Duration,
+  latency: Duration,
+  offset: i64,
+}
+
+impl ClockSync {
+  fn pong(&mut self, sent: u64, received: u64, now: u64) {
+    let Some(round_trip) = now.checked_sub(sent) else {
+      return;
+    };
+
+    let latency = round_trip / 2;
+
+    if self.samples.len() == CLOCK_SAMPLES {
+      self.samples.pop_front();
+    }
+
+    self.samples.push_back(ClockSample {
+      round_trip: Duration::from_millis(round_trip),
+      latency: Duration::from_millis(latency),
+      offset: received as i64 - (sent + latency) as i64,
+    });
+  }
+
+  /// Sample with the shortest round trip, the one least thrown off by a slow network
+  fn best(&self) -> Option<ClockSample> {
+    self
+      .samples
+      .iter()
+      .min_by_key(|sample| sample.round_trip)
+      .copied()
+  }
+}
+
+/// Variants of [ClientMessage], anything else without an envelope is parsed as a [MediaEvent]
+const CLIENT_MESSAGES: &[&str] = &["Capabilities", "Player", "PlayerRemoved", "Pong", "Control"];
+
+/// Parses a [ClientEnvelope], a [ClientMessage] or a bare [MediaEvent]
+fn parse_message(text: &str) -> Result<ClientMessage, ProtocolError> {
+  let malformed =
+    |err: serde_json::Error| ProtocolError::new(ProtocolErrorKind::Malformed, err.to_string());
+  let value = serde_json::from_str::<serde_json::Value>(text).map_err(malformed)?;
+
+  if let Some(version) = value.get("version") {
+    let supported = version
+      .as_u64()
+      .is_some_and(|version| version <= PROTOCOL_VERSION as u64);
+
+    if !supported {
+      return Err(ProtocolError::new(
+        ProtocolErrorKind::UnsupportedVersion,
+        format!("version {version} isn't supported, the latest is {PROTOCOL_VERSION}"),
+      ));
+    }
+
+    return serde_json::from_value::<ClientEnvelope>(value)
+      .map(|envelope| envelope.message)
+      .map_err(malformed);
+  }
+
+  // the error of whatever the client was most likely going for
+  let is_client_message = value.as_object().is_some_and(|object| {
+    object
+      .keys()
+      .all(|key| CLIENT_MESSAGES.contains(&key.as_str()))
+  });
+
+  match is_client_message {
+    true => serde_json::from_value::<ClientMessage>(value),
+    false => serde_json::from_value::<MediaEvent>(value).map(|event| ClientMessage::Player {
+      player_id: String::new(),
+      event,
+    }),
+  }
+  .map_err(malformed)
+}
+
+impl MessageLimits {
+  fn check_message(&self, message: &ClientMessage) -> Result<(), ProtocolError> {
+    match message {
+      ClientMessage::Player { player_id, event } => {
+        self.check_string("player_id", player_id)?;
+
+        match event {
+          MediaEvent::MediaChanged(metadata) | MediaEvent::TrackEnded(metadata) => {
+            self.check_metadata(metadata)
+          }
+          MediaEvent::QueueChanged(queue) => self.check_queue(queue),
+          _ => Ok(()),
+        }
+      }
+      ClientMessage::PlayerRemoved(player_id) => self.check_string("player_id", player_id),
+      ClientMessage::Control { token, .. } => self.check_string("token", token),
+      ClientMessage::Capabilities(_) | ClientMessage::Pong { .. } => Ok(()),
+    }
+  }
+
+  fn check_metadata(&self, metadata: &MediaMetadata) -> Result<(), ProtocolError> {
+    let optional = [
+      ("uid", &metadata.uid),
+      ("uri", &metadata.uri),
+      ("album", &metadata.album),
+      ("cover_url", &metadata.cover_url),
+      ("background_url", &metadata.background_url),
+      ("app", &metadata.app),
+    ];
+
+    for (field, value) in optional {
+      self.check_string(field, value.as_deref().unwrap_or_default())?;
+    }
+
+    self.check_string("title", &metadata.title)?;
+    self.check_strings("artists", &metadata.artists)?;
+    self.check_strings("featured_artists", &metadata.featured_artists)?;
+    self.check_len("extra", metadata.extra.len())?;
+    self.check_queue(&metadata.queue)
+  }
+
+  fn check_queue(&self, queue: &[QueueEntry]) -> Result<(), ProtocolError> {
+    self.check_len("queue", queue.len())?;
+
+    for entry in queue {
+      let optional = [
+        ("queue.uid", &entry.uid),
+        ("queue.uri", &entry.uri),
+        ("queue.album", &entry.album),
+        ("queue.cover_url", &entry.cover_url),
+      ];
+
+      for (field, value) in optional {
+        self.check_string(field, value.as_deref().unwrap_or_default())?;
+      }
+
+      self.check_string("queue.title", &entry.title)?;
+      self.check_strings("queue.artists", &entry.artists)?;
+    }
+
+    Ok(())
+  }
+
+  fn check_strings(&self, field: &str, values: &[String]) -> Result<(), ProtocolError> {
+    self.check_len(field, values.len())?;
+
+    for value in values {
+      self.check_string(field, value)?;
+    }
+
+    Ok(())
+  }
+
+  fn check_string(&self, field: &str, value: &str) -> Result<(), ProtocolError> {
+    if value.len() <= self.max_string_len {
+      return Ok(());
+    }
+
+    Err(ProtocolError::new(
+      ProtocolErrorKind::LimitExceeded,
+      format!(
+        "{field} is {} bytes, at most {} are allowed",
+        value.len(),
+        self.max_string_len
+      ),
+    ))
+  }
+
+  fn check_len(&self, field: &str, len: usize) -> Result<(), ProtocolError> {
+    if len <= self.max_list_len {
+      return Ok(());
+    }
+
+    Err(ProtocolError::new(
+      ProtocolErrorKind::LimitExceeded,
+      format!(
+        "{field} has {len} entries, at most {} are allowed",
+        self.max_list_len
+      ),
+    ))
+  }
+}
+
+/// Checks `token` against [MediaSourceConfig::control_token]
+fn authorize(cfg: &MediaSourceConfig, token: &str) -> Result<(), ProtocolError> {
+  let unauthorized = |message| Err(ProtocolError::new(ProtocolErrorKind::Unauthorized, message));
+
+  let Some(expected) = &cfg.control_token else {
+    return unauthorized("remote control is turned off");
+  };
+
+  match same_secret(expected, token) {
+    true => Ok(()),
+    false => unauthorized("wrong control token"),
+  }
+}
+
+/// Compares every byte so how long it takes doesn't tell how much of `token` was right
+fn same_secret(expected: &str, token: &str) -> bool {
+  expected.len() == token.len()
+    && expected
+      .bytes()
+      .zip(token.bytes())
+      .fold(0, |diff, (a, b)| diff | (a ^ b))
+      == 0
+}
+
+fn close_frame(reason: CloseReason) -> CloseFrame<'static> {
+  CloseFrame {
+    code: CloseCode::from(reason.code()),
+    reason: reason.reason().into(),
+  }
+}
+
+fn unix_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|time| time.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+/// Covers sent in chunks larger than this are rejected
+const MAX_COVER_SIZE: usize = 32 * 1024 * 1024;
+
+/// Puts a cover sent as [CoverChunk]s back together
+#[derive(Debug, Default)]
+struct CoverAssembler {
+  id: Option<u64>,
+  rejected: bool,
+  data: Vec<u8>,
+  next_index: u32,
+}
+
+#[derive(Debug)]
+enum CoverProgress {
+  Pending,
+  Done(MediaImage),
+  /// Sent once per id, later chunks of a rejected cover are ignored
+  Rejected(u64),
+}
+
+impl CoverAssembler {
+  fn push(&mut self, chunk: CoverChunk) -> CoverProgress {
+    if self.id != Some(chunk.id) {
+      // a new id replaces a cover that never finished
+      *self = Self {
+        id: Some(chunk.id),
+        ..Self::default()
+      };
+    }
+
+    if self.rejected {
+      return CoverProgress::Pending;
+    }
+
+    if chunk.index != self.next_index || self.data.len() + chunk.data.len() > MAX_COVER_SIZE {
+      return self.reject(chunk.id);
+    }
+
+    self.data.extend_from_slice(&chunk.data);
+    self.next_index += 1;
+
+    let Some(end) = chunk.end else {
+      return CoverProgress::Pending;
+    };
+
+    if CoverChunk::checksum(&self.data) != end.checksum {
+      return self.reject(chunk.id);
+    }
+
+    let data = std::mem::take(&mut self.data);
+    *self = Self::default();
+
+    CoverProgress::Done(MediaImage::from_reported(end.format, data))
+  }
+
+  fn reject(&mut self, id: u64) -> CoverProgress {
+    self.rejected = true;
+    self.data = Vec::new();
+
+    CoverProgress::Rejected(id)
+  }
+}
+
+/// Every player a media client reports, see [ClientMessage::Player]
+///
+/// The player that started playing last is the active one, only its events are passed on
+#[derive(Debug, Default)]
+struct Players {
+  players: HashMap<String, Player>,
+  active: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Player {
+  metadata: MediaMetadata,
+  started: Option<Instant>,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum PlayerUpdate {
+  /// Event is from the active player
+  Active,
+  /// Event is from another player and only kept for later
+  Inactive,
+  /// Player the event is from took over, with its metadata
+  Switched(MediaMetadata),
+}
+
+impl Players {
+  fn update(&mut self, player_id: &str, event: &MediaEvent) -> PlayerUpdate {
+    let player = self.players.entry(player_id.to_string()).or_default();
+
+    let was_playing = player.metadata.state == MediaState::Playing;
+    apply_event(&mut player.metadata, event);
+    let started = !was_playing && player.metadata.state == MediaState::Playing;
+
+    if started {
+      player.started = Some(Instant::now());
+    }
+
+    match &self.active {
+      Some(active) if active == player_id => PlayerUpdate::Active,
+      Some(_) if started => {
+        self.active = Some(player_id.to_string());
+        PlayerUpdate::Switched(player.metadata.clone())
+      }
+      Some(_) => PlayerUpdate::Inactive,
+      None => {
+        self.active = Some(player_id.to_string());
+        PlayerUpdate::Active
+      }
+    }
+  }
+
+  /// Forgets the player, if it was the active one returns the metadata of the player taking over,
+  /// or empty metadata when there are none left
+  fn remove(&mut self, player_id: &str) -> Option<MediaMetadata> {
+    self.players.remove(player_id)?;
+
+    if self.active.as_deref() != Some(player_id) {
+      return None;
+    }
+
+    let next = self
+      .players
+      .iter()
+      .max_by_key(|(_, player)| (player.metadata.state == MediaState::Playing, player.started));
+
+    match next {
+      Some((id, player)) => {
+        self.active = Some(id.clone());
+        Some(player.metadata.clone())
+      }
+      None => {
+        self.active = None;
+        Some(MediaMetadata::default())
+      }
+    }
+  }
+}
+
+/// Updates `metadata` with what `event` changed
+fn apply_event(metadata: &mut MediaMetadata, event: &MediaEvent) {
+  match event {
+    MediaEvent::MediaChanged(info) => *metadata = info.clone(),
+    MediaEvent::StateChanged(state) => metadata.state = *state,
+    MediaEvent::ProgressChanged(elapsed) => metadata.elapsed = *elapsed,
+    MediaEvent::QueueChanged(queue) => metadata.queue = queue.clone(),
+    MediaEvent::TrackEnded(_)
+    | MediaEvent::ActiveSourceChanged(_)
+    | MediaEvent::ClientConnected(_)
+    | MediaEvent::ClientDisconnected(_)
+    | MediaEvent::SourceStale(_)
+    | MediaEvent::SourceGaveUp(_)
+    | MediaEvent::SourceError { .. }
+    | MediaEvent::Throttled(_)
+    | MediaEvent::ConflictDetected { .. }
+    | MediaEvent::SourceAdded(_)
+    | MediaEvent::SourceRemoved(_)
+    | MediaEvent::CoverChunk(_)
+    | MediaEvent::ListenThresholdReached { .. }
+    | MediaEvent::ControlRequested(_) => {}
+  }
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+enum BackgroundTask {
+  /// Dedicated thread running its own runtime
+  Thread(std::thread::JoinHandle<()>),
+  /// Task on the runtime from [MediaSourceConfig::runtime]
+  #[cfg(feature = "ws-tokio")]
+  Task(tokio::task::JoinHandle<()>),
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+pub struct WebsocketMediaSourceBackground {
+  timeout: Duration,
+  cancel_token: Arc<CancelToken>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  outbox: Arc<Outbox>,
+  /// Address the listener is bound to, [None] while it isn't
+  local_addr: Arc<RwLock<Option<SocketAddr>>>,
+  background_task: BackgroundTask,
+}
+
+impl MediaSource for WebsocketMediaSourceBackground {
+  fn create(cfg: MediaSourceConfig) -> crate::Result<Self> {
+    let cfg = cfg.for_source(&SourceKind::Websocket);
+
+    if !cfg.websocket_enabled {
+      return Err(crate::Error::NotEnabled);
+    }
+
+    // a socket from systemd is this listener's own
+    let addr = cfg.addr.socket_addr();
+    let check = cfg.instance_policy != InstancePolicy::Retry && cfg.addr != WebsocketAddr::Activated;
+
+    if check && instance::is_running(addr) {
+      match cfg.instance_policy {
+        InstancePolicy::TakeOver => instance::take_over(addr)?,
+        _ => return Err(crate::Error::InstanceRunning(addr)),
+      }
+    }
+
+    let cancel_token = Arc::new(CancelToken::default());
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Websocket);
+    let outbox = Arc::new(Outbox::default());
+    let local_addr = Arc::new(RwLock::new(None));
+
+    let background_task = spawn_background_task(
+      cfg.clone(),
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      last_error.clone(),
+      outbox.clone(),
+      local_addr.clone(),
+      send,
+    )?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      outbox,
+      local_addr,
+      background_task,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.cancel_token.is_cancelled()
+  }
+
+  fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  fn poll(&self) -> crate::Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> crate::Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(crate::Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  fn next(&self) -> crate::Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(crate::Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+
+  /// [None] until the background task has bound [MediaSourceConfig::addr], which happens shortly
+  /// after it's created, with [WebsocketAddr::Local(0)](WebsocketAddr::Local) this is where to find the port
+  fn local_addr(&self) -> Option<SocketAddr> {
+    *self.local_addr.read().unwrap()
+  }
+
+  /// Passed on to the connected media client, fails with [Error::NotExist](crate::Error::NotExist)
+  /// if no client is connected and [Error::Unsupported](crate::Error::Unsupported)
+  /// if it didn't say it handles [MediaCapabilities::control]
+  fn control(&self, command: MediaCommand) -> crate::Result<()> {
+    if self.is_closed() {
+      return Err(crate::Error::Closed);
+    }
+
+    if !self.is_running() {
+      return Err(crate::Error::NotExist);
+    }
+
+    if !self.outbox.control.load(Ordering::SeqCst) {
+      return Err(crate::Error::Unsupported);
+    }
+
+    self.outbox.push(command.into());
+
+    Ok(())
+  }
+}
+
+impl Drop for WebsocketMediaSourceBackground {
+  fn drop(&mut self) {
+    // wakes the background task so it drops the listener and its runtime shuts down,
+    // a task on the user's runtime can just be stopped
+    self.cancel_token.cancel();
+
+    #[cfg(feature = "ws-tokio")]
+    if let BackgroundTask::Task(task) = &self.background_task {
+      task.abort();
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_background_task(
+  cfg: MediaSourceConfig,
+  cancel_token: Arc<CancelToken>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  outbox: Arc<Outbox>,
+  local_addr: Arc<RwLock<Option<SocketAddr>>>,
+  send: ChannelSender,
+) -> std::io::Result<BackgroundTask> {
+  let task = run(
+    cfg.clone(),
+    cancel_token,
+    is_running,
+    metadata,
+    heartbeat,
+    last_error,
+    outbox,
+    local_addr,
+    send,
+  );
+
+  backend::spawn(&cfg, task)
+}
+
+/// Keeps binding and accepting connections until cancelled
+#[allow(clippy::too_many_arguments)]
+async fn run(
+  cfg: MediaSourceConfig,
+  cancel_token: Arc<CancelToken>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  outbox: Arc<Outbox>,
+  local_addr: Arc<RwLock<Option<SocketAddr>>>,
+  send: ChannelSender,
+) {
+  let backoff = cfg.bind_backoff.as_ref().unwrap_or(&cfg.backoff);
+  let mut retry = Retry::new(backoff.clone());
+  let mut events = EventSender::new(send.clone(), &cfg);
+  let mut addr = cfg.addr.socket_addr();
+
+  // kept for rebinding, the socket can't be bound again while systemd holds on to it
+  let activated = match cfg.addr {
+    WebsocketAddr::Activated => activated_listener(),
+    _ => None,
+  };
+
+  loop {
+    if cancel_token.is_cancelled() {
+      // like after another listener took over, the address is theirs now
+      *local_addr.write().unwrap() = None;
+      instance::remove_secret(addr);
+      return;
+    };
+
+    let bound = match &activated {
+      Some(listener) => listener
+        .try_clone()
+        .and_then(WebsocketMediaSource::from_std_listener),
+      None => WebsocketMediaSource::bind_with(addr, &cfg.socket).await,
+    };
+
+    match bound {
+      Ok(source) => {
+        retry.reset();
+
+        // binding again keeps the port the OS picked, clients already know it
+        if let Ok(bound) = source.local_addr() {
+          addr = bound;
+        }
+
+        *local_addr.write().unwrap() = Some(addr);
+
+        // without it nobody can take over, but the listener still works
+        let _ = instance::write_secret(addr);
+
+        let task = background_task(
+          source,
+          &cfg,
+          cancel_token.clone(),
+          is_running.clone(),
+          metadata.clone(),
+          &heartbeat,
+          &outbox,
+          send.clone(),
+        );
+
+        // the socket is dropped with the task, so it's bound again after the delay like when binding fails
+        if let Err(payload) = backend::catch_unwind(task).await {
+          // it's only running while a client is connected, which the panic cut off
+          if is_running.swap(false, Ordering::SeqCst) {
+            cfg.metrics.client_disconnected();
+          }
+
+          *local_addr.write().unwrap() = None;
+
+          let message = panicked(&SourceKind::Websocket, &metadata, &*payload).to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+
+          events.send(MediaEvent::SourceError {
+            source: SourceKind::Websocket,
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(SourceKind::Websocket));
+            return;
+          };
+
+          cfg.metrics.reconnected();
+
+          if cancel_token.run(backend::sleep(delay)).await.is_none() {
+            return;
+          }
+        }
+      }
+      Err(err) => {
+        is_running.store(false, Ordering::SeqCst);
+        *local_addr.write().unwrap() = None;
+
+        let retry_in = retry.next_delay();
+
+        if let Some(on_bind_error) = &cfg.on_bind_error {
+          on_bind_error.on_bind_error(&BindError {
+            addr,
+            error: &err,
+            retry_in,
+          });
+        }
+
+        let message = format!("failed to bind {addr}: {err}");
+        *last_error.write().unwrap() = Some(message.clone());
+
+        events.send(MediaEvent::SourceError {
+          source: SourceKind::Websocket,
+          message,
+        });
+
+        let Some(delay) = retry_in else {
+          events.send(MediaEvent::SourceGaveUp(SourceKind::Websocket));
+          return;
+        };
+
+        cfg.metrics.reconnected();
+
+        if cancel_token.run(backend::sleep(delay)).await.is_none() {
+          return;
+        }
+      }
+    }
+  }
+}
+
+/// Waits for the request head of a client that isn't served yet, see [SideRequests]
+async fn peek(incoming: Incoming) -> SideRequest {
+  match http::peek_request(&incoming).await {
+    Some(request) => SideRequest::Peeked(incoming, request),
+    None => SideRequest::Done,
+  }
+}
+
+/// Answers a plain HTTP request or starts sending an event consumer its events,
+/// returns the client if it's a media client
+#[allow(clippy::too_many_arguments)]
+fn handle_side_request(
+  incoming: Incoming,
+  request: http::Request,
+  cfg: &MediaSourceConfig,
+  metadata: &RwLock<MediaMetadata>,
+  outbox: &Outbox,
+  send: &mut EventSender,
+  side: &mut SideRequests,
+  cancel_token: &Arc<CancelToken>,
+  consumers: &Arc<Consumers>,
+  client_connected: bool,
+) -> Option<(Incoming, http::Request)> {
+  if request.route == http::Route::Events {
+    match consumers.is_full() {
+      true => side.push(async move {
+        let response = http::error("503 Service Unavailable", "too many consumers");
+        http::send(incoming, &request, &response).await;
+        SideRequest::Done
+      }),
+      false => side.push(broadcast::consume(incoming, consumers.clone())),
+    }
+
+    return None;
+  }
+
+  let response = http::answer(
+    &incoming,
+    &request,
+    cfg,
+    metadata,
+    outbox,
+    send,
+    client_connected,
+  );
+
+  let Some(response) = response else {
+    return Some((incoming, request));
+  };
+
+  let cancel_token = cancel_token.clone();
+  side.push(async move {
+    http::reply(incoming, &request, response, &cancel_token).await;
+    SideRequest::Done
+  });
+
+  None
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn background_task(
+  source: WebsocketMediaSource,
+  cfg: &MediaSourceConfig,
+  cancel_token: Arc<CancelToken>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: &Heartbeat,
+  outbox: &Outbox,
+  send: ChannelSender,
+) {
+  let mut send = EventSender::new(send, cfg);
+  let consumers = Arc::new(Consumers::new(cfg.channel_capacity));
+  send.set_tap(consumers.clone());
+
+  // client that replaced the previous one, see [ConnectionPolicy::ReplaceOld]
+  let mut replacement = None;
+  let mut side = SideRequests::default();
+
+  loop {
+    let incoming = match replacement.take() {
+      Some(incoming) => incoming,
+      None => {
+        let next = race(source.accept(&cfg.socket), side.next());
+
+        let media_client = match cancel_token.run(next).await {
+          None | Some(Either::Left(Err(_))) => return,
+          Some(Either::Left(Ok(incoming))) => {
+            side.push(peek(incoming));
+            continue;
+          }
+          Some(Either::Right(SideRequest::Peeked(incoming, request))) => handle_side_request(
+            incoming,
+            request,
+            cfg,
+            &metadata,
+            outbox,
+            &mut send,
+            &mut side,
+            &cancel_token,
+            &consumers,
+            false,
+          ),
+          Some(Either::Right(SideRequest::Done)) => continue,
+        };
+
+        match media_client {
+          Some((incoming, _)) => incoming,
+          None => continue,
+        }
+      }
+    };
+
+    let Some(Ok(mut connection)) = cancel_token.run(incoming.handshake()).await else {
+      continue;
+    };
+
+    if cancel_token.is_cancelled() {
+      let _ = connection.close_with(CloseReason::Shutdown).await;
+      return;
+    };
+
+    outbox.clear();
+    is_running.store(true, Ordering::SeqCst);
+    connection.set_limits(cfg.message_limits);
+
+    let addr = connection.addr;
+    cfg.metrics.client_connected();
+    send.send(MediaEvent::ClientConnected(addr));
+
+    if cfg.events != EventKind::ALL {
+      let _ = connection.subscribe(cfg.events).await;
+    }
+
+    let mut track_end = TrackEndDetector::default();
+    let mut covers = CoverAssembler::default();
+    let mut players = Players::default();
+    let mut next_ping = Instant::now();
+    let mut bucket = cfg.rate_limit.map(TokenBucket::new);
+    // latest progress over the rate limit, passed on once the client is allowed to send again
+    let mut held: Option<(String, MediaEvent)> = None;
+
+    loop {
+      let ping_due = match cfg.ping_interval {
+        Some(interval) if connection.capabilities.ping => {
+          if Instant::now() >= next_ping {
+            next_ping = Instant::now() + interval;
+            let _ = connection.ping().await;
+          }
+
+          Some(next_ping)
+        }
+        _ => None,
+      };
+
+      let released = held.is_some()
+        && bucket
+          .as_mut()
+          .is_some_and(|bucket| bucket.try_take(Instant::now()));
+
+      let message = match held.take_if(|_| released) {
+        Some((player_id, event)) => ClientMessage::Player { player_id, event },
+        None => {
+          let release_due = held
+            .as_ref()
+            .and(bucket.as_ref())
+            .map(TokenBucket::next_token);
+
+          let due = [send.next_due(), ping_due, release_due]
+            .into_iter()
+            .flatten()
+            .min();
+
+          let next = async {
+            match due {
+              Some(due) => backend::timeout_at(due, connection.next_message()).await,
+              None => Some(connection.next_message().await),
+            }
+          };
+
+          let next = race(
+            race(next, outbox.next()),
+            race(source.accept(&cfg.socket), side.next()),
+          );
+
+          let Some(next) = cancel_token.run(next).await else {
+            cfg.metrics.client_disconnected();
+            let _ = connection.close_with(CloseReason::Shutdown).await;
+            return;
+          };
+
+          let next = match next {
+            Either::Left(Either::Left(next)) => next,
+            Either::Left(Either::Right(command)) => {
+              let _ = connection.send_message(command).await;
+              continue;
+            }
+            Either::Right(Either::Left(Ok(incoming))) => {
+              side.push(peek(incoming));
+              continue;
+            }
+            Either::Right(Either::Left(Err(_))) => continue,
+            Either::Right(Either::Right(SideRequest::Peeked(incoming, request))) => {
+              let media_client = handle_side_request(
+                incoming,
+                request,
+                cfg,
+                &metadata,
+                outbox,
+                &mut send,
+                &mut side,
+                &cancel_token,
+                &consumers,
+                true,
+              );
+
+              let Some((incoming, request)) = media_client else {
+                continue;
+              };
+
+              // only one client is served at a time, so the limit is always reached
+              match cfg.connection_policy {
+                ConnectionPolicy::RejectNew => {
+                  side.push(async move {
+                    http::send(incoming, &request, &http::busy()).await;
+                    SideRequest::Done
+                  });
+                  continue;
+                }
+                ConnectionPolicy::ReplaceOld => {
+                  replacement = Some(incoming);
+                  let _ = connection.close_with(CloseReason::Replaced).await;
+                  break;
+                }
+              }
+            }
+            Either::Right(Either::Right(SideRequest::Done)) => continue,
+          };
+
+          let Some(message) = next else {
+            send.flush();
+            continue;
+          };
+
+          let Some(message) = message else {
+            break;
+          };
+
+          if cancel_token.is_cancelled() {
+            cfg.metrics.client_disconnected();
+            let _ = connection.close_with(CloseReason::Shutdown).await;
+            return;
+          };
+
+          // the connection can't be trusted to frame messages right anymore
+          if let Err(Error::Protocol(_) | Error::Capacity(_) | Error::Utf8) = message {
+            let _ = connection.close_with(CloseReason::ProtocolError).await;
+            break;
+          }
+
+          match message {
+            Ok(message) => message,
+            // the client stays connected after a rejected message, see [MediaConnection::receive]
+            Err(Error::Io(err))
+              if matches!(err.kind(), ErrorKind::InvalidData | ErrorKind::Unsupported) =>
+            {
+              continue
+            }
+            Err(_) => break,
+          }
+        }
+      };
+
+      if let Some(state) = heartbeat.beat() {
+        metadata.write().unwrap().state = state;
+      }
+
+      let (player_id, mut event) = match message {
+        ClientMessage::Player { player_id, event } => (player_id, event),
+        ClientMessage::PlayerRemoved(player_id) => {
+          if let Some(info) = players.remove(&player_id) {
+            *metadata.write().unwrap() = info.clone();
+            send.send(MediaEvent::MediaChanged(info));
+          }
+
+          continue;
+        }
+        ClientMessage::Capabilities(capabilities) => {
+          outbox.control.store(capabilities.control, Ordering::SeqCst);
+          continue;
+        }
+        // handled by the connection itself
+        ClientMessage::Pong { .. } => continue,
+        ClientMessage::Control { token, request } => {
+          match authorize(cfg, &token) {
+            Ok(()) => send.send(MediaEvent::ControlRequested(request)),
+            // so tokens can't be guessed one message after another
+            Err(err) => {
+              let _ = connection
+                .send_message(MediaMessage::ProtocolError(err))
+                .await;
+              let _ = connection.close_with(CloseReason::Unauthorized).await;
+              break;
+            }
+          }
+
+          continue;
+        }
+      };
+
+      // cover chunks are already limited by the size of the cover
+      let limited = !released
+        && !matches!(event, MediaEvent::CoverChunk(_))
+        && bucket
+          .as_mut()
+          .is_some_and(|bucket| !bucket.try_take(Instant::now()));
+
+      if limited {
+        if let MediaEvent::ProgressChanged(_) = event {
+          held = Some((player_id, event));
+        }
+
+        continue;
+      }
+
+      // progress held from before no longer applies to new media
+      if let MediaEvent::MediaChanged(_) = event {
+        if held.as_ref().is_some_and(|(id, _)| *id == player_id) {
+          held = None;
+        }
+      }
+
+      if let MediaEvent::CoverChunk(chunk) = event {
+        if !cfg.covers {
+          continue;
+        }
+
+        event = match covers.push(chunk) {
+          CoverProgress::Pending => continue,
+          CoverProgress::Rejected(id) => {
+            let _ = connection
+              .send_message(MediaMessage::CoverRejected(id))
+              .await;
+            continue;
+          }
+          CoverProgress::Done(cover) => {
+            let mut info = players
+              .players
+              .get(&player_id)
+              .map(|player| player.metadata.clone())
+              .unwrap_or_default();
+            info.cover = Some(cover);
+            MediaEvent::MediaChanged(info)
+          }
+        };
+      }
+
+      if let MediaEvent::MediaChanged(info) = &mut event {
+        cfg.sanitize.apply(info);
+        cfg.apply_zone(info);
+
+        // clients on other machines are told apart by where they connect from
+        if info.zone.is_none() && !addr.ip().is_loopback() {
+          info.zone = Some(addr.ip().to_string());
+        }
+
+        if !cfg.covers {
+          info.cover = None;
+          info.background = None;
+        }
+      }
+
+      // progress was measured when the client sent it, it kept playing while the message was underway
+      if let Some(latency) = connection.latency() {
+        match &mut event {
+          MediaEvent::ProgressChanged(elapsed) => *elapsed += latency,
+          MediaEvent::MediaChanged(info) if info.state == MediaState::Playing => {
+            info.elapsed += latency;
+          }
+          _ => {}
+        }
+      }
+
+      match players.update(&player_id, &event) {
+        PlayerUpdate::Active => {}
+        PlayerUpdate::Inactive => continue,
+        PlayerUpdate::Switched(info) => {
+          *metadata.write().unwrap() = info.clone();
+          send.send(MediaEvent::MediaChanged(info));
+          continue;
+        }
+      }
+
+      let ended = track_end.detect(&metadata.read().unwrap(), &event);
+
+      if let Some(ended) = ended {
+        send.send(ended);
+      }
+
+      send.send(event.clone());
+
+      apply_event(&mut metadata.write().unwrap(), &event);
+    }
+
+    is_running.store(false, Ordering::SeqCst);
+    cfg.metrics.client_disconnected();
+
+    send.send(MediaEvent::ClientDisconnected(addr));
+  }
+}
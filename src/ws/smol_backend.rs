@@ -0,0 +1,252 @@
+use std::future::Future;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_io::{Async, Timer};
+use futures_lite::{AsyncReadExt, AsyncWriteExt, FutureExt};
+use socket2::SockRef;
+use tungstenite::handshake::HandshakeError;
+use tungstenite::{Error, Message, WebSocket};
+
+use super::{BackgroundTask, ClockSync, MediaMessage};
+use crate::listener::{MediaSourceConfig, MessageLimits, SocketOptions};
+use crate::{ClientMessage, CloseReason, MediaCapabilities};
+
+/// Wraps around [TcpListener] on async-io's reactor, works on any executor
+#[derive(Debug)]
+pub struct WebsocketMediaSource {
+  pub listener: Async<TcpListener>,
+}
+
+#[derive(Debug)]
+pub struct MediaConnection {
+  ws: WebSocket<SharedStream>,
+  stream: Arc<Async<TcpStream>>,
+  /// Address of the connected media client
+  pub addr: SocketAddr,
+  pub(super) capabilities: MediaCapabilities,
+  pub(super) clock: ClockSync,
+  pub(super) limits: MessageLimits,
+}
+
+/// Non-blocking stream handed to tungstenite while the reactor waits on the same socket
+#[derive(Debug)]
+struct SharedStream(Arc<Async<TcpStream>>);
+
+impl Read for SharedStream {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.0.get_ref().read(buf)
+  }
+}
+
+impl Write for SharedStream {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0.get_ref().write(buf)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.0.get_ref().flush()
+  }
+}
+
+fn would_block<T>(result: &Result<T, Error>) -> bool {
+  matches!(result, Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock)
+}
+
+impl WebsocketMediaSource {
+  /// Binds to the given address with the default [SocketOptions]
+  pub async fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+    Self::bind_with(addr, &SocketOptions::default()).await
+  }
+
+  /// Binds to the given address with [SocketOptions::reuse_address] of `options`
+  pub async fn bind_with(addr: SocketAddr, options: &SocketOptions) -> std::io::Result<Self> {
+    let listener = Async::new(super::listen(addr, options)?)?;
+
+    Ok(Self { listener })
+  }
+
+  /// Takes over a listener that is already bound, like one passed by a service manager,
+  /// it's made non-blocking
+  pub fn from_std_listener(listener: std::net::TcpListener) -> std::io::Result<Self> {
+    listener.set_nonblocking(true)?;
+    let listener = Async::new(listener)?;
+
+    Ok(Self { listener })
+  }
+
+  /// Address it's bound to, with the port the OS picked when binding to port 0
+  pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+    self.listener.get_ref().local_addr()
+  }
+
+  /// Establishes a websocket connection to the client
+  pub async fn get_connection(&self) -> Result<MediaConnection, Error> {
+    self.accept(&SocketOptions::default()).await?.handshake().await
+  }
+
+  /// Waits for a client without doing the websocket handshake yet, unlike
+  /// [WebsocketMediaSource::get_connection] this can be cancelled without losing the client
+  pub(super) async fn accept(&self, options: &SocketOptions) -> Result<Incoming, Error> {
+    let listener = self.listener.accept().await;
+    let (stream, addr) = listener.map_err(|_| Error::ConnectionClosed)?;
+
+    // the client is still served if its socket can't be tuned
+    let _ = super::configure(SockRef::from(stream.get_ref()), options);
+
+    Ok(Incoming { stream, addr })
+  }
+}
+
+/// Client that connected but hasn't done the websocket handshake yet
+#[derive(Debug)]
+pub(super) struct Incoming {
+  stream: Async<TcpStream>,
+  pub(super) addr: SocketAddr,
+}
+
+impl Incoming {
+  /// Looks at what the client sent without taking it out of the stream
+  pub(super) async fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.stream.peek(buf).await
+  }
+
+  /// Takes the `len` bytes of the request out of the stream, sends `response` and closes the connection
+  pub(super) async fn respond(self, len: usize, response: &[u8]) -> std::io::Result<()> {
+    let mut stream = &self.stream;
+    let mut request = vec![0; len];
+
+    stream.read_exact(&mut request).await?;
+    stream.write_all(response).await?;
+    stream.flush().await
+  }
+
+  pub(super) async fn handshake(self) -> Result<MediaConnection, Error> {
+    let Self { stream, addr } = self;
+    let stream = Arc::new(stream);
+
+    let mut handshake = tungstenite::accept(SharedStream(stream.clone()));
+
+    let ws = loop {
+      match handshake {
+        Ok(ws) => break ws,
+        Err(HandshakeError::Interrupted(mid)) => {
+          stream.readable().await?;
+          handshake = mid.handshake();
+        }
+        Err(HandshakeError::Failure(err)) => return Err(err),
+      }
+    };
+
+    Ok(MediaConnection {
+      ws,
+      stream,
+      addr,
+      capabilities: MediaCapabilities::default(),
+      clock: ClockSync::default(),
+      limits: MessageLimits::default(),
+    })
+  }
+}
+
+impl MediaConnection {
+  pub(super) async fn send_message(&mut self, message: MediaMessage) -> Result<(), Error> {
+    self.send_text(Self::message_to_text(message)).await
+  }
+
+  pub(super) async fn send_text(&mut self, text: String) -> Result<(), Error> {
+    let result = self.ws.send(Message::Text(text));
+
+    self.flush_until_done(result).await
+  }
+
+  pub async fn close(&mut self) -> Result<(), Error> {
+    let result = self.ws.close(None);
+
+    self.flush_until_done(result).await
+  }
+
+  /// Closes the connection with `reason` in the close frame
+  pub async fn close_with(&mut self, reason: CloseReason) -> Result<(), Error> {
+    let result = self.ws.close(Some(super::close_frame(reason)));
+
+    self.flush_until_done(result).await
+  }
+
+  /// Messages stay queued when the socket would block, so keep flushing until they're out
+  async fn flush_until_done(&mut self, mut result: Result<(), Error>) -> Result<(), Error> {
+    while would_block(&result) {
+      self.stream.writable().await?;
+      result = self.ws.flush();
+    }
+
+    result
+  }
+
+  /// Waits for the next message to be received
+  pub async fn next_message(&mut self) -> Option<Result<ClientMessage, Error>> {
+    match self.next_text().await? {
+      Ok(message) => Some(self.receive(message.into()).await),
+      Err(err) => Some(Err(err)),
+    }
+  }
+
+  /// Waits for the next text message, any other type of message is an error
+  pub(super) async fn next_text(&mut self) -> Option<Result<String, Error>> {
+    let message = loop {
+      let message = self.ws.read();
+
+      if !would_block(&message) {
+        break message;
+      }
+
+      if let Err(err) = self.stream.readable().await {
+        return Some(Err(err.into()));
+      }
+    };
+
+    match message {
+      Ok(Message::Text(message)) => Some(Ok(message)),
+      Ok(_) => Some(Err(Error::Io(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "Unsupported message type, only supports Text",
+      )))),
+      Err(Error::ConnectionClosed | Error::AlreadyClosed) => None,
+      Err(err) => Some(Err(err)),
+    }
+  }
+}
+
+/// Runs `task` on its own thread, [MediaSourceConfig::runtime] and the worker threads are tokio only
+pub(super) fn spawn<F>(cfg: &MediaSourceConfig, task: F) -> std::io::Result<BackgroundTask>
+where
+  F: Future<Output = ()> + Send + 'static,
+{
+  let thread = cfg.threads.spawn("ws", move || {
+    async_io::block_on(task);
+  })?;
+
+  Ok(BackgroundTask::Thread(thread))
+}
+
+pub(super) async fn sleep(duration: Duration) {
+  Timer::after(duration).await;
+}
+
+/// [Err] with the panic's payload if `future` panicked
+pub(super) async fn catch_unwind<F: Future>(future: F) -> std::thread::Result<F::Output> {
+  FutureExt::catch_unwind(AssertUnwindSafe(future)).await
+}
+
+/// Returns [None] if `due` passed before `future` finished
+pub(super) async fn timeout_at<F: Future>(due: Instant, future: F) -> Option<F::Output> {
+  let timeout = async {
+    Timer::at(due).await;
+    None
+  };
+
+  futures_lite::future::or(async { Some(future.await) }, timeout).await
+}
@@ -0,0 +1,151 @@
+use std::io::Cursor;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::imageops::FilterType;
+use serde::Serialize;
+
+use super::http::{self, Route};
+use super::Outbox;
+use crate::{CoverChunk, MediaCommand, MediaImage, MediaMetadata, MediaState};
+
+/// Key sizes of the Stream Deck models, 144 is for the high dpi ones
+const KEY_SIZES: [u32; 2] = [72, 144];
+
+/// Covers scaled to [KEY_SIZES] with the checksum of the cover they're from,
+/// plugins poll a lot more often than covers change
+static COVERS: Mutex<Option<(u32, [Option<String>; 2])>> = Mutex::new(None);
+
+/// `POST /streamdeck/<action>`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Action {
+  Play,
+  Pause,
+  /// Pauses while playing, plays otherwise
+  Toggle,
+  Next,
+  Previous,
+}
+
+/// What a Stream Deck plugin needs to draw its keys, answered to `GET /streamdeck`
+#[derive(Debug, Serialize)]
+struct State {
+  title: String,
+  /// Every artist, joined by `, `
+  artist: String,
+  playing: bool,
+  /// Icon of a play/pause key, `"pause"` while playing and `"play"` otherwise
+  icon: &'static str,
+  /// Cover as a `data:image/png;base64,` url for `setImage`, [None] without a cover
+  cover_72: Option<String>,
+  cover_144: Option<String>,
+  /// The connected media client takes the actions
+  controllable: bool,
+}
+
+/// [None] if `path` isn't under `/streamdeck`
+pub(super) fn route(method: &str, path: &str) -> Option<Route> {
+  let action = match path.strip_prefix("/streamdeck")? {
+    "" => {
+      return Some(if method == "GET" {
+        Route::StreamDeck
+      } else {
+        Route::MethodNotAllowed
+      })
+    }
+    "/play" => Action::Play,
+    "/pause" => Action::Pause,
+    "/toggle" => Action::Toggle,
+    "/next" => Action::Next,
+    "/previous" => Action::Previous,
+    _ => return None,
+  };
+
+  Some(match method {
+    "POST" => Route::StreamDeckAction(action),
+    _ => Route::MethodNotAllowed,
+  })
+}
+
+/// Response to `GET /streamdeck`, `metadata` is expected to be redacted already
+pub(super) fn state(metadata: &MediaMetadata, outbox: &Outbox, client_connected: bool) -> Vec<u8> {
+  let playing = metadata.state == MediaState::Playing;
+  let [cover_72, cover_144] = metadata.cover.as_ref().map(covers).unwrap_or_default();
+
+  http::json(
+    "200 OK",
+    &State {
+      title: metadata.title.clone(),
+      artist: metadata.artists.join(", "),
+      playing,
+      icon: if playing { "pause" } else { "play" },
+      cover_72,
+      cover_144,
+      controllable: client_connected && outbox.control.load(Ordering::SeqCst),
+    },
+  )
+}
+
+/// Response to `POST /streamdeck/<action>`, the action is queued for the media client
+pub(super) fn act(
+  action: Action,
+  metadata: &MediaMetadata,
+  outbox: &Outbox,
+  client_connected: bool,
+) -> Vec<u8> {
+  if !client_connected {
+    return http::error("503 Service Unavailable", "no media client is connected");
+  }
+
+  if !outbox.control.load(Ordering::SeqCst) {
+    return http::error(
+      "501 Not Implemented",
+      "the media client doesn't take controls",
+    );
+  }
+
+  let command = match action {
+    Action::Play => MediaCommand::Play,
+    Action::Pause => MediaCommand::Pause,
+    Action::Toggle if metadata.state == MediaState::Playing => MediaCommand::Pause,
+    Action::Toggle => MediaCommand::Play,
+    Action::Next => MediaCommand::NextTrack,
+    Action::Previous => MediaCommand::PreviousTrack,
+  };
+
+  outbox.push(command.into());
+
+  http::json("202 Accepted", &serde_json::json!({ "status": "queued" }))
+}
+
+/// `cover` scaled to each of [KEY_SIZES], [None] for sizes it couldn't be scaled to
+fn covers(cover: &MediaImage) -> [Option<String>; 2] {
+  let checksum = CoverChunk::checksum(&cover.data);
+  let mut cached = COVERS.lock().unwrap();
+
+  if let Some((cached_checksum, covers)) = cached.as_ref() {
+    if *cached_checksum == checksum {
+      return covers.clone();
+    }
+  }
+
+  let image = image::load_from_memory(&cover.data).ok();
+  let covers = KEY_SIZES.map(|size| {
+    let mut png = Vec::new();
+
+    // cropped to a square, keys have no room for borders
+    image
+      .as_ref()?
+      .resize_to_fill(size, size, FilterType::Triangle)
+      .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+      .ok()?;
+
+    Some(format!("data:image/png;base64,{}", STANDARD.encode(png)))
+  });
+
+  *cached = Some((checksum, covers.clone()));
+
+  covers
+}
@@ -0,0 +1,208 @@
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use socket2::SockRef;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::{accept_async, WebSocketStream};
+
+use super::{BackgroundTask, ClockSync, MediaMessage};
+use crate::listener::{MediaSourceConfig, MessageLimits, SocketOptions};
+use crate::{ClientMessage, CloseReason, MediaCapabilities};
+
+/// Wraps around [TcpListener]
+#[derive(Debug)]
+pub struct WebsocketMediaSource {
+  pub listener: TcpListener,
+}
+
+#[derive(Debug)]
+pub struct MediaConnection {
+  pub ws: WebSocketStream<TcpStream>,
+  /// Address of the connected media client
+  pub addr: SocketAddr,
+  pub(super) capabilities: MediaCapabilities,
+  pub(super) clock: ClockSync,
+  pub(super) limits: MessageLimits,
+}
+
+impl WebsocketMediaSource {
+  /// Binds to the given address with the default [SocketOptions]
+  pub async fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+    Self::bind_with(addr, &SocketOptions::default()).await
+  }
+
+  /// Binds to the given address with [SocketOptions::reuse_address] of `options`
+  pub async fn bind_with(addr: SocketAddr, options: &SocketOptions) -> std::io::Result<Self> {
+    let listener = TcpListener::from_std(super::listen(addr, options)?)?;
+
+    Ok(Self { listener })
+  }
+
+  /// Takes over a listener that is already bound, like one passed by a service manager,
+  /// it's made non-blocking, has to be called from within a tokio runtime
+  pub fn from_std_listener(listener: std::net::TcpListener) -> std::io::Result<Self> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+
+    Ok(Self { listener })
+  }
+
+  /// Address it's bound to, with the port the OS picked when binding to port 0
+  pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+    self.listener.local_addr()
+  }
+
+  /// Establishes a websocket connection to the client
+  pub async fn get_connection(&self) -> Result<MediaConnection, Error> {
+    self.accept(&SocketOptions::default()).await?.handshake().await
+  }
+
+  /// Waits for a client without doing the websocket handshake yet, unlike
+  /// [WebsocketMediaSource::get_connection] this can be cancelled without losing the client
+  pub(super) async fn accept(&self, options: &SocketOptions) -> Result<Incoming, Error> {
+    let listener = self.listener.accept().await;
+    let (stream, addr) = listener.map_err(|_| Error::ConnectionClosed)?;
+
+    // the client is still served if its socket can't be tuned
+    let _ = super::configure(SockRef::from(&stream), options);
+
+    Ok(Incoming { stream, addr })
+  }
+}
+
+/// Client that connected but hasn't done the websocket handshake yet
+#[derive(Debug)]
+pub(super) struct Incoming {
+  stream: TcpStream,
+  pub(super) addr: SocketAddr,
+}
+
+impl Incoming {
+  /// Looks at what the client sent without taking it out of the stream
+  pub(super) async fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.stream.peek(buf).await
+  }
+
+  /// Takes the `len` bytes of the request out of the stream, sends `response` and closes the connection
+  pub(super) async fn respond(self, len: usize, response: &[u8]) -> std::io::Result<()> {
+    let mut request = vec![0; len];
+    let mut read = 0;
+
+    while read < len {
+      self.stream.readable().await?;
+
+      match self.stream.try_read(&mut request[read..]) {
+        Ok(0) => return Ok(()),
+        Ok(n) => read += n,
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+      }
+    }
+
+    let mut written = 0;
+
+    while written < response.len() {
+      self.stream.writable().await?;
+
+      match self.stream.try_write(&response[written..]) {
+        Ok(n) => written += n,
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+      }
+    }
+
+    Ok(())
+  }
+
+  pub(super) async fn handshake(self) -> Result<MediaConnection, Error> {
+    let ws = accept_async(self.stream).await?;
+
+    Ok(MediaConnection {
+      ws,
+      addr: self.addr,
+      capabilities: MediaCapabilities::default(),
+      clock: ClockSync::default(),
+      limits: MessageLimits::default(),
+    })
+  }
+}
+
+impl MediaConnection {
+  pub(super) async fn send_message(&mut self, message: MediaMessage) -> Result<(), Error> {
+    self.send_text(Self::message_to_text(message)).await
+  }
+
+  pub(super) async fn send_text(&mut self, text: String) -> Result<(), Error> {
+    self.ws.send(Message::Text(text)).await
+  }
+
+  pub async fn close(&mut self) -> Result<(), Error> {
+    self.ws.close(None).await
+  }
+
+  /// Closes the connection with `reason` in the close frame
+  pub async fn close_with(&mut self, reason: CloseReason) -> Result<(), Error> {
+    self.ws.close(Some(super::close_frame(reason))).await
+  }
+
+  /// Waits for the next message to be received
+  pub async fn next_message(&mut self) -> Option<Result<ClientMessage, Error>> {
+    match self.next_text().await? {
+      Ok(message) => Some(self.receive(message.into()).await),
+      Err(err) => Some(Err(err)),
+    }
+  }
+
+  /// Waits for the next text message, any other type of message is an error
+  pub(super) async fn next_text(&mut self) -> Option<Result<String, Error>> {
+    let message = self.ws.next().await?;
+
+    match message {
+      Ok(Message::Text(message)) => Some(Ok(message)),
+      Ok(_) => Some(Err(Error::Io(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "Unsupported message type, only supports Text",
+      )))),
+      Err(err) => Some(Err(err)),
+    }
+  }
+}
+
+/// Runs `task` on [MediaSourceConfig::runtime] if set, otherwise on its own thread and runtime
+pub(super) fn spawn<F>(cfg: &MediaSourceConfig, task: F) -> std::io::Result<BackgroundTask>
+where
+  F: Future<Output = ()> + Send + 'static,
+{
+  if let Some(runtime) = &cfg.runtime {
+    return Ok(BackgroundTask::Task(runtime.spawn(task)));
+  }
+
+  let runtime = cfg.threads.runtime("ws")?;
+
+  let thread = cfg.threads.spawn("ws", move || {
+    runtime.block_on(task);
+  })?;
+
+  Ok(BackgroundTask::Thread(thread))
+}
+
+pub(super) async fn sleep(duration: Duration) {
+  tokio::time::sleep(duration).await
+}
+
+/// [Err] with the panic's payload if `future` panicked
+pub(super) async fn catch_unwind<F: Future>(future: F) -> std::thread::Result<F::Output> {
+  FutureExt::catch_unwind(AssertUnwindSafe(future)).await
+}
+
+/// Returns [None] if `due` passed before `future` finished
+pub(super) async fn timeout_at<F: Future>(due: Instant, future: F) -> Option<F::Output> {
+  let due = tokio::time::Instant::from_std(due);
+
+  tokio::time::timeout_at(due, future).await.ok()
+}
@@ -0,0 +1,600 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backoff::Retry;
+use crate::http_client::{self, parse_response};
+use crate::listener::{EventNotifier, MediaSource, MediaSourceConfig};
+use crate::pipeline::{
+  catch_panic, event_channel, ChannelReceiver, ChannelSender, EventSender, Heartbeat,
+  TrackEndDetector,
+};
+use crate::{Error, MediaEvent, MediaMetadata, MediaState, QueueEntry, Result, SourceKind};
+
+/// Port the companion server listens on unless changed in the app
+pub const DEFAULT_PORT: u16 = 9863;
+
+#[derive(thiserror::Error, Debug)]
+pub enum YtmdError {
+  /// The token is missing, wrong or was revoked in the app
+  #[error("unauthorized, request a new token with `YtmdConfig::request_token`")]
+  Unauthorized,
+  /// Companion server is turned off in the app settings, or it doesn't allow new apps to connect
+  #[error("companion server refused: {0}")]
+  Forbidden(String),
+  #[error("unexpected status {0}: {1}")]
+  Status(u16, String),
+  #[error("malformed http response")]
+  MalformedResponse,
+}
+
+/// Where to find the companion server of the [YouTube Music Desktop App](https://ytmdesktop.app)
+/// and how to authenticate with it
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct YtmdConfig {
+  pub addr: SocketAddr,
+  /// Token from [YtmdConfig::request_token], needed by every request
+  pub token: Option<String>,
+  /// How often the player state is fetched, the companion server rate limits its api
+  /// so going much below a second gets requests rejected
+  pub poll_interval: Duration,
+}
+
+impl Default for YtmdConfig {
+  fn default() -> Self {
+    Self {
+      addr: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+      token: None,
+      poll_interval: Duration::from_millis(1000),
+    }
+  }
+}
+
+impl YtmdConfig {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_addr(self, addr: SocketAddr) -> Self {
+    Self { addr, ..self }
+  }
+
+  pub fn set_token(self, token: impl Into<String>) -> Self {
+    Self {
+      token: Some(token.into()),
+      ..self
+    }
+  }
+
+  pub fn set_poll_interval(self, poll_interval: Duration) -> Self {
+    Self {
+      poll_interval,
+      ..self
+    }
+  }
+
+  /// Asks the app for a token, this blocks until the user accepts or denies the request in the app,
+  /// store the token, requesting it again shows another prompt
+  ///
+  /// `app_id` has to be lowercase alphanumeric or `_`, 2 to 32 characters long
+  ///
+  /// ```rs
+  /// let ytmd = YtmdConfig::new();
+  /// let token = ytmd.request_token("my_overlay", "My Overlay", "1.0.0")?;
+  /// let cfg = MediaSourceConfig::new().set_ytmd(ytmd.set_token(token));
+  /// ```
+  pub fn request_token(&self, app_id: &str, app_name: &str, app_version: &str) -> Result<String> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct CodeRequest<'a> {
+      app_id: &'a str,
+      app_name: &'a str,
+      app_version: &'a str,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TokenRequest<'a> {
+      app_id: &'a str,
+      code: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    struct Code {
+      code: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Token {
+      token: String,
+    }
+
+    let body = serde_json::to_string(&CodeRequest {
+      app_id,
+      app_name,
+      app_version,
+    })
+    .map_err(|err| Error::Protocol(err.into()))?;
+
+    let Code { code } = self.request("POST", "/api/v1/auth/requestcode", Some(&body), None)?;
+
+    let body = serde_json::to_string(&TokenRequest {
+      app_id,
+      code: &code,
+    })
+    .map_err(|err| Error::Protocol(err.into()))?;
+
+    // the app shows the prompt for 30 seconds
+    let Token { token } = self.request(
+      "POST",
+      "/api/v1/auth/request",
+      Some(&body),
+      Some(Duration::from_secs(35)),
+    )?;
+
+    Ok(token)
+  }
+
+  fn state(&self) -> Result<YtmdState> {
+    self.request("GET", "/api/v1/state", None, None)
+  }
+
+  /// Plain HTTP/1.1 request, the companion server only listens on the local network so there's no tls
+  fn request<T: for<'de> Deserialize<'de>>(
+    &self,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Option<Duration>,
+  ) -> Result<T> {
+    let timeout = timeout.unwrap_or(Duration::from_secs(5));
+    let headers = self
+      .token
+      .as_deref()
+      .map(|token| ("Authorization", token))
+      .into_iter()
+      .collect::<Vec<_>>();
+
+    let response = http_client::request(&self.addr, method, path, &headers, body, timeout)?;
+    let (status, body) = parse_response(&response).ok_or(YtmdError::MalformedResponse)?;
+
+    match status {
+      200..=299 => serde_json::from_slice(&body).map_err(|err| Error::Protocol(err.into())),
+      401 => Err(YtmdError::Unauthorized.into()),
+      403 => Err(YtmdError::Forbidden(String::from_utf8_lossy(&body).into_owned()).into()),
+      _ => Err(YtmdError::Status(status, String::from_utf8_lossy(&body).into_owned()).into()),
+    }
+  }
+}
+
+impl From<YtmdError> for Error {
+  fn from(value: YtmdError) -> Self {
+    match value {
+      YtmdError::MalformedResponse => Self::Protocol(value.into()),
+      _ => Self::Connection(value.into()),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YtmdState {
+  player: YtmdPlayer,
+  video: Option<YtmdVideo>,
+  playlist_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YtmdPlayer {
+  /// -1 unknown, 0 paused, 1 playing, 2 buffering
+  track_state: i8,
+  /// Seconds
+  video_progress: f64,
+  #[serde(default)]
+  volume: f64,
+  #[serde(default)]
+  ad_playing: bool,
+  queue: Option<YtmdQueue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YtmdQueue {
+  #[serde(default)]
+  items: Vec<YtmdQueueItem>,
+  selected_item_index: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YtmdQueueItem {
+  video_id: String,
+  title: String,
+  author: String,
+  /// `m:ss` or `h:mm:ss`
+  duration: String,
+  #[serde(default)]
+  thumbnails: Vec<YtmdThumbnail>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YtmdVideo {
+  id: String,
+  title: String,
+  author: String,
+  album: Option<String>,
+  album_id: Option<String>,
+  channel_id: Option<String>,
+  /// -1 unknown, 0 dislike, 1 indifferent, 2 like
+  like_status: Option<i8>,
+  #[serde(default)]
+  thumbnails: Vec<YtmdThumbnail>,
+  duration_seconds: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct YtmdThumbnail {
+  url: String,
+  #[serde(default)]
+  width: u32,
+}
+
+/// Largest thumbnail, they're not always ordered by size
+fn largest_thumbnail(thumbnails: &[YtmdThumbnail]) -> Option<String> {
+  thumbnails
+    .iter()
+    .max_by_key(|thumbnail| thumbnail.width)
+    .map(|thumbnail| thumbnail.url.clone())
+}
+
+fn watch_url(video_id: &str) -> String {
+  format!("https://music.youtube.com/watch?v={video_id}")
+}
+
+/// Reads `m:ss` or `h:mm:ss`
+fn parse_clock(text: &str) -> Duration {
+  let secs = text
+    .split(':')
+    .try_fold(0u64, |secs, part| {
+      Some(secs * 60 + part.trim().parse::<u64>().ok()?)
+    })
+    .unwrap_or_default();
+
+  Duration::from_secs(secs)
+}
+
+impl YtmdState {
+  fn into_metadata(self) -> MediaMetadata {
+    let Some(video) = self.video else {
+      return MediaMetadata::default();
+    };
+
+    let state = match self.player.track_state {
+      // buffering is only ever a short pause in between playing
+      1 | 2 => MediaState::Playing,
+      0 => MediaState::Paused,
+      _ => MediaState::Stopped,
+    };
+
+    let mut extra = BTreeMap::new();
+    extra.insert("volume".into(), self.player.volume.into());
+    extra.insert("ad_playing".into(), self.player.ad_playing.into());
+
+    if let Some(like_status) = video.like_status {
+      extra.insert("like_status".into(), like_status.into());
+    }
+
+    for (key, value) in [
+      ("album_id", video.album_id),
+      ("channel_id", video.channel_id),
+      ("playlist_id", self.playlist_id),
+    ] {
+      if let Some(value) = value {
+        extra.insert(key.into(), value.into());
+      }
+    }
+
+    let queue = self
+      .player
+      .queue
+      .map(|queue| {
+        let next = queue
+          .selected_item_index
+          .map(|index| index + 1)
+          .unwrap_or_default();
+
+        queue
+          .items
+          .into_iter()
+          .skip(next)
+          .map(|item| QueueEntry {
+            uri: Some(watch_url(&item.video_id)),
+            uid: Some(item.video_id),
+            duration: parse_clock(&item.duration),
+            title: item.title,
+            album: None,
+            artists: vec![item.author],
+            cover_url: largest_thumbnail(&item.thumbnails),
+          })
+          .collect()
+      })
+      .unwrap_or_default();
+
+    MediaMetadata {
+      uri: Some(watch_url(&video.id)),
+      uid: Some(video.id),
+      state,
+      duration: Duration::from_secs(video.duration_seconds),
+      elapsed: Duration::try_from_secs_f64(self.player.video_progress).unwrap_or_default(),
+      title: video.title,
+      album: video.album,
+      artists: vec![video.author],
+      featured_artists: Vec::new(),
+      cover_url: largest_thumbnail(&video.thumbnails),
+      cover: None,
+      background_url: None,
+      background: None,
+      app: Some("YouTube Music Desktop".into()),
+      zone: None,
+      extra,
+      queue,
+    }
+  }
+}
+
+/// Source for the companion server of the [YouTube Music Desktop App](https://ytmdesktop.app),
+/// which reports durations and covers that GSMTC doesn't get from it
+///
+/// Enable the companion server and "allow browser communication" in the app settings,
+/// then add it as a custom source
+///
+/// ```rs
+/// let cfg = MediaSourceConfig::default().set_ytmd(YtmdConfig::new().set_token(token));
+///
+/// let listener = MediaListener::builder()
+///   .with_config(cfg.clone())
+///   .with_source(YtmdMediaSource::NAME, YtmdMediaSource::create(cfg)?)
+///   .build()?;
+/// ```
+#[derive(Debug)]
+pub struct YtmdMediaSource {
+  timeout: Duration,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  /// Message of the last error the background task ran into
+  last_error: Arc<RwLock<Option<String>>>,
+  recv: ChannelReceiver,
+  _background_task: JoinHandle<()>,
+}
+
+impl YtmdMediaSource {
+  /// Name to add the source under, it's what [MediaEvent::SourceError] and friends report
+  pub const NAME: &'static str = "ytmd";
+}
+
+impl MediaSource for YtmdMediaSource {
+  fn create(cfg: MediaSourceConfig) -> Result<Self> {
+    let Some(ytmd) = cfg.ytmd.clone() else {
+      return Err(Error::NotEnabled);
+    };
+
+    let cfg = cfg.for_source(&SourceKind::Custom(YtmdMediaSource::NAME.into()));
+
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    let is_running = Arc::new(AtomicBool::new(false));
+    let metadata = Arc::new(RwLock::new(MediaMetadata::default()));
+    let heartbeat = Heartbeat::new(cfg.stale_after);
+    let last_error = Arc::new(RwLock::new(None));
+    let (send, recv) = event_channel(&cfg, SourceKind::Custom(YtmdMediaSource::NAME.into()));
+
+    let _background_task = spawn_background_task(
+      cfg.clone(),
+      ytmd,
+      cancel_token.clone(),
+      is_running.clone(),
+      metadata.clone(),
+      heartbeat.clone(),
+      last_error.clone(),
+      send,
+    )?;
+
+    Ok(Self {
+      timeout: cfg.timeout,
+      cancel_token,
+      is_running,
+      metadata,
+      heartbeat,
+      last_error,
+      recv,
+      _background_task,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.cancel_token.load(Ordering::SeqCst)
+  }
+
+  fn is_running(&self) -> bool {
+    self.is_running.load(Ordering::SeqCst)
+  }
+
+  fn poll(&self) -> Result<MediaMetadata> {
+    self.poll_guarded().map(|v| v.clone())
+  }
+
+  fn poll_guarded(&self) -> Result<RwLockReadGuard<'_, MediaMetadata>> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    self.heartbeat.check(&self.metadata);
+
+    Ok(self.metadata.read().unwrap())
+  }
+
+  fn is_stale(&self) -> bool {
+    self.heartbeat.check(&self.metadata)
+  }
+
+  fn last_error(&self) -> Option<String> {
+    self.last_error.read().unwrap().clone()
+  }
+
+  fn next(&self) -> Result<MediaEvent> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+
+    let event = self.recv.recv_timeout(self.timeout)?;
+
+    Ok(event)
+  }
+
+  fn drain(&self) -> Vec<MediaEvent> {
+    self.recv.drain()
+  }
+
+  fn set_notifier(&self, notifier: Arc<EventNotifier>) -> bool {
+    self.recv.set_notifier(notifier);
+    true
+  }
+}
+
+impl Drop for YtmdMediaSource {
+  fn drop(&mut self) {
+    self.cancel_token.store(true, Ordering::SeqCst)
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_background_task(
+  cfg: MediaSourceConfig,
+  ytmd: YtmdConfig,
+  cancel_token: Arc<AtomicBool>,
+  is_running: Arc<AtomicBool>,
+  metadata: Arc<RwLock<MediaMetadata>>,
+  heartbeat: Heartbeat,
+  last_error: Arc<RwLock<Option<String>>>,
+  send: ChannelSender,
+) -> std::io::Result<JoinHandle<()>> {
+  let threads = cfg.threads.clone();
+
+  threads.spawn(YtmdMediaSource::NAME, move || {
+    let mut retry = Retry::new(cfg.backoff.clone());
+    let mut events = EventSender::new(send.clone(), &cfg);
+    let source = SourceKind::Custom(YtmdMediaSource::NAME.into());
+
+    loop {
+      let result = catch_panic(&source, &metadata, || {
+        background_task(
+          &cfg,
+          &ytmd,
+          &cancel_token,
+          &is_running,
+          &metadata,
+          &heartbeat,
+          send.clone(),
+        )
+      });
+
+      match result {
+        Ok(_) => break,
+        Err(err) => {
+          // it was up before failing, so this is a new outage
+          if is_running.swap(false, Ordering::SeqCst) {
+            retry.reset();
+          }
+
+          let message = err.to_string();
+          *last_error.write().unwrap() = Some(message.clone());
+
+          events.send(MediaEvent::SourceError {
+            source: source.clone(),
+            message,
+          });
+
+          let Some(delay) = retry.next_delay() else {
+            events.send(MediaEvent::SourceGaveUp(source));
+            break;
+          };
+
+          cfg.metrics.reconnected();
+          std::thread::sleep(delay);
+        }
+      }
+    }
+  })
+}
+
+fn background_task(
+  cfg: &MediaSourceConfig,
+  ytmd: &YtmdConfig,
+  cancel_token: &AtomicBool,
+  is_running: &AtomicBool,
+  metadata: &RwLock<MediaMetadata>,
+  heartbeat: &Heartbeat,
+  send: ChannelSender,
+) -> Result<()> {
+  let mut send = EventSender::new(send, cfg);
+  let mut track_end = TrackEndDetector::default();
+
+  loop {
+    if cancel_token.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+
+    let mut new_metadata = ytmd.state()?.into_metadata();
+    cfg.sanitize.apply(&mut new_metadata);
+    cfg.apply_zone(&mut new_metadata);
+
+    is_running.store(true, Ordering::SeqCst);
+
+    let restore = heartbeat.beat();
+    let mut metadata = metadata.write().unwrap();
+    let mut events = Vec::new();
+
+    if let Some(state) = restore {
+      metadata.state = state;
+    }
+
+    let state = new_metadata.state;
+    let media_changed = cfg.identity.is_different(&metadata, &new_metadata);
+
+    match () {
+      _ if media_changed => events.push(MediaEvent::MediaChanged(new_metadata.clone())),
+      _ if metadata.state != state => events.push(MediaEvent::StateChanged(state)),
+      _ if state == MediaState::Playing => {
+        events.push(MediaEvent::ProgressChanged(new_metadata.elapsed))
+      }
+      _ => {}
+    };
+
+    // a new track already carries its queue
+    if !media_changed && metadata.queue != new_metadata.queue {
+      events.push(MediaEvent::QueueChanged(new_metadata.queue.clone()));
+    }
+
+    if let Some(ended) = events.first().and_then(|e| track_end.detect(&metadata, e)) {
+      events.insert(0, ended);
+    }
+
+    *metadata = new_metadata;
+    drop(metadata);
+
+    for event in events {
+      send.send(event);
+    }
+
+    send.flush();
+    std::thread::sleep(ytmd.poll_interval);
+  }
+}
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use currently_playing::platform::raw::RawMetadata;
+use currently_playing::sanitize::SanitizeConfig;
+use currently_playing::MediaMetadata;
+
+/// What a platform reported, like from `currently-playing dump-raw`, and what the system source has to make of it
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+  raw: RawMetadata,
+  #[serde(default, skip_serializing_if = "is_default")]
+  sanitize: SanitizeConfig,
+  /// Left out of new fixtures until they're blessed
+  #[serde(default)]
+  expected: Option<MediaMetadata>,
+}
+
+fn is_default(sanitize: &SanitizeConfig) -> bool {
+  *sanitize == SanitizeConfig::default()
+}
+
+/// Maps every fixture in `fixtures/` and compares it to what it's expected to be,
+/// with `BLESS=1` it writes what it's mapped to as expected instead, check the diff before committing it
+#[test]
+fn fixtures_map_to_expected() {
+  let bless = std::env::var_os("BLESS").is_some_and(|bless| bless == "1");
+  let mut failed = Vec::new();
+
+  for path in fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")) {
+    let name = path.display();
+    let data = std::fs::read(&path).unwrap_or_else(|err| panic!("{name}: {err}"));
+    let mut fixture: Fixture =
+      serde_json::from_slice(&data).unwrap_or_else(|err| panic!("{name}: {err}"));
+
+    let mut metadata = fixture.raw.to_metadata();
+    fixture.sanitize.apply(&mut metadata);
+
+    // compared like it's written, durations are whole milliseconds
+    let metadata: MediaMetadata =
+      serde_json::from_value(serde_json::to_value(metadata).unwrap()).unwrap();
+
+    if bless {
+      fixture.expected = Some(metadata);
+
+      let json = serde_json::to_string_pretty(&fixture).unwrap();
+      std::fs::write(&path, json + "\n").unwrap_or_else(|err| panic!("{name}: {err}"));
+
+      println!("blessed {name}");
+      continue;
+    }
+
+    match &fixture.expected {
+      Some(expected) if *expected == metadata => {}
+      Some(expected) => {
+        eprintln!("{name}\nexpected {expected:#?}\ngot {metadata:#?}");
+        failed.push(name.to_string());
+      }
+      None => {
+        eprintln!("{name}: nothing is expected yet, run with BLESS=1");
+        failed.push(name.to_string());
+      }
+    }
+  }
+
+  assert!(failed.is_empty(), "fixtures failed: {failed:?}");
+}
+
+/// Json files in the directories of `dir`, sorted
+fn fixtures(dir: &Path) -> Vec<PathBuf> {
+  let mut fixtures = std::fs::read_dir(dir)
+    .unwrap_or_else(|err| panic!("{}: {err}", dir.display()))
+    .flatten()
+    .filter(|entry| entry.path().is_dir())
+    .flat_map(|platform| {
+      std::fs::read_dir(platform.path())
+        .into_iter()
+        .flatten()
+        .flatten()
+    })
+    .map(|entry| entry.path())
+    .filter(|path| {
+      path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    })
+    .collect::<Vec<_>>();
+
+  fixtures.sort();
+  fixtures
+}